@@ -41,7 +41,7 @@ fn get(expr: &mut Expr, out: &mut State) -> Result<()> {
     match expr.desc_mut() {
         ExprDesc::Nil => {}
         ExprDesc::Bool(_) => {}
-        ExprDesc::Number(_) => {}
+        ExprDesc::Number(_) | ExprDesc::Int(_) => {}
         ExprDesc::String(_) => {}
         ExprDesc::Name(name) => {
             if !out.read.contains_key(name) {
@@ -88,14 +88,37 @@ fn get(expr: &mut Expr, out: &mut State) -> Result<()> {
                 get(other, out)?;
             }
         }
-        ExprDesc::For(target, container, body) => {
+        ExprDesc::For(target, container, body, label) => {
             gettarget(target, out)?;
             get(container, out)?;
+            enter_loop(label, &mark, out)?;
             get(body, out)?;
+            out.loops.pop();
         }
-        ExprDesc::While(cond, body) => {
+        ExprDesc::While(cond, body, label) => {
+            enter_loop(label, &mark, out)?;
             get(cond, out)?;
             get(body, out)?;
+            out.loops.pop();
+        }
+        ExprDesc::Break(label) => check_loop_exit("break", label, mark, out)?,
+        ExprDesc::Continue(label) => check_loop_exit("continue", label, mark, out)?,
+        ExprDesc::Try(body, clauses, finally) => {
+            get(body, out)?;
+            for clause in clauses {
+                if let Some(name) = &clause.name {
+                    if !out.write.contains_key(name) {
+                        out.write.insert(name.clone(), mark.clone());
+                    }
+                }
+                if let Some(cls) = &mut clause.cls {
+                    get(cls, out)?;
+                }
+                get(&mut clause.body, out)?;
+            }
+            if let Some(finally) = finally {
+                get(finally, out)?;
+            }
         }
         ExprDesc::Binop(_op, lhs, rhs) => {
             get(lhs, out)?;
@@ -237,6 +260,36 @@ fn get(expr: &mut Expr, out: &mut State) -> Result<()> {
     Ok(())
 }
 
+fn enter_loop(label: &Option<RcStr>, mark: &Mark, out: &mut State) -> Result<()> {
+    if label.is_some() && out.loops.contains(label) {
+        return Err(Error::rt(
+            format!("Loop label {:?} is already in use", label.as_ref().unwrap()).into(),
+            vec![mark.clone()],
+        ));
+    }
+    out.loops.push(label.clone());
+    Ok(())
+}
+
+/// Checks that a 'break' or 'continue' has a loop to apply to
+fn check_loop_exit(keyword: &str, label: &Option<RcStr>, mark: Mark, out: &State) -> Result<()> {
+    match label {
+        None if out.loops.is_empty() => Err(Error::rt(
+            format!("'{}' outside of a loop", keyword).into(),
+            vec![mark],
+        )),
+        Some(name) if !out.loops.contains(label) => Err(Error::rt(
+            format!(
+                "'{} {}' outside of a loop labeled {:?}",
+                keyword, name, name
+            )
+            .into(),
+            vec![mark],
+        )),
+        _ => Ok(()),
+    }
+}
+
 fn getargs(args: &mut Args, out: &mut State) -> Result<()> {
     for arg in &mut args.args {
         get(arg, out)?;
@@ -331,6 +384,10 @@ struct State {
     nested_free: HashMap<RcStr, Mark>,
 
     class_stack: Vec<RcStr>,
+
+    /// Labels of the loops enclosing the current expression
+    /// (within the current function), innermost last
+    loops: Vec<Option<RcStr>>,
 }
 
 impl From<State> for VarSpec {
@@ -32,6 +32,14 @@ impl fmt::Debug for Variable {
 }
 
 impl Variable {
+    pub(crate) fn new(type_: VariableType, slot: usize, name: RcStr, mark: Mark) -> Self {
+        Self {
+            type_,
+            slot,
+            name,
+            mark,
+        }
+    }
     pub fn type_(&self) -> VariableType {
         self.type_
     }
@@ -41,6 +49,9 @@ impl Variable {
     pub fn name(&self) -> &RcStr {
         &self.name
     }
+    pub fn mark(&self) -> &Mark {
+        &self.mark
+    }
 }
 
 /// Description of the state of variables in a given scope
@@ -224,6 +235,7 @@ pub enum ExprDesc {
     Nil,
     Bool(bool),
     Number(f64),
+    Int(i64),
     String(RcStr),
     Name(RcStr),
     List(Vec<Expr>),
@@ -234,8 +246,17 @@ pub enum ExprDesc {
 
     Switch(Box<Expr>, Vec<(Vec<Expr>, Expr)>, Option<Box<Expr>>),
     If(Vec<(Expr, Expr)>, Option<Box<Expr>>),
-    For(AssignTarget, Box<Expr>, Box<Expr>),
-    While(Box<Expr>, Box<Expr>),
+    /// The last field of a loop is its label, if it has one
+    For(AssignTarget, Box<Expr>, Box<Expr>, Option<RcStr>),
+    While(Box<Expr>, Box<Expr>, Option<RcStr>),
+
+    /// 'break' and 'continue', with the label of the loop they apply to.
+    /// Without a label, they apply to the innermost loop
+    Break(Option<RcStr>),
+    Continue(Option<RcStr>),
+
+    /// try-body, catch clauses, and the finally-body
+    Try(Box<Expr>, Vec<CatchClause>, Option<Box<Expr>>),
 
     Binop(Binop, Box<Expr>, Box<Expr>),
     LogicalBinop(LogicalBinop, Box<Expr>, Box<Expr>),
@@ -288,6 +309,16 @@ pub enum ExprDesc {
     },
 }
 
+/// A single 'catch' clause of a try expression
+/// If an exception class is specified, the clause only handles exceptions
+/// that are instances of that class
+#[derive(Debug)]
+pub struct CatchClause {
+    pub name: Option<RcStr>,
+    pub cls: Option<Expr>,
+    pub body: Expr,
+}
+
 #[derive(Debug)]
 pub struct AssignTarget {
     mark: Mark,
@@ -325,6 +356,7 @@ pub enum Unop {
     Pos,
     Neg,
     Not,
+    Invert,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -337,6 +369,11 @@ pub enum Binop {
     Rem,
     ERem, // Euclidean remainder
     Pow,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
     Lt,
     Le,
     Gt,
@@ -14,6 +14,10 @@ pub struct Code {
 
     doc: Option<RcStr>,
     docmap: Rc<HashMap<RcStr, RcStr>>,
+
+    /// Opcode indices where the debugger has line breakpoints,
+    /// along with the debugger generation they were computed for
+    breakpoint_cache: RefCell<Option<(usize, HashSet<usize>)>>,
 }
 
 impl fmt::Debug for Code {
@@ -40,6 +44,7 @@ impl Code {
             marks,
             doc,
             docmap: Rc::new(docmap),
+            breakpoint_cache: RefCell::new(None),
         }
     }
     pub fn name(&self) -> &RcStr {
@@ -63,6 +68,9 @@ impl Code {
     pub fn docmap(&self) -> &HashMap<RcStr, RcStr> {
         &self.docmap
     }
+    pub(super) fn breakpoint_cache(&self) -> &RefCell<Option<(usize, HashSet<usize>)>> {
+        &self.breakpoint_cache
+    }
     pub(crate) fn new_frame(&self, bindings: Vec<Rc<RefCell<Value>>>) -> Frame {
         Frame::new(
             self.varspec.local().len(),
@@ -137,11 +145,25 @@ impl Code {
     /// For repl
     /// runs this code object while using the given map essentially as a dynamic module scope
     pub fn apply_for_repl(&self, globals: &mut Globals) -> Result<Value> {
+        let mut frame = self.new_frame_for_scope(globals.repl_scope_mut())?;
+        self.run_frame(globals, &mut frame)
+    }
+
+    /// Like apply_for_repl, but with an explicitly provided scope
+    pub fn apply_for_scope(
+        &self,
+        globals: &mut Globals,
+        scope: &mut HashMap<RcStr, Rc<RefCell<Value>>>,
+    ) -> Result<Value> {
+        let mut frame = self.new_frame_for_scope(scope)?;
+        self.run_frame(globals, &mut frame)
+    }
+
+    fn new_frame_for_scope(&self, scope: &mut HashMap<RcStr, Rc<RefCell<Value>>>) -> Result<Frame> {
         assert_eq!(self.params.len(), 0);
-        let repl_scope = globals.repl_scope_mut();
         let mut input_bindings = Vec::new();
         for (name, mark) in self.varspec.free() {
-            if let Some(cell) = repl_scope.get(name) {
+            if let Some(cell) = scope.get(name) {
                 input_bindings.push(cell.clone());
             } else {
                 return Err(crate::Error::rt(
@@ -158,24 +180,24 @@ impl Code {
         let owned_entries = self.varspec.owned().clone();
         let owned_cells = &mut frame.getcells_mut()[freelen..][..ownedlen];
         for ((name, _mark), new_cell) in owned_entries.into_iter().zip(owned_cells) {
-            if let Some(old_cell) = repl_scope.get(&name) {
+            if let Some(old_cell) = scope.get(&name) {
                 *new_cell = old_cell.clone();
             } else {
-                repl_scope.insert(name, new_cell.clone());
+                scope.insert(name, new_cell.clone());
             }
         }
-
-        self.run_frame(globals, &mut frame)
+        Ok(frame)
     }
 
     fn run_frame(&self, globals: &mut Globals, frame: &mut Frame) -> Result<Value> {
+        frame.enter(globals);
         loop {
             match step(globals, self, frame) {
                 StepResult::Ok => {}
                 StepResult::Yield(_) => return Err(rterr!("Yield from unyieldable context")),
                 StepResult::Return(value) => return Ok(value),
                 StepResult::Await(_) => return Err(rterr!("Await outside async function")),
-                StepResult::Err(error) => return Err(error),
+                StepResult::Err(error) => frame.catch(globals, error)?,
             }
         }
     }
@@ -186,6 +208,7 @@ impl Code {
         frame: &mut Frame,
         arg: Value,
     ) -> ResumeResult {
+        frame.enter(globals);
         frame.push(arg);
         loop {
             match step(globals, self, frame) {
@@ -195,23 +218,18 @@ impl Code {
                 StepResult::Await(_) => {
                     return ResumeResult::Err(rterr!("Await outside async function"))
                 }
-                StepResult::Err(error) => return ResumeResult::Err(error),
+                StepResult::Err(error) => {
+                    if let Err(error) = frame.catch(globals, error) {
+                        return ResumeResult::Err(error);
+                    }
+                }
             }
         }
     }
 
     pub(crate) fn start_async(&self, globals: &mut Globals, frame: &mut Frame) -> AsyncResult {
-        loop {
-            match step(globals, self, frame) {
-                StepResult::Ok => {}
-                StepResult::Yield(_) => {
-                    return AsyncResult::Err(rterr!("Yield outside async function"))
-                }
-                StepResult::Return(value) => return AsyncResult::Return(value),
-                StepResult::Await(promise) => return AsyncResult::Await(promise),
-                StepResult::Err(error) => return AsyncResult::Err(error),
-            }
-        }
+        frame.enter(globals);
+        self.run_async(globals, frame)
     }
 
     pub(crate) fn resume_async(
@@ -224,6 +242,38 @@ impl Code {
         self.start_async(globals, frame)
     }
 
+    /// Resumes an async frame by throwing the given error at the point
+    /// where it is suspended (i.e. the promise it was waiting on failed).
+    /// If nothing in the frame catches it, the error is handed back as is
+    pub(crate) fn throw_async(
+        &self,
+        globals: &mut Globals,
+        frame: &mut Frame,
+        error: Error,
+    ) -> Result<AsyncResult> {
+        frame.enter(globals);
+        frame.catch(globals, error)?;
+        Ok(self.run_async(globals, frame))
+    }
+
+    fn run_async(&self, globals: &mut Globals, frame: &mut Frame) -> AsyncResult {
+        loop {
+            match step(globals, self, frame) {
+                StepResult::Ok => {}
+                StepResult::Yield(_) => {
+                    return AsyncResult::Err(rterr!("Yield outside async function"))
+                }
+                StepResult::Return(value) => return AsyncResult::Return(value),
+                StepResult::Await(promise) => return AsyncResult::Await(promise),
+                StepResult::Err(error) => {
+                    if let Err(error) = frame.catch(globals, error) {
+                        return AsyncResult::Err(error);
+                    }
+                }
+            }
+        }
+    }
+
     pub fn disasm(&self) -> Result<String> {
         let mut ret = String::new();
         let out = &mut ret;
@@ -236,6 +286,11 @@ impl Code {
         for (loc, op) in self.ops.iter().enumerate() {
             writeln!(out, "        {:<8} {:?}", loc, op)?;
         }
+        for op in &self.ops {
+            if let Opcode::NewFunction(desc) = op {
+                out.push_str(&desc.code.disasm()?);
+            }
+        }
         Ok(ret)
     }
 }
@@ -0,0 +1,542 @@
+use super::*;
+
+/// What the program should do when a DebugHook returns control to it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugAction {
+    /// Run until the next breakpoint
+    Continue,
+    /// Pause again at the next line, entering any function calls
+    StepIn,
+    /// Pause again at the next line of the current function,
+    /// or of its caller if the current function returns first
+    StepOver,
+    /// Pause again once the current function returns
+    StepOut,
+}
+
+/// Why execution was paused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PauseReason {
+    /// A 'def break' breakpoint was reached
+    Breakpoint,
+    /// A step requested by a previous DebugAction finished
+    Step,
+    /// A pause was requested with Globals::request_debug_pause
+    Requested,
+}
+
+/// Takes control whenever execution pauses, and decides how to resume.
+/// By default, a DebugConsole is used, but embedders (e.g. IDEs) can
+/// install their own with Globals::set_debug_hook
+pub trait DebugHook {
+    fn pause(
+        &mut self,
+        globals: &mut Globals,
+        frame: &mut DebugFrame,
+        reason: PauseReason,
+    ) -> Result<DebugAction>;
+}
+
+/// The frame a DebugHook was paused in
+pub struct DebugFrame<'a> {
+    code: &'a Code,
+    frame: &'a mut Frame,
+    pc: usize,
+}
+
+impl<'a> DebugFrame<'a> {
+    /// The name of the function or module this frame is running
+    pub fn name(&self) -> &RcStr {
+        self.code.name()
+    }
+
+    /// The location execution is paused at
+    pub fn mark(&self) -> &Mark {
+        &self.code.marks()[self.pc]
+    }
+
+    /// The full stack trace, with the location of this frame last
+    pub fn trace(&self, globals: &Globals) -> Vec<Mark> {
+        let mut trace = globals.trace().clone();
+        trace.push(self.mark().clone());
+        trace
+    }
+
+    /// The names of all variables in this frame, whether set or not
+    pub fn names(&self) -> Vec<RcStr> {
+        let varspec = self.code.varspec();
+        varspec
+            .local()
+            .iter()
+            .chain(varspec.free())
+            .chain(varspec.owned())
+            .map(|(name, _)| name.clone())
+            // hidden variables (e.g. for classes) contain a '/'
+            .filter(|name| !name.contains('/'))
+            .collect()
+    }
+
+    /// All variables in this frame that are currently set, sorted by name
+    pub fn variables(&self) -> Vec<(RcStr, Value)> {
+        let mut names = self.names();
+        names.sort();
+        names
+            .into_iter()
+            .filter_map(|name| self.get(&name).map(|value| (name, value)))
+            .collect()
+    }
+
+    /// Gets the value of a variable in this frame.
+    /// Returns None if there is no such variable, or if it is not yet set
+    pub fn get(&self, name: &str) -> Option<Value> {
+        let var = self.code.varspec().get(&name.into())?;
+        self.frame.getvar(&var).ok()
+    }
+
+    pub fn set(&mut self, name: &str, value: Value) -> Result<()> {
+        match self.code.varspec().get(&name.into()) {
+            Some(var) => {
+                self.frame.setvar(&var, value);
+                Ok(())
+            }
+            None => Err(kinderr!(NotFound, "Variable {:?} not found", name)),
+        }
+    }
+
+    /// Evaluates the given source as if it appeared in this frame.
+    /// Besides the variables in this frame, the globals of the frame's module
+    /// and the builtins are also visible.
+    /// Assignments to variables of this frame are written back to it
+    pub fn eval(&mut self, globals: &mut Globals, source: &str) -> Result<Value> {
+        let mut scope = HashMap::new();
+        for (name, value) in globals.builtins() {
+            scope.insert(name.clone(), Rc::new(RefCell::new(value.clone())));
+        }
+        if let Some(module) = globals.get_module(self.mark().source().name()) {
+            for (name, cell) in module.map() {
+                scope.insert(name.clone(), cell.clone());
+            }
+        }
+        let mut locals = Vec::new();
+        for name in self.names() {
+            let var = self.code.varspec().get(&name).unwrap();
+            let cell = match var.type_() {
+                VariableType::Local => {
+                    let value = self.frame.getvar(&var).unwrap_or(Value::Invalid);
+                    let cell = Rc::new(RefCell::new(value));
+                    locals.push((var, cell.clone()));
+                    cell
+                }
+                VariableType::Upval => self.frame.getcell(var.slot()),
+            };
+            scope.insert(name, cell);
+        }
+        let result = globals.exec_in_scope("[debug]", source, &mut scope);
+        for (var, cell) in locals {
+            self.frame.setvar(&var, cell.replace(Value::Invalid));
+        }
+        result
+    }
+}
+
+/// Debugger state kept in Globals
+#[derive(Default)]
+pub struct Debugger {
+    hook: Option<Box<dyn DebugHook>>,
+    step: Option<Step>,
+    pause_requested: bool,
+
+    /// Line breakpoints, keyed by canonicalized source path
+    breakpoints: HashMap<PathBuf, HashSet<usize>>,
+
+    /// Bumped whenever the breakpoints change, so that the
+    /// breakpoint locations cached in each Code can be invalidated
+    generation: usize,
+
+    /// Whether a hook is currently running.
+    /// Breakpoints are ignored while paused (e.g. when evaluating expressions)
+    paused: bool,
+
+    /// Whether there is anything that needs to be checked before every opcode
+    active: bool,
+
+    /// Whether a hook has quit the debugger
+    quit: bool,
+}
+
+/// A pending step requested by a hook
+struct Step {
+    action: DebugAction,
+    depth: usize,
+    mark: Mark,
+}
+
+impl Debugger {
+    pub(crate) fn set_hook(&mut self, hook: Box<dyn DebugHook>) {
+        self.hook = Some(hook);
+    }
+    pub(crate) fn set_breakpoints(&mut self, path: &Path, lines: Vec<usize>) {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_owned());
+        if lines.is_empty() {
+            self.breakpoints.remove(&path);
+        } else {
+            self.breakpoints.insert(path, lines.into_iter().collect());
+        }
+        self.generation += 1;
+        self.update_active();
+    }
+    pub(crate) fn quit(&self) -> bool {
+        self.quit
+    }
+    pub(crate) fn request_pause(&mut self) {
+        self.pause_requested = true;
+        self.update_active();
+    }
+    fn update_active(&mut self) {
+        self.active = self.step.is_some() || self.pause_requested || !self.breakpoints.is_empty();
+    }
+    #[inline(always)]
+    pub(super) fn active(&self) -> bool {
+        self.active
+    }
+
+    /// Checks whether the opcode at the given pc is the first opcode of
+    /// a line with a breakpoint
+    fn is_breakpoint(&self, code: &Code, pc: usize) -> bool {
+        let mut cache = code.breakpoint_cache().borrow_mut();
+        match &*cache {
+            Some((generation, pcs)) if *generation == self.generation => pcs.contains(&pc),
+            _ => {
+                let pcs = self.find_breakpoints(code);
+                let found = pcs.contains(&pc);
+                *cache = Some((self.generation, pcs));
+                found
+            }
+        }
+    }
+
+    /// Maps the line breakpoints for the source of the given code to opcode indices
+    fn find_breakpoints(&self, code: &Code) -> HashSet<usize> {
+        let mut pcs = HashSet::new();
+        let source = match code.marks().first() {
+            Some(mark) => mark.source(),
+            None => return pcs,
+        };
+        let lines = match source
+            .path()
+            .as_ref()
+            .and_then(|path| path.canonicalize().ok())
+            .and_then(|path| self.breakpoints.get(&path))
+        {
+            Some(lines) => lines,
+            None => return pcs,
+        };
+        let mut last_line = 0;
+        for (pc, line) in mark_lines(source, code.marks()).into_iter().enumerate() {
+            if line != last_line && lines.contains(&line) {
+                pcs.insert(pc);
+            }
+            last_line = line;
+        }
+        pcs
+    }
+}
+
+/// The line numbers of the given marks, which must all be in the given source
+fn mark_lines(source: &Source, marks: &[Mark]) -> Vec<usize> {
+    let line_starts: Vec<_> = std::iter::once(0)
+        .chain(source.data().match_indices('\n').map(|(i, _)| i + 1))
+        .collect();
+    marks
+        .iter()
+        .map(|mark| match line_starts.binary_search(&mark.pos()) {
+            Ok(i) => i + 1,
+            Err(i) => i,
+        })
+        .collect()
+}
+
+/// The lines that breakpoints can stop at in the given code,
+/// including the code of the functions it defines
+pub(crate) fn code_lines(code: &Code) -> HashSet<usize> {
+    let mut lines: HashSet<usize> = match code.marks().first() {
+        Some(mark) => mark_lines(mark.source(), code.marks())
+            .into_iter()
+            .collect(),
+        None => HashSet::new(),
+    };
+    for op in code.ops() {
+        if let Opcode::NewFunction(desc) = op {
+            lines.extend(code_lines(&desc.code));
+        }
+    }
+    lines
+}
+
+/// Pauses execution and hands control to the debug hook
+pub(super) fn pause(
+    globals: &mut Globals,
+    code: &Code,
+    frame: &mut Frame,
+    pc: usize,
+    reason: PauseReason,
+) -> Result<()> {
+    if globals.debugger().paused {
+        return Ok(());
+    }
+    let mut hook = globals
+        .debugger_mut()
+        .hook
+        .take()
+        .unwrap_or_else(|| Box::new(DebugConsole::default()));
+    globals.debugger_mut().paused = true;
+    let result = hook.pause(globals, &mut DebugFrame { code, frame, pc }, reason);
+    let depth = globals.trace().len();
+    let debugger = globals.debugger_mut();
+    debugger.paused = false;
+    debugger.hook = Some(hook);
+    if let Err(error) = &result {
+        if let ErrorKind::DebuggerQuit = error.kind() {
+            debugger.quit = true;
+        }
+    }
+    debugger.step = match &result {
+        Ok(DebugAction::Continue) | Err(_) => None,
+        Ok(action) => Some(Step {
+            action: *action,
+            depth,
+            mark: code.marks()[pc].clone(),
+        }),
+    };
+    debugger.update_active();
+    result.map(|_| ())
+}
+
+/// Called before each opcode while the debugger is active, to check
+/// whether execution should pause before running it
+#[cold]
+pub(super) fn check(
+    globals: &mut Globals,
+    code: &Code,
+    frame: &mut Frame,
+    pc: usize,
+) -> Result<()> {
+    let debugger = globals.debugger();
+    if debugger.paused {
+        return Ok(());
+    }
+    let reason = if debugger.pause_requested {
+        Some(PauseReason::Requested)
+    } else if let Some(step) = &debugger.step {
+        let depth = globals.trace().len();
+        let mark = &code.marks()[pc];
+        let new_line =
+            !Rc::ptr_eq(mark.source(), step.mark.source()) || mark.lineno() != step.mark.lineno();
+        let done = match step.action {
+            DebugAction::Continue => true,
+            DebugAction::StepIn => new_line,
+            DebugAction::StepOver => depth < step.depth || (depth == step.depth && new_line),
+            DebugAction::StepOut => depth < step.depth,
+        };
+        if done {
+            Some(PauseReason::Step)
+        } else {
+            None
+        }
+    } else {
+        None
+    };
+    let reason = match reason {
+        Some(reason) => Some(reason),
+        None if !debugger.breakpoints.is_empty() && debugger.is_breakpoint(code, pc) => {
+            Some(PauseReason::Breakpoint)
+        }
+        None => None,
+    };
+    match reason {
+        Some(reason) => {
+            let debugger = globals.debugger_mut();
+            debugger.pause_requested = false;
+            debugger.step = None;
+            pause(globals, code, frame, pc, reason)
+        }
+        None => Ok(()),
+    }
+}
+
+/// The default DebugHook: a simple debugger prompt on the terminal
+/// that uses Globals::readline
+#[derive(Default)]
+pub struct DebugConsole {
+    last_command: Option<String>,
+}
+
+const DEBUG_CONSOLE_HELP: &str = r#"Commands:
+  c, continue      resume execution until the next breakpoint
+  s, step          step to the next line, entering function calls
+  n, next          step to the next line in the current function
+  o, out           step out of the current function
+  bt, where        print the stack trace
+  l, locals        list variables in the current frame
+  p EXPR           evaluate EXPR in the current frame and print it
+  q, quit          stop the program
+  h, help          show this message
+Any other input is evaluated as an expression in the current frame.
+An empty line repeats the last command.
+"#;
+
+impl DebugHook for DebugConsole {
+    fn pause(
+        &mut self,
+        globals: &mut Globals,
+        frame: &mut DebugFrame,
+        reason: PauseReason,
+    ) -> Result<DebugAction> {
+        let header = match reason {
+            PauseReason::Breakpoint => "Breakpoint",
+            PauseReason::Step => "Step",
+            PauseReason::Requested => "Paused",
+        };
+        globals.print(&format!("{} in {}\n", header, frame.name()));
+        globals.print(&frame.mark().format());
+        loop {
+            let line = match globals.readline("(debug) ")? {
+                Some(line) => line.trim().to_owned(),
+                None => return Ok(DebugAction::Continue),
+            };
+            let line = if line.is_empty() {
+                match &self.last_command {
+                    Some(last) => last.clone(),
+                    None => continue,
+                }
+            } else {
+                self.last_command = Some(line.clone());
+                line
+            };
+            let (command, rest) = match line.find(' ') {
+                Some(i) => (&line[..i], line[i..].trim()),
+                None => (line.as_str(), ""),
+            };
+            match command {
+                "c" | "continue" => return Ok(DebugAction::Continue),
+                "s" | "step" => return Ok(DebugAction::StepIn),
+                "n" | "next" => return Ok(DebugAction::StepOver),
+                "o" | "out" => return Ok(DebugAction::StepOut),
+                "q" | "quit" => return Err(kinderr!(DebuggerQuit, "Quit the debugger")),
+                "h" | "help" => globals.print(DEBUG_CONSOLE_HELP),
+                "bt" | "where" => {
+                    for mark in frame.trace(globals) {
+                        globals.print(&mark.format());
+                    }
+                }
+                "l" | "locals" => {
+                    for (name, value) in frame.variables() {
+                        globals.print(&format!("  {} = {:?}\n", name, value));
+                    }
+                }
+                _ => {
+                    let source = if command == "p" { rest } else { &line };
+                    match frame.eval(globals, source) {
+                        Ok(value) => globals.print(&format!("{:?}\n", value)),
+                        Err(error) => {
+                            globals.eprint(&format!("{}: {}\n", error.type_(), error.message()))
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct RecordingHook {
+        log: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl DebugHook for RecordingHook {
+        fn pause(
+            &mut self,
+            globals: &mut Globals,
+            frame: &mut DebugFrame,
+            reason: PauseReason,
+        ) -> Result<DebugAction> {
+            let sum = frame.eval(globals, "a + b")?;
+            self.log.borrow_mut().push(format!(
+                "{:?} line {} {:?} {:?}",
+                reason,
+                frame.mark().lineno(),
+                frame.variables(),
+                sum,
+            ));
+            Ok(match reason {
+                PauseReason::Breakpoint => {
+                    frame.eval(globals, "b = 100")?;
+                    DebugAction::StepOver
+                }
+                PauseReason::Step | PauseReason::Requested => DebugAction::Continue,
+            })
+        }
+    }
+
+    #[test]
+    fn hook() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut globals = Globals::new();
+        globals.set_debug_hook(RecordingHook { log: log.clone() });
+        let module = globals
+            .exec_str(
+                "__main",
+                None,
+                "def f(a) {\n  b = a + 1\n  def break\n  return a + b\n}\nx = f(1)\n",
+            )
+            .unwrap();
+        assert_eq!(module.get("x"), Some(Value::from(101)));
+        assert_eq!(
+            *log.borrow(),
+            vec![
+                r#"Breakpoint line 3 [("a", 1), ("b", 2)] 3"#.to_owned(),
+                r#"Step line 4 [("a", 1), ("b", 100)] 101"#.to_owned(),
+            ]
+        );
+    }
+
+    struct QuittingHook;
+
+    impl DebugHook for QuittingHook {
+        fn pause(
+            &mut self,
+            _globals: &mut Globals,
+            _frame: &mut DebugFrame,
+            _reason: PauseReason,
+        ) -> Result<DebugAction> {
+            Err(kinderr!(DebuggerQuit, "Quit the debugger"))
+        }
+    }
+
+    #[test]
+    fn quit() {
+        let mut globals = Globals::new();
+        globals.set_debug_hook(QuittingHook);
+        assert!(!globals.debugger_quit());
+
+        // quitting can't be caught by the script, and stops the event loop
+        let ran = Rc::new(RefCell::new(false));
+        let ran2 = ran.clone();
+        globals.enqueue_task(move |_| *ran2.borrow_mut() = true);
+        let error = globals
+            .exec_str(
+                "__main",
+                None,
+                "x = try {\n  def break\n  1\n} catch e {\n  2\n}\n",
+            )
+            .err()
+            .unwrap();
+        assert!(matches!(error.kind(), ErrorKind::DebuggerQuit));
+        assert!(globals.debugger_quit());
+        assert!(!globals.run_pending());
+        assert!(!*ran.borrow());
+    }
+}
@@ -5,6 +5,28 @@ pub struct Frame {
     locals: Vec<Value>,
     upvals: Vec<Rc<RefCell<Value>>>,
     pc: usize,
+
+    /// Exception handlers currently active in this frame
+    handlers: Vec<Handler>,
+
+    /// Errors that have been caught, but whose handlers
+    /// have not yet finished running
+    errors: Vec<Error>,
+
+    /// Length of the global trace when this frame last started or resumed
+    /// running. Generators and async functions may be resumed with a different
+    /// trace than the one they were suspended with, so handlers remember
+    /// their trace lengths relative to this
+    trace_base: usize,
+}
+
+/// Describes where to jump to when an error is thrown inside
+/// a try block, and the state to restore before jumping
+struct Handler {
+    pc: usize,
+    stack_len: usize,
+    trace_depth: usize,
+    errors_len: usize,
 }
 
 impl Frame {
@@ -28,8 +50,16 @@ impl Frame {
                 vec
             },
             pc: 0,
+            handlers: vec![],
+            errors: vec![],
+            trace_base: 0,
         }
     }
+    /// Should be called whenever this frame starts or resumes running
+    #[inline(always)]
+    pub(super) fn enter(&mut self, globals: &Globals) {
+        self.trace_base = globals.trace().len();
+    }
     #[inline(always)]
     pub(super) fn len(&mut self) -> usize {
         self.stack.len()
@@ -155,4 +185,49 @@ impl Frame {
     pub(crate) fn pc(&self) -> usize {
         self.pc
     }
+    #[inline(always)]
+    pub(super) fn push_handler(&mut self, pc: usize, trace_len: usize) {
+        self.handlers.push(Handler {
+            pc,
+            stack_len: self.stack.len(),
+            trace_depth: trace_len - self.trace_base,
+            errors_len: self.errors.len(),
+        });
+    }
+    #[inline(always)]
+    pub(super) fn pop_handler(&mut self) {
+        self.handlers.pop().unwrap();
+    }
+    /// Tries to catch the given error with the innermost active handler.
+    /// On success, the stack is unwound to where it was when the handler
+    /// was pushed, and execution resumes at the handler.
+    /// If there are no active handlers, the error is handed back.
+    pub(super) fn catch(&mut self, globals: &mut Globals, error: Error) -> Result<()> {
+        // trampoline requests (and quitting the debugger) must always
+        // unwind all the way to the host
+        if let ErrorKind::Trampoline | ErrorKind::DebuggerQuit = error.kind() {
+            return Err(error);
+        }
+        match self.handlers.pop() {
+            Some(handler) => {
+                let trace_len = self.trace_base + handler.trace_depth;
+                let error = error.prepended(globals.trace()[trace_len..].to_vec());
+                globals.trace_unwind(trace_len);
+                self.stack.truncate(handler.stack_len);
+                self.errors.truncate(handler.errors_len);
+                self.errors.push(error);
+                self.pc = handler.pc;
+                Ok(())
+            }
+            None => Err(error),
+        }
+    }
+    #[inline(always)]
+    pub(super) fn peek_error(&self) -> &Error {
+        self.errors.last().unwrap()
+    }
+    #[inline(always)]
+    pub(super) fn pop_error(&mut self) -> Error {
+        self.errors.pop().unwrap()
+    }
 }
@@ -2,7 +2,9 @@ use crate::ArgSpec;
 use crate::AsyncResult;
 use crate::Binop;
 use crate::Class;
+use crate::ConstVal;
 use crate::Error;
+use crate::ErrorKind;
 use crate::Function;
 use crate::FunctionKind;
 use crate::Globals;
@@ -14,6 +16,7 @@ use crate::Promise;
 use crate::RcStr;
 use crate::Result;
 use crate::ResumeResult;
+use crate::Source;
 use crate::Table;
 use crate::Unop;
 use crate::Value;
@@ -23,15 +26,23 @@ use crate::VariableType;
 use std::cell::RefCell;
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::convert::TryFrom;
+use std::convert::TryInto;
 use std::fmt;
 use std::fmt::Write;
+use std::path::Path;
+use std::path::PathBuf;
 use std::rc::Rc;
 
 mod code;
+mod dbg;
 mod frame;
 mod opc;
+mod ser;
 
 pub use code::*;
+pub use dbg::*;
 pub use frame::*;
 pub(crate) use opc::*;
+pub use ser::*;
@@ -14,6 +14,7 @@ pub(crate) enum Opcode {
     Nil,
     Bool(bool),
     Number(f64),
+    Int(i64),
     String(RcStr),
 
     NewList(u32),
@@ -46,6 +47,22 @@ pub(crate) enum Opcode {
     JumpIfTrue(usize),
     TeeJumpIfFalse(usize),
     TeeJumpIfTrue(usize),
+
+    /// Pushes an exception handler that will jump to the given
+    /// location if an error is thrown before the matching PopHandler
+    PushHandler(usize),
+    PopHandler,
+
+    /// Pushes the error currently being handled as a value
+    LoadError,
+    /// Pops an exception class, and pushes whether the error
+    /// currently being handled is an instance of it
+    MatchError,
+    /// Discards the error currently being handled
+    PopError,
+    /// Rethrows the error currently being handled
+    Reraise,
+
     CallFunction(Box<CallFunctionDesc>),
     CallMethod(Box<CallMethodDesc>),
 
@@ -64,9 +81,75 @@ impl Opcode {
             Self::JumpIfTrue(d) => *d = dest,
             Self::TeeJumpIfFalse(d) => *d = dest,
             Self::TeeJumpIfTrue(d) => *d = dest,
+            Self::PushHandler(d) => *d = dest,
             _ => panic!("patch_jump on non-jump: {:?}", self),
         }
     }
+    /// The net change in the size of the stack after running this opcode,
+    /// when execution continues with the next opcode.
+    /// (When a conditional jump is taken instead, the change is the same,
+    /// except for TeeJumpIfFalse and TeeJumpIfTrue, which leave the stack as is)
+    pub(crate) fn stack_effect(&self) -> isize {
+        match self {
+            Self::Pop => -1,
+            Self::Dup => 1,
+            Self::Dup2 => 2,
+            Self::Pull2 | Self::Pull3 | Self::Unpull2 | Self::Swap01 => 0,
+            Self::Unpack(n) => *n as isize - 1,
+            Self::Nil | Self::Bool(_) | Self::Number(_) | Self::Int(_) | Self::String(_) => 1,
+            Self::NewList(n) => 1 - *n as isize,
+            Self::NewMap(n) => 1 - 2 * *n as isize,
+            Self::GetVar(_) => 1,
+            Self::SetVar(_) => -1,
+            Self::TeeVar(_) => 0,
+            Self::GetAttr(_) => 0,
+            Self::SetAttr(_) => -2,
+            Self::TeeAttr(_) => -1,
+            Self::New(names) => -(names.len() as isize),
+            Self::Del(_) => 1,
+            Self::Binop(_) => -1,
+            Self::Unop(_) => 0,
+            Self::GetItem => -1,
+            Self::SetItem => -3,
+            Self::TeeItem => -2,
+            Self::Iter => 0,
+            Self::Next => 2,
+            Self::Import(_) => 1,
+            Self::Yield | Self::Await => 0,
+            Self::Return => -1,
+            Self::Jump(_) => 0,
+            Self::JumpIfFalse(_) | Self::JumpIfTrue(_) => -1,
+            Self::TeeJumpIfFalse(_) | Self::TeeJumpIfTrue(_) => -1,
+            Self::PushHandler(_) | Self::PopHandler => 0,
+            Self::LoadError => 1,
+            Self::MatchError | Self::PopError => 0,
+            Self::Reraise => 0,
+            Self::CallFunction(desc) => {
+                -((desc.argc + desc.kwargs.len()) as isize)
+                    - desc.variadic as isize
+                    - desc.kwmap as isize
+            }
+            Self::CallMethod(desc) => -((desc.argc + desc.kwargs.len()) as isize),
+            Self::NewFunction(_) => 1,
+            Self::NewClass(desc) => {
+                1 - (desc.nbases + desc.method_names.len() + desc.static_method_names.len())
+                    as isize
+            }
+            Self::GetCallingModule => 1,
+            Self::Breakpoint => 0,
+        }
+    }
+    pub(crate) fn jump_dest(&self) -> Option<usize> {
+        match self {
+            Self::Jump(d)
+            | Self::JumpIfFalse(d)
+            | Self::JumpIfTrue(d)
+            | Self::TeeJumpIfFalse(d)
+            | Self::TeeJumpIfTrue(d)
+            | Self::PushHandler(d) => Some(*d),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -160,7 +243,7 @@ pub(super) fn step(globals: &mut Globals, code: &Code, frame: &mut Frame) -> Ste
                     return StepResult::Err(error);
                 }
             }
-        };};
+        }};
     }
 
     macro_rules! err {
@@ -172,6 +255,13 @@ pub(super) fn step(globals: &mut Globals, code: &Code, frame: &mut Frame) -> Ste
         };
     }
 
+    if globals.debugger().active() {
+        if let Err(error) = check(globals, code, frame, pc) {
+            addtrace!();
+            return StepResult::Err(error);
+        }
+    }
+
     match opc {
         Opcode::Pop => {
             frame.pop();
@@ -205,6 +295,7 @@ pub(super) fn step(globals: &mut Globals, code: &Code, frame: &mut Frame) -> Ste
         Opcode::Nil => frame.push(Value::Nil),
         Opcode::Bool(b) => frame.push(Value::Bool(*b)),
         Opcode::Number(x) => frame.push(Value::Number(*x)),
+        Opcode::Int(x) => frame.push(Value::Int(*x)),
         Opcode::String(x) => frame.push(Value::String(x.clone())),
         Opcode::NewList(len) => {
             let len = *len as usize;
@@ -277,75 +368,46 @@ pub(super) fn step(globals: &mut Globals, code: &Code, frame: &mut Frame) -> Ste
             // for arithmetic operations it's critical to keep overhead
             // as low as possible, but in all other cases we call a method
             // corresponding to each operation instead
-            let result = match op {
-                Binop::Add => match lhs {
-                    Value::Number(a) => Value::Number(a + get0!(rhs.number())),
-                    _ => get1!(lhs.apply_method(globals, "__add", vec![rhs], None)),
-                },
-                Binop::Sub => match lhs {
-                    Value::Number(a) => Value::Number(a - get0!(rhs.number())),
-                    _ => get1!(lhs.apply_method(globals, "__sub", vec![rhs], None)),
-                },
-                Binop::Mul => match lhs {
-                    Value::Number(a) => Value::Number(a * get0!(rhs.number())),
-                    _ => get1!(lhs.apply_method(globals, "__mul", vec![rhs], None)),
-                },
-                Binop::Div => match lhs {
-                    Value::Number(a) => Value::Number(a / get0!(rhs.number())),
-                    _ => get1!(lhs.apply_method(globals, "__div", vec![rhs], None)),
-                },
-                Binop::TruncDiv => match lhs {
-                    Value::Number(a) => Value::Number((a / get0!(rhs.number())).trunc()),
-                    _ => get1!(lhs.apply_method(globals, "__truncdiv", vec![rhs], None)),
-                },
-                Binop::Rem => match lhs {
-                    Value::Number(a) => Value::Number(a % get0!(rhs.number())),
-                    _ => get1!(lhs.apply_method(globals, "__rem", vec![rhs], None)),
-                },
-                Binop::ERem => match lhs {
-                    Value::Number(a) => Value::Number(a.rem_euclid(get0!(rhs.number()))),
-                    _ => get1!(lhs.apply_method(globals, "__erem", vec![rhs], None)),
-                },
-                Binop::Pow => match lhs {
-                    Value::Number(a) => Value::Number(a.powf(get0!(rhs.number()))),
-                    _ => get1!(lhs.apply_method(globals, "__pow", vec![rhs], None)),
-                },
-                Binop::Lt => Value::from(get0!(lhs.lt(&rhs))),
-                Binop::Le => Value::from(!get0!(rhs.lt(&lhs))),
-                Binop::Gt => Value::from(get0!(rhs.lt(&lhs))),
-                Binop::Ge => Value::from(!get0!(lhs.lt(&rhs))),
-                Binop::Eq => Value::from(lhs == rhs),
-                Binop::Ne => Value::from(lhs != rhs),
-                Binop::Is => Value::from(lhs.is(&rhs)),
-                Binop::IsNot => Value::from(!lhs.is(&rhs)),
-                Binop::In => Value::from(get1!(lhs.in_(globals, &rhs))),
-                Binop::NotIn => Value::from(!get1!(lhs.in_(globals, &rhs))),
+            let result = match (op, lhs, rhs) {
+                (Binop::Add, Value::Number(a), Value::Number(b)) => Value::Number(a + b),
+                (Binop::Sub, Value::Number(a), Value::Number(b)) => Value::Number(a - b),
+                (Binop::Mul, Value::Number(a), Value::Number(b)) => Value::Number(a * b),
+                (Binop::Div, Value::Number(a), Value::Number(b)) => Value::Number(a / b),
+                (Binop::TruncDiv, Value::Number(a), Value::Number(b)) => {
+                    Value::Number((a / b).trunc())
+                }
+                (Binop::Rem, Value::Number(a), Value::Number(b)) => Value::Number(a % b),
+                (Binop::ERem, Value::Number(a), Value::Number(b)) => Value::Number(a.rem_euclid(b)),
+                (Binop::Pow, Value::Number(a), Value::Number(b)) => Value::Number(a.powf(b)),
+                (Binop::Add | Binop::Sub | Binop::Mul, Value::Int(a), Value::Int(b)) => {
+                    get1!(Value::int_binop(*op, a, b))
+                }
+                (Binop::Lt, lhs, rhs) => Value::from(get1!(lhs.lt_(globals, &rhs))),
+                (Binop::Le, lhs, rhs) => Value::from(get1!(lhs.le_(globals, &rhs))),
+                (Binop::Gt, lhs, rhs) => Value::from(get1!(rhs.lt_(globals, &lhs))),
+                (Binop::Ge, lhs, rhs) => Value::from(get1!(rhs.le_(globals, &lhs))),
+                (Binop::Eq, lhs, rhs) => Value::from(get1!(lhs.eq_(globals, &rhs))),
+                (Binop::Ne, lhs, rhs) => Value::from(!get1!(lhs.eq_(globals, &rhs))),
+                (Binop::Is, lhs, rhs) => Value::from(lhs.is(&rhs)),
+                (Binop::IsNot, lhs, rhs) => Value::from(!lhs.is(&rhs)),
+                (Binop::In, lhs, rhs) => Value::from(get1!(lhs.in_(globals, &rhs))),
+                (Binop::NotIn, lhs, rhs) => Value::from(!get1!(lhs.in_(globals, &rhs))),
+                (op, lhs, rhs) => get1!(lhs.binop_method(globals, *op, rhs)),
             };
             frame.push(result);
         }
         Opcode::Unop(op) => {
             let arg = frame.pop();
-
-            macro_rules! operr {
-                () => {
-                    err!(
-                        "Unop {:?} not supported for {:?}",
-                        op,
-                        arg.get_class(globals)
-                    )
-                };
-            }
-
-            let result = match op {
-                Unop::Pos => match arg {
-                    Value::Number(_) => arg,
-                    _ => operr!(),
-                },
-                Unop::Neg => match arg {
-                    Value::Number(a) => Value::Number(-a),
-                    _ => operr!(),
-                },
-                Unop::Not => Value::from(!arg.truthy()),
+            let result = match (op, arg) {
+                (Unop::Pos, arg @ Value::Number(_)) => arg,
+                (Unop::Neg, Value::Number(a)) => Value::Number(-a),
+                (Unop::Pos, arg @ Value::Int(_)) => arg,
+                (Unop::Neg, Value::Int(a)) if a != i64::MIN => Value::Int(-a),
+                (Unop::Invert, Value::Int(a)) => Value::Int(!a),
+                (Unop::Not, arg @ Value::Bool(_)) | (Unop::Not, arg @ Value::Nil) => {
+                    Value::from(!arg.truthy())
+                }
+                (op, arg) => get1!(arg.unop_method(globals, *op)),
             };
             frame.push(result);
         }
@@ -437,6 +499,35 @@ pub(super) fn step(globals: &mut Globals, code: &Code, frame: &mut Frame) -> Ste
                 frame.pop();
             }
         }
+        Opcode::PushHandler(dest) => {
+            frame.push_handler(*dest, globals.trace().len());
+        }
+        Opcode::PopHandler => {
+            frame.pop_handler();
+        }
+        Opcode::LoadError => {
+            let exception = globals
+                .class_manager()
+                .exception_from_error(frame.peek_error());
+            frame.push(exception);
+        }
+        Opcode::MatchError => {
+            let cls = get0!(frame.pop().into_class());
+            let exception = globals
+                .class_manager()
+                .exception_from_error(frame.peek_error());
+            let matched = globals
+                .class_manager()
+                .get_class(&exception)
+                .is_subclass_of(&cls);
+            frame.push(matched.into());
+        }
+        Opcode::PopError => {
+            frame.pop_error();
+        }
+        Opcode::Reraise => {
+            return StepResult::Err(frame.pop_error());
+        }
         Opcode::CallFunction(desc) => {
             let kwmap = if desc.kwmap {
                 let map = get0!(frame.pop().into_map());
@@ -512,9 +603,8 @@ pub(super) fn step(globals: &mut Globals, code: &Code, frame: &mut Frame) -> Ste
                 .popn_iter(desc.nbases)
                 .map(Value::into_class)
                 .collect());
-            let map = Class::join_class_maps(map, bases);
 
-            let cls = Class::new(desc.name.clone(), map, static_map);
+            let cls = Class::new_with_bases(desc.name.clone(), bases, map, static_map);
 
             frame.push(cls.into());
         }
@@ -523,7 +613,10 @@ pub(super) fn step(globals: &mut Globals, code: &Code, frame: &mut Frame) -> Ste
             frame.push(name.map(Value::from).unwrap_or(Value::Nil));
         }
         Opcode::Breakpoint => {
-            panic!("TODO: implement breakpoints");
+            if let Err(error) = pause(globals, code, frame, pc, PauseReason::Breakpoint) {
+                addtrace!();
+                return StepResult::Err(error);
+            }
         }
     }
     StepResult::Ok
@@ -0,0 +1,655 @@
+//! Binary serialization of Code objects, for the bytecode cache
+//! Marks are stored as offsets into the source, so a Code object can
+//! only be deserialized against the same source it was compiled from
+//! (the cache checks this with a hash of the source text).
+use super::*;
+
+/// Bump this whenever the layout below (or the meaning of any Opcode) changes
+const FORMAT_VERSION: u32 = 3;
+const MAGIC: &[u8; 4] = b"MTBC";
+
+/// Hash of the source text that a cache entry was compiled from.
+/// (FNV-1a, so that it is stable across builds, unlike DefaultHasher)
+pub fn source_hash(data: &str) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in data.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+impl Code {
+    /// Serializes this code object, tagged with the hash of its source
+    /// and the optimization level it was compiled with
+    pub fn serialize(&self, source_hash: u64, opt_level: u8) -> Vec<u8> {
+        let mut out = Writer { buf: Vec::new() };
+        out.buf.extend_from_slice(MAGIC);
+        out.u32(FORMAT_VERSION);
+        out.str(env!("CARGO_PKG_VERSION"));
+        out.u64(source_hash);
+        out.u8(opt_level);
+        out.code(self);
+        out.buf
+    }
+
+    /// Deserializes a code object written by 'serialize'.
+    /// Returns None if the data is malformed, was written by a different
+    /// version, or was compiled from a source with a different hash
+    /// or at a different optimization level
+    pub fn deserialize(
+        source: &Rc<Source>,
+        source_hash: u64,
+        opt_level: u8,
+        data: &[u8],
+    ) -> Option<Code> {
+        let mut inp = Reader {
+            source,
+            data,
+            pos: 0,
+        };
+        if inp.bytes(MAGIC.len())? != MAGIC
+            || inp.u32()? != FORMAT_VERSION
+            || inp.str()?.str() != env!("CARGO_PKG_VERSION")
+            || inp.u64()? != source_hash
+            || inp.u8()? != opt_level
+        {
+            return None;
+        }
+        let code = inp.code()?;
+
+        // the same file may be loaded under a different module name
+        // (e.g. through another source root), and relative imports are
+        // resolved against the module name at compile time
+        if inp.pos == data.len() && code.name() == source.name() {
+            Some(code)
+        } else {
+            None
+        }
+    }
+}
+
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, x: u8) {
+        self.buf.push(x);
+    }
+    fn u32(&mut self, x: u32) {
+        self.buf.extend_from_slice(&x.to_le_bytes());
+    }
+    fn u64(&mut self, x: u64) {
+        self.buf.extend_from_slice(&x.to_le_bytes());
+    }
+    fn usize(&mut self, x: usize) {
+        self.u64(x as u64);
+    }
+    fn bool(&mut self, x: bool) {
+        self.u8(x as u8);
+    }
+    fn f64(&mut self, x: f64) {
+        self.u64(x.to_bits());
+    }
+    fn str(&mut self, s: &str) {
+        self.usize(s.len());
+        self.buf.extend_from_slice(s.as_bytes());
+    }
+    fn strs(&mut self, strs: &[RcStr]) {
+        self.usize(strs.len());
+        for s in strs {
+            self.str(s);
+        }
+    }
+    fn optstr(&mut self, s: &Option<RcStr>) {
+        match s {
+            Some(s) => {
+                self.bool(true);
+                self.str(s);
+            }
+            None => self.bool(false),
+        }
+    }
+    fn mark(&mut self, mark: &Mark) {
+        self.usize(mark.pos());
+        self.usize(mark.lineno());
+    }
+    fn optmark(&mut self, mark: &Option<Mark>) {
+        match mark {
+            Some(mark) => {
+                self.bool(true);
+                self.mark(mark);
+            }
+            None => self.bool(false),
+        }
+    }
+    fn names(&mut self, names: &[(RcStr, Mark)]) {
+        self.usize(names.len());
+        for (name, mark) in names {
+            self.str(name);
+            self.mark(mark);
+        }
+    }
+    fn variable(&mut self, var: &Variable) {
+        self.bool(var.type_() == VariableType::Upval);
+        self.usize(var.slot());
+        self.str(var.name());
+        self.mark(var.mark());
+    }
+    fn constval(&mut self, cv: &ConstVal) {
+        match cv {
+            ConstVal::Invalid => self.u8(0),
+            ConstVal::Nil => self.u8(1),
+            ConstVal::Bool(b) => {
+                self.u8(2);
+                self.bool(*b);
+            }
+            ConstVal::Number(x) => {
+                self.u8(3);
+                self.f64(*x);
+            }
+            ConstVal::Int(x) => {
+                self.u8(6);
+                self.u64(*x as u64);
+            }
+            ConstVal::String(s) => {
+                self.u8(4);
+                self.str(s);
+            }
+            ConstVal::List(list) => {
+                self.u8(5);
+                self.usize(list.len());
+                for item in list {
+                    self.constval(item);
+                }
+            }
+        }
+    }
+    fn argspec(&mut self, argspec: &ArgSpec) {
+        self.strs(argspec.req());
+        self.usize(argspec.def().len());
+        for (name, value) in argspec.def() {
+            self.str(name);
+            self.constval(value);
+        }
+        self.optstr(argspec.var());
+        self.optstr(argspec.key());
+        self.optmark(argspec.mark());
+    }
+    fn code(&mut self, code: &Code) {
+        self.str(code.name());
+        self.usize(code.params().len());
+        for param in code.params() {
+            self.variable(param);
+        }
+        self.names(code.varspec().local());
+        self.names(code.varspec().free());
+        self.names(code.varspec().owned());
+        self.optstr(code.doc());
+        let mut docmap: Vec<_> = code.docmap().iter().collect();
+        docmap.sort();
+        self.usize(docmap.len());
+        for (key, doc) in docmap {
+            self.str(key);
+            self.str(doc);
+        }
+        self.usize(code.ops().len());
+        for (op, mark) in code.ops().iter().zip(code.marks()) {
+            self.opcode(op);
+            self.mark(mark);
+        }
+    }
+    fn opcode(&mut self, op: &Opcode) {
+        match op {
+            Opcode::Pop => self.u8(0),
+            Opcode::Dup => self.u8(1),
+            Opcode::Dup2 => self.u8(2),
+            Opcode::Pull2 => self.u8(3),
+            Opcode::Pull3 => self.u8(4),
+            Opcode::Unpull2 => self.u8(5),
+            Opcode::Swap01 => self.u8(6),
+            Opcode::Unpack(n) => {
+                self.u8(7);
+                self.u32(*n);
+            }
+            Opcode::Nil => self.u8(8),
+            Opcode::Bool(b) => {
+                self.u8(9);
+                self.bool(*b);
+            }
+            Opcode::Number(x) => {
+                self.u8(10);
+                self.f64(*x);
+            }
+            Opcode::Int(x) => {
+                self.u8(50);
+                self.u64(*x as u64);
+            }
+            Opcode::String(s) => {
+                self.u8(11);
+                self.str(s);
+            }
+            Opcode::NewList(n) => {
+                self.u8(12);
+                self.u32(*n);
+            }
+            Opcode::NewMap(n) => {
+                self.u8(13);
+                self.u32(*n);
+            }
+            Opcode::GetVar(var) => {
+                self.u8(14);
+                self.variable(var);
+            }
+            Opcode::SetVar(var) => {
+                self.u8(15);
+                self.variable(var);
+            }
+            Opcode::TeeVar(var) => {
+                self.u8(16);
+                self.variable(var);
+            }
+            Opcode::GetAttr(name) => {
+                self.u8(17);
+                self.str(name);
+            }
+            Opcode::SetAttr(name) => {
+                self.u8(18);
+                self.str(name);
+            }
+            Opcode::TeeAttr(name) => {
+                self.u8(19);
+                self.str(name);
+            }
+            Opcode::New(names) => {
+                self.u8(20);
+                self.strs(names);
+            }
+            Opcode::Del(var) => {
+                self.u8(21);
+                self.variable(var);
+            }
+            Opcode::Binop(op) => {
+                self.u8(22);
+                self.u8(binop_tag(*op));
+            }
+            Opcode::Unop(op) => {
+                self.u8(23);
+                self.u8(unop_tag(*op));
+            }
+            Opcode::GetItem => self.u8(24),
+            Opcode::SetItem => self.u8(25),
+            Opcode::TeeItem => self.u8(26),
+            Opcode::Iter => self.u8(27),
+            Opcode::Next => self.u8(28),
+            Opcode::Import(name) => {
+                self.u8(29);
+                self.str(name);
+            }
+            Opcode::Yield => self.u8(30),
+            Opcode::Await => self.u8(31),
+            Opcode::Return => self.u8(32),
+            Opcode::Jump(dest) => {
+                self.u8(33);
+                self.usize(*dest);
+            }
+            Opcode::JumpIfFalse(dest) => {
+                self.u8(34);
+                self.usize(*dest);
+            }
+            Opcode::JumpIfTrue(dest) => {
+                self.u8(35);
+                self.usize(*dest);
+            }
+            Opcode::TeeJumpIfFalse(dest) => {
+                self.u8(36);
+                self.usize(*dest);
+            }
+            Opcode::TeeJumpIfTrue(dest) => {
+                self.u8(37);
+                self.usize(*dest);
+            }
+            Opcode::PushHandler(dest) => {
+                self.u8(38);
+                self.usize(*dest);
+            }
+            Opcode::PopHandler => self.u8(39),
+            Opcode::LoadError => self.u8(40),
+            Opcode::MatchError => self.u8(41),
+            Opcode::PopError => self.u8(42),
+            Opcode::Reraise => self.u8(43),
+            Opcode::CallFunction(desc) => {
+                self.u8(44);
+                self.usize(desc.argc);
+                self.bool(desc.variadic);
+                self.strs(&desc.kwargs);
+                self.bool(desc.kwmap);
+            }
+            Opcode::CallMethod(desc) => {
+                self.u8(45);
+                self.usize(desc.argc);
+                self.strs(&desc.kwargs);
+                self.str(&desc.method_name);
+            }
+            Opcode::NewFunction(desc) => {
+                self.u8(46);
+                self.code(&desc.code);
+                self.argspec(&desc.argspec);
+                self.usize(desc.freevar_binding_slots.len());
+                for slot in &desc.freevar_binding_slots {
+                    self.usize(*slot);
+                }
+                self.u8(match desc.kind {
+                    FunctionKind::Normal => 0,
+                    FunctionKind::Generator => 1,
+                    FunctionKind::Async => 2,
+                });
+            }
+            Opcode::NewClass(desc) => {
+                self.u8(47);
+                self.str(&desc.name);
+                self.usize(desc.nbases);
+                self.strs(&desc.method_names);
+                self.strs(&desc.static_method_names);
+            }
+            Opcode::GetCallingModule => self.u8(48),
+            Opcode::Breakpoint => self.u8(49),
+        }
+    }
+}
+
+const BINOPS: [Binop; 23] = [
+    Binop::Add,
+    Binop::Sub,
+    Binop::Mul,
+    Binop::Div,
+    Binop::TruncDiv,
+    Binop::Rem,
+    Binop::ERem,
+    Binop::Pow,
+    Binop::Lt,
+    Binop::Le,
+    Binop::Gt,
+    Binop::Ge,
+    Binop::Eq,
+    Binop::Ne,
+    Binop::Is,
+    Binop::IsNot,
+    Binop::In,
+    Binop::NotIn,
+    Binop::BitAnd,
+    Binop::BitOr,
+    Binop::BitXor,
+    Binop::Shl,
+    Binop::Shr,
+];
+
+const UNOPS: [Unop; 4] = [Unop::Pos, Unop::Neg, Unop::Not, Unop::Invert];
+
+fn binop_tag(op: Binop) -> u8 {
+    BINOPS
+        .iter()
+        .position(|x| std::mem::discriminant(x) == std::mem::discriminant(&op))
+        .unwrap() as u8
+}
+
+fn unop_tag(op: Unop) -> u8 {
+    UNOPS
+        .iter()
+        .position(|x| std::mem::discriminant(x) == std::mem::discriminant(&op))
+        .unwrap() as u8
+}
+
+struct Reader<'a> {
+    source: &'a Rc<Source>,
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Option<&'a [u8]> {
+        let end = self.pos.checked_add(n)?;
+        let bytes = self.data.get(self.pos..end)?;
+        self.pos = end;
+        Some(bytes)
+    }
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.bytes(1)?[0])
+    }
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.bytes(4)?.try_into().ok()?))
+    }
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.bytes(8)?.try_into().ok()?))
+    }
+    fn usize(&mut self) -> Option<usize> {
+        usize::try_from(self.u64()?).ok()
+    }
+    fn bool(&mut self) -> Option<bool> {
+        match self.u8()? {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        }
+    }
+    fn f64(&mut self) -> Option<f64> {
+        Some(f64::from_bits(self.u64()?))
+    }
+    fn str(&mut self) -> Option<RcStr> {
+        let len = self.usize()?;
+        Some(std::str::from_utf8(self.bytes(len)?).ok()?.into())
+    }
+    fn strs(&mut self) -> Option<Vec<RcStr>> {
+        (0..self.usize()?).map(|_| self.str()).collect()
+    }
+    fn optstr(&mut self) -> Option<Option<RcStr>> {
+        Some(if self.bool()? {
+            Some(self.str()?)
+        } else {
+            None
+        })
+    }
+    fn mark(&mut self) -> Option<Mark> {
+        let pos = self.usize()?;
+        let lineno = self.usize()?;
+        if pos > self.source.data().len() {
+            return None;
+        }
+        Some(Mark::new(self.source.clone(), pos, lineno))
+    }
+    fn optmark(&mut self) -> Option<Option<Mark>> {
+        Some(if self.bool()? {
+            Some(self.mark()?)
+        } else {
+            None
+        })
+    }
+    fn names(&mut self) -> Option<Vec<(RcStr, Mark)>> {
+        (0..self.usize()?)
+            .map(|_| Some((self.str()?, self.mark()?)))
+            .collect()
+    }
+    fn variable(&mut self) -> Option<Box<Variable>> {
+        let type_ = if self.bool()? {
+            VariableType::Upval
+        } else {
+            VariableType::Local
+        };
+        let slot = self.usize()?;
+        let name = self.str()?;
+        let mark = self.mark()?;
+        Some(Box::new(Variable::new(type_, slot, name, mark)))
+    }
+    fn constval(&mut self) -> Option<ConstVal> {
+        Some(match self.u8()? {
+            0 => ConstVal::Invalid,
+            1 => ConstVal::Nil,
+            2 => ConstVal::Bool(self.bool()?),
+            3 => ConstVal::Number(self.f64()?),
+            4 => ConstVal::String(self.str()?),
+            5 => ConstVal::List(
+                (0..self.usize()?)
+                    .map(|_| self.constval())
+                    .collect::<Option<_>>()?,
+            ),
+            6 => ConstVal::Int(self.u64()? as i64),
+            _ => return None,
+        })
+    }
+    fn argspec(&mut self) -> Option<ArgSpec> {
+        let req = self.strs()?;
+        let def = (0..self.usize()?)
+            .map(|_| Some((self.str()?, self.constval()?)))
+            .collect::<Option<_>>()?;
+        let var = self.optstr()?;
+        let key = self.optstr()?;
+        let mark = self.optmark()?;
+        Some(ArgSpec::new(req, def, var, key, mark))
+    }
+    fn code(&mut self) -> Option<Code> {
+        let name = self.str()?;
+        let params = (0..self.usize()?)
+            .map(|_| Some(*self.variable()?))
+            .collect::<Option<_>>()?;
+        let varspec = VarSpec::new(self.names()?, self.names()?, self.names()?);
+        let doc = self.optstr()?;
+        let docmap = (0..self.usize()?)
+            .map(|_| Some((self.str()?, self.str()?)))
+            .collect::<Option<_>>()?;
+        let len = self.usize()?;
+        let mut ops = Vec::new();
+        let mut marks = Vec::new();
+        for _ in 0..len {
+            ops.push(self.opcode()?);
+            marks.push(self.mark()?);
+        }
+        for op in &ops {
+            if let Some(dest) = op.jump_dest() {
+                if dest > ops.len() {
+                    return None;
+                }
+            }
+        }
+        Some(Code::new(name, ops, params, varspec, marks, doc, docmap))
+    }
+    fn opcode(&mut self) -> Option<Opcode> {
+        Some(match self.u8()? {
+            0 => Opcode::Pop,
+            1 => Opcode::Dup,
+            2 => Opcode::Dup2,
+            3 => Opcode::Pull2,
+            4 => Opcode::Pull3,
+            5 => Opcode::Unpull2,
+            6 => Opcode::Swap01,
+            7 => Opcode::Unpack(self.u32()?),
+            8 => Opcode::Nil,
+            9 => Opcode::Bool(self.bool()?),
+            10 => Opcode::Number(self.f64()?),
+            11 => Opcode::String(self.str()?),
+            12 => Opcode::NewList(self.u32()?),
+            13 => Opcode::NewMap(self.u32()?),
+            14 => Opcode::GetVar(self.variable()?),
+            15 => Opcode::SetVar(self.variable()?),
+            16 => Opcode::TeeVar(self.variable()?),
+            17 => Opcode::GetAttr(self.str()?),
+            18 => Opcode::SetAttr(self.str()?),
+            19 => Opcode::TeeAttr(self.str()?),
+            20 => Opcode::New(Box::new(self.strs()?)),
+            21 => Opcode::Del(self.variable()?),
+            22 => Opcode::Binop(*BINOPS.get(self.u8()? as usize)?),
+            23 => Opcode::Unop(*UNOPS.get(self.u8()? as usize)?),
+            24 => Opcode::GetItem,
+            25 => Opcode::SetItem,
+            26 => Opcode::TeeItem,
+            27 => Opcode::Iter,
+            28 => Opcode::Next,
+            29 => Opcode::Import(self.str()?),
+            30 => Opcode::Yield,
+            31 => Opcode::Await,
+            32 => Opcode::Return,
+            33 => Opcode::Jump(self.usize()?),
+            34 => Opcode::JumpIfFalse(self.usize()?),
+            35 => Opcode::JumpIfTrue(self.usize()?),
+            36 => Opcode::TeeJumpIfFalse(self.usize()?),
+            37 => Opcode::TeeJumpIfTrue(self.usize()?),
+            38 => Opcode::PushHandler(self.usize()?),
+            39 => Opcode::PopHandler,
+            40 => Opcode::LoadError,
+            41 => Opcode::MatchError,
+            42 => Opcode::PopError,
+            43 => Opcode::Reraise,
+            44 => Opcode::CallFunction(Box::new(CallFunctionDesc {
+                argc: self.usize()?,
+                variadic: self.bool()?,
+                kwargs: self.strs()?,
+                kwmap: self.bool()?,
+            })),
+            45 => Opcode::CallMethod(Box::new(CallMethodDesc {
+                argc: self.usize()?,
+                kwargs: self.strs()?,
+                method_name: self.str()?,
+            })),
+            46 => Opcode::NewFunction(Box::new(NewFunctionDesc {
+                code: Rc::new(self.code()?),
+                argspec: Rc::new(self.argspec()?),
+                freevar_binding_slots: (0..self.usize()?)
+                    .map(|_| self.usize())
+                    .collect::<Option<_>>()?,
+                kind: match self.u8()? {
+                    0 => FunctionKind::Normal,
+                    1 => FunctionKind::Generator,
+                    2 => FunctionKind::Async,
+                    _ => return None,
+                },
+            })),
+            47 => Opcode::NewClass(Box::new(NewClassDesc {
+                name: self.str()?,
+                nbases: self.usize()?,
+                method_names: self.strs()?,
+                static_method_names: self.strs()?,
+            })),
+            48 => Opcode::GetCallingModule,
+            49 => Opcode::Breakpoint,
+            50 => Opcode::Int(self.u64()? as i64),
+            _ => return None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::annotate;
+    use crate::compile;
+
+    #[test]
+    fn round_trip() {
+        let globals = Globals::new();
+        let data = concat!(
+            "# module doc\n",
+            "class Foo(Exception) {\n",
+            "    # method doc\n",
+            "    def bar(self, a, b=[1, 'x', nil], *c, **d) = a + b\n",
+            "    static def* gen() { yield 1 }\n",
+            "}\n",
+            "async def f(x) = x.await\n",
+            "try { f(1) } catch e: Foo { print(e) } finally { print(2) }\n",
+        );
+        let source = Rc::new(Source::new("t".into(), None, data.into()));
+        let mut display = globals.parse(source.clone()).unwrap();
+        annotate(&mut display).unwrap();
+        let code = compile(&display).unwrap();
+        let hash = source_hash(data);
+        let bytes = code.serialize(hash, 1);
+        let copy = Code::deserialize(&source, hash, 1, &bytes).unwrap();
+        assert_eq!(copy.serialize(hash, 1), bytes);
+        assert_eq!(format!("{:?}", copy.ops()), format!("{:?}", code.ops()));
+        assert_eq!(copy.marks()[3].pos(), code.marks()[3].pos());
+        assert_eq!(copy.docmap(), code.docmap());
+
+        assert!(Code::deserialize(&source, hash + 1, 1, &bytes).is_none());
+        assert!(Code::deserialize(&source, hash, 0, &bytes).is_none());
+        assert!(Code::deserialize(&source, hash, 1, &bytes[..bytes.len() - 1]).is_none());
+    }
+}
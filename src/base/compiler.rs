@@ -1,4 +1,5 @@
 use crate::base::ast::*;
+use crate::optimize;
 use crate::CallMethodDesc;
 use crate::Code;
 use crate::Error;
@@ -8,17 +9,25 @@ use crate::NewFunctionDesc;
 use crate::Opcode;
 use crate::RcStr;
 use crate::Result;
+use crate::DEFAULT_OPT_LEVEL;
 use std::collections::HashMap;
 
 const INVALID_JUMP: usize = usize::MAX;
 
 pub fn compile(md: &ModuleDisplay) -> Result<Code> {
+    compile_with_opt_level(md, DEFAULT_OPT_LEVEL)
+}
+
+/// Like compile, but with an explicit optimization level
+/// (see the optimizer module for what each level does)
+pub fn compile_with_opt_level(md: &ModuleDisplay, opt_level: u8) -> Result<Code> {
     let mut builder = Builder::new(
         Type::Module,
         md.name().clone(),
         md.doc().clone(),
         vec![],
         md.varspec().clone().unwrap(),
+        opt_level,
     );
     builder.expr(md.body(), true)?;
     Ok(builder.build())
@@ -31,7 +40,40 @@ enum Type {
     Async,
 }
 
-struct Builder {
+/// Work that has to be done when control leaves a region of code
+/// early (e.g. with a 'return')
+#[derive(Clone, Copy)]
+enum Cleanup<'a> {
+    /// Inside a try block with an exception handler
+    Handler,
+
+    /// Inside a catch block where the caught error is still pending
+    Error,
+
+    /// Inside a try block with a finally clause
+    Finally(&'a Expr),
+}
+
+/// A loop that 'break' and 'continue' may jump out of
+struct Loop {
+    label: Option<RcStr>,
+
+    /// Where 'continue' jumps to. The stack at this point is
+    /// the baseline for the body of the loop
+    start: usize,
+
+    /// Depth of the cleanup stack when the loop was entered
+    cleanup_depth: usize,
+
+    /// Whether 'break' needs to push a value in place of the one a for loop
+    /// gets back from its exhausted iterator
+    push_on_break: bool,
+
+    /// Jumps emitted for 'break', to be patched to the end of the loop
+    break_jumps: Vec<usize>,
+}
+
+struct Builder<'a> {
     type_: Type,
     name: RcStr,
     params: Vec<Variable>,
@@ -40,15 +82,19 @@ struct Builder {
     marks: Vec<Mark>,
     doc: Option<RcStr>,
     docmap: HashMap<RcStr, RcStr>,
+    cleanups: Vec<Cleanup<'a>>,
+    loops: Vec<Loop>,
+    opt_level: u8,
 }
 
-impl Builder {
+impl<'a> Builder<'a> {
     fn new(
         type_: Type,
         name: RcStr,
         doc: Option<RcStr>,
         params: Vec<Variable>,
         varspec: VarSpec,
+        opt_level: u8,
     ) -> Self {
         Self {
             type_,
@@ -59,10 +105,14 @@ impl Builder {
             ops: vec![],
             marks: vec![],
             docmap: HashMap::new(),
+            cleanups: vec![],
+            loops: vec![],
+            opt_level,
         }
     }
-    fn build(self) -> Code {
+    fn build(mut self) -> Code {
         assert_eq!(self.ops.len(), self.marks.len());
+        optimize(&mut self.ops, &mut self.marks, self.opt_level);
         Code::new(
             self.name,
             self.ops,
@@ -84,8 +134,8 @@ impl Builder {
     }
 }
 
-impl Builder {
-    fn expr(&mut self, expr: &Expr, used: bool) -> Result<()> {
+impl<'a> Builder<'a> {
+    fn expr(&mut self, expr: &'a Expr, used: bool) -> Result<()> {
         let mark = expr.mark().clone();
         match expr.desc() {
             ExprDesc::Nil => {
@@ -103,6 +153,11 @@ impl Builder {
                     self.add(Opcode::Number(*x), mark);
                 }
             }
+            ExprDesc::Int(x) => {
+                if used {
+                    self.add(Opcode::Int(*x), mark);
+                }
+            }
             ExprDesc::String(x) => {
                 if used {
                     self.add(Opcode::String(x.clone()), mark);
@@ -222,16 +277,18 @@ impl Builder {
                     self.patch_jump(id);
                 }
             }
-            ExprDesc::For(target, container, body) => {
+            ExprDesc::For(target, container, body, label) => {
                 self.expr(container, true)?;
                 self.add(Opcode::Iter, mark.clone());
                 let start_label = self.len();
                 self.add(Opcode::Next, mark.clone());
                 let end_jump_id = self.add(Opcode::JumpIfFalse(INVALID_JUMP), mark.clone());
                 self.target(target, true)?;
+                self.enter_loop(label, start_label, true);
                 self.expr(body, false)?;
                 self.add(Opcode::Jump(start_label), mark.clone());
                 self.patch_jump(end_jump_id);
+                self.exit_loop();
                 if used {
                     // pop the exhausted container while retaining the final
                     // return value from the generator
@@ -243,17 +300,93 @@ impl Builder {
                     self.add(Opcode::Pop, mark);
                 }
             }
-            ExprDesc::While(cond, body) => {
+            ExprDesc::While(cond, body, label) => {
                 let start_label = self.len();
+                self.enter_loop(label, start_label, false);
                 self.expr(cond, true)?;
                 let cond_jump_id = self.add(Opcode::JumpIfFalse(INVALID_JUMP), mark.clone());
                 self.expr(body, false)?;
                 self.add(Opcode::Jump(start_label), mark.clone());
                 self.patch_jump(cond_jump_id);
+                self.exit_loop();
                 if used {
                     self.add(Opcode::Nil, mark);
                 }
             }
+            ExprDesc::Try(body, clauses, finally) => {
+                // The finally handler has to cover both the try body and the
+                // catch body, so that the finally body runs even if
+                // an error is thrown from inside the catch body
+                let finally_handler_id = match finally {
+                    Some(finally) => {
+                        self.cleanups.push(Cleanup::Finally(finally));
+                        Some(self.add(Opcode::PushHandler(INVALID_JUMP), mark.clone()))
+                    }
+                    None => None,
+                };
+                if clauses.is_empty() {
+                    self.expr(body, used)?;
+                } else {
+                    let handler_id = self.add(Opcode::PushHandler(INVALID_JUMP), mark.clone());
+                    self.cleanups.push(Cleanup::Handler);
+                    self.expr(body, used)?;
+                    self.cleanups.pop();
+                    self.add(Opcode::PopHandler, mark.clone());
+                    let mut end_jumps = vec![self.add(Opcode::Jump(INVALID_JUMP), mark.clone())];
+                    self.patch_jump(handler_id);
+                    let mut catches_all = false;
+                    for clause in clauses {
+                        let next_jump_id = match &clause.cls {
+                            Some(cls) => {
+                                self.expr(cls, true)?;
+                                self.add(Opcode::MatchError, cls.mark().clone());
+                                Some(self.add(Opcode::JumpIfFalse(INVALID_JUMP), mark.clone()))
+                            }
+                            None => {
+                                catches_all = true;
+                                None
+                            }
+                        };
+                        if let Some(name) = &clause.name {
+                            let variable = self.varspec.get(name).unwrap();
+                            self.add(Opcode::LoadError, mark.clone());
+                            self.add(Opcode::SetVar(variable.into()), mark.clone());
+                        }
+                        self.cleanups.push(Cleanup::Error);
+                        self.expr(&clause.body, used)?;
+                        self.cleanups.pop();
+                        self.add(Opcode::PopError, mark.clone());
+                        end_jumps.push(self.add(Opcode::Jump(INVALID_JUMP), mark.clone()));
+                        if let Some(next_jump_id) = next_jump_id {
+                            self.patch_jump(next_jump_id);
+                        }
+                        if catches_all {
+                            // any remaining clauses are unreachable
+                            break;
+                        }
+                    }
+                    if !catches_all {
+                        // None of the catch clauses matched
+                        self.add(Opcode::Reraise, mark.clone());
+                    }
+                    for id in end_jumps {
+                        self.patch_jump(id);
+                    }
+                }
+                if let (Some(finally), Some(finally_handler_id)) = (finally, finally_handler_id) {
+                    self.cleanups.pop();
+                    self.add(Opcode::PopHandler, mark.clone());
+                    self.expr(finally, false)?;
+                    let end_jump_id = self.add(Opcode::Jump(INVALID_JUMP), mark.clone());
+
+                    // If we get here, an error was thrown, so we run the
+                    // finally body and then rethrow the error
+                    self.patch_jump(finally_handler_id);
+                    self.expr(finally, false)?;
+                    self.add(Opcode::Reraise, mark.clone());
+                    self.patch_jump(end_jump_id);
+                }
+            }
             ExprDesc::Binop(op, lhs, rhs) => {
                 self.expr(lhs, true)?;
                 self.expr(rhs, true)?;
@@ -441,8 +574,33 @@ impl Builder {
                 } else {
                     self.add(Opcode::Nil, mark.clone());
                 }
+                self.unwind_cleanups(0, &mark)?;
                 self.add(Opcode::Return, mark);
             }
+            ExprDesc::Break(label) => {
+                let i = self.find_loop(label);
+                self.leave_loop_body(i, &mark)?;
+                if self.loops[i].push_on_break {
+                    self.add(Opcode::Nil, mark.clone());
+                }
+                let jump_id = self.add(Opcode::Jump(INVALID_JUMP), mark.clone());
+                self.loops[i].break_jumps.push(jump_id);
+
+                // Nothing after this point is reachable, but whatever follows
+                // still expects a value to be here if one is used
+                if used {
+                    self.add(Opcode::Nil, mark);
+                }
+            }
+            ExprDesc::Continue(label) => {
+                let i = self.find_loop(label);
+                self.leave_loop_body(i, &mark)?;
+                let start = self.loops[i].start;
+                self.add(Opcode::Jump(start), mark.clone());
+                if used {
+                    self.add(Opcode::Nil, mark);
+                }
+            }
             ExprDesc::Import(name) => {
                 let name = if name.starts_with('.') {
                     let mut depth = 1;
@@ -514,8 +672,14 @@ impl Builder {
                     FunctionKind::Generator => Type::Generator,
                     FunctionKind::Async => Type::Async,
                 };
-                let mut func_builder =
-                    Builder::new(type_, name, docstr.clone(), param_vars, varspec);
+                let mut func_builder = Builder::new(
+                    type_,
+                    name,
+                    docstr.clone(),
+                    param_vars,
+                    varspec,
+                    self.opt_level,
+                );
                 match *kind {
                     FunctionKind::Generator => {
                         // The first resume on a generator will push a value
@@ -587,7 +751,7 @@ impl Builder {
         }
         Ok(())
     }
-    fn args(&mut self, args: &Args) -> Result<()> {
+    fn args(&mut self, args: &'a Args) -> Result<()> {
         for arg in &args.args {
             self.expr(arg, true)?;
         }
@@ -602,7 +766,7 @@ impl Builder {
         }
         Ok(())
     }
-    fn target(&mut self, target: &AssignTarget, consume: bool) -> Result<()> {
+    fn target(&mut self, target: &'a AssignTarget, consume: bool) -> Result<()> {
         let mark = target.mark().clone();
         match target.desc() {
             AssignTargetDesc::Name(name) => {
@@ -642,7 +806,7 @@ impl Builder {
         }
         Ok(())
     }
-    fn augtarget(&mut self, target: &AssignTarget, op: Binop, consume: bool) -> Result<()> {
+    fn augtarget(&mut self, target: &'a AssignTarget, op: Binop, consume: bool) -> Result<()> {
         let mark = target.mark().clone();
         match target.desc() {
             AssignTargetDesc::Name(name) => {
@@ -690,6 +854,105 @@ impl Builder {
         }
         Ok(())
     }
+    /// Emits the code needed to leave all the try and catch
+    /// blocks entered since the given cleanup depth
+    fn unwind_cleanups(&mut self, depth: usize, mark: &Mark) -> Result<()> {
+        let cleanups = self.cleanups.clone();
+        for (i, cleanup) in cleanups.iter().enumerate().skip(depth).rev() {
+            match cleanup {
+                Cleanup::Handler => {
+                    self.add(Opcode::PopHandler, mark.clone());
+                }
+                Cleanup::Error => {
+                    self.add(Opcode::PopError, mark.clone());
+                }
+                Cleanup::Finally(finally) => {
+                    self.add(Opcode::PopHandler, mark.clone());
+
+                    // While running the finally body, we are no longer
+                    // inside its own try block
+                    self.cleanups.truncate(i);
+                    self.expr(finally, false)?;
+                }
+            }
+        }
+        self.cleanups = cleanups;
+        Ok(())
+    }
+    fn enter_loop(&mut self, label: &Option<RcStr>, start: usize, push_on_break: bool) {
+        self.loops.push(Loop {
+            label: label.clone(),
+            start,
+            cleanup_depth: self.cleanups.len(),
+            push_on_break,
+            break_jumps: vec![],
+        });
+    }
+    /// Patches all the 'break' jumps of the innermost loop to the current position
+    fn exit_loop(&mut self) {
+        let lp = self.loops.pop().unwrap();
+        for jump_id in lp.break_jumps {
+            self.patch_jump(jump_id);
+        }
+    }
+    /// Finds the loop a 'break' or 'continue' applies to
+    /// (the annotator has already checked that there is one)
+    fn find_loop(&self, label: &Option<RcStr>) -> usize {
+        match label {
+            None => self.loops.len() - 1,
+            Some(_) => self
+                .loops
+                .iter()
+                .rposition(|lp| &lp.label == label)
+                .unwrap(),
+        }
+    }
+    /// Emits the code needed to jump out of the body of the given loop:
+    /// discards any values pushed since the start of the loop, and leaves
+    /// any try and catch blocks entered inside it
+    fn leave_loop_body(&mut self, i: usize, mark: &Mark) -> Result<()> {
+        let depth = self.stack_depth_since(self.loops[i].start);
+        for _ in 0..depth {
+            self.add(Opcode::Pop, mark.clone());
+        }
+        self.unwind_cleanups(self.loops[i].cleanup_depth, mark)
+    }
+    /// The number of values on the stack at the current position, relative to
+    /// the given earlier position, found by following the opcodes emitted
+    /// (and jumps patched) so far
+    fn stack_depth_since(&self, start: usize) -> usize {
+        let here = self.here();
+        let mut depths: Vec<Option<isize>> = vec![None; here + 1 - start];
+        depths[0] = Some(0);
+        for i in start..here {
+            let depth = match depths[i - start] {
+                Some(depth) => depth,
+                None => continue,
+            };
+            let op = &self.ops[i];
+            let after = depth + op.stack_effect();
+            let mut reach = |dest: usize, depth: isize| {
+                if dest >= start && dest <= here && depths[dest - start].is_none() {
+                    depths[dest - start] = Some(depth);
+                }
+            };
+            match op {
+                Opcode::Jump(dest) => reach(*dest, depth),
+                Opcode::Return | Opcode::Reraise => {}
+                Opcode::TeeJumpIfFalse(dest) | Opcode::TeeJumpIfTrue(dest) => {
+                    reach(*dest, depth);
+                    reach(i + 1, after);
+                }
+                _ => {
+                    if let Some(dest) = op.jump_dest() {
+                        reach(dest, after);
+                    }
+                    reach(i + 1, after);
+                }
+            }
+        }
+        depths[here - start].unwrap_or(0) as usize
+    }
     fn here(&self) -> usize {
         self.ops.len()
     }
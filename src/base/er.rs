@@ -1,4 +1,5 @@
 use crate::RcStr;
+use crate::Value;
 use std::fmt;
 use std::fmt::Write;
 use std::path::Path;
@@ -44,6 +45,12 @@ pub struct Mark {
     lineno: usize,
 }
 
+impl PartialEq for Mark {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.source, &other.source) && self.pos == other.pos
+    }
+}
+
 impl Mark {
     pub fn new(source: Rc<Source>, pos: usize, lineno: usize) -> Self {
         Self {
@@ -92,12 +99,123 @@ impl Mark {
         // TODO: use self.lineno... just make sure it's correct
         self.source.data[..self.pos].matches('\n').count() + 1
     }
+    /// 1-based column of this mark, counted in chars
+    pub fn column(&self) -> usize {
+        let start = self.source.data[..self.pos]
+            .rfind('\n')
+            .map(|x| x + 1)
+            .unwrap_or(0);
+        self.source.data[start..self.pos].chars().count() + 1
+    }
+}
+
+/// Structured classification of an Error, so that embedders (and catch
+/// clauses in scripts) don't have to match on type name strings
+#[derive(Debug, Clone)]
+pub enum ErrorKind {
+    /// Generic runtime failure
+    Runtime,
+
+    /// An operating system or I/O failure
+    Io(std::io::ErrorKind),
+
+    /// A value had the wrong type
+    Type,
+
+    /// An attribute or method could not be found on a value
+    Attribute,
+
+    /// Invalid arguments were passed to a function
+    Argument,
+
+    /// Failure to parse a value or a source file
+    Parse,
+
+    /// Failure to tokenize a source file
+    Lex,
+
+    /// A lookup by key, name or path failed
+    NotFound,
+
+    /// An async operation was cancelled, or timed out
+    Cancelled,
+
+    /// Not a real error: used by Globals::request_trampoline to unwind
+    /// the stack back to the host
+    Trampoline,
+
+    /// Not a real error: the user quit the debugger, and the stack should
+    /// unwind back to the host, which decides how to shut down
+    DebuggerQuit,
+
+    /// An exception value explicitly thrown from a script
+    User(Value),
+}
+
+impl ErrorKind {
+    /// Guesses the kind of an error from its type name
+    pub fn from_type_name(type_: &str) -> Self {
+        match type_ {
+            "TypeError" => Self::Type,
+            "AttributeError" => Self::Attribute,
+            "ArgumentError" => Self::Argument,
+            "ParseError" => Self::Parse,
+            "NotFoundError" => Self::NotFound,
+            "CancelledError" => Self::Cancelled,
+            "OSError" | "IOError" => Self::Io(std::io::ErrorKind::Other),
+            "TrampolineRequest" => Self::Trampoline,
+            "DebuggerQuit" => Self::DebuggerQuit,
+            _ => Self::Runtime,
+        }
+    }
+
+    /// The default type name for errors of this kind
+    pub fn type_name(&self) -> RcStr {
+        match self {
+            Self::Runtime => "RuntimeError".into(),
+            Self::Io(_) => "OSError".into(),
+            Self::Type => "TypeError".into(),
+            Self::Attribute => "AttributeError".into(),
+            Self::Argument => "ArgumentError".into(),
+            Self::Parse | Self::Lex => "ParseError".into(),
+            Self::NotFound => "NotFoundError".into(),
+            Self::Cancelled => "CancelledError".into(),
+            Self::Trampoline => "TrampolineRequest".into(),
+            Self::DebuggerQuit => "DebuggerQuit".into(),
+            Self::User(Value::Table(table)) => table.cls().name().clone(),
+            Self::User(_) => "RuntimeError".into(),
+        }
+    }
+
+    /// Short name of this kind, as seen from scripts through
+    /// the 'kind' field of builtin exceptions
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Runtime => "Runtime",
+            Self::Io(_) => "Io",
+            Self::Type => "Type",
+            Self::Attribute => "Attribute",
+            Self::Argument => "Argument",
+            Self::Parse => "Parse",
+            Self::Lex => "Lex",
+            Self::NotFound => "NotFound",
+            Self::Cancelled => "Cancelled",
+            Self::Trampoline => "Trampoline",
+            Self::DebuggerQuit => "DebuggerQuit",
+            Self::User(_) => "User",
+        }
+    }
 }
 
 pub struct ErrorData {
     type_: RcStr,
     message: RcStr,
     trace: Vec<Mark>,
+    kind: ErrorKind,
+
+    // indices into 'trace' where an async boundary (i.e. an await) separates
+    // the mark at that index from the ones before it
+    async_breaks: Vec<usize>,
 }
 
 #[derive(Clone)]
@@ -105,11 +223,25 @@ pub struct Error(Rc<ErrorData>);
 
 impl Error {
     pub fn new(type_: RcStr, message: RcStr, trace: Vec<Mark>) -> Self {
+        let kind = ErrorKind::from_type_name(&type_);
+        Self::new_with_type_and_kind(type_, kind, message, trace)
+    }
+    pub fn new_with_kind(kind: ErrorKind, message: RcStr, trace: Vec<Mark>) -> Self {
+        Self::new_with_type_and_kind(kind.type_name(), kind, message, trace)
+    }
+    fn new_with_type_and_kind(
+        type_: RcStr,
+        kind: ErrorKind,
+        message: RcStr,
+        trace: Vec<Mark>,
+    ) -> Self {
         Self(
             ErrorData {
                 type_,
                 message,
                 trace,
+                kind,
+                async_breaks: vec![],
             }
             .into(),
         )
@@ -123,24 +255,62 @@ impl Error {
         }
         Self::new("RuntimeError".into(), message, trace)
     }
+    pub fn parse(message: RcStr, trace: Vec<Mark>) -> Self {
+        Self::new_with_kind(ErrorKind::Parse, message, trace)
+    }
     pub fn type_(&self) -> &RcStr {
         &self.0.type_
     }
     pub fn message(&self) -> &RcStr {
         &self.0.message
     }
+    pub fn trace(&self) -> &Vec<Mark> {
+        &self.0.trace
+    }
+    pub fn kind(&self) -> &ErrorKind {
+        &self.0.kind
+    }
     pub fn format(&self) -> String {
         format!("{}", self)
     }
-    pub fn prepended(&self, mut trace: Vec<Mark>) -> Self {
+    /// Indices into the trace where an await separates the mark at
+    /// that index from the marks before it
+    pub fn async_breaks(&self) -> &Vec<usize> {
+        &self.0.async_breaks
+    }
+    pub fn prepended(&self, trace: Vec<Mark>) -> Self {
+        self.prepended_with_break(trace, false)
+    }
+    /// Like prepended, but the given marks are separated from the existing
+    /// ones by an async boundary (i.e. they belong to the function that
+    /// awaited on whatever produced this error)
+    pub fn prepended_async(&self, trace: Vec<Mark>) -> Self {
+        self.prepended_with_break(trace, true)
+    }
+    fn prepended_with_break(&self, mut trace: Vec<Mark>, async_break: bool) -> Self {
+        let offset = trace.len();
         trace.extend(self.0.trace.clone());
-        Self::new(self.0.type_.clone(), self.0.message.clone(), trace)
+        let mut async_breaks = Vec::new();
+        if async_break {
+            async_breaks.push(offset);
+        }
+        async_breaks.extend(self.0.async_breaks.iter().map(|i| i + offset));
+        Self(
+            ErrorData {
+                type_: self.0.type_.clone(),
+                message: self.0.message.clone(),
+                trace,
+                kind: self.0.kind.clone(),
+                async_breaks,
+            }
+            .into(),
+        )
     }
 }
 
 impl From<std::io::Error> for Error {
     fn from(e: std::io::Error) -> Self {
-        Self::new("IOError".into(), format!("{:?}", e).into(), vec![])
+        Self::new_with_kind(ErrorKind::Io(e.kind()), format!("{:?}", e).into(), vec![])
     }
 }
 
@@ -152,13 +322,13 @@ impl From<std::fmt::Error> for Error {
 
 impl From<std::num::ParseIntError> for Error {
     fn from(e: std::num::ParseIntError) -> Self {
-        Self::rt(format!("{:?}", e).into(), vec![])
+        Self::parse(format!("{:?}", e).into(), vec![])
     }
 }
 
 impl From<std::num::ParseFloatError> for Error {
     fn from(e: std::num::ParseFloatError) -> Self {
-        Self::rt(format!("{:?}", e).into(), vec![])
+        Self::parse(format!("{:?}", e).into(), vec![])
     }
 }
 
@@ -170,7 +340,7 @@ impl From<std::char::CharTryFromError> for Error {
 
 impl From<std::str::Utf8Error> for Error {
     fn from(e: std::str::Utf8Error) -> Self {
-        Self::rt(format!("{:?}", e).into(), vec![])
+        Self::parse(format!("{:?}", e).into(), vec![])
     }
 }
 
@@ -198,7 +368,10 @@ impl fmt::Display for Error {
         writeln!(f, "=================")?;
         writeln!(f, "== STACK TRACE ==")?;
         writeln!(f, "=================")?;
-        for mark in &self.0.trace {
+        for (i, mark) in self.0.trace.iter().enumerate() {
+            if self.0.async_breaks.contains(&i) {
+                writeln!(f, "[async]")?;
+            }
             write!(f, "{}", mark.format())?;
         }
         writeln!(f, "{}: {}", self.0.type_, self.0.message)
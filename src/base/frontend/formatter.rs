@@ -0,0 +1,637 @@
+//! Source preserving formatter
+//! Works on the token stream (including LineString comments) rather than
+//! the AST, so that comments, string literals and number literals come out
+//! exactly as they were written. Only the whitespace between tokens changes:
+//!
+//!   * 4 space indents, one level per open bracket (for lines that start
+//!     inside it), with closing brackets dedented to match their opening line,
+//!     and one more for lines continuing a method chain
+//!   * at most one blank line in a row, and none at the start or end
+//!     of a block
+//!   * single spaces around binary operators and after commas, none inside
+//!     brackets, and '{ x }' for blocks that fit on one line
+//!   * no spaces around the '=' of default parameters and keyword arguments,
+//!     or around the ':' of a slice
+//!
+//! Line breaks inside '()' and '[]' are kept as written, since the lexer
+//! does not emit newlines there.
+//!
+//! As a safety net, 'ast_fingerprint' lets callers check that the formatted
+//! source still parses to the same AST.
+use crate::Args;
+use crate::AssignTarget;
+use crate::AssignTargetDesc;
+use crate::Expr;
+use crate::ExprDesc;
+use crate::ModuleDisplay;
+use crate::Punctuator;
+use crate::Token;
+
+const INDENT: &str = "    ";
+
+/// An open bracket
+struct Group {
+    open: Punctuator,
+
+    /// indentation level of the line the bracket was opened on
+    indent: usize,
+
+    /// whether this is the '(' of a call or parameter list, or the '[' of
+    /// a subscript (as opposed to a parenthesized expression or list/map display)
+    applied: bool,
+}
+
+/// Reformats the given source, given the output of Lexer::lex on it
+pub(crate) fn format_tokens(src: &str, tokens: &[Token], posinfo: &[(usize, usize)]) -> String {
+    let starts: Vec<usize> = tokens
+        .iter()
+        .zip(posinfo)
+        .map(|(token, (pos, _))| match token {
+            // the position of a LineString points past its '#' (and
+            // an optional space)
+            Token::LineString(_) if src[..*pos].ends_with("# ") => pos - 2,
+            Token::LineString(_) => pos - 1,
+            _ => *pos,
+        })
+        .collect();
+    let ends: Vec<usize> = (0..tokens.len())
+        .map(|i| match tokens[i] {
+            Token::LineString(s) => posinfo[i].0 + s.len(),
+            Token::EOF => src.len(),
+            _ => starts[i] + src[starts[i]..starts[i + 1]].trim_end().len(),
+        })
+        .collect();
+
+    let mut out = String::new();
+    let mut groups: Vec<Group> = Vec::new();
+    let mut line_indent = 0;
+    let mut pending_newlines = 0;
+
+    // the last two tokens written, with whether each was a unary operator
+    // or a tight '=' (of a default parameter or keyword argument)
+    let mut prev: Option<(Token, bool)> = None;
+    let mut prev2: Option<Token> = None;
+
+    for (i, token) in tokens.iter().enumerate() {
+        let token = match (*token, prev) {
+            // keywords used as attribute names (e.g. 'promise.catch') are
+            // formatted like names ('from . import' is not an attribute)
+            (Token::Punctuator(p), Some((Token::Punctuator(Punctuator::Dot), _)))
+                if p != Punctuator::Import && p.str().chars().all(char::is_alphabetic) =>
+            {
+                Token::Name(p.str())
+            }
+            (token, _) => token,
+        };
+        match token {
+            Token::EOF => break,
+            Token::Newline(n) => {
+                pending_newlines = pending_newlines.max(if n >= 2 { 2 } else { 1 });
+                continue;
+            }
+            _ => {}
+        }
+
+        // line breaks inside '()' and '[]' come from the source text
+        if let Some(group) = groups.last() {
+            if group.open != Punctuator::LBrace && i > 0 {
+                let gap = &src[ends[i - 1]..starts[i]];
+                let newlines = gap.matches('\n').count().min(2);
+                pending_newlines = pending_newlines.max(newlines);
+            }
+        }
+
+        let closes = matches!(
+            token,
+            Token::Punctuator(Punctuator::RParen)
+                | Token::Punctuator(Punctuator::RBracket)
+                | Token::Punctuator(Punctuator::RBrace)
+        );
+
+        let special = match prev {
+            Some((prev_token, _)) => is_special(token, prev_token, prev2, groups.last()),
+            None => false,
+        };
+
+        if pending_newlines > 0 && !out.is_empty() {
+            if closes || prev.map(|(t, _)| opens(t)).unwrap_or(false) {
+                pending_newlines = 1;
+            }
+            for _ in 0..pending_newlines {
+                out.push('\n');
+            }
+            line_indent = match groups.last() {
+                Some(group) if closes => group.indent,
+                Some(group) => group.indent + 1,
+                None => 0,
+            };
+            // method chains continued on the next line
+            if token == Token::Punctuator(Punctuator::Dot) {
+                line_indent += 1;
+            }
+            for _ in 0..line_indent {
+                out.push_str(INDENT);
+            }
+        } else if let Some((prev_token, prev_special)) = prev {
+            if spaced(
+                prev_token,
+                prev_special,
+                prev2,
+                token,
+                special,
+                groups.last(),
+            ) {
+                out.push(' ');
+            }
+        }
+        pending_newlines = 0;
+
+        match token {
+            Token::LineString("") => out.push('#'),
+            Token::LineString(s) => {
+                out.push_str("# ");
+                out.push_str(s);
+            }
+            _ => out.push_str(&src[starts[i]..ends[i]]),
+        }
+
+        match token {
+            Token::Punctuator(open @ Punctuator::LParen)
+            | Token::Punctuator(open @ Punctuator::LBracket)
+            | Token::Punctuator(open @ Punctuator::LBrace) => {
+                let applied = match prev {
+                    Some((prev_token, _)) => applies(prev_token, prev2, open),
+                    None => false,
+                };
+                groups.push(Group {
+                    open,
+                    indent: line_indent,
+                    applied,
+                });
+            }
+            _ if closes => {
+                groups.pop();
+            }
+            _ => {}
+        }
+
+        prev2 = prev.map(|(t, _)| t);
+        prev = Some((token, special));
+    }
+
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    out
+}
+
+fn opens(token: Token) -> bool {
+    matches!(
+        token,
+        Token::Punctuator(Punctuator::LParen)
+            | Token::Punctuator(Punctuator::LBracket)
+            | Token::Punctuator(Punctuator::LBrace)
+    )
+}
+
+/// Whether the token can end an expression (so that a following
+/// '-' is binary, and a following '(' is a call)
+fn ends_value(token: Token) -> bool {
+    match token {
+        Token::Name(_)
+        | Token::Int(_)
+        | Token::Float(_)
+        | Token::NormalString(_)
+        | Token::RawString(_)
+        | Token::FormatString(_)
+        | Token::Symbol(_) => true,
+        Token::Punctuator(p) => matches!(
+            p,
+            Punctuator::RParen
+                | Punctuator::RBracket
+                | Punctuator::RBrace
+                | Punctuator::True
+                | Punctuator::False
+                | Punctuator::Nil
+        ),
+        _ => false,
+    }
+}
+
+/// Whether a bracket opened after 'prev' is a call, parameter list or subscript
+fn applies(prev: Token, prev2: Option<Token>, open: Punctuator) -> bool {
+    match open {
+        Punctuator::LParen => {
+            ends_value(prev)
+                || matches!(
+                    prev,
+                    Token::Punctuator(Punctuator::Def) | Token::Punctuator(Punctuator::New)
+                )
+                || (prev == Token::Punctuator(Punctuator::Star)
+                    && prev2 == Some(Token::Punctuator(Punctuator::Def)))
+        }
+        Punctuator::LBracket => ends_value(prev) && prev != Token::Punctuator(Punctuator::RBrace),
+        _ => false,
+    }
+}
+
+/// Whether the token is a unary operator, or the '=' of a default parameter
+/// or keyword argument. Both are written without a space on one side
+fn is_special(token: Token, prev: Token, prev2: Option<Token>, group: Option<&Group>) -> bool {
+    match token {
+        Token::Punctuator(Punctuator::Minus)
+        | Token::Punctuator(Punctuator::Plus)
+        | Token::Punctuator(Punctuator::Star)
+        | Token::Punctuator(Punctuator::Star2) => {
+            // 'def*' marks a generator
+            !ends_value(prev) && prev != Token::Punctuator(Punctuator::Def)
+        }
+        Token::Punctuator(Punctuator::Exclamation)
+        | Token::Punctuator(Punctuator::Tilde)
+        | Token::Punctuator(Punctuator::Dollar) => true,
+        Token::Punctuator(Punctuator::Eq) => {
+            matches!(
+                group,
+                Some(Group {
+                    open: Punctuator::LParen,
+                    applied: true,
+                    ..
+                })
+            ) && matches!(prev, Token::Name(_))
+                && matches!(
+                    prev2,
+                    Some(Token::Punctuator(Punctuator::LParen))
+                        | Some(Token::Punctuator(Punctuator::Comma))
+                        | Some(Token::LineString(_))
+                )
+        }
+        _ => false,
+    }
+}
+
+/// Whether to put a space between two tokens on the same line
+fn spaced(
+    prev: Token,
+    prev_special: bool,
+    prev2: Option<Token>,
+    token: Token,
+    special: bool,
+    group: Option<&Group>,
+) -> bool {
+    use Punctuator::*;
+    let in_subscript = matches!(
+        group,
+        Some(Group {
+            open: LBracket,
+            applied: true,
+            ..
+        })
+    );
+    match (prev, token) {
+        (Token::Punctuator(LBrace), Token::Punctuator(RBrace)) => false,
+        (Token::Punctuator(LBrace), _) | (_, Token::Punctuator(RBrace)) => true,
+        (Token::Punctuator(LParen), _) | (Token::Punctuator(LBracket), _) => false,
+        (_, Token::Punctuator(RParen)) | (_, Token::Punctuator(RBracket)) => false,
+        (_, Token::Punctuator(Comma))
+        | (_, Token::Punctuator(Semicolon))
+        | (_, Token::Punctuator(Colon)) => false,
+        (Token::Punctuator(Colon), _) => !in_subscript,
+
+        // 'from .foo import bar', and the '.' after a number
+        // that would otherwise be lexed as part of the number
+        (Token::Punctuator(From), Token::Punctuator(Dot))
+        | (Token::Punctuator(Import), Token::Punctuator(Dot))
+        | (Token::Int(_), Token::Punctuator(Dot))
+        | (Token::Punctuator(Dot), Token::Punctuator(Import)) => true,
+        (_, Token::Punctuator(Dot)) | (Token::Punctuator(Dot), _) => false,
+
+        (Token::Punctuator(Def), Token::Punctuator(Star)) => false,
+        _ if prev_special => false,
+        _ if special && token == Token::Punctuator(Eq) => false,
+        (_, Token::Punctuator(LParen)) | (_, Token::Punctuator(LBracket)) => {
+            let open = token.punctuator().unwrap();
+            !applies(prev, prev2, open)
+        }
+        _ => true,
+    }
+}
+
+/// The structure of an AST, with all marks left out, so that the ASTs of
+/// sources that only differ in their layout have the same fingerprint
+pub(crate) fn ast_fingerprint(display: &ModuleDisplay) -> String {
+    let mut out = format!("{:?} ", display.doc());
+    fingerprint(&mut out, display.body());
+    out
+}
+
+fn fingerprint(out: &mut String, expr: &Expr) {
+    match expr.desc() {
+        desc @ (ExprDesc::Nil
+        | ExprDesc::Bool(_)
+        | ExprDesc::Number(_)
+        | ExprDesc::Int(_)
+        | ExprDesc::String(_)
+        | ExprDesc::Name(_)
+        | ExprDesc::Break(_)
+        | ExprDesc::Continue(_)
+        | ExprDesc::Nonlocal(_)
+        | ExprDesc::Del(_)
+        | ExprDesc::Import(_)
+        | ExprDesc::BreakPoint
+        | ExprDesc::GetCallingModule) => out.push_str(&format!("{:?}", desc)),
+        ExprDesc::List(exprs) => {
+            out.push_str("List");
+            fingerprint_all(out, exprs);
+        }
+        ExprDesc::Map(pairs) => {
+            out.push_str("Map(");
+            for (key, val) in pairs {
+                fingerprint(out, key);
+                out.push(':');
+                fingerprint(out, val);
+                out.push(',');
+            }
+            out.push(')');
+        }
+        ExprDesc::Parentheses(expr) => {
+            out.push_str("Parentheses");
+            fingerprint_all(out, [&**expr]);
+        }
+        ExprDesc::Block(exprs) => {
+            out.push_str("Block");
+            fingerprint_all(out, exprs);
+        }
+        ExprDesc::Switch(value, cases, default) => {
+            out.push_str("Switch");
+            fingerprint_all(out, [&**value]);
+            for (matches, body) in cases {
+                fingerprint_all(out, matches);
+                fingerprint_all(out, [body]);
+            }
+            fingerprint_all(out, default.as_deref());
+        }
+        ExprDesc::If(pairs, other) => {
+            out.push_str("If");
+            for (cond, body) in pairs {
+                fingerprint_all(out, [cond, body]);
+            }
+            fingerprint_all(out, other.as_deref());
+        }
+        ExprDesc::For(target, container, body, label) => {
+            out.push_str(&format!("For({:?})", label));
+            fingerprint_target(out, target);
+            fingerprint_all(out, [&**container, &**body]);
+        }
+        ExprDesc::While(cond, body, label) => {
+            out.push_str(&format!("While({:?})", label));
+            fingerprint_all(out, [&**cond, &**body]);
+        }
+        ExprDesc::Try(body, clauses, finally) => {
+            out.push_str("Try");
+            fingerprint_all(out, [&**body]);
+            for clause in clauses {
+                out.push_str(&format!("Catch({:?})", clause.name));
+                fingerprint_all(out, clause.cls.as_ref());
+                fingerprint_all(out, [&clause.body]);
+            }
+            fingerprint_all(out, finally.as_deref());
+        }
+        ExprDesc::Binop(op, lhs, rhs) => {
+            out.push_str(&format!("Binop({:?})", op));
+            fingerprint_all(out, [&**lhs, &**rhs]);
+        }
+        ExprDesc::LogicalBinop(op, lhs, rhs) => {
+            out.push_str(&format!("LogicalBinop({:?})", op));
+            fingerprint_all(out, [&**lhs, &**rhs]);
+        }
+        ExprDesc::Unop(op, expr) => {
+            out.push_str(&format!("Unop({:?})", op));
+            fingerprint_all(out, [&**expr]);
+        }
+        ExprDesc::Subscript(owner, index) => {
+            out.push_str("Subscript");
+            fingerprint_all(out, [&**owner, &**index]);
+        }
+        ExprDesc::Slice(owner, start, end) => {
+            out.push_str("Slice");
+            fingerprint_all(out, [&**owner]);
+            fingerprint_all(out, start.as_deref());
+            fingerprint_all(out, end.as_deref());
+        }
+        ExprDesc::Attr(owner, name) => {
+            out.push_str(&format!("Attr({:?})", name));
+            fingerprint_all(out, [&**owner]);
+        }
+        ExprDesc::CallFunction(f, args) => {
+            out.push_str("CallFunction");
+            fingerprint_all(out, [&**f]);
+            fingerprint_args(out, args);
+        }
+        ExprDesc::CallMethod(owner, name, args) => {
+            out.push_str(&format!("CallMethod({:?})", name));
+            fingerprint_all(out, [&**owner]);
+            fingerprint_args(out, args);
+        }
+        ExprDesc::Assign(target, valexpr) => {
+            out.push_str("Assign");
+            fingerprint_target(out, target);
+            fingerprint_all(out, [&**valexpr]);
+        }
+        ExprDesc::AugAssign(target, op, valexpr) => {
+            out.push_str(&format!("AugAssign({:?})", op));
+            fingerprint_target(out, target);
+            fingerprint_all(out, [&**valexpr]);
+        }
+        ExprDesc::NonlocalAssign(name, valexpr) => {
+            out.push_str(&format!("NonlocalAssign({:?})", name));
+            fingerprint_all(out, [&**valexpr]);
+        }
+        ExprDesc::New(hidden_name, pairs) => {
+            out.push_str(&format!("New({:?})", hidden_name));
+            for (name, expr) in pairs {
+                out.push_str(&format!("{:?}:", name));
+                fingerprint_all(out, [expr]);
+            }
+        }
+        ExprDesc::Yield(expr) => {
+            out.push_str("Yield");
+            fingerprint_all(out, [&**expr]);
+        }
+        ExprDesc::Await(expr) => {
+            out.push_str("Await");
+            fingerprint_all(out, [&**expr]);
+        }
+        ExprDesc::Return(expr) => {
+            out.push_str("Return");
+            fingerprint_all(out, expr.as_deref());
+        }
+        ExprDesc::AssignDoc(expr, name, doc) => {
+            out.push_str(&format!("AssignDoc({:?}, {:?})", name, doc));
+            fingerprint_all(out, [&**expr]);
+        }
+        // the varspec is only computed during annotation,
+        // and so is never set on a freshly parsed AST
+        ExprDesc::Function {
+            kind,
+            name,
+            params,
+            docstr,
+            body,
+            varspec: _,
+        } => {
+            out.push_str(&format!(
+                "Function({:?}, {:?}, {:?}, {:?}, {:?}, {:?}, {:?})",
+                kind,
+                name,
+                params.req(),
+                params.def(),
+                params.var(),
+                params.key(),
+                docstr,
+            ));
+            fingerprint_all(out, [&**body]);
+        }
+        ExprDesc::Class {
+            name,
+            bases,
+            docstr,
+            methods,
+            static_methods,
+            hidden_name,
+        } => {
+            out.push_str(&format!(
+                "Class({:?}, {:?}, {:?})",
+                name, docstr, hidden_name
+            ));
+            fingerprint_all(out, bases);
+            for methods in [methods, static_methods] {
+                out.push('(');
+                for (name, method) in methods {
+                    out.push_str(&format!("{:?}:", name));
+                    fingerprint_all(out, [method]);
+                }
+                out.push(')');
+            }
+        }
+    }
+}
+
+fn fingerprint_all<'a, I: IntoIterator<Item = &'a Expr>>(out: &mut String, exprs: I) {
+    out.push('(');
+    for expr in exprs {
+        fingerprint(out, expr);
+        out.push(',');
+    }
+    out.push(')');
+}
+
+fn fingerprint_args(out: &mut String, args: &Args) {
+    fingerprint_all(out, &args.args);
+    fingerprint_all(out, args.varargs.as_deref());
+    for (name, expr) in &args.kwargs {
+        out.push_str(&format!("{:?}:", name));
+        fingerprint_all(out, [expr]);
+    }
+    fingerprint_all(out, args.kwmap.as_deref());
+}
+
+fn fingerprint_target(out: &mut String, target: &AssignTarget) {
+    match target.desc() {
+        AssignTargetDesc::Name(name) => out.push_str(&format!("Name({:?})", name)),
+        AssignTargetDesc::List(targets) => {
+            out.push_str("List(");
+            for target in targets {
+                fingerprint_target(out, target);
+                out.push(',');
+            }
+            out.push(')');
+        }
+        AssignTargetDesc::Attr(owner, name) => {
+            out.push_str(&format!("Attr({:?})", name));
+            fingerprint_all(out, [&**owner]);
+        }
+        AssignTargetDesc::Subscript(owner, index) => {
+            out.push_str("Subscript");
+            fingerprint_all(out, [&**owner, &**index]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format_tokens;
+    use crate::Globals;
+    use crate::Lexer;
+    use crate::Source;
+    use std::path::Path;
+    use std::rc::Rc;
+
+    fn format(src: &str) -> String {
+        let (tokens, posinfo) = Lexer::new().lex(src).unwrap();
+        format_tokens(src, &tokens, &posinfo)
+    }
+
+    #[test]
+    fn spacing_and_indent() {
+        assert_eq!(
+            format(
+                "def  foo( a,b = 1 ,*args ) {\n\n\n  x=a+-b*2\n\n  return f(a,k = x[1 : 2])\n\n}"
+            ),
+            "def foo(a, b=1, *args) {\n    x = a + -b * 2\n\n    return f(a, k=x[1:2])\n}\n",
+        );
+        assert_eq!(
+            format("x = switch y {\n1|2 => 'a'\n  => r'b'\n}\n"),
+            "x = switch y {\n    1 | 2 => 'a'\n    => r'b'\n}\n",
+        );
+        assert_eq!(
+            format("class Foo(Bar) {\n# doc\nnew(a) = new(a = a)\nstatic def* gen() {yield 1}\n}"),
+            "class Foo(Bar) {\n    # doc\n    new(a) = new(a=a)\n    static def* gen() { yield 1 }\n}\n",
+        );
+        assert_eq!(
+            format("print(foo(\n1,\n  ['a' :0x1_0],\n))"),
+            "print(foo(\n    1,\n    ['a': 0x1_0],\n))\n",
+        );
+        assert_eq!(format("p.catch( f ).finally(g)"), "p.catch(f).finally(g)\n",);
+    }
+
+    #[test]
+    fn braces_in_names() {
+        // the marks of the AST are left out when comparing it before and after
+        // formatting, whatever the name of their source
+        let globals = Globals::new();
+        let data = "def  f( x ) {\n  y = '}}'\n  if x { return y + '{' }\n}\n";
+        let source = Rc::new(Source::new("{braces}".into(), None, data.into()));
+        assert_eq!(
+            globals.format(source).unwrap(),
+            "def f(x) {\n    y = '}}'\n    if x { return y + '{' }\n}\n",
+        );
+    }
+
+    fn visit(dir: &Path, f: &mut dyn FnMut(&Path)) {
+        for entry in dir.read_dir().unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                visit(&path, f);
+            } else if path.extension().map(|ext| ext == "u").unwrap_or(false) {
+                f(&path);
+            }
+        }
+    }
+
+    #[test]
+    fn samples_round_trip() {
+        // Globals::format itself checks that the AST is unchanged
+        let globals = Globals::new();
+        let samples = Path::new(env!("CARGO_MANIFEST_DIR")).join("samples");
+        visit(&samples, &mut |path| {
+            let data = std::fs::read_to_string(path).unwrap();
+            let source = Rc::new(Source::new("sample".into(), None, data.into()));
+            if globals.parse(source.clone()).is_err() {
+                return;
+            }
+            let formatted = globals.format(source).unwrap();
+            let source = Rc::new(Source::new("sample".into(), None, formatted.clone().into()));
+            assert_eq!(globals.format(source).unwrap(), formatted, "{:?}", path);
+        });
+    }
+}
@@ -16,6 +16,7 @@ pub struct LexError {
 pub enum LexErrorKind {
     UnrecognizedToken(RcStr),
     UnterminatedStringLiteral,
+    InvalidNumberLiteral(RcStr),
     UnmatchedOpeningSymbol,
     UnmatchedClosingSymbol,
     MismatchedGroupingPunctuator {
@@ -169,15 +170,19 @@ impl Lexer {
             }
 
             // string
-            let at_str = c == '"' || c == '\'' || c == 'r' && (c2 == Some('"') || c2 == Some('\''));
+            // (an 'r' prefix marks a raw string, and an 'f' or '$' prefix
+            // marks a format string with embedded '{expr}' fields)
+            let prefixed = c == 'r' || c == 'f' || c == '$';
+            let at_str = c == '"' || c == '\'' || prefixed && (c2 == Some('"') || c2 == Some('\''));
             if at_str {
-                // rs = s without raw indicator
-                // rindlen = raw indicator len (the 'r' char)
-                let (raw, rindlen, quote_char) = if c == 'r' {
-                    (true, 'r'.len_utf8(), c2.unwrap())
+                // rs = s without the prefix
+                // rindlen = prefix len
+                let (prefix, rindlen, quote_char) = if prefixed {
+                    (Some(c), c.len_utf8(), c2.unwrap())
                 } else {
-                    (false, 0, c)
+                    (None, 0, c)
                 };
+                let raw = prefix == Some('r');
                 let rs = &s[rindlen..];
                 let quote_len = if rs.starts_with("'''") || rs.starts_with("\"\"\"") {
                     3
@@ -236,10 +241,10 @@ impl Lexer {
                 }
 
                 let text = &rs[quote_bytelen..data_and_end_quotes_len];
-                let token = if raw {
-                    Token::RawString(text)
-                } else {
-                    Token::NormalString(text)
+                let token = match prefix {
+                    Some('r') => Token::RawString(text),
+                    Some(_) => Token::FormatString(text),
+                    None => Token::NormalString(text),
                 };
                 add(&mut tokens, &mut pos_info, token, pos, lineno);
                 incr(
@@ -272,25 +277,42 @@ impl Lexer {
                     let token = Token::Float(filter_underscores(text).parse().unwrap());
                     add(&mut tokens, &mut pos_info, token, pos, lineno);
                     incr(&mut s, &mut pos, dp);
-                } else if &s[..dp1] == "0" && s[dp1..].chars().next() == Some('x') {
-                    // int (hex)
-                    let dp1_with_x = dp1 + 'x'.len_utf8();
-                    let dp2: usize = s[dp1_with_x..]
+                } else if let Some(radix) = radix_prefix(&s[..dp1], s[dp1..].chars().next()) {
+                    // int (hex, binary or octal)
+                    let dp1_with_prefix = dp1 + 1;
+                    let dp2: usize = s[dp1_with_prefix..]
                         .chars()
-                        .take_while(|c| c.is_ascii_hexdigit() || *c == '_')
+                        .take_while(|c| c.is_ascii_alphanumeric() || *c == '_')
                         .map(|c| c.len_utf8())
                         .sum();
-                    let dp = dp1_with_x + dp2;
-                    let text = &s[dp1_with_x..dp];
-                    let token =
-                        Token::Int(i64::from_str_radix(&filter_underscores(text), 16).unwrap());
-                    add(&mut tokens, &mut pos_info, token, pos, lineno);
+                    let dp = dp1_with_prefix + dp2;
+                    let text = &s[dp1_with_prefix..dp];
+                    let value = match i64::from_str_radix(&filter_underscores(text), radix) {
+                        Ok(value) => value,
+                        Err(_) => {
+                            return Err(LexError {
+                                offset: pos,
+                                lineno,
+                                kind: LexErrorKind::InvalidNumberLiteral(s[..dp].into()),
+                            })
+                        }
+                    };
+                    add(&mut tokens, &mut pos_info, Token::Int(value), pos, lineno);
                     incr(&mut s, &mut pos, dp);
                 } else {
                     // int
                     let text = &s[..dp1];
-                    let token = Token::Int(filter_underscores(text).parse().unwrap());
-                    add(&mut tokens, &mut pos_info, token, pos, lineno);
+                    let value = match filter_underscores(text).parse() {
+                        Ok(value) => value,
+                        Err(_) => {
+                            return Err(LexError {
+                                offset: pos,
+                                lineno,
+                                kind: LexErrorKind::InvalidNumberLiteral(text.into()),
+                            })
+                        }
+                    };
+                    add(&mut tokens, &mut pos_info, Token::Int(value), pos, lineno);
                     incr(&mut s, &mut pos, dp1);
                 }
                 continue;
@@ -424,6 +446,17 @@ fn add<'a>(
     posinfos.push((offset, lineno));
 }
 
+/// The radix of an integer literal starting with the given digits
+/// and followed by the given character, e.g. 16 for '0x'
+fn radix_prefix(digits: &str, next: Option<char>) -> Option<u32> {
+    match (digits, next) {
+        ("0", Some('x')) => Some(16),
+        ("0", Some('b')) => Some(2),
+        ("0", Some('o')) => Some(8),
+        _ => None,
+    }
+}
+
 fn filter_underscores(s: &str) -> String {
     s.chars().filter(|c| *c != '_').collect()
 }
@@ -481,6 +514,16 @@ mod tests {
             vec![Token::NormalString("hi"), Token::EOF],
         );
 
+        assert_eq!(
+            lex(&lexer, "f'a{b}'").unwrap(),
+            vec![Token::FormatString("a{b}"), Token::EOF],
+        );
+
+        assert_eq!(
+            lex(&lexer, "$\"{x:>3}\"").unwrap(),
+            vec![Token::FormatString("{x:>3}"), Token::EOF],
+        );
+
         assert_eq!(
             lex(&lexer, "r'\\'").unwrap(),
             vec![Token::RawString("\\"), Token::EOF],
@@ -507,6 +550,30 @@ mod tests {
             vec![Token::Int(418), Token::EOF],
         );
 
+        assert_eq!(
+            lex(&lexer, "0b1010_0101").unwrap(),
+            vec![Token::Int(165), Token::EOF],
+        );
+
+        assert_eq!(
+            lex(&lexer, "0o17").unwrap(),
+            vec![Token::Int(15), Token::EOF],
+        );
+
+        assert_eq!(
+            lex(&lexer, "0b12"),
+            Err(LexError {
+                offset: 0,
+                lineno: 1,
+                kind: LexErrorKind::InvalidNumberLiteral("0b12".into()),
+            })
+        );
+
+        assert_eq!(
+            lex(&lexer, "<<=").unwrap(),
+            vec![Token::Punctuator(Punctuator::ShlEq), Token::EOF],
+        );
+
         assert_eq!(
             lex(&lexer, "hello").unwrap(),
             vec![Token::Name("hello"), Token::EOF,],
@@ -632,11 +699,11 @@ mod tests {
     fn error_unrecognized() {
         let lexer = Lexer::new();
         assert_eq!(
-            lex(&lexer, "asdf &%^"),
+            lex(&lexer, "asdf ?%^"),
             Err(LexError {
                 offset: 5,
                 lineno: 1,
-                kind: LexErrorKind::UnrecognizedToken("&%^".into()),
+                kind: LexErrorKind::UnrecognizedToken("?%^".into()),
             }),
         );
     }
@@ -1,7 +1,9 @@
+mod formatter;
 mod lexer;
 mod parser;
 mod token;
 
+pub(crate) use formatter::*;
 pub use lexer::*;
 pub(crate) use parser::*;
 pub use token::*;
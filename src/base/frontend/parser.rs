@@ -3,11 +3,14 @@ use crate::Args;
 use crate::AssignTarget;
 use crate::AssignTargetDesc;
 use crate::Binop;
+use crate::CatchClause;
 use crate::ConstVal;
 use crate::Error;
+use crate::ErrorKind;
 use crate::Expr;
 use crate::ExprDesc;
 use crate::FunctionKind;
+use crate::Lexer;
 use crate::LogicalBinop;
 use crate::Mark;
 use crate::ModuleDisplay;
@@ -43,6 +46,7 @@ impl ParameterKind {
 }
 
 pub(crate) struct Parser {
+    lexer: Lexer,
     prectable: Vec<Prec>,
     prefix_table: Vec<Option<for<'a> fn(&mut ParserState<'a>) -> Result<Expr>>>,
     infix_table: Vec<Option<fn(&mut ParserState, Expr, Prec) -> Result<Expr>>>,
@@ -52,6 +56,7 @@ impl Parser {
     pub(crate) fn new() -> Parser {
         let (infix_table, prectable) = geninfix();
         Parser {
+            lexer: Lexer::new(),
             prectable,
             prefix_table: genprefix(),
             infix_table,
@@ -69,6 +74,7 @@ impl Parser {
             i: 0,
             tokens,
             posinfo,
+            lexer: &self.lexer,
             prectable: &self.prectable,
             prefix_table: &self.prefix_table,
             infix_table: &self.infix_table,
@@ -84,6 +90,7 @@ struct ParserState<'a> {
     i: usize,
     tokens: Vec<Token<'a>>,
     posinfo: Vec<(usize, usize)>,
+    lexer: &'a Lexer,
     prectable: &'a Vec<Prec>,
     prefix_table: &'a Vec<Option<fn(&mut ParserState) -> Result<Expr>>>,
     infix_table: &'a Vec<Option<fn(&mut ParserState, Expr, Prec) -> Result<Expr>>>,
@@ -126,7 +133,7 @@ impl<'a> ParserState<'a> {
             Ok(self.gettok())
         } else {
             let mark = self.mark();
-            Err(Error::rt(
+            Err(Error::parse(
                 format!(
                     "Invalid token: expected {:?}, but got {:?}",
                     expected,
@@ -156,7 +163,7 @@ impl<'a> ParserState<'a> {
             Ok(())
         } else {
             let mark = self.mark();
-            Err(Error::rt(
+            Err(Error::parse(
                 format!("Expected delimiter, but got {:?}", self.peek().kind()).into(),
                 vec![mark],
             ))
@@ -215,7 +222,7 @@ impl<'a> ParserState<'a> {
                         let InterpretationError { offset, kind } = error;
                         let mark = self.mark();
                         let mark = Mark::new(mark.source().clone(), mark.pos() + offset, 0);
-                        Err(Error::rt(format!("{:?}", kind).into(), vec![mark]))
+                        Err(Error::parse(format!("{:?}", kind).into(), vec![mark]))
                     }
                 }
             }
@@ -245,7 +252,7 @@ impl<'a> ParserState<'a> {
             }
             _ => {
                 let mark = self.mark();
-                Err(Error::rt(
+                Err(Error::parse(
                     format!("Expected string but got {:?}", self.peek().kind()).into(),
                     vec![mark],
                 ))
@@ -253,6 +260,201 @@ impl<'a> ParserState<'a> {
         }
     }
 
+    /// Parses a format string literal into a call to 'String.format' on a
+    /// template, with the embedded expressions as the arguments
+    fn format_string(&mut self) -> Result<Expr> {
+        let mark = self.mark();
+        let body = self.peek().format_string().unwrap();
+        let (token_pos, token_lineno) = self.posinfo[self.i];
+        self.gettok();
+
+        // the body starts after the prefix and the opening quotes,
+        // and the lexer records the line a string literal ends on
+        let data = &self.source.data()[token_pos + 1..];
+        let quote_len = if data.starts_with("'''") || data.starts_with("\"\"\"") {
+            3
+        } else {
+            1
+        };
+        let start = token_pos + 1 + quote_len;
+        let start_lineno = token_lineno - body.matches('\n').count();
+        let lineno_at = |i: usize| start_lineno + body[..i].matches('\n').count();
+        let error_at = |i: usize, message: &str| {
+            let mark = Mark::new(self.source.clone(), start + i, lineno_at(i));
+            Error::parse(message.into(), vec![mark])
+        };
+
+        let mut template = String::new();
+        let mut args = Vec::new();
+        let mut literal_start = 0;
+        let mut chars = body.char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\\' => {
+                    chars.next();
+                    continue;
+                }
+                '{' | '}' => {}
+                _ => continue,
+            }
+
+            // flush the literal text before the brace
+            match interpret_string(&body[literal_start..i]) {
+                Ok(text) => template.push_str(&text.replace('{', "{{").replace('}', "}}")),
+                Err(InterpretationError { offset, kind }) => {
+                    return Err(error_at(literal_start + offset, &format!("{:?}", kind)));
+                }
+            }
+            if chars.peek().map(|(_, c2)| *c2) == Some(c) {
+                chars.next();
+                template.push(c);
+                template.push(c);
+                literal_start = i + 2;
+                continue;
+            }
+            if c == '}' {
+                return Err(error_at(i, "Single '}' in format string"));
+            }
+
+            // find the end of the embedded expression, skipping over
+            // nested brackets and string literals
+            let expr_start = i + 1;
+            let mut depth = 0;
+            let mut quote = None;
+            let expr_end = loop {
+                let (j, c) = match chars.next() {
+                    Some(pair) => pair,
+                    None => return Err(error_at(i, "Unterminated '{' in format string")),
+                };
+                match (quote, c) {
+                    (Some(_), '\\') => {
+                        chars.next();
+                    }
+                    (Some(q), c) if c == q => quote = None,
+                    (Some(_), _) => {}
+                    (None, '\'') | (None, '"') => quote = Some(c),
+                    (None, '(') | (None, '[') | (None, '{') => depth += 1,
+                    (None, ')') | (None, ']') | (None, '}') if depth > 0 => depth -= 1,
+                    (None, '}') => break j,
+                    (None, ':') if depth == 0 => break j,
+                    (None, '!')
+                        if depth == 0
+                            && matches!(body[j + 1..].chars().next(), Some('r') | Some('s'))
+                            && matches!(body[j + 2..].chars().next(), Some(':') | Some('}')) =>
+                    {
+                        break j
+                    }
+                    _ => {}
+                }
+            };
+
+            // the conversion and format spec are kept in the template
+            let mut field_end = expr_end;
+            if body[field_end..].starts_with('!') {
+                chars.next();
+                field_end += 2;
+            }
+            if body[field_end..].starts_with(':') {
+                loop {
+                    match chars.next() {
+                        Some((j, '}')) => {
+                            field_end = j;
+                            break;
+                        }
+                        Some(_) => {}
+                        None => return Err(error_at(i, "Unterminated '{' in format string")),
+                    }
+                }
+            } else if field_end > expr_end {
+                // the closing '}' after the conversion
+                chars.next();
+            }
+            template.push('{');
+            template.push_str(&body[expr_end..field_end]);
+            template.push('}');
+            literal_start = field_end + 1;
+
+            let text = &body[expr_start..expr_end];
+            let leading = text.len() - text.trim_start().len();
+            let text = text.trim();
+            if text.is_empty() {
+                return Err(error_at(i, "Empty expression in format string"));
+            }
+            args.push(self.embedded_expr(
+                text,
+                start + expr_start + leading,
+                lineno_at(expr_start + leading),
+            )?);
+        }
+        match interpret_string(&body[literal_start..]) {
+            Ok(text) => template.push_str(&text.replace('{', "{{").replace('}', "}}")),
+            Err(InterpretationError { offset, kind }) => {
+                return Err(error_at(literal_start + offset, &format!("{:?}", kind)));
+            }
+        }
+
+        if args.is_empty() {
+            let text = template.replace("{{", "{").replace("}}", "}");
+            return Ok(Expr::new(mark, ExprDesc::String(text.into())));
+        }
+        let template = Expr::new(mark.clone(), ExprDesc::String(template.into()));
+        Ok(Expr::new(
+            mark,
+            ExprDesc::CallMethod(
+                template.into(),
+                "format".into(),
+                Args::new(args, None, vec![], None),
+            ),
+        ))
+    }
+
+    /// Parses an expression embedded in a format string, that starts at the
+    /// given offset and line of the source
+    fn embedded_expr(&self, text: &'a str, offset: usize, lineno: usize) -> Result<Expr> {
+        let (tokens, posinfo) = match self.lexer.lex(text) {
+            Ok(r) => r,
+            Err(error) => {
+                let mark = Mark::new(
+                    self.source.clone(),
+                    offset + error.offset(),
+                    lineno + error.lineno() - 1,
+                );
+                return Err(Error::new_with_kind(
+                    ErrorKind::Lex,
+                    format!("{:?}", error.kind()).into(),
+                    vec![mark],
+                ));
+            }
+        };
+        let posinfo = posinfo
+            .into_iter()
+            .map(|(pos, line)| (offset + pos, lineno + line - 1))
+            .collect();
+        let mut state = ParserState {
+            source: self.source.clone(),
+            i: 0,
+            tokens,
+            posinfo,
+            lexer: self.lexer,
+            prectable: self.prectable,
+            prefix_table: self.prefix_table,
+            infix_table: self.infix_table,
+        };
+        let expr = state.expr(0)?;
+        if state.peek() != Token::EOF {
+            let mark = state.mark();
+            return Err(Error::parse(
+                format!(
+                    "Expected the end of the embedded expression, but got {:?}",
+                    state.peek().kind()
+                )
+                .into(),
+                vec![mark],
+            ));
+        }
+        Ok(expr)
+    }
+
     fn parse(&mut self) -> Result<ModuleDisplay> {
         let mark = self.mark();
 
@@ -424,7 +626,7 @@ impl<'a> ParserState<'a> {
             f(self)
         } else {
             let mark = self.mark();
-            Err(Error::rt(
+            Err(Error::parse(
                 format!("Expected expression but got {:?}", self.peek().kind()).into(),
                 vec![mark],
             ))
@@ -472,20 +674,20 @@ impl<'a> ParserState<'a> {
                     ParameterKind::Keywords
                 }
                 token => {
-                    return Err(Error::rt(
+                    return Err(Error::parse(
                         format!("Expected parameter but got {:?}", token).into(),
                         vec![mark],
                     ));
                 }
             };
             if last_kind > kind {
-                return Err(Error::rt(
+                return Err(Error::parse(
                     format!("InvalidParameterOrder").into(),
                     vec![mark],
                 ));
             }
             if !kind.multiple_allowed() && last_kind == kind {
-                return Err(Error::rt(
+                return Err(Error::parse(
                     format!("IllegalDuplicateParameterKind({:?})", kind).into(),
                     vec![mark],
                 ));
@@ -524,13 +726,13 @@ impl<'a> ParserState<'a> {
             if self.consume(TokenKind::Punctuator(Punctuator::Star)) {
                 // keyword and keyword map arguments have to come after vararg arguments
                 if key.len() > 0 || kwtable.is_some() {
-                    return Err(Error::rt(
+                    return Err(Error::parse(
                         format!("Illegal argument order").into(),
                         vec![mark],
                     ));
                 }
                 if variadic.is_some() {
-                    return Err(Error::rt(
+                    return Err(Error::parse(
                         format!("Multiple variadic arguments are not allowed").into(),
                         vec![mark],
                     ));
@@ -538,7 +740,7 @@ impl<'a> ParserState<'a> {
                 variadic = Some(self.expr(0)?);
             } else if self.consume(TokenKind::Punctuator(Punctuator::Star2)) {
                 if kwtable.is_some() {
-                    return Err(Error::rt(
+                    return Err(Error::parse(
                         format!("Multiple keyword talbe arguments are not allowed").into(),
                         vec![mark],
                     ));
@@ -550,7 +752,7 @@ impl<'a> ParserState<'a> {
             {
                 // kwtables have to come after vararg arguments
                 if kwtable.is_some() {
-                    return Err(Error::rt(
+                    return Err(Error::parse(
                         format!("Illegal argument order").into(),
                         vec![mark],
                     ));
@@ -562,7 +764,7 @@ impl<'a> ParserState<'a> {
             } else {
                 // keyword, variadic and kwtables have to come after vararg arguments
                 if !key.is_empty() || variadic.is_some() || kwtable.is_some() {
-                    return Err(Error::rt(
+                    return Err(Error::parse(
                         format!("Illegal argument order").into(),
                         vec![mark],
                     ));
@@ -602,7 +804,7 @@ fn genprefix() -> Vec<Option<fn(&mut ParserState) -> Result<Expr>>> {
         }),
         (&["Int"], |state: &mut ParserState| {
             let value = state.peek().int().unwrap();
-            mk1tokexpr(state, ExprDesc::Number(value as f64))
+            mk1tokexpr(state, ExprDesc::Int(value))
         }),
         (&["Float"], |state: &mut ParserState| {
             let value = state.peek().float().unwrap();
@@ -616,8 +818,32 @@ fn genprefix() -> Vec<Option<fn(&mut ParserState) -> Result<Expr>>> {
                 Ok(Expr::new(mark, ExprDesc::String(s.into())))
             },
         ),
+        (&["FormatString"], |state: &mut ParserState| {
+            state.format_string()
+        }),
         (&["Name"], |state: &mut ParserState| {
             let name = state.peek().name().unwrap();
+
+            // a labeled loop, e.g. 'outer: for x in xs { .. }'
+            if state.peek1() == Some(Token::Punctuator(Punctuator::Colon))
+                && matches!(
+                    state.tokens.get(state.i + 2),
+                    Some(Token::Punctuator(Punctuator::For))
+                        | Some(Token::Punctuator(Punctuator::While))
+                )
+            {
+                state.gettok();
+                state.gettok();
+                let mut expr = state.prefix()?;
+                match expr.desc_mut() {
+                    ExprDesc::For(_, _, _, label) | ExprDesc::While(_, _, label) => {
+                        *label = Some(name.into());
+                    }
+                    _ => panic!("Labeled loop parsed as {:?}", expr),
+                }
+                return Ok(expr);
+            }
+
             mk1tokexpr(state, ExprDesc::Name(name.into()))
         }),
         (&["{"], |state: &mut ParserState| state.block()),
@@ -716,10 +942,13 @@ fn genprefix() -> Vec<Option<fn(&mut ParserState) -> Result<Expr>>> {
                     state.expect(TokenKind::Punctuator(Punctuator::RBrace))?;
                     break;
                 } else {
-                    let mut match_ = vec![state.expr(0)?];
+                    // '|' separates alternatives, so each one is parsed
+                    // at a precedence that stops before a bitwise or
+                    let prec = state.prec(TokenKind::Punctuator(Punctuator::VerticalBar));
+                    let mut match_ = vec![state.expr(prec)?];
                     while state.consume(TokenKind::Punctuator(Punctuator::VerticalBar)) {
                         state.skip_delim();
-                        match_.push(state.expr(0)?);
+                        match_.push(state.expr(prec)?);
                     }
                     state.expect(TokenKind::Punctuator(Punctuator::Arrow))?;
                     let body = state.expr(0)?;
@@ -741,21 +970,70 @@ fn genprefix() -> Vec<Option<fn(&mut ParserState) -> Result<Expr>>> {
             state.expect(TokenKind::Punctuator(Punctuator::In))?;
             let iterable = state.expr(0)?.into();
             let body = state.block()?.into();
-            Ok(Expr::new(mark, ExprDesc::For(target, iterable, body)))
+            Ok(Expr::new(mark, ExprDesc::For(target, iterable, body, None)))
         }),
         (&["while"], |state: &mut ParserState| {
             let mark = state.mark();
             state.gettok();
             let cond = state.expr(0)?.into();
             let body = state.block()?.into();
-            Ok(Expr::new(mark, ExprDesc::While(cond, body)))
+            Ok(Expr::new(mark, ExprDesc::While(cond, body, None)))
+        }),
+        (&["break", "continue"], |state: &mut ParserState| {
+            let mark = state.mark();
+            let is_break = state.gettok() == Token::Punctuator(Punctuator::Break);
+            let label = if state.peek().kind() == TokenKind::Name {
+                Some(state.expect_name()?.into())
+            } else {
+                None
+            };
+            Ok(Expr::new(
+                mark,
+                if is_break {
+                    ExprDesc::Break(label)
+                } else {
+                    ExprDesc::Continue(label)
+                },
+            ))
+        }),
+        (&["try"], |state: &mut ParserState| {
+            let mark = state.mark();
+            state.gettok();
+            let body = state.block()?.into();
+            let mut clauses = Vec::new();
+            while state.consume(TokenKind::Punctuator(Punctuator::Catch)) {
+                let name = if state.peek().kind() == TokenKind::Name {
+                    Some(state.expect_name()?.into())
+                } else {
+                    None
+                };
+                let cls = if state.consume(TokenKind::Punctuator(Punctuator::Colon)) {
+                    Some(state.expr(0)?)
+                } else {
+                    None
+                };
+                let body = state.block()?;
+                clauses.push(CatchClause { name, cls, body });
+            }
+            let finally = if state.consume(TokenKind::Punctuator(Punctuator::Finally)) {
+                Some(state.block()?.into())
+            } else {
+                None
+            };
+            if clauses.is_empty() && finally.is_none() {
+                return Err(Error::parse(
+                    "A try block requires a catch or finally clause".into(),
+                    vec![mark],
+                ));
+            }
+            Ok(Expr::new(mark, ExprDesc::Try(body, clauses, finally)))
         }),
         (&["new"], |state: &mut ParserState| {
             let mark = state.mark();
             state.gettok();
             let args = state.args()?;
             if args.args.len() > 0 {
-                return Err(Error::rt(
+                return Err(Error::parse(
                     format!("The new operator cannot take positional arguments").into(),
                     vec![mark],
                 ));
@@ -907,7 +1185,7 @@ fn genprefix() -> Vec<Option<fn(&mut ParserState) -> Result<Expr>>> {
                             let (name, member) = match break_assignment(stmt) {
                                 Some((name, member)) => (name, member),
                                 None => {
-                                    return Err(Error::rt(
+                                    return Err(Error::parse(
                                         format!("Expected class member").into(),
                                         vec![mark],
                                     ))
@@ -965,11 +1243,12 @@ fn genprefix() -> Vec<Option<fn(&mut ParserState) -> Result<Expr>>> {
             };
             Ok(assign_name(alias, field_applied))
         }),
-        (&["-", "+", "!"], |state| {
+        (&["-", "+", "!", "~"], |state| {
             let op = match state.peek().kind() {
                 TokenKind::Punctuator(Punctuator::Plus) => Unop::Pos,
                 TokenKind::Punctuator(Punctuator::Minus) => Unop::Neg,
                 TokenKind::Punctuator(Punctuator::Exclamation) => Unop::Not,
+                TokenKind::Punctuator(Punctuator::Tilde) => Unop::Invert,
                 _ => panic!("{:?}", state.peek()),
             };
             // the '+/-' unary operators should bind less tightly than
@@ -1023,7 +1302,10 @@ fn geninfix() -> (
                 ))
             }),
             (
-                &["+=", "-=", "*=", "/=", "//=", "%=", "%%=", "**="],
+                &[
+                    "+=", "-=", "*=", "/=", "//=", "%=", "%%=", "**=", "&=", "|=", "^=", "<<=",
+                    ">>=",
+                ],
                 |state, lhs, prec| {
                     let mark = state.mark();
                     let op = match state.gettok() {
@@ -1035,6 +1317,11 @@ fn geninfix() -> (
                         Token::Punctuator(Punctuator::RemEq) => Binop::Rem,
                         Token::Punctuator(Punctuator::Rem2Eq) => Binop::ERem,
                         Token::Punctuator(Punctuator::Star2Eq) => Binop::Pow,
+                        Token::Punctuator(Punctuator::AmpersandEq) => Binop::BitAnd,
+                        Token::Punctuator(Punctuator::VerticalBarEq) => Binop::BitOr,
+                        Token::Punctuator(Punctuator::CaretEq) => Binop::BitXor,
+                        Token::Punctuator(Punctuator::ShlEq) => Binop::Shl,
+                        Token::Punctuator(Punctuator::ShrEq) => Binop::Shr,
                         tok => panic!("Unhandled augassign binop {:?}", tok),
                     };
                     let rhs = state.expr(prec - 1)?;
@@ -1093,6 +1380,23 @@ fn geninfix() -> (
                 Ok(Expr::new(mark, ExprDesc::Binop(op, lhs.into(), rhs.into())))
             }),
         ],
+        &[(&["|"], |state, lhs, prec| {
+            mkbinop(state, lhs, prec, Binop::BitOr)
+        })],
+        &[(&["^"], |state, lhs, prec| {
+            mkbinop(state, lhs, prec, Binop::BitXor)
+        })],
+        &[(&["&"], |state, lhs, prec| {
+            mkbinop(state, lhs, prec, Binop::BitAnd)
+        })],
+        &[
+            (&["<<"], |state, lhs, prec| {
+                mkbinop(state, lhs, prec, Binop::Shl)
+            }),
+            (&[">>"], |state, lhs, prec| {
+                mkbinop(state, lhs, prec, Binop::Shr)
+            }),
+        ],
         &[
             (&["+"], |state, lhs, prec| {
                 mkbinop(state, lhs, prec, Binop::Add)
@@ -1175,7 +1479,14 @@ fn geninfix() -> (
                 if state.consume(TokenKind::Punctuator(Punctuator::Await)) {
                     Ok(Expr::new(mark, ExprDesc::Await(lhs.into())))
                 } else {
-                    let name = state.expect_name()?;
+                    // keywords are allowed as attribute names (e.g. 'promise.catch')
+                    let name = match state.peek() {
+                        Token::Punctuator(p) if p.str().chars().all(char::is_alphabetic) => {
+                            state.gettok();
+                            p.str()
+                        }
+                        _ => state.expect_name()?,
+                    };
                     Ok(Expr::new(mark, ExprDesc::Attr(lhs.into(), name.into())))
                 }
             }),
@@ -1425,13 +1736,17 @@ fn to_constval(expr: Expr) -> Result<ConstVal> {
         ExprDesc::Nil => Ok(ConstVal::Nil),
         ExprDesc::Bool(b) => Ok(ConstVal::Bool(b)),
         ExprDesc::Number(x) => Ok(ConstVal::Number(x)),
+        ExprDesc::Int(x) => Ok(ConstVal::Int(x)),
         ExprDesc::String(x) => Ok(ConstVal::String(x.clone())),
         ExprDesc::List(list) => Ok(ConstVal::List(
             list.into_iter()
                 .map(to_constval)
                 .collect::<Result<Vec<_>>>()?,
         )),
-        _ => Err(Error::rt("Expected constant expression".into(), vec![mark])),
+        _ => Err(Error::parse(
+            "Expected constant expression".into(),
+            vec![mark],
+        )),
     }
 }
 
@@ -1445,7 +1760,7 @@ fn to_target(expr: Expr) -> Result<AssignTarget> {
         ExprDesc::Attr(owner, name) => AssignTargetDesc::Attr(owner, name),
         ExprDesc::Subscript(owner, index) => AssignTargetDesc::Subscript(owner, index),
         _ => {
-            return Err(Error::rt(
+            return Err(Error::parse(
                 "The target expression is not assignable".into(),
                 vec![mark],
             ))
@@ -6,6 +6,7 @@ pub enum Token<'a> {
     NormalString(&'a str),
     RawString(&'a str),
     LineString(&'a str),
+    FormatString(&'a str),
     Float(f64),
     Int(i64),
     Name(&'a str),
@@ -21,6 +22,7 @@ impl<'a> Token<'a> {
             Token::NormalString(_) => TokenKind::NormalString,
             Token::RawString(_) => TokenKind::RawString,
             Token::LineString(_) => TokenKind::LineString,
+            Token::FormatString(_) => TokenKind::FormatString,
             Token::Float(_) => TokenKind::Float,
             Token::Int(_) => TokenKind::Int,
             Token::Name(_) => TokenKind::Name,
@@ -54,6 +56,14 @@ impl<'a> Token<'a> {
         }
     }
 
+    pub fn format_string(&self) -> Option<&'a str> {
+        if let Token::FormatString(s) = self {
+            Some(s)
+        } else {
+            None
+        }
+    }
+
     pub fn float(&self) -> Option<f64> {
         if let Token::Float(x) = self {
             Some(*x)
@@ -101,6 +111,7 @@ pub enum TokenKind {
     NormalString,
     RawString,
     LineString,
+    FormatString,
     Float,
     Int,
     Name,
@@ -110,7 +121,7 @@ pub enum TokenKind {
 }
 
 impl TokenKind {
-    pub const LEN: usize = 9 + Punctuator::LIST.len();
+    pub const LEN: usize = 10 + Punctuator::LIST.len();
 
     pub fn id(&self) -> usize {
         match self {
@@ -123,7 +134,8 @@ impl TokenKind {
             TokenKind::Name => 6,
             TokenKind::Symbol => 7,
             TokenKind::EOF => 8,
-            TokenKind::Punctuator(punctuator) => 9 + (*punctuator as usize),
+            TokenKind::FormatString => 9,
+            TokenKind::Punctuator(punctuator) => 10 + (*punctuator as usize),
         }
     }
 
@@ -133,6 +145,7 @@ impl TokenKind {
             TokenKind::NormalString => "NormalString",
             TokenKind::RawString => "RawString",
             TokenKind::LineString => "LineString",
+            TokenKind::FormatString => "FormatString",
             TokenKind::Float => "Float",
             TokenKind::Int => "Int",
             TokenKind::Name => "Name",
@@ -148,6 +161,7 @@ impl TokenKind {
             "NormalString" => TokenKind::NormalString,
             "RawString" => TokenKind::RawString,
             "LineString" => TokenKind::LineString,
+            "FormatString" => TokenKind::FormatString,
             "Float" => TokenKind::Float,
             "Int" => TokenKind::Int,
             "Name" => TokenKind::Name,
@@ -195,6 +209,7 @@ define_punctuators! {
     Await "await",
     Break "break",
     Case "case",
+    Catch "catch",
     Class "class",
     Continue "continue",
     Def "def",
@@ -204,6 +219,7 @@ define_punctuators! {
     Except "except",
     False "false",
     Final "final",
+    Finally "finally",
     For "for",
     From "from",
     If "if",
@@ -248,6 +264,11 @@ define_punctuators! {
     SlashEq "/=",
     DoubleSlashEq "//=",
     Star2Eq "**=",
+    AmpersandEq "&=",
+    VerticalBarEq "|=",
+    CaretEq "^=",
+    ShlEq "<<=",
+    ShrEq ">>=",
     Slash "/",
     DoubleSlash "//",
     Lt "<",
@@ -259,6 +280,11 @@ define_punctuators! {
     Ne "!=",
     Exclamation "!",
     VerticalBar "|",
+    Ampersand "&",
+    Caret "^",
+    Tilde "~",
+    Shl "<<",
+    Shr ">>",
     LParen "(",
     RParen ")",
     LBracket "[",
@@ -43,14 +43,16 @@ impl Globals {
                 vec.reverse();
                 Ok(vec.into())
             }),
-            NativeFunction::new("abs", ["x"], None, |_globals, args, _| {
-                let mut args = args.into_iter();
-                let x = args.next().unwrap().number()?;
-                Ok(x.abs().into())
+            NativeFunction::new("abs", ["x"], None, |_globals, args, _| match &args[0] {
+                Value::Int(x) => match x.checked_abs() {
+                    Some(x) => Ok(x.into()),
+                    None => Err(rterr!("Integer overflow (abs({}))", x)),
+                },
+                x => Ok(x.number()?.abs().into()),
             }),
             NativeFunction::new("sorted", ["iterable"], None, |globals, args, _| {
                 let mut vec = args.into_iter().next().unwrap().unpack(globals)?;
-                List::sort(&mut vec)?;
+                List::sort(globals, &mut vec)?;
                 Ok(vec.into())
             }),
             NativeFunction::new(
@@ -61,6 +63,12 @@ impl Globals {
                     .def("step", 1),
                 None,
                 |_globals, args, _| {
+                    if args
+                        .iter()
+                        .all(|arg| matches!(arg, Value::Int(_) | Value::Nil))
+                    {
+                        return int_range(args);
+                    }
                     let args1_is_nil = args[1].is_nil();
                     let mut args = args.into_iter();
                     let (mut start, stop) = if args1_is_nil {
@@ -206,7 +214,7 @@ impl Globals {
                 cls.map()
                     .get(&name)
                     .cloned()
-                    .ok_or_else(|| rterr!("Method {:?} not found for {:?}", name, cls))
+                    .ok_or_else(|| kinderr!(Attribute, "Method {:?} not found for {:?}", name, cls))
             }),
             NativeFunction::new("getmethods", ["cls"], None, |_globals, args, _| {
                 let mut args = args.into_iter();
@@ -219,59 +227,17 @@ impl Globals {
             }),
             NativeFunction::new(
                 "throw",
-                ["message"],
+                ["exception"],
                 concat!(
-                    "Makeshift function for throwing runtime exceptions\n",
-                    "API of this function is likely to change a lot in the future\n",
-                ),
-                |_globals, args, _| {
-                    let mut args = args.into_iter();
-                    let error = Error::try_from(args.next().unwrap())?;
-                    Err(error)
-                },
-            ),
-            NativeFunction::new(
-                "pcall",
-                ["f", "on_error"],
-                concat!(
-                    "Makeshift function for handling exceptions\n",
-                    "Calls the function passed as the first argument.\n",
-                    "If it finishes without any errors, this function will return that value.\n",
-                    "If it throws, the second argument is called with the exception information ",
-                    "and whatever is returned from it is returned.\n",
-                    "API of this function is likely to change a lot in the future\n",
+                    "Throws the given exception.\n",
+                    "The exception should be an instance of some subclass of BaseException, ",
+                    "but a message string is also accepted, and is thrown as a RuntimeError\n",
                 ),
                 |globals, args, _| {
-                    let trace_len = globals.trace().len();
                     let mut args = args.into_iter();
-                    let f = args.next().unwrap();
-                    let on_error = args.next().unwrap();
-                    match f.apply(globals, vec![], None) {
-                        Ok(value) => Ok(value),
-                        Err(error) => {
-                            // we wait on unwinding the stack trace, so that the stack
-                            // can be inspected from inside the error handler
-                            // If we error from inside the handler, we let the
-                            // trace accumulate.
-                            let value = Value::from(error);
-                            // We also put in an extra trace element
-                            // so that we get an indication if an error occurred inside
-                            // the error handler
-                            globals.trace_push(Mark::new(
-                                Source::new(
-                                    "[pcall]".into(),
-                                    None,
-                                    "<while handling error...>".into(),
-                                )
-                                .into(),
-                                0,
-                                0,
-                            ));
-                            let result = on_error.apply(globals, vec![value], None)?;
-                            globals.trace_unwind(trace_len);
-                            Ok(result)
-                        }
-                    }
+                    let exception = args.next().unwrap();
+                    let error = globals.class_manager().error_from_exception(exception)?;
+                    Err(error)
                 },
             ),
             NativeFunction::new("hash", ["x"], None, |_globals, args, _| {
@@ -283,7 +249,7 @@ impl Globals {
                 let key = Key::try_from(x)?;
                 let mut s = DefaultHasher::new();
                 key.hash(&mut s);
-                Ok(Value::from(s.finish()))
+                Ok(Value::from(s.finish() as i64))
             }),
             NativeFunction::new("type", ["x"], None, |globals, args, _| {
                 let mut args = args.into_iter();
@@ -322,7 +288,7 @@ impl Globals {
                         (curr, args)
                     };
                     for arg in args {
-                        if best.lt(&arg)? {
+                        if best.lt_(globals, &arg)? {
                             best = arg;
                         }
                     }
@@ -351,7 +317,7 @@ impl Globals {
                         (curr, args)
                     };
                     for arg in args {
-                        if arg.lt(&best)? {
+                        if arg.lt_(globals, &best)? {
                             best = arg;
                         }
                     }
@@ -363,12 +329,34 @@ impl Globals {
                 let name = name.string()?;
                 Ok(globals.load(name)?.into())
             }),
-            NativeFunction::new("__disasm", ["func"], None, |_globals, args, _| {
-                let func = args.into_iter().next().unwrap();
-                let func = func.function()?;
-                let string = func.code().disasm()?;
-                Ok(Value::from(string))
-            }),
+            NativeFunction::new(
+                "__disasm",
+                ArgSpec::builder().req("x").def("opt_level", ()),
+                concat!(
+                    "Disassembles a function, or a string of source code compiled ",
+                    "at the given optimization level (by default, the current one)",
+                ),
+                |globals, args, _| {
+                    let mut args = args.into_iter();
+                    let x = args.next().unwrap();
+                    let opt_level = args.next().unwrap();
+                    let string = match x {
+                        Value::String(data) => {
+                            let opt_level = if opt_level.is_nil() {
+                                globals.opt_level()
+                            } else {
+                                opt_level.u8()?
+                            };
+                            let source = Source::new("[disasm]".into(), None, data);
+                            let mut display = globals.parse(source.into())?;
+                            annotate(&mut display)?;
+                            compile_with_opt_level(&display, opt_level)?.disasm()?
+                        }
+                        _ => x.function()?.code().disasm()?,
+                    };
+                    Ok(Value::from(string))
+                },
+            ),
             NativeFunction::new(
                 "__main",
                 (),
@@ -394,3 +382,26 @@ impl Globals {
         map
     }
 }
+
+/// 'range' where all the arguments are ints
+fn int_range(args: Vec<Value>) -> Result<Value> {
+    let (mut start, stop) = if args[1].is_nil() {
+        (0, args[0].int()?)
+    } else {
+        (args[0].int()?, args[1].int()?)
+    };
+    let step = args[2].int()?;
+    if step == 0 {
+        return Err(rterr!("Invalid range step: {}", step));
+    }
+    Ok(NativeGenerator::new("range", move |_globals, _| {
+        if (step > 0 && start < stop) || (step < 0 && start > stop) {
+            let x = start;
+            start = start.saturating_add(step);
+            ResumeResult::Yield(Value::from(x))
+        } else {
+            ResumeResult::Return(Value::Nil)
+        }
+    })
+    .into())
+}
@@ -0,0 +1,61 @@
+use super::*;
+
+pub(super) fn new() -> Rc<Class> {
+    Class::new_with_behavior(
+        "CancelToken".into(),
+        Class::map_from_funcs(vec![
+            NativeFunction::new(
+                "cancel",
+                ArgSpec::builder().req("self").def("message", ()),
+                concat!(
+                    "Cancels every operation this token was passed to, which then fail ",
+                    "with a CancelledError with the given message.\n",
+                    "Cancelling a token more than once has no effect",
+                ),
+                |globals, args, _| {
+                    let mut args = args.into_iter();
+                    let owner = args.next().unwrap().into_handle::<CancelToken>()?;
+                    let reason = match args.next().unwrap() {
+                        Value::Nil => None,
+                        message => Some(kinderr!(Cancelled, "{}", message.into_string()?)),
+                    };
+                    let token = owner.borrow().clone();
+                    token.cancel(globals, reason);
+                    Ok(Value::Nil)
+                },
+            ),
+            NativeFunction::new(
+                "cancelled",
+                ["self"],
+                "Checks whether this token has been cancelled",
+                |_globals, args, _| {
+                    let owner = args.into_iter().next().unwrap();
+                    let owner = owner.into_handle::<CancelToken>()?;
+                    let cancelled = owner.borrow().is_cancelled();
+                    Ok(cancelled.into())
+                },
+            ),
+            NativeFunction::new(
+                "check",
+                ["self"],
+                "Throws the CancelledError of this token if it has been cancelled",
+                |_globals, args, _| {
+                    let owner = args.into_iter().next().unwrap();
+                    let owner = owner.into_handle::<CancelToken>()?;
+                    let result = owner.borrow().check();
+                    result.map(|()| Value::Nil)
+                },
+            ),
+        ]),
+        Class::map_from_funcs(vec![NativeFunction::new(
+            "__call",
+            (),
+            concat!(
+                "Creates a new token, that can be passed to async functions ",
+                "to be able to cancel them later",
+            ),
+            |globals, _args, _| Ok(globals.new_handle(CancelToken::new())?.into()),
+        )]),
+        Some(Behavior::builder_for_handle::<CancelToken>().build()),
+    )
+}
@@ -0,0 +1,111 @@
+use super::*;
+
+/// Creates one of the builtin exception classes.
+/// Instances are plain tables with a 'message' field, so that script
+/// classes can subclass these and add their own fields.
+/// Instances also have 'kind' and 'io_kind' fields describing the ErrorKind
+/// of the native error they were created from (nil when created from a script)
+pub(super) fn new(name: &'static str, base: Option<&Rc<Class>>) -> Rc<Class> {
+    Class::new_with_bases(
+        name.into(),
+        base.into_iter().cloned().collect(),
+        HashMap::new(),
+        Class::map_from_funcs(vec![NativeFunction::new(
+            "__call",
+            ArgSpec::builder().def("message", ""),
+            None,
+            move |globals, args, _| {
+                let message = args.into_iter().next().unwrap();
+                let cls = globals.class_manager().exception_class(name).unwrap();
+                Ok(new_exception(cls.clone(), message, Value::Nil, Value::Nil))
+            },
+        )]),
+    )
+}
+
+fn new_exception(cls: Rc<Class>, message: Value, kind: Value, io_kind: Value) -> Value {
+    let mut map = HashMap::new();
+    map.insert("message".into(), RefCell::new(message));
+    map.insert("kind".into(), RefCell::new(kind));
+    map.insert("io_kind".into(), RefCell::new(io_kind));
+    Table::new(cls, map).into()
+}
+
+impl ClassManager {
+    /// Looks up a builtin exception class by name
+    pub fn exception_class(&self, name: &str) -> Option<&Rc<Class>> {
+        match name {
+            "BaseException" => Some(&self.BaseException),
+            "Exception" => Some(&self.Exception),
+            "RuntimeError" => Some(&self.RuntimeError),
+            "TypeError" => Some(&self.TypeError),
+            "AttributeError" => Some(&self.AttributeError),
+            "ArgumentError" => Some(&self.ArgumentError),
+            "ParseError" => Some(&self.ParseError),
+            "NotFoundError" => Some(&self.NotFoundError),
+            "CancelledError" => Some(&self.CancelledError),
+            "OSError" | "IOError" => Some(&self.OSError),
+            _ => None,
+        }
+    }
+
+    /// Checks whether the given value is an instance of some exception class
+    pub fn is_exception(&self, value: &Value) -> bool {
+        match value {
+            Value::Table(table) => table.cls().is_subclass_of(&self.BaseException),
+            _ => false,
+        }
+    }
+
+    /// Gets the exception value to present to a catch block for the given error.
+    /// If the error was thrown with an exception instance, that instance is returned.
+    /// Otherwise, a new instance of the builtin exception class matching
+    /// the error's kind is created
+    pub fn exception_from_error(&self, error: &Error) -> Value {
+        let cls = match error.kind() {
+            ErrorKind::User(value) => return value.clone(),
+            ErrorKind::Runtime => self
+                .exception_class(error.type_())
+                .unwrap_or(&self.RuntimeError),
+            ErrorKind::Io(_) => &self.OSError,
+            ErrorKind::Type => &self.TypeError,
+            ErrorKind::Attribute => &self.AttributeError,
+            ErrorKind::Argument => &self.ArgumentError,
+            ErrorKind::Parse | ErrorKind::Lex => &self.ParseError,
+            ErrorKind::NotFound => &self.NotFoundError,
+            ErrorKind::Cancelled => &self.CancelledError,
+            ErrorKind::Trampoline | ErrorKind::DebuggerQuit => &self.RuntimeError,
+        };
+        let io_kind = match error.kind() {
+            ErrorKind::Io(kind) => format!("{:?}", kind).into(),
+            _ => Value::Nil,
+        };
+        new_exception(
+            cls.clone(),
+            error.message().clone().into(),
+            error.kind().name().into(),
+            io_kind,
+        )
+    }
+
+    /// Converts a value passed to 'throw' into an Error.
+    /// Exception instances are attached to the error so that they can be
+    /// caught again as is. For backwards compatibility, a message string or
+    /// a [type, message] pair is also accepted
+    pub fn error_from_exception(&self, value: Value) -> Result<Error> {
+        match &value {
+            Value::Table(table) if self.is_exception(&value) => {
+                let message = match table.map().get("message") {
+                    Some(message) => message.borrow().clone().convert_to_rcstr(),
+                    None => "".into(),
+                };
+                Ok(Error::new_with_kind(
+                    ErrorKind::User(value.clone()),
+                    message,
+                    vec![],
+                ))
+            }
+            _ => Error::try_from(value),
+        }
+    }
+}
@@ -235,7 +235,7 @@ fn contains(globals: &mut Globals, owner: Value, item: Value) -> Result<bool> {
     loop {
         match owner.resume(globals, Value::Nil) {
             ResumeResult::Yield(value) => {
-                if item == value {
+                if item.eq_(globals, &value)? {
                     return Ok(true);
                 }
             }
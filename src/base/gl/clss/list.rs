@@ -35,19 +35,15 @@ pub(super) fn new(iterable: &Rc<Class>) -> Rc<Class> {
                         .req("x")
                         .def("default", ConstVal::Invalid),
                     None,
-                    |_globals, args, _| {
+                    |globals, args, _| {
                         let mut args = args.into_iter();
                         let owner = args.next().unwrap().into_list()?;
-                        let owner = owner.borrow();
                         let x = args.next().unwrap();
                         let default = args.next().unwrap();
-                        for (i, t) in owner.iter().enumerate() {
-                            if x == *t {
-                                return Ok(i.into());
-                            }
-                        }
-                        if let Value::Invalid = default {
-                            Err(rterr!("Item not found in list"))
+                        if let Some(i) = owner.position(globals, &x)? {
+                            Ok(i.into())
+                        } else if let Value::Invalid = default {
+                            Err(kinderr!(NotFound, "Item not found in list"))
                         } else {
                             Ok(default)
                         }
@@ -84,11 +80,16 @@ pub(super) fn new(iterable: &Rc<Class>) -> Rc<Class> {
                     owner.reverse();
                     Ok(Value::Nil)
                 }),
-                NativeFunction::new("sort", ["self"], None, |_globals, args, _| {
+                NativeFunction::new("sort", ["self"], None, |globals, args, _| {
                     let mut args = args.into_iter();
                     let owner = args.next().unwrap().into_list()?;
-                    let mut owner = owner.borrow_mut();
-                    List::sort(&mut owner)?;
+
+                    // the list is not borrowed while sorting, since comparisons
+                    // may call back into the script
+                    let mut vec = std::mem::take(&mut *owner.borrow_mut());
+                    let r = List::sort(globals, &mut vec);
+                    *owner.borrow_mut() = vec;
+                    r?;
                     Ok(Value::Nil)
                 }),
                 NativeFunction::new("__mul", ["self", "n"], "", |_globals, args, _| {
@@ -180,27 +181,17 @@ pub(super) fn new(iterable: &Rc<Class>) -> Rc<Class> {
                     }
                     Ok(ret.into())
                 }),
-                NativeFunction::new("__contains", ["self", "x"], None, |_globals, args, _| {
+                NativeFunction::new("__contains", ["self", "x"], None, |globals, args, _| {
                     let mut args = args.into_iter();
                     let owner = args.next().unwrap().into_list()?;
                     let x = args.next().unwrap();
-                    for value in owner.borrow().iter() {
-                        if &x == value {
-                            return Ok(true.into());
-                        }
-                    }
-                    Ok(false.into())
+                    Ok(owner.position(globals, &x)?.is_some().into())
                 }),
-                NativeFunction::new("has", ["self", "x"], None, |_globals, args, _| {
+                NativeFunction::new("has", ["self", "x"], None, |globals, args, _| {
                     let mut args = args.into_iter();
                     let owner = args.next().unwrap().into_list()?;
                     let x = args.next().unwrap();
-                    for value in owner.borrow().iter() {
-                        if &x == value {
-                            return Ok(true.into());
-                        }
-                    }
-                    Ok(false.into())
+                    Ok(owner.position(globals, &x)?.is_some().into())
                 }),
                 NativeFunction::new(
                     "splice",
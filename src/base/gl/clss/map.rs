@@ -27,19 +27,29 @@ pub(super) fn new(iterable: &Rc<Class>) -> Rc<Class> {
                         if let Some(value) = owner.get(&key) {
                             Ok(value.clone())
                         } else if let Value::Invalid = default {
-                            Err(rterr!("Key {:?} not found in map", key))
+                            Err(kinderr!(NotFound, "Key {:?} not found in map", key))
                         } else {
                             Ok(default)
                         }
                     },
                 ),
-                NativeFunction::new("has_key", ["self", "key"], "", |_globals, args, _| {
+                NativeFunction::new("has_key", ["self", "key"], "", |globals, args, _| {
                     let mut args = args.into_iter();
                     let owner = args.next().unwrap().into_map()?;
-                    let owner = owner.borrow();
-                    let key = Key::try_from(args.next().unwrap())?;
-                    Ok(owner.contains_key(&key).into())
+                    let key = args.next().unwrap();
+                    Ok(owner.has_key(globals, &key)?.into())
                 }),
+                NativeFunction::new(
+                    "__contains",
+                    ["self", "key"],
+                    "Checks whether the given key is in this map",
+                    |globals, args, _| {
+                        let mut args = args.into_iter();
+                        let owner = args.next().unwrap().into_map()?;
+                        let key = args.next().unwrap();
+                        Ok(owner.has_key(globals, &key)?.into())
+                    },
+                ),
             ]),
             vec![iterable],
         ),
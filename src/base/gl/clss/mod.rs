@@ -1,4 +1,6 @@
 use super::*;
+mod cancel;
+mod exc;
 mod iter;
 mod iterble;
 mod list;
@@ -14,6 +16,7 @@ pub struct ClassManager {
     pub Nil: Rc<Class>,
     pub Bool: Rc<Class>,
     pub Number: Rc<Class>,
+    pub Int: Rc<Class>,
     pub Iterable: Rc<Class>,
     pub String: Rc<Class>,
     pub List: Rc<Class>,
@@ -25,8 +28,20 @@ pub struct ClassManager {
     pub Generator: Rc<Class>,
     pub NativeGenerator: Rc<Class>,
     pub Promise: Rc<Class>,
+    pub CancelToken: Rc<Class>,
     pub Class: Rc<Class>,
     pub Module: Rc<Class>,
+
+    pub BaseException: Rc<Class>,
+    pub Exception: Rc<Class>,
+    pub RuntimeError: Rc<Class>,
+    pub TypeError: Rc<Class>,
+    pub AttributeError: Rc<Class>,
+    pub ArgumentError: Rc<Class>,
+    pub ParseError: Rc<Class>,
+    pub NotFoundError: Rc<Class>,
+    pub CancelledError: Rc<Class>,
+    pub OSError: Rc<Class>,
 }
 
 impl ClassManager {
@@ -35,6 +50,7 @@ impl ClassManager {
         let Nil = nil::new();
         let Bool = Class::new("Bool".into(), HashMap::new(), HashMap::new());
         let Number = num::new();
+        let Int = num::new_int(&Number);
         let Iterable = iterble::new();
         let String = strcls::new();
         let List = list::new(&Iterable);
@@ -80,12 +96,24 @@ impl ClassManager {
             HashMap::new(),
         );
         let Promise = promise::new();
+        let CancelToken = cancel::new();
         let Class = Class::new("Class".into(), HashMap::new(), HashMap::new());
         let Module = Class::new("Module".into(), HashMap::new(), HashMap::new());
+        let BaseException = exc::new("BaseException", None);
+        let Exception = exc::new("Exception", Some(&BaseException));
+        let RuntimeError = exc::new("RuntimeError", Some(&Exception));
+        let TypeError = exc::new("TypeError", Some(&RuntimeError));
+        let AttributeError = exc::new("AttributeError", Some(&RuntimeError));
+        let ArgumentError = exc::new("ArgumentError", Some(&RuntimeError));
+        let ParseError = exc::new("ParseError", Some(&RuntimeError));
+        let NotFoundError = exc::new("NotFoundError", Some(&RuntimeError));
+        let CancelledError = exc::new("CancelledError", Some(&Exception));
+        let OSError = exc::new("OSError", Some(&Exception));
         Self {
             Nil,
             Bool,
             Number,
+            Int,
             Iterable,
             String,
             List,
@@ -97,8 +125,19 @@ impl ClassManager {
             Generator,
             NativeGenerator,
             Promise,
+            CancelToken,
             Class,
             Module,
+            BaseException,
+            Exception,
+            RuntimeError,
+            TypeError,
+            AttributeError,
+            ArgumentError,
+            ParseError,
+            NotFoundError,
+            CancelledError,
+            OSError,
         }
     }
 
@@ -108,6 +147,7 @@ impl ClassManager {
             Value::Nil => &self.Nil,
             Value::Bool(..) => &self.Bool,
             Value::Number(..) => &self.Number,
+            Value::Int(..) => &self.Int,
             Value::String(..) => &self.String,
             Value::List(..) => &self.List,
             Value::Set(..) => &self.Set,
@@ -129,6 +169,7 @@ impl ClassManager {
             &self.Nil,
             &self.Bool,
             &self.Number,
+            &self.Int,
             &self.Iterable,
             &self.String,
             &self.List,
@@ -140,8 +181,19 @@ impl ClassManager {
             &self.Generator,
             &self.NativeGenerator,
             &self.Promise,
+            &self.CancelToken,
             &self.Class,
             &self.Module,
+            &self.BaseException,
+            &self.Exception,
+            &self.RuntimeError,
+            &self.TypeError,
+            &self.AttributeError,
+            &self.ArgumentError,
+            &self.ParseError,
+            &self.NotFoundError,
+            &self.CancelledError,
+            &self.OSError,
         ]
     }
 }
@@ -16,3 +16,64 @@ pub(super) fn new() -> Rc<Class> {
         HashMap::new(),
     )
 }
+
+pub(super) fn new_int(number: &Rc<Class>) -> Rc<Class> {
+    let mut static_map = HashMap::new();
+    static_map.insert("MIN".into(), i64::MIN.into());
+    static_map.insert("MAX".into(), i64::MAX.into());
+    Class::new_with_bases(
+        "Int".into(),
+        vec![number.clone()],
+        Class::map_from_funcs(vec![
+            NativeFunction::new(
+                "wrapping_add",
+                ["self", "other"],
+                "Addition that wraps around on overflow",
+                |_globals, args, _| {
+                    let (a, b) = (args[0].int()?, args[1].int()?);
+                    Ok(a.wrapping_add(b).into())
+                },
+            ),
+            NativeFunction::new(
+                "wrapping_sub",
+                ["self", "other"],
+                "Subtraction that wraps around on overflow",
+                |_globals, args, _| {
+                    let (a, b) = (args[0].int()?, args[1].int()?);
+                    Ok(a.wrapping_sub(b).into())
+                },
+            ),
+            NativeFunction::new(
+                "wrapping_mul",
+                ["self", "other"],
+                "Multiplication that wraps around on overflow",
+                |_globals, args, _| {
+                    let (a, b) = (args[0].int()?, args[1].int()?);
+                    Ok(a.wrapping_mul(b).into())
+                },
+            ),
+            NativeFunction::new(
+                "wrapping_neg",
+                ["self"],
+                "Negation that wraps around on overflow",
+                |_globals, args, _| Ok(args[0].int()?.wrapping_neg().into()),
+            ),
+            NativeFunction::new(
+                "wrapping_pow",
+                ["self", "exp"],
+                "Exponentiation that wraps around on overflow",
+                |_globals, args, _| {
+                    let (a, b) = (args[0].int()?, args[1].u32()?);
+                    Ok(a.wrapping_pow(b).into())
+                },
+            ),
+            NativeFunction::new(
+                "count_ones",
+                ["self"],
+                "The number of ones in the binary representation",
+                |_globals, args, _| Ok(args[0].int()?.count_ones().into()),
+            ),
+        ]),
+        static_map,
+    )
+}
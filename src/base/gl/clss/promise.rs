@@ -1,19 +1,398 @@
 use super::*;
+use crate::IndexMap;
+use crate::Promise;
+use std::rc::Weak;
+
+type Resolve = Box<dyn FnOnce(&mut Globals, Result<Value>)>;
+type Decide = fn(&mut Globals, &[Option<Result<Value>>], Option<usize>) -> Option<Result<Value>>;
 
 pub(super) fn new() -> Rc<Class> {
     Class::new(
         "Promise".into(),
-        Class::map_from_funcs(vec![NativeFunction::new(
-            "ordie",
-            ["self"],
-            "",
-            |globals, args, _| {
+        Class::map_from_funcs(vec![
+            NativeFunction::new("ordie", ["self"], "", |globals, args, _| {
                 let mut args = args.into_iter();
                 let owner = args.next().unwrap().into_promise()?;
                 owner.borrow_mut().ordie(globals);
                 Ok(Value::Nil)
-            },
-        )]),
-        HashMap::new(),
+            }),
+            NativeFunction::new(
+                "then",
+                ArgSpec::builder()
+                    .req("self")
+                    .def("on_ok", ())
+                    .def("on_err", ()),
+                concat!(
+                    "Returns a promise for the result of calling on_ok with the value ",
+                    "of this promise, or on_err with the exception if this promise ",
+                    "fails. A nil callback passes the result through as is.\n",
+                    "If the callback returns a promise, its result is used instead",
+                ),
+                |globals, args, _| {
+                    let mut args = args.into_iter();
+                    let owner = args.next().unwrap().into_promise()?;
+                    let on_ok = args.next().unwrap();
+                    let on_err = args.next().unwrap();
+                    Ok(then(globals, &owner, on_ok, on_err).into())
+                },
+            ),
+            NativeFunction::new(
+                "catch",
+                ["self", "on_err"],
+                concat!(
+                    "Returns a promise for the result of calling on_err with the ",
+                    "exception if this promise fails, or for the value of this promise ",
+                    "otherwise",
+                ),
+                |globals, args, _| {
+                    let mut args = args.into_iter();
+                    let owner = args.next().unwrap().into_promise()?;
+                    let on_err = args.next().unwrap();
+                    Ok(then(globals, &owner, Value::Nil, on_err).into())
+                },
+            ),
+            NativeFunction::new(
+                "finally",
+                ["self", "f"],
+                concat!(
+                    "Returns a promise that calls f with no arguments when this promise ",
+                    "settles, and then settles the same way as this promise ",
+                    "(unless f itself fails)",
+                ),
+                |globals, args, _| {
+                    let mut args = args.into_iter();
+                    let owner = args.next().unwrap().into_promise()?;
+                    let f = args.next().unwrap();
+                    Ok(Promise::new(globals, |globals, resolve| {
+                        Promise::observe(&owner, globals, move |globals, result| {
+                            match globals.catch(|globals| f.apply(globals, vec![], None)) {
+                                Err(error) => resolve(globals, Err(error)),
+                                Ok(Value::Promise(promise)) => {
+                                    Promise::observe(&promise, globals, move |globals, r| {
+                                        resolve(globals, r.and(result))
+                                    })
+                                }
+                                Ok(_) => resolve(globals, result),
+                            }
+                        });
+                    })
+                    .into())
+                },
+            ),
+        ]),
+        Class::map_from_funcs(vec![
+            NativeFunction::new(
+                "__call",
+                ["executor"],
+                concat!(
+                    "Creates a new promise, calling executor(resolve, reject) immediately.\n",
+                    "The promise settles with the value passed to the first call of ",
+                    "resolve, or fails with the exception passed to the first call of ",
+                    "reject (or thrown by executor)",
+                ),
+                |globals, args, _| {
+                    let executor = args.into_iter().next().unwrap();
+                    let (promise, resolve) = pending(globals);
+                    let target = Rc::downgrade(&promise);
+                    let cell = Rc::new(RefCell::new(Some(resolve)));
+                    let resolve_fn = settle_function("resolve", &target, cell.clone(), true);
+                    let reject_fn = settle_function("reject", &target, cell.clone(), false);
+                    if let Err(error) = globals
+                        .catch(|globals| executor.apply(globals, vec![resolve_fn, reject_fn], None))
+                    {
+                        let resolve = cell.borrow_mut().take();
+                        if let Some(resolve) = resolve {
+                            resolve(globals, Err(error));
+                        }
+                    }
+                    Ok(promise.into())
+                },
+            ),
+            NativeFunction::new(
+                "resolved",
+                ArgSpec::builder().def("value", ()),
+                "Returns a promise that has already succeeded with the given value",
+                |_globals, args, _| Ok(to_promise(args.into_iter().next().unwrap()).into()),
+            ),
+            NativeFunction::new(
+                "rejected",
+                ["exc"],
+                "Returns a promise that has already failed with the given exception",
+                |globals, args, _| {
+                    let exc = args.into_iter().next().unwrap();
+                    let error = globals.class_manager().error_from_exception(exc)?;
+                    Ok(
+                        Promise::new(globals, |globals, resolve| resolve(globals, Err(error)))
+                            .into(),
+                    )
+                },
+            ),
+            NativeFunction::new(
+                "all",
+                ["promises"],
+                concat!(
+                    "Returns a promise for the list of values of all the given promises, ",
+                    "that fails as soon as any of them fails",
+                ),
+                |globals, args, _| {
+                    combine(globals, args, |_globals, slots, last| match last {
+                        Some(i) if slots[i].as_ref().unwrap().is_err() => slots[i].clone(),
+                        _ if slots.iter().all(Option::is_some) => Some(Ok(slots
+                            .iter()
+                            .map(|slot| slot.clone().unwrap().unwrap())
+                            .collect::<Vec<_>>()
+                            .into())),
+                        _ => None,
+                    })
+                },
+            ),
+            NativeFunction::new(
+                "all_settled",
+                ["promises"],
+                concat!(
+                    "Returns a promise for a list with a Map for each given promise ",
+                    "once they have all settled: either ['status': 'ok', 'value': value] ",
+                    "or ['status': 'err', 'error': exc]",
+                ),
+                |globals, args, _| {
+                    combine(globals, args, |globals, slots, _last| {
+                        if !slots.iter().all(Option::is_some) {
+                            return None;
+                        }
+                        let mut list = Vec::new();
+                        for slot in slots {
+                            let mut map = IndexMap::new();
+                            match slot.as_ref().unwrap() {
+                                Ok(value) => {
+                                    map.insert(Key::from("status"), Value::from("ok"));
+                                    map.insert(Key::from("value"), value.clone());
+                                }
+                                Err(error) => {
+                                    let exc = globals.class_manager().exception_from_error(error);
+                                    map.insert(Key::from("status"), Value::from("err"));
+                                    map.insert(Key::from("error"), exc);
+                                }
+                            }
+                            list.push(Value::from(map));
+                        }
+                        Some(Ok(list.into()))
+                    })
+                },
+            ),
+            NativeFunction::new(
+                "race",
+                ["promises"],
+                "Returns a promise that settles the same way as the first given promise to settle",
+                |globals, args, _| {
+                    combine(globals, args, |_globals, slots, last| {
+                        last.and_then(|i| slots[i].clone())
+                    })
+                },
+            ),
+            NativeFunction::new(
+                "timeout",
+                ArgSpec::builder()
+                    .req("promise")
+                    .req("duration")
+                    .def("token", ()),
+                concat!(
+                    "Returns a promise that settles the same way as the given promise, ",
+                    "or fails with a CancelledError if it has not settled after the ",
+                    "given number of seconds.\n",
+                    "If a CancelToken is given, it is cancelled on timeout, so that ",
+                    "whatever the given promise was waiting on can be abandoned",
+                ),
+                |globals, args, _| {
+                    let mut args = args.into_iter();
+                    let promise = to_promise(args.next().unwrap());
+                    let delay = args.next().unwrap().duration()?;
+                    let deadline = Globals::deadline_after(delay)?;
+                    let token = CancelToken::from_arg(args.next().unwrap())?;
+                    Ok(Promise::new(globals, |globals, resolve| {
+                        let cell = Rc::new(RefCell::new(Some(resolve)));
+                        let timer_cell = cell.clone();
+                        let id = globals.set_timer_at(deadline, move |globals| {
+                            let resolve = timer_cell.borrow_mut().take();
+                            if let Some(resolve) = resolve {
+                                let error = kinderr!(
+                                    Cancelled,
+                                    "Timed out after {} seconds",
+                                    delay.as_secs_f64()
+                                );
+                                if let Some(token) = token {
+                                    token.cancel(globals, Some(error.clone()));
+                                }
+                                resolve(globals, Err(error));
+                            }
+                        });
+                        Promise::observe(&promise, globals, move |globals, result| {
+                            let resolve = cell.borrow_mut().take();
+                            if let Some(resolve) = resolve {
+                                globals.cancel_timer(id);
+                                resolve(globals, result);
+                            }
+                        });
+                    })
+                    .into())
+                },
+            ),
+            NativeFunction::new(
+                "any",
+                ["promises"],
+                concat!(
+                    "Returns a promise for the value of the first given promise to succeed, ",
+                    "that fails only if all of them fail",
+                ),
+                |globals, args, _| {
+                    combine(globals, args, |_globals, slots, last| match last {
+                        Some(i) if slots[i].as_ref().unwrap().is_ok() => slots[i].clone(),
+                        _ if slots.iter().all(Option::is_some) => {
+                            let messages: Vec<_> = slots
+                                .iter()
+                                .map(|slot| slot.clone().unwrap().unwrap_err())
+                                .map(|error| format!("{}: {}", error.type_(), error.message()))
+                                .collect();
+                            Some(Err(rterr!(
+                                "All promises were rejected ({})",
+                                messages.join(", ")
+                            )))
+                        }
+                        _ => None,
+                    })
+                },
+            ),
+        ]),
+    )
+}
+
+/// Converts a value into a promise, wrapping it in an already successful
+/// one if it is not a promise already
+fn to_promise(value: Value) -> Rc<RefCell<Promise>> {
+    match value {
+        Value::Promise(promise) => promise,
+        value => Promise::unit(Ok(value)),
+    }
+}
+
+/// Creates a pending promise, together with the function that resolves it
+fn pending(globals: &mut Globals) -> (Rc<RefCell<Promise>>, Resolve) {
+    let mut resolver = None;
+    let promise = Promise::new(globals, |_globals, resolve| resolver = Some(resolve));
+    (promise, resolver.unwrap())
+}
+
+/// Settles the target promise with the given result, waiting on the result first
+/// if it is itself a promise.
+/// The target cannot wait on itself, so resolving it with itself fails instead
+fn settle(
+    globals: &mut Globals,
+    target: &Weak<RefCell<Promise>>,
+    resolve: Resolve,
+    result: Result<Value>,
+) {
+    match result {
+        Ok(Value::Promise(promise)) if Rc::as_ptr(&promise) == target.as_ptr() => resolve(
+            globals,
+            Err(kinderr!(Type, "A promise cannot be resolved with itself")),
+        ),
+        Ok(Value::Promise(promise)) => Promise::observe(&promise, globals, resolve),
+        result => resolve(globals, result),
+    }
+}
+
+fn then(
+    globals: &mut Globals,
+    owner: &Rc<RefCell<Promise>>,
+    on_ok: Value,
+    on_err: Value,
+) -> Rc<RefCell<Promise>> {
+    let (promise, resolve) = pending(globals);
+    let target = Rc::downgrade(&promise);
+    Promise::observe(owner, globals, move |globals, result| {
+        let result = match result {
+            Ok(value) if !on_ok.is_nil() => {
+                globals.catch(|globals| on_ok.apply(globals, vec![value], None))
+            }
+            Err(error) if !on_err.is_nil() => {
+                let exc = globals.class_manager().exception_from_error(&error);
+                globals.catch(|globals| on_err.apply(globals, vec![exc], None))
+            }
+            result => result,
+        };
+        settle(globals, &target, resolve, result);
+    });
+    promise
+}
+
+/// The 'resolve' and 'reject' functions passed to a promise executor.
+/// Only the first call to either of them has any effect
+fn settle_function(
+    name: &'static str,
+    target: &Weak<RefCell<Promise>>,
+    cell: Rc<RefCell<Option<Resolve>>>,
+    ok: bool,
+) -> Value {
+    let target = target.clone();
+    NativeFunction::new(
+        name,
+        ArgSpec::builder().def("value", ()),
+        None,
+        move |globals, args, _| {
+            let value = args.into_iter().next().unwrap();
+            let result = if ok {
+                Ok(value)
+            } else {
+                Err(globals.class_manager().error_from_exception(value)?)
+            };
+            let resolve = cell.borrow_mut().take();
+            if let Some(resolve) = resolve {
+                settle(globals, &target, resolve, result);
+            }
+            Ok(Value::Nil)
+        },
     )
+    .into()
+}
+
+/// Shared implementation of the combinators.
+/// Each time one of the given promises settles, its result is stored in its
+/// slot, and 'decide' is called with the slots and the index of the promise that
+/// just settled, to determine whether the combined promise can settle.
+/// 'decide' is also called once with no index if there are no promises at all
+fn combine(globals: &mut Globals, args: Vec<Value>, decide: Decide) -> Result<Value> {
+    let promises: Vec<_> = args
+        .into_iter()
+        .next()
+        .unwrap()
+        .unpack(globals)?
+        .into_iter()
+        .map(to_promise)
+        .collect();
+    Ok(Promise::new(globals, |globals, resolve| {
+        let state = Rc::new(RefCell::new((Some(resolve), vec![None; promises.len()])));
+        if promises.is_empty() {
+            if let Some(result) = decide(globals, &[], None) {
+                let resolve = state.borrow_mut().0.take().unwrap();
+                resolve(globals, result);
+            }
+        }
+        for (i, promise) in promises.into_iter().enumerate() {
+            let state = state.clone();
+            Promise::observe(&promise, globals, move |globals, result| {
+                let (resolve, result) = {
+                    let mut state = state.borrow_mut();
+                    if state.0.is_none() {
+                        return;
+                    }
+                    state.1[i] = Some(result);
+                    match decide(globals, &state.1, Some(i)) {
+                        Some(result) => (state.0.take().unwrap(), result),
+                        None => return,
+                    }
+                };
+                resolve(globals, result);
+            });
+        }
+    })
+    .into())
 }
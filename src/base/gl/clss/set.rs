@@ -19,19 +19,17 @@ pub(super) fn new(iterable: &Rc<Class>) -> Rc<Class> {
                     owner.insert(key);
                     Ok(Value::Nil)
                 }),
-                NativeFunction::new("has", ["self", "x"], None, |_globals, args, _| {
+                NativeFunction::new("has", ["self", "x"], None, |globals, args, _| {
                     let mut args = args.into_iter();
                     let owner = args.next().unwrap().into_set()?;
-                    let owner = owner.borrow();
-                    let key = Key::try_from(args.next().unwrap())?;
-                    Ok(owner.contains(&key).into())
+                    let x = args.next().unwrap();
+                    Ok(owner.has(globals, &x)?.into())
                 }),
-                NativeFunction::new("__contains", ["self", "x"], None, |_globals, args, _| {
+                NativeFunction::new("__contains", ["self", "x"], None, |globals, args, _| {
                     let mut args = args.into_iter();
                     let owner = args.next().unwrap().into_set()?;
-                    let owner = owner.borrow();
-                    let key = Key::try_from(args.next().unwrap())?;
-                    Ok(owner.contains(&key).into())
+                    let x = args.next().unwrap();
+                    Ok(owner.has(globals, &x)?.into())
                 }),
                 NativeFunction::new("__add", ["self", "other"], None, |_globals, args, _| {
                     let mut args = args.into_iter();
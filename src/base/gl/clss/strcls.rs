@@ -113,6 +113,24 @@ pub(super) fn new() -> Rc<Class> {
                 let args = args.next().unwrap().unpack(globals)?;
                 Ok(Value::from(Value::format_string(owner.str(), args)?))
             }),
+            NativeFunction::new(
+                "format",
+                ArgSpec::builder().req("self").var("args").key("kwargs"),
+                concat!(
+                    "Substitutes the arguments into '{}' fields of this string\n",
+                    "A field is written '{[field][!conv][:spec]}', where 'field' is ",
+                    "empty (the next positional argument), an argument index or a ",
+                    "keyword argument name, and 'conv' is 'r' (repr) or 's' (str).\n",
+                    "'spec' is '[[fill]align][sign][#][0][width][,|_][.precision][type]' ",
+                    "with 'align' one of '<', '>', '^', '=' and 'type' one of ",
+                    "'s', 'd', 'b', 'o', 'x', 'X', 'e', 'E', 'f', 'F', 'g', 'G', '%'.\n",
+                    "'{{' and '}}' stand for literal braces",
+                ),
+                |_globals, args, kwargs| {
+                    let owner = args[0].string()?;
+                    Ok(Value::format_template(owner.str(), &args[1..], kwargs.as_ref())?.into())
+                },
+            ),
             NativeFunction::new(
                 "__mul",
                 ["self", "n"],
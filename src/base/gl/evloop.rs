@@ -0,0 +1,179 @@
+use super::*;
+use crate::CancelToken;
+use crate::Promise;
+use std::cmp;
+use std::collections::BinaryHeap;
+use std::collections::VecDeque;
+use std::time::Duration;
+use std::time::Instant;
+
+type Task = Box<dyn FnOnce(&mut Globals)>;
+
+/// Single threaded event loop state: tasks that are ready to run,
+/// and timers waiting for their deadlines
+#[derive(Default)]
+pub(super) struct EventLoop {
+    tasks: VecDeque<Task>,
+    timers: BinaryHeap<Timer>,
+
+    // tasks of the timers that have not yet fired or been cancelled.
+    // Cancelled timers stay in the heap, and are skipped when popped
+    timer_tasks: HashMap<u64, Task>,
+
+    // breaks ties between timers with the same deadline,
+    // so that they fire in the order they were set
+    next_seq: u64,
+}
+
+/// Identifies a timer set with Globals::set_timer, so that it can be cancelled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TimerId(u64);
+
+struct Timer {
+    deadline: Instant,
+    seq: u64,
+}
+
+impl cmp::PartialEq for Timer {
+    fn eq(&self, other: &Self) -> bool {
+        (self.deadline, self.seq) == (other.deadline, other.seq)
+    }
+}
+
+impl cmp::Eq for Timer {}
+
+impl cmp::PartialOrd for Timer {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl cmp::Ord for Timer {
+    // reversed, so that the BinaryHeap pops the earliest deadline first
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        (other.deadline, other.seq).cmp(&(self.deadline, self.seq))
+    }
+}
+
+impl Globals {
+    /// Queues a task to run on a later turn of the event loop
+    pub fn enqueue_task<F>(&mut self, f: F)
+    where
+        F: FnOnce(&mut Globals) + 'static,
+    {
+        self.event_loop.tasks.push_back(Box::new(f));
+    }
+
+    /// Schedules a task to run on the event loop once the given delay has passed.
+    /// Fails if the delay is too long for its deadline to be represented
+    pub fn set_timer<F>(&mut self, delay: Duration, f: F) -> Result<TimerId>
+    where
+        F: FnOnce(&mut Globals) + 'static,
+    {
+        let deadline = Self::deadline_after(delay)?;
+        Ok(self.set_timer_at(deadline, f))
+    }
+
+    /// The instant at which a timer with the given delay set now should fire
+    pub(crate) fn deadline_after(delay: Duration) -> Result<Instant> {
+        Instant::now()
+            .checked_add(delay)
+            .ok_or_else(|| rterr!("A delay of {} seconds is too long", delay.as_secs_f64()))
+    }
+
+    /// Like set_timer, but with a deadline from deadline_after, so that
+    /// callers can fail before starting whatever the timer is for
+    pub(crate) fn set_timer_at<F>(&mut self, deadline: Instant, f: F) -> TimerId
+    where
+        F: FnOnce(&mut Globals) + 'static,
+    {
+        let seq = self.event_loop.next_seq;
+        self.event_loop.next_seq += 1;
+        self.event_loop.timers.push(Timer { deadline, seq });
+        self.event_loop.timer_tasks.insert(seq, Box::new(f));
+        TimerId(seq)
+    }
+
+    /// Cancels a timer so that its task never runs.
+    /// Returns false if the timer had already fired or been cancelled
+    pub fn cancel_timer(&mut self, id: TimerId) -> bool {
+        self.event_loop.timer_tasks.remove(&id.0).is_some()
+    }
+
+    /// Returns a promise that resolves to nil once the given delay has passed.
+    /// If the given token is cancelled first, the timer is cancelled, and the
+    /// promise fails with a CancelledError
+    pub fn sleep_async(
+        &mut self,
+        delay: Duration,
+        token: Option<&CancelToken>,
+    ) -> Result<Rc<RefCell<Promise>>> {
+        let deadline = Self::deadline_after(delay)?;
+        Ok(Promise::new_cancellable(self, token, |globals, resolve| {
+            let id =
+                globals.set_timer_at(deadline, move |globals| resolve(globals, Ok(Value::Nil)));
+            move |globals: &mut Globals| {
+                globals.cancel_timer(id);
+            }
+        }))
+    }
+
+    /// Runs all tasks that are ready, including timers that are due, without blocking,
+    /// and then reports any promises that failed without anything waiting on them.
+    /// Returns whether there is still work left (i.e. timers that are not yet due),
+    /// which is never the case once the Die policy for unhandled rejections
+    /// has been violated, or the user has quit the debugger
+    pub fn run_pending(&mut self) -> bool {
+        loop {
+            if self.debugger_quit() {
+                return false;
+            }
+            let now = Instant::now();
+            while self
+                .event_loop
+                .timers
+                .peek()
+                .map(|timer| timer.deadline <= now)
+                .unwrap_or(false)
+            {
+                // the task is only looked up when it is about to run, since
+                // the tasks queued before it may still cancel the timer
+                let seq = self.event_loop.timers.pop().unwrap().seq;
+                self.event_loop.tasks.push_back(Box::new(move |globals| {
+                    if let Some(task) = globals.event_loop.timer_tasks.remove(&seq) {
+                        task(globals);
+                    }
+                }));
+            }
+            match self.event_loop.tasks.pop_front() {
+                Some(task) => task(self),
+                None => break,
+            }
+        }
+        if self.report_unhandled_rejections() {
+            return false;
+        }
+
+        // so that run_event_loop does not wait on a timer that was cancelled
+        while let Some(timer) = self.event_loop.timers.peek() {
+            if self.event_loop.timer_tasks.contains_key(&timer.seq) {
+                break;
+            }
+            self.event_loop.timers.pop();
+        }
+        !self.event_loop.timer_tasks.is_empty()
+    }
+
+    /// Runs the event loop until there are no more tasks or timers left,
+    /// sleeping while waiting for the next timer
+    pub fn run_event_loop(&mut self) {
+        while self.run_pending() {
+            if let Some(timer) = self.event_loop.timers.peek() {
+                let now = Instant::now();
+                if timer.deadline > now {
+                    std::thread::sleep(timer.deadline - now);
+                }
+            }
+        }
+    }
+}
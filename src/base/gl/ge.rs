@@ -19,6 +19,24 @@ impl Globals {
         self.source_roots.push(root.into());
     }
 
+    /// Enables or disables the bytecode cache (on by default).
+    /// When enabled, modules loaded from files are compiled once and
+    /// the result saved in a '__cache__' directory next to the file
+    /// (or under ~/.mtots/cache if that directory is not writable)
+    pub fn set_bytecode_cache(&mut self, enabled: bool) {
+        self.bytecode_cache = enabled;
+    }
+
+    /// Sets the optimization level used when compiling scripts
+    /// (0 disables optimization)
+    pub fn set_opt_level(&mut self, opt_level: u8) {
+        self.opt_level = opt_level;
+    }
+
+    pub fn opt_level(&self) -> u8 {
+        self.opt_level
+    }
+
     pub fn add_custom_source(&mut self, source: Rc<Source>) -> Result<()> {
         if self.custom_sources.contains_key(source.name()) {
             panic!("Duplicate custom source for {:?}", source.name());
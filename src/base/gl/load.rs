@@ -10,6 +10,14 @@ impl Globals {
         }
         Ok(self.module_map.get(name).unwrap())
     }
+    /// Checks whether the given module is implemented natively
+    /// (and so can be loaded without running any script)
+    pub fn is_native_module(&self, name: &RcStr) -> bool {
+        self.native_modules.contains_key(name)
+            || (self.module_map.contains_key(name)
+                && !self.custom_sources.contains_key(name)
+                && self.find_source_path(name).is_none())
+    }
     fn load_uncached(&mut self, name: &RcStr) -> Result<Rc<Module>> {
         if let Some(native_module) = self.native_modules.remove(name) {
             let data = native_module.data(self);
@@ -24,10 +32,38 @@ impl Globals {
             return Ok(module);
         }
         if let Some(source) = self.find_source(name)? {
-            self.exec(source)
+            let code = self.load_code(source.clone())?;
+            self.exec_code(&source, &code)
         } else {
-            Err(rterr!("Module {:?} not found", name))
+            Err(kinderr!(NotFound, "Module {:?} not found", name))
+        }
+    }
+    /// Compiles the given source, going through the bytecode cache
+    /// if the source came from a file
+    fn load_code(&self, source: Rc<Source>) -> Result<Code> {
+        let path = match source.path() {
+            Some(path) if self.bytecode_cache => path.clone(),
+            _ => return self.compile_source(source),
+        };
+        let hash = source_hash(source.data());
+        let cache_paths = cache_paths(&path, source.name());
+        for cache_path in &cache_paths {
+            if let Ok(data) = fs::read(cache_path) {
+                if let Some(code) = Code::deserialize(&source, hash, self.opt_level, &data) {
+                    return Ok(code);
+                }
+            }
+        }
+        let code = self.compile_source(source)?;
+        let data = code.serialize(hash, self.opt_level);
+        for cache_path in &cache_paths {
+            // failing to write to the cache is not an error,
+            // just try the next location
+            if write_cache_entry(cache_path, &data).is_ok() {
+                break;
+            }
         }
+        Ok(code)
     }
     fn find_source(&self, name: &RcStr) -> Result<Option<Rc<Source>>> {
         if let Some(source) = self.custom_sources.get(name) {
@@ -40,7 +76,9 @@ impl Globals {
             Ok(None)
         }
     }
-    fn find_source_path(&self, name: &RcStr) -> Option<PathBuf> {
+    /// Finds the path of the script that would be loaded for the given
+    /// module name, searching the source roots in order
+    pub fn find_source_path(&self, name: &RcStr) -> Option<PathBuf> {
         let relpaths = vec![
             {
                 let mut path = PathBuf::new();
@@ -74,3 +112,36 @@ impl Globals {
         None
     }
 }
+
+/// The locations to look for a cache entry for the given source file, in order:
+/// the '__cache__' directory next to it, then ~/.mtots/cache
+fn cache_paths(path: &Path, name: &RcStr) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    if let (Some(dir), Some(stem)) = (path.parent(), path.file_stem()) {
+        let filename = format!("{}.uc", stem.to_string_lossy());
+        paths.push(dir.join("__cache__").join(filename));
+    }
+    if let Some(home) = crate::mtots_home() {
+        // different files may be loaded under the same module name,
+        // so the path is mixed into the filename too
+        let pathhash = source_hash(&path.to_string_lossy());
+        let filename = format!("{}-{:016x}.uc", name, pathhash);
+        paths.push(home.join("cache").join(filename));
+    }
+    paths
+}
+
+fn write_cache_entry(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    // write to a temporary file first, so that a concurrent reader
+    // never sees a partially written entry
+    let tmp = path.with_extension(format!("uc.{}", std::process::id()));
+    fs::write(&tmp, data)?;
+    let result = fs::rename(&tmp, path);
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
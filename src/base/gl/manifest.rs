@@ -0,0 +1,424 @@
+//! Module search roots from the environment and from project manifests
+//!
+//! A project manifest is an 'mtots.toml' file at the top of a project:
+//!
+//!   [package]
+//!   name = "myproject"
+//!   roots = ["src"]                       # defaults to ["."]
+//!
+//!   [dependencies]
+//!   util = { path = "../util" }
+//!
+//! Roots and dependency paths are relative to the directory of the manifest.
+//! A dependency is a directory with a manifest of its own (whose roots and
+//! dependencies are added in turn), or a plain directory of modules.
+//!
+//! Only the subset of TOML needed for this is understood: tables, and
+//! strings, booleans, numbers, arrays and inline tables as values.
+use super::*;
+use crate::IndexMap;
+use crate::Json;
+use std::fs;
+
+/// The name of the project manifest file
+pub const MANIFEST_NAME: &str = "mtots.toml";
+
+/// The environment variable with extra source roots, separated the same way
+/// as PATH (':' on most platforms)
+pub const MTOTS_PATH: &str = "MTOTS_PATH";
+
+impl Globals {
+    pub fn source_roots(&self) -> &Vec<RcStr> {
+        &self.source_roots
+    }
+
+    /// Adds a source root ahead of the existing ones, so that it is searched first
+    pub fn prepend_source_root<S: Into<RcStr>>(&mut self, root: S) {
+        self.source_roots.insert(0, root.into());
+    }
+
+    /// Removes all occurrences of the given root,
+    /// returning whether there were any
+    pub fn remove_source_root(&mut self, root: &str) -> bool {
+        let len = self.source_roots.len();
+        self.source_roots.retain(|r| r.str() != root);
+        self.source_roots.len() != len
+    }
+
+    /// Adds the source roots listed in MTOTS_PATH, if it is set
+    pub fn add_source_roots_from_env(&mut self) {
+        if let Some(paths) = std::env::var_os(MTOTS_PATH) {
+            for path in std::env::split_paths(&paths) {
+                if !path.as_os_str().is_empty() {
+                    self.add_source_root(path.to_string_lossy().into_owned());
+                }
+            }
+        }
+    }
+
+    /// Adds the source roots declared by the given manifest and
+    /// (recursively) by its dependencies
+    pub fn add_source_roots_from_manifest(&mut self, path: &Path) -> Result<()> {
+        let mut seen = HashSet::new();
+        self.add_manifest(path, &mut seen)
+    }
+
+    /// Sets up the source roots the way the command line tools do:
+    /// from the manifest of the project containing 'dir' (if any),
+    /// followed by those in MTOTS_PATH
+    pub fn init_source_roots(&mut self, dir: &Path) -> Result<()> {
+        if let Some(manifest) = find_manifest(dir) {
+            self.add_source_roots_from_manifest(&manifest)?;
+        }
+        self.add_source_roots_from_env();
+        Ok(())
+    }
+
+    fn add_manifest(&mut self, path: &Path, seen: &mut HashSet<PathBuf>) -> Result<()> {
+        let path = path.canonicalize()?;
+        if !seen.insert(path.clone()) {
+            // dependency cycles are harmless, each project is added once
+            return Ok(());
+        }
+        let dir = path.parent().unwrap().to_owned();
+        let data = fs::read_to_string(&path)?;
+        let manifest = parse_toml(&data).map_err(|(lineno, message)| {
+            rterr!("{}:{}: {}", path.to_string_lossy(), lineno, message)
+        })?;
+        let field_error = |field: &str, expected: &str| {
+            rterr!(
+                "{}: Expected '{}' to be {}",
+                path.to_string_lossy(),
+                field,
+                expected
+            )
+        };
+
+        let package = manifest.get("package");
+        match package.and_then(|p| p.get("roots")) {
+            Some(roots) => {
+                let roots = roots
+                    .as_array()
+                    .ok_or_else(|| field_error("package.roots", "an array of strings"))?;
+                for root in roots {
+                    let root = root
+                        .as_str()
+                        .ok_or_else(|| field_error("package.roots", "an array of strings"))?;
+                    self.add_source_root(dir.join(root).to_string_lossy().into_owned());
+                }
+            }
+            None => self.add_source_root(dir.to_string_lossy().into_owned()),
+        }
+
+        if let Some(deps) = manifest.get("dependencies") {
+            let deps = deps
+                .as_object()
+                .ok_or_else(|| field_error("dependencies", "a table"))?;
+            for (name, dep) in deps {
+                let field = format!("dependencies.{}.path", name);
+                let deppath = dep
+                    .get("path")
+                    .and_then(Json::as_str)
+                    .ok_or_else(|| field_error(&field, "a string"))?;
+                let depdir = dir.join(deppath);
+                let depmanifest = depdir.join(MANIFEST_NAME);
+                if depmanifest.is_file() {
+                    self.add_manifest(&depmanifest, seen)?;
+                } else if depdir.is_dir() {
+                    self.add_source_root(depdir.to_string_lossy().into_owned());
+                } else {
+                    return Err(rterr!(
+                        "{}: Dependency {:?} not found at {:?}",
+                        path.to_string_lossy(),
+                        name,
+                        depdir
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Finds the nearest manifest in the given directory or any of its ancestors
+pub fn find_manifest(dir: &Path) -> Option<PathBuf> {
+    let dir = dir.canonicalize().ok()?;
+    dir.ancestors()
+        .map(|d| d.join(MANIFEST_NAME))
+        .find(|path| path.is_file())
+}
+
+/// Parses TOML into a JSON object (tables become objects)
+/// On failure, returns the line number and a message
+fn parse_toml(text: &str) -> std::result::Result<Json, (usize, String)> {
+    let mut parser = TomlParser {
+        chars: text.chars().collect(),
+        i: 0,
+        lineno: 1,
+    };
+    let mut root = IndexMap::new();
+    let mut table_path: Vec<String> = vec![];
+    loop {
+        parser.skip_space(true);
+        match parser.peek() {
+            None => break,
+            Some('[') => {
+                parser.i += 1;
+                table_path = parser.key()?;
+                parser.expect(']')?;
+                table(&mut root, &table_path).map_err(|m| parser.error(m))?;
+            }
+            Some(_) => {
+                let key = parser.key()?;
+                parser.expect('=')?;
+                let value = parser.value()?;
+                let mut full_key = table_path.clone();
+                full_key.extend(key);
+                let last = full_key.pop().unwrap();
+                let map = table(&mut root, &full_key).map_err(|m| parser.error(m))?;
+                if map.insert(last.clone(), value).is_some() {
+                    return Err(parser.error(format!("Duplicate key {:?}", last)));
+                }
+            }
+        }
+        parser.skip_space(false);
+        match parser.peek() {
+            None | Some('\n') => {}
+            Some(c) => return Err(parser.error(format!("Unexpected {:?}", c))),
+        }
+    }
+    Ok(Json::Object(root))
+}
+
+/// Gets the table at the given path, creating any that are missing
+fn table<'a>(
+    root: &'a mut IndexMap<String, Json>,
+    path: &[String],
+) -> std::result::Result<&'a mut IndexMap<String, Json>, String> {
+    let mut map = root;
+    for key in path {
+        let entry = map
+            .entry(key.clone())
+            .or_insert_with(|| Json::Object(IndexMap::new()));
+        map = match entry {
+            Json::Object(map) => map,
+            _ => return Err(format!("{:?} is not a table", key)),
+        };
+    }
+    Ok(map)
+}
+
+struct TomlParser {
+    chars: Vec<char>,
+    i: usize,
+    lineno: usize,
+}
+
+impl TomlParser {
+    fn error(&self, message: String) -> (usize, String) {
+        (self.lineno, message)
+    }
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.i).cloned()
+    }
+    fn next(&mut self) -> std::result::Result<char, (usize, String)> {
+        match self.peek() {
+            Some(c) => {
+                self.i += 1;
+                if c == '\n' {
+                    self.lineno += 1;
+                }
+                Ok(c)
+            }
+            None => Err(self.error("Unexpected end of file".to_owned())),
+        }
+    }
+    /// Skips spaces and comments, and newlines too if 'newlines' is set
+    fn skip_space(&mut self, newlines: bool) {
+        while let Some(c) = self.peek() {
+            match c {
+                ' ' | '\t' | '\r' => self.i += 1,
+                '\n' if newlines => {
+                    self.i += 1;
+                    self.lineno += 1;
+                }
+                '#' => {
+                    while self.peek().map(|c| c != '\n').unwrap_or(false) {
+                        self.i += 1;
+                    }
+                }
+                _ => break,
+            }
+        }
+    }
+    fn expect(&mut self, expected: char) -> std::result::Result<(), (usize, String)> {
+        self.skip_space(false);
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.i += 1;
+                Ok(())
+            }
+            Some(c) => Err(self.error(format!("Expected {:?} but got {:?}", expected, c))),
+            None => Err(self.error("Unexpected end of file".to_owned())),
+        }
+    }
+    /// A possibly dotted key
+    fn key(&mut self) -> std::result::Result<Vec<String>, (usize, String)> {
+        let mut parts = vec![];
+        loop {
+            self.skip_space(false);
+            let part = match self.peek() {
+                Some(q @ '"') | Some(q @ '\'') => {
+                    self.i += 1;
+                    self.string(q)?
+                }
+                _ => {
+                    let start = self.i;
+                    while let Some(c) = self.peek() {
+                        if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                            self.i += 1;
+                        } else {
+                            break;
+                        }
+                    }
+                    if start == self.i {
+                        return Err(self.error("Expected a key".to_owned()));
+                    }
+                    self.chars[start..self.i].iter().collect()
+                }
+            };
+            parts.push(part);
+            self.skip_space(false);
+            if self.peek() == Some('.') {
+                self.i += 1;
+            } else {
+                return Ok(parts);
+            }
+        }
+    }
+    fn value(&mut self) -> std::result::Result<Json, (usize, String)> {
+        self.skip_space(false);
+        match self.next()? {
+            q @ '"' | q @ '\'' => Ok(Json::String(self.string(q)?)),
+            '[' => {
+                let mut items = vec![];
+                loop {
+                    self.skip_space(true);
+                    if self.peek() == Some(']') {
+                        self.i += 1;
+                        return Ok(Json::Array(items));
+                    }
+                    items.push(self.value()?);
+                    self.skip_space(true);
+                    match self.next()? {
+                        ',' => {}
+                        ']' => return Ok(Json::Array(items)),
+                        c => return Err(self.error(format!("Unexpected {:?} in array", c))),
+                    }
+                }
+            }
+            '{' => {
+                let mut map = IndexMap::new();
+                self.skip_space(false);
+                if self.peek() == Some('}') {
+                    self.i += 1;
+                    return Ok(Json::Object(map));
+                }
+                loop {
+                    let mut key = self.key()?;
+                    self.expect('=')?;
+                    let value = self.value()?;
+                    let last = key.pop().unwrap();
+                    let inner = table(&mut map, &key).map_err(|m| self.error(m))?;
+                    inner.insert(last, value);
+                    self.skip_space(false);
+                    match self.next()? {
+                        ',' => {}
+                        '}' => return Ok(Json::Object(map)),
+                        c => return Err(self.error(format!("Unexpected {:?} in inline table", c))),
+                    }
+                }
+            }
+            c => {
+                let mut word: String = c.to_string();
+                while let Some(c) = self.peek() {
+                    if c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '+' || c == '.' {
+                        word.push(c);
+                        self.i += 1;
+                    } else {
+                        break;
+                    }
+                }
+                match word.as_str() {
+                    "true" => Ok(Json::Bool(true)),
+                    "false" => Ok(Json::Bool(false)),
+                    _ => match word.replace('_', "").parse::<f64>() {
+                        Ok(x) => Ok(Json::Number(x)),
+                        Err(_) => Err(self.error(format!("Unrecognized value {:?}", word))),
+                    },
+                }
+            }
+        }
+    }
+    /// The rest of a string, after its opening quote.
+    /// Escapes are only processed in basic ("...") strings
+    fn string(&mut self, quote: char) -> std::result::Result<String, (usize, String)> {
+        let mut s = String::new();
+        loop {
+            if self.peek() == Some('\n') {
+                return Err(self.error("Unterminated string".to_owned()));
+            }
+            match self.next()? {
+                c if c == quote => return Ok(s),
+                '\\' if quote == '"' => match self.next()? {
+                    'n' => s.push('\n'),
+                    't' => s.push('\t'),
+                    'r' => s.push('\r'),
+                    '"' => s.push('"'),
+                    '\\' => s.push('\\'),
+                    c => return Err(self.error(format!("Unrecognized escape '\\{}'", c))),
+                },
+                c => s.push(c),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_toml;
+    use crate::Json;
+
+    #[test]
+    fn toml_subset() {
+        let manifest = parse_toml(concat!(
+            "# comment\n",
+            "[package]\n",
+            "name = \"foo\"  # trailing comment\n",
+            "roots = [\n  'src',\n  \"lib\\\\x\",\n]\n",
+            "\n",
+            "[dependencies]\n",
+            "bar = { path = \"../bar\" }\n",
+            "baz.path = '../baz'\n",
+        ))
+        .unwrap();
+        let package = manifest.get("package").unwrap();
+        assert_eq!(package.get("name"), Some(&Json::from("foo")));
+        assert_eq!(
+            package.get("roots"),
+            Some(&Json::Array(vec!["src".into(), "lib\\x".into()]))
+        );
+        let deps = manifest.get("dependencies").unwrap();
+        assert_eq!(
+            deps.get("bar").unwrap().get("path"),
+            Some(&Json::from("../bar"))
+        );
+        assert_eq!(
+            deps.get("baz").unwrap().get("path"),
+            Some(&Json::from("../baz"))
+        );
+
+        assert_eq!(parse_toml("a = 1\na = 2\n").unwrap_err().0, 2);
+        assert_eq!(parse_toml("[a]\nb = \"x\ny\"").unwrap_err().0, 2);
+    }
+}
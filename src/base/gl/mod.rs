@@ -1,11 +1,22 @@
 use crate::annotate;
-use crate::compile;
+use crate::ast_fingerprint;
+use crate::code_lines;
+use crate::compile_with_opt_level;
+use crate::format_tokens;
+use crate::lint;
+use crate::source_hash;
 use crate::ArgSpec;
 use crate::Behavior;
+use crate::CancelToken;
 use crate::Class;
+use crate::Code;
 use crate::ConstVal;
+use crate::DebugHook;
+use crate::Debugger;
+use crate::Diagnostic;
 use crate::DocStr;
 use crate::Error;
+use crate::ErrorKind;
 use crate::Handle;
 use crate::HandleBehaviorBuilder;
 use crate::Key;
@@ -24,7 +35,9 @@ use crate::Result;
 use crate::ResumeResult;
 use crate::Set;
 use crate::Source;
+use crate::Table;
 use crate::Value;
+use crate::DEFAULT_OPT_LEVEL;
 use std::any::Any;
 use std::any::TypeId;
 use std::cell::Ref;
@@ -34,22 +47,29 @@ use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::convert::TryFrom;
+use std::path::Path;
 use std::path::PathBuf;
 use std::rc::Rc;
 mod bltn;
 mod clss;
+mod evloop;
 mod ge;
 mod hist;
 mod hnd;
 mod load;
+mod manifest;
 mod nm;
 mod parse;
 mod stash;
 mod trampoline;
+mod unhandled;
 pub use clss::*;
+pub use evloop::TimerId;
 pub use ge::*;
+pub use manifest::*;
 pub use nm::*;
 pub use stash::*;
+pub use unhandled::UnhandledRejectionPolicy;
 
 /// The global state for mtots
 pub struct Globals {
@@ -66,6 +86,8 @@ pub struct Globals {
     source_roots: Vec<RcStr>,
     main_module: Option<RcStr>,
     custom_sources: HashMap<RcStr, Rc<Source>>,
+    bytecode_cache: bool,
+    opt_level: u8,
 
     // builtins
     class_manager: ClassManager,
@@ -86,6 +108,13 @@ pub struct Globals {
     // environment.
     trampoline: Option<Box<dyn FnOnce(Globals)>>,
 
+    // breakpoints, stepping and the hook that takes control when paused
+    debugger: Debugger,
+
+    // tasks and timers for promise based code
+    event_loop: evloop::EventLoop,
+    rejections: unhandled::Rejections,
+
     // command line arguments; need to be explicitly set to be nonempty
     argv: Option<Vec<RcStr>>,
 
@@ -114,18 +143,26 @@ impl Globals {
             source_roots: vec![],
             main_module: None,
             custom_sources: HashMap::new(),
+            bytecode_cache: true,
+            opt_level: DEFAULT_OPT_LEVEL,
             class_manager,
             builtins,
             repl_scope: None,
             handle_class_map: HashMap::new(),
             stash: Default::default(),
             trampoline: None,
+            debugger: Default::default(),
+            event_loop: Default::default(),
+            rejections: Default::default(),
             argv: None,
             #[cfg(feature = "line")]
             line,
             print: None,
             eprint: None,
         };
+        globals
+            .set_handle_class::<CancelToken>(globals.class_manager.CancelToken.clone())
+            .unwrap();
         globals.add_builtin_native_libraries();
         globals
     }
@@ -135,6 +172,21 @@ impl Globals {
     pub fn trace_unwind(&mut self, len: usize) {
         self.trace.truncate(len);
     }
+    /// Runs f, and if it fails, moves the marks it left on the trace into the
+    /// error (like a catch clause in a script would).
+    /// For native code that holds on to errors (e.g. to settle a promise with)
+    /// instead of returning them right away
+    pub fn catch<T, F>(&mut self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Self) -> Result<T>,
+    {
+        let len = self.trace.len();
+        f(self).map_err(|error| {
+            let error = error.prepended(self.trace[len..].to_vec());
+            self.trace_unwind(len);
+            error
+        })
+    }
     pub(crate) fn trace_push(&mut self, mark: Mark) {
         self.trace.push(mark);
     }
@@ -169,14 +221,19 @@ impl Globals {
         self.main_module = Some(main_module_name);
     }
     pub fn exec(&mut self, source: Rc<Source>) -> Result<Rc<Module>> {
-        let name = source.name().clone();
-        let path = source.path().clone();
+        let code = self.compile_source(source.clone())?;
+        self.exec_code(&source, &code)
+    }
+    fn compile_source(&self, source: Rc<Source>) -> Result<Code> {
         let mut display = self.parse(source)?;
         annotate(&mut display)?;
-        let code = compile(&display)?;
+        compile_with_opt_level(&display, self.opt_level)
+    }
+    /// Runs the code compiled from the given source as a module
+    fn exec_code(&mut self, source: &Source, code: &Code) -> Result<Rc<Module>> {
         let mut map = self.builtins.clone();
-        map.insert("__name".into(), name.into());
-        if let Some(path) = path {
+        map.insert("__name".into(), source.name().clone().into());
+        if let Some(path) = source.path() {
             if let Some(pathstr) = path.to_str() {
                 map.insert("__file".into(), pathstr.into());
             }
@@ -196,9 +253,61 @@ impl Globals {
     pub fn exec_repl(&mut self, data: &str) -> Result<Value> {
         let mut display = self.parse(Rc::new(Source::new("[repl]".into(), None, data.into())))?;
         annotate(&mut display)?;
-        let code = compile(&display)?;
+        let code = compile_with_opt_level(&display, self.opt_level)?;
         code.apply_for_repl(self)
     }
+    /// Runs the given source with the given map as its scope.
+    /// Names not in the scope are added to it when assigned to
+    pub fn exec_in_scope(
+        &mut self,
+        name: &str,
+        data: &str,
+        scope: &mut HashMap<RcStr, Rc<RefCell<Value>>>,
+    ) -> Result<Value> {
+        let mut display = self.parse(Rc::new(Source::new(name.into(), None, data.into())))?;
+        annotate(&mut display)?;
+        let code = compile_with_opt_level(&display, self.opt_level)?;
+        code.apply_for_scope(self, scope)
+    }
+    pub(crate) fn builtins(&self) -> &HashMap<RcStr, Value> {
+        &self.builtins
+    }
+    pub(crate) fn get_module(&self, name: &RcStr) -> Option<&Rc<Module>> {
+        self.module_map.get(name)
+    }
+    /// Sets the hook that takes control whenever execution pauses at a breakpoint
+    /// or after a step. By default, a simple debugger prompt is shown on the terminal
+    pub fn set_debug_hook<H: DebugHook + 'static>(&mut self, hook: H) {
+        self.debugger.set_hook(Box::new(hook));
+    }
+    /// Sets the lines of the given source file that the debugger should pause at.
+    /// Passing no lines clears the breakpoints for the file
+    pub fn set_breakpoints(&mut self, path: &Path, lines: Vec<usize>) {
+        self.debugger.set_breakpoints(path, lines);
+    }
+    /// The lines of the script at the given path that breakpoints can stop at,
+    /// i.e. the lines that any of its opcodes come from
+    pub fn breakpoint_lines(&self, path: &Path) -> Result<HashSet<usize>> {
+        let data = std::fs::read_to_string(path)?;
+        let name = path.to_string_lossy();
+        let source = Source::new(name.as_ref().into(), Some(path.into()), data.into());
+        Ok(code_lines(&self.compile_source(source.into())?))
+    }
+    /// Requests that the debugger pause before running the next opcode
+    pub fn request_debug_pause(&mut self) {
+        self.debugger.request_pause();
+    }
+    /// Whether the user quit the debugger. The error it was quit with unwinds
+    /// all the way back to the host, and the event loop stops running tasks
+    pub fn debugger_quit(&self) -> bool {
+        self.debugger.quit()
+    }
+    pub(crate) fn debugger(&self) -> &Debugger {
+        &self.debugger
+    }
+    pub(crate) fn debugger_mut(&mut self) -> &mut Debugger {
+        &mut self.debugger
+    }
     pub(super) fn repl_scope_mut(&mut self) -> &mut HashMap<RcStr, Rc<RefCell<Value>>> {
         if self.repl_scope.is_none() {
             let mut scope = HashMap::new();
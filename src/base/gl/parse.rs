@@ -7,7 +7,8 @@ impl Globals {
             Err(error) => {
                 let pos = error.offset();
                 let lineno = error.lineno();
-                return Err(Error::rt(
+                return Err(Error::new_with_kind(
+                    ErrorKind::Lex,
                     format!("{:?}", error).into(),
                     vec![Mark::new(source, pos, lineno)],
                 ));
@@ -15,6 +16,36 @@ impl Globals {
         };
         self.parser.parse_tokens(source.clone(), tokens, posinfo)
     }
+    /// Returns the canonically formatted version of the given source
+    /// Fails if the source does not parse, or (as a safety net) if the
+    /// formatted source would not parse to the same AST
+    pub fn format(&self, source: Rc<Source>) -> Result<String> {
+        let before = self.parse(source.clone())?;
+        let (tokens, posinfo) = self
+            .lexer
+            .lex(source.data())
+            .expect("Source that parses should also lex");
+        let formatted = format_tokens(source.data(), &tokens, &posinfo);
+        let new_source = Rc::new(Source::new(
+            source.name().clone(),
+            source.path().clone(),
+            formatted.clone().into(),
+        ));
+        let after = self.parse(new_source)?;
+        if ast_fingerprint(&before) != ast_fingerprint(&after) {
+            return Err(rterr!("Formatting {} would change its AST", source.name()));
+        }
+        Ok(formatted)
+    }
+    /// Parses and annotates the given source, and runs the linter on it
+    pub fn lint(&self, source: Rc<Source>) -> Result<Vec<Diagnostic>> {
+        let mut display = self.parse(source)?;
+        annotate(&mut display)?;
+        let mut predefined: HashSet<RcStr> = self.builtins.keys().cloned().collect();
+        predefined.insert("__name".into());
+        predefined.insert("__file".into());
+        Ok(lint(&display, &predefined))
+    }
     pub fn repl_ready(&self, line: &str) -> bool {
         match self.lexer.lex(line) {
             Ok(_) => true,
@@ -13,7 +13,7 @@ impl Globals {
         match r {
             Ok(_) => {}
             Err(error) => {
-                if error.type_().str() == "TrampolineRequest" {
+                if let ErrorKind::Trampoline = error.kind() {
                     if let Some(trampoline) = std::mem::replace(&mut self.trampoline, None) {
                         trampoline(self)
                     }
@@ -32,7 +32,11 @@ impl Globals {
             Err(rterr!("There is already a pending trampoline request"))
         } else {
             self.trampoline = Some(Box::new(trampoline));
-            Err(Error::new("TrampolineRequest".into(), "".into(), vec![]))
+            Err(Error::new_with_kind(
+                ErrorKind::Trampoline,
+                "".into(),
+                vec![],
+            ))
         }
     }
 }
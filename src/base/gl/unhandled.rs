@@ -0,0 +1,90 @@
+use super::*;
+use crate::Promise;
+use std::rc::Weak;
+
+/// What to do about promises that fail without anything ever
+/// waiting on them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UnhandledRejectionPolicy {
+    /// Report the error with its trace, and keep going
+    #[default]
+    Warn,
+
+    /// Report the error with its trace, and stop the event loop.
+    /// The host should then exit with a failure
+    Die,
+
+    /// Do nothing
+    Ignore,
+}
+
+type WeakPromise = Weak<RefCell<Promise>>;
+type Hook = Box<dyn Fn(&Error)>;
+
+/// Promises that have failed, but that no callback has been registered on yet
+#[derive(Default)]
+pub(super) struct Rejections {
+    unhandled: Vec<(WeakPromise, Error)>,
+    policy: UnhandledRejectionPolicy,
+    hook: Option<Hook>,
+
+    /// whether anything has been reported under the Die policy
+    violated: bool,
+}
+
+impl Globals {
+    pub fn set_unhandled_rejection_policy(&mut self, policy: UnhandledRejectionPolicy) {
+        self.rejections.policy = policy;
+    }
+
+    /// Routes reports of unhandled rejections to the given function,
+    /// instead of printing them with eprint
+    pub fn set_unhandled_rejection_hook<F>(&mut self, f: F)
+    where
+        F: Fn(&Error) + 'static,
+    {
+        self.rejections.hook = Some(Box::new(f));
+    }
+
+    /// Called when a promise fails with no callbacks registered on it
+    pub(crate) fn track_rejection(&mut self, promise: &Rc<RefCell<Promise>>, error: Error) {
+        self.rejections
+            .unhandled
+            .push((Rc::downgrade(promise), error));
+    }
+
+    /// Called when a callback is registered on a failed promise
+    pub(crate) fn handle_rejection(&mut self, promise: *const Promise) {
+        // promises that were dropped can't be the one being handled
+        self.rejections
+            .unhandled
+            .retain(|(weak, _)| match weak.upgrade() {
+                Some(rc) => !std::ptr::eq(rc.as_ptr(), promise),
+                None => true,
+            });
+    }
+
+    /// Reports (according to the policy) all the promises that have failed
+    /// without anything waiting on them so far.
+    /// This happens whenever the event loop runs out of tasks that are ready,
+    /// and embedders should call it once more before shutting down.
+    /// Returns true if the Die policy has been violated (now, or by an earlier report),
+    /// in which case the host should exit with a failure
+    pub fn report_unhandled_rejections(&mut self) -> bool {
+        let unhandled = std::mem::take(&mut self.rejections.unhandled);
+        if !unhandled.is_empty() && self.rejections.policy != UnhandledRejectionPolicy::Ignore {
+            for (_, error) in &unhandled {
+                match &self.rejections.hook {
+                    Some(hook) => hook(error),
+                    None => {
+                        self.eprint(&format!("Unhandled promise rejection\n{}", error.format()))
+                    }
+                }
+            }
+            if self.rejections.policy == UnhandledRejectionPolicy::Die {
+                self.rejections.violated = true;
+            }
+        }
+        self.rejections.violated
+    }
+}
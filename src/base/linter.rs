@@ -0,0 +1,651 @@
+//! Static checks on annotated modules
+//! These only report likely mistakes (as Diagnostics); they never
+//! prevent a module from being compiled or run.
+use crate::ArgSpec;
+use crate::Args;
+use crate::AssignTarget;
+use crate::AssignTargetDesc;
+use crate::Expr;
+use crate::ExprDesc;
+use crate::Mark;
+use crate::ModuleDisplay;
+use crate::RcStr;
+use crate::VarSpec;
+use std::collections::HashSet;
+use std::fmt;
+use std::ops::Range;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagnosticKind {
+    UndefinedName,
+    UnusedVariable,
+    UnusedParameter,
+    NonlocalMisuse,
+    UnreachableCode,
+    DuplicateMethod,
+    Shadowing,
+}
+
+impl DiagnosticKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DiagnosticKind::UndefinedName => "undefined-name",
+            DiagnosticKind::UnusedVariable => "unused-variable",
+            DiagnosticKind::UnusedParameter => "unused-parameter",
+            DiagnosticKind::NonlocalMisuse => "nonlocal-misuse",
+            DiagnosticKind::UnreachableCode => "unreachable-code",
+            DiagnosticKind::DuplicateMethod => "duplicate-method",
+            DiagnosticKind::Shadowing => "shadowing",
+        }
+    }
+}
+
+/// A problem found by the linter, spanning 'len' bytes starting at 'mark'
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    kind: DiagnosticKind,
+    message: RcStr,
+    mark: Mark,
+    len: usize,
+}
+
+impl Diagnostic {
+    pub fn kind(&self) -> DiagnosticKind {
+        self.kind
+    }
+    pub fn message(&self) -> &RcStr {
+        &self.message
+    }
+    pub fn mark(&self) -> &Mark {
+        &self.mark
+    }
+    /// The byte range in the source that this diagnostic is about
+    pub fn span(&self) -> Range<usize> {
+        self.mark.pos()..self.mark.pos() + self.len
+    }
+}
+
+/// path:line:column: kind: message
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let source = self.mark.source();
+        let data = source.data();
+        let line_start = data[..self.mark.pos()]
+            .rfind('\n')
+            .map(|i| i + 1)
+            .unwrap_or(0);
+        let column = data[line_start..self.mark.pos()].chars().count() + 1;
+        match source.path() {
+            Some(path) => write!(f, "{}", path.display())?,
+            None => write!(f, "{}", source.name())?,
+        }
+        write!(
+            f,
+            ":{}:{}: {}: {}",
+            self.mark.lineno(),
+            column,
+            self.kind.name(),
+            self.message
+        )
+    }
+}
+
+/// Checks an annotated module.
+/// 'predefined' are the names available to every module without an import
+/// (i.e. the builtins, plus '__name' and '__file')
+pub fn lint(md: &ModuleDisplay, predefined: &HashSet<RcStr>) -> Vec<Diagnostic> {
+    let varspec = md
+        .varspec()
+        .as_ref()
+        .expect("lint requires an annotated module");
+    let mut linter = Linter {
+        predefined,
+        scopes: vec![Scope {
+            varspec,
+            params: vec![],
+            reads: HashSet::new(),
+        }],
+        misused_nonlocals: HashSet::new(),
+        out: vec![],
+    };
+    linter.expr(md.body());
+    for (name, mark) in varspec.free() {
+        if !predefined.contains(name) && !linter.misused_nonlocals.contains(name) {
+            linter.report(
+                DiagnosticKind::UndefinedName,
+                format!("Name {:?} is not defined", name),
+                mark,
+                name.len(),
+            );
+        }
+    }
+    let mut out = linter.out;
+    out.sort_by(|a, b| (a.mark.pos(), &a.message).cmp(&(b.mark.pos(), &b.message)));
+    out
+}
+
+struct Scope<'a> {
+    varspec: &'a VarSpec,
+    params: Vec<RcStr>,
+
+    /// names read directly in this scope, or captured by a nested function
+    reads: HashSet<RcStr>,
+}
+
+struct Linter<'a> {
+    predefined: &'a HashSet<RcStr>,
+    scopes: Vec<Scope<'a>>,
+
+    /// nonlocal names already reported, so that they are not reported
+    /// a second time as undefined
+    misused_nonlocals: HashSet<RcStr>,
+
+    out: Vec<Diagnostic>,
+}
+
+impl<'a> Linter<'a> {
+    fn report(&mut self, kind: DiagnosticKind, message: String, mark: &Mark, len: usize) {
+        self.out.push(Diagnostic {
+            kind,
+            message: message.into(),
+            mark: mark.clone(),
+            len,
+        });
+    }
+
+    fn read(&mut self, name: &RcStr) {
+        self.scopes.last_mut().unwrap().reads.insert(name.clone());
+    }
+
+    fn nonlocal(&mut self, name: &RcStr, mark: &Mark) {
+        let count = self.out.len();
+        if self.scopes.len() == 1 {
+            self.report(
+                DiagnosticKind::NonlocalMisuse,
+                format!("nonlocal {:?} used outside of a function", name),
+                mark,
+                0,
+            );
+        } else if self.scopes.last().unwrap().params.contains(name) {
+            self.report(
+                DiagnosticKind::NonlocalMisuse,
+                format!("Parameter {:?} cannot also be nonlocal", name),
+                mark,
+                0,
+            );
+        } else {
+            let defined = self.defined_in(&self.scopes[..self.scopes.len() - 1], name);
+            if !defined && !self.predefined.contains(name) {
+                self.report(
+                    DiagnosticKind::NonlocalMisuse,
+                    format!(
+                        "nonlocal {:?} does not refer to a variable in an enclosing scope",
+                        name
+                    ),
+                    mark,
+                    0,
+                );
+            }
+        }
+        if self.out.len() > count {
+            self.misused_nonlocals.insert(name.clone());
+        }
+    }
+
+    fn defined_in(&self, scopes: &[Scope], name: &RcStr) -> bool {
+        scopes.iter().any(|scope| {
+            scope
+                .varspec
+                .local()
+                .iter()
+                .chain(scope.varspec.owned())
+                .any(|(n, _)| n == name)
+        })
+    }
+
+    /// Reports variables and parameters of a function that hide a variable
+    /// (or import) of an enclosing scope, or a builtin
+    fn shadowing(&mut self, params: &ArgSpec, varspec: &VarSpec) {
+        let names = params.params();
+        for (name, mark) in varspec.local().iter().chain(varspec.owned()) {
+            if name.starts_with('_') || name.contains('/') || name.str() == "self" {
+                continue;
+            }
+            let hidden = if self.defined_in(&self.scopes, name) {
+                "a variable in an enclosing scope"
+            } else if self.predefined.contains(name) {
+                "a builtin"
+            } else {
+                continue;
+            };
+            let (what, mark) = if names.contains(name) {
+                ("Parameter", param_mark(params, name, mark))
+            } else {
+                ("Variable", mark.clone())
+            };
+            self.report(
+                DiagnosticKind::Shadowing,
+                format!("{} {:?} shadows {}", what, name, hidden),
+                &mark,
+                name.len(),
+            );
+        }
+    }
+
+    fn function(&mut self, params: &ArgSpec, body: &'a Expr, varspec: &'a VarSpec) {
+        self.shadowing(params, varspec);
+        self.scopes.push(Scope {
+            varspec,
+            params: params.params(),
+            reads: HashSet::new(),
+        });
+        self.expr(body);
+        let scope = self.scopes.pop().unwrap();
+        for (name, mark) in varspec.local() {
+            if scope.reads.contains(name) || name.starts_with('_') || name.contains('/') {
+                continue;
+            }
+            if scope.params.contains(name) {
+                if name.str() != "self" {
+                    self.report(
+                        DiagnosticKind::UnusedParameter,
+                        format!("Parameter {:?} is never used", name),
+                        &param_mark(params, name, mark),
+                        name.len(),
+                    );
+                }
+            } else {
+                self.report(
+                    DiagnosticKind::UnusedVariable,
+                    format!("Variable {:?} is assigned but never used", name),
+                    mark,
+                    name.len(),
+                );
+            }
+        }
+
+        // variables captured from enclosing scopes count as used there
+        for (name, _) in varspec.free() {
+            self.read(name);
+        }
+    }
+
+    fn block(&mut self, exprs: &'a [Expr]) {
+        for (i, expr) in exprs.iter().enumerate() {
+            self.expr(expr);
+            if let Some(keyword) = always_exits(expr) {
+                if let Some(next) = exprs.get(i + 1) {
+                    self.report(
+                        DiagnosticKind::UnreachableCode,
+                        format!("Unreachable code after {}", keyword),
+                        next.mark(),
+                        0,
+                    );
+                }
+                for expr in &exprs[i + 1..] {
+                    self.expr(expr);
+                }
+                break;
+            }
+        }
+    }
+
+    fn methods(&mut self, class_name: &RcStr, methods: &'a [(RcStr, Expr)]) {
+        let mut seen = HashSet::new();
+        for (name, expr) in methods {
+            if !seen.insert(name) {
+                self.report(
+                    DiagnosticKind::DuplicateMethod,
+                    format!(
+                        "Method {:?} is defined more than once in {}",
+                        name, class_name
+                    ),
+                    expr.mark(),
+                    0,
+                );
+            }
+            self.expr(expr);
+        }
+    }
+
+    fn exprs<I: IntoIterator<Item = &'a Expr>>(&mut self, exprs: I) {
+        for expr in exprs {
+            self.expr(expr);
+        }
+    }
+
+    fn args(&mut self, args: &'a Args) {
+        self.exprs(&args.args);
+        self.exprs(args.varargs.iter().map(|e| &**e));
+        self.exprs(args.kwargs.iter().map(|(_, e)| e));
+        self.exprs(args.kwmap.iter().map(|e| &**e));
+    }
+
+    fn target(&mut self, target: &'a AssignTarget) {
+        match target.desc() {
+            AssignTargetDesc::Name(_) => {}
+            AssignTargetDesc::List(targets) => {
+                for target in targets {
+                    self.target(target);
+                }
+            }
+            AssignTargetDesc::Attr(owner, _) => self.expr(owner),
+            AssignTargetDesc::Subscript(owner, index) => {
+                self.expr(owner);
+                self.expr(index);
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &'a Expr) {
+        match expr.desc() {
+            ExprDesc::Nil
+            | ExprDesc::Bool(_)
+            | ExprDesc::Number(_)
+            | ExprDesc::Int(_)
+            | ExprDesc::String(_)
+            | ExprDesc::Del(_)
+            | ExprDesc::Import(_)
+            | ExprDesc::BreakPoint
+            | ExprDesc::Break(_)
+            | ExprDesc::Continue(_)
+            | ExprDesc::GetCallingModule => {}
+            ExprDesc::Name(name) => self.read(name),
+            ExprDesc::Block(exprs) => self.block(exprs),
+            ExprDesc::List(exprs) => self.exprs(exprs),
+            ExprDesc::Map(pairs) => {
+                for (key, val) in pairs {
+                    self.expr(key);
+                    self.expr(val);
+                }
+            }
+            ExprDesc::Parentheses(inner)
+            | ExprDesc::Unop(_, inner)
+            | ExprDesc::Yield(inner)
+            | ExprDesc::Await(inner)
+            | ExprDesc::Attr(inner, _) => self.expr(inner),
+            ExprDesc::Switch(value, pairs, default) => {
+                self.expr(value);
+                for (cases, body) in pairs {
+                    self.exprs(cases);
+                    self.expr(body);
+                }
+                self.exprs(default.iter().map(|e| &**e));
+            }
+            ExprDesc::If(pairs, other) => {
+                for (cond, body) in pairs {
+                    self.expr(cond);
+                    self.expr(body);
+                }
+                self.exprs(other.iter().map(|e| &**e));
+            }
+            ExprDesc::For(target, container, body, _) => {
+                self.target(target);
+                self.expr(container);
+                self.expr(body);
+            }
+            ExprDesc::While(cond, body, _) => {
+                self.expr(cond);
+                self.expr(body);
+            }
+            ExprDesc::Try(body, clauses, finally) => {
+                self.expr(body);
+                for clause in clauses {
+                    self.exprs(clause.cls.iter());
+                    self.expr(&clause.body);
+                }
+                self.exprs(finally.iter().map(|e| &**e));
+            }
+            ExprDesc::Binop(_, lhs, rhs) | ExprDesc::LogicalBinop(_, lhs, rhs) => {
+                self.expr(lhs);
+                self.expr(rhs);
+            }
+            ExprDesc::Subscript(owner, index) => {
+                self.expr(owner);
+                self.expr(index);
+            }
+            ExprDesc::Slice(owner, start, end) => {
+                self.expr(owner);
+                self.exprs(start.iter().map(|e| &**e));
+                self.exprs(end.iter().map(|e| &**e));
+            }
+            ExprDesc::CallFunction(f, args) => {
+                self.expr(f);
+                self.args(args);
+            }
+            ExprDesc::CallMethod(owner, _, args) => {
+                self.expr(owner);
+                self.args(args);
+            }
+            ExprDesc::Assign(target, value) => {
+                self.target(target);
+                self.expr(value);
+            }
+            ExprDesc::AugAssign(target, _, value) => {
+                if let AssignTargetDesc::Name(name) = target.desc() {
+                    self.read(name);
+                }
+                self.target(target);
+                self.expr(value);
+            }
+            ExprDesc::NonlocalAssign(name, value) => {
+                self.nonlocal(name, expr.mark());
+                self.expr(value);
+            }
+            ExprDesc::Nonlocal(names) => {
+                for name in names {
+                    self.nonlocal(name, expr.mark());
+                }
+            }
+            ExprDesc::New(_, pairs) => self.exprs(pairs.iter().map(|(_, e)| e)),
+            ExprDesc::Return(value) => self.exprs(value.iter().map(|e| &**e)),
+            ExprDesc::AssignDoc(inner, _, _) => self.expr(inner),
+            ExprDesc::Function {
+                params,
+                body,
+                varspec,
+                ..
+            } => self.function(params, body, varspec.as_ref().unwrap()),
+            ExprDesc::Class {
+                name,
+                bases,
+                methods,
+                static_methods,
+                ..
+            } => {
+                self.exprs(bases);
+                self.methods(name, methods);
+                self.methods(name, static_methods);
+            }
+        }
+    }
+}
+
+/// The mark to report a parameter at: where it is named in the parameter
+/// list if that can be found, and otherwise the mark of its variable
+fn param_mark(params: &ArgSpec, name: &str, mark: &Mark) -> Mark {
+    match params.mark() {
+        Some(list_mark) => match param_pos(list_mark, name) {
+            Some(pos) => Mark::new(list_mark.source().clone(), pos, 0),
+            None => mark.clone(),
+        },
+        None => mark.clone(),
+    }
+}
+
+/// Finds where a parameter is named, given the mark of its parameter list
+fn param_pos(list_mark: &Mark, name: &str) -> Option<usize> {
+    let data = list_mark.source().data();
+    let mut depth = 0;
+    let mut word_start = None;
+    for (i, c) in data[list_mark.pos()..].char_indices() {
+        let i = i + list_mark.pos();
+        if c == '_' || c.is_alphanumeric() {
+            word_start.get_or_insert(i);
+            continue;
+        }
+        if let Some(start) = word_start.take() {
+            if depth == 1 && &data[start..i] == name {
+                return Some(start);
+            }
+        }
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// If evaluating the expression always ends in a return, break or continue,
+/// the keyword for (one of) the ways it does so
+fn always_exits(expr: &Expr) -> Option<&'static str> {
+    match expr.desc() {
+        ExprDesc::Return(_) => Some("return"),
+        ExprDesc::Break(_) => Some("break"),
+        ExprDesc::Continue(_) => Some("continue"),
+        ExprDesc::Block(exprs) => exprs.iter().find_map(always_exits),
+        ExprDesc::If(pairs, Some(other)) => {
+            if pairs.iter().all(|(_, body)| always_exits(body).is_some()) {
+                always_exits(other)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DiagnosticKind;
+    use crate::Globals;
+    use crate::Source;
+    use std::rc::Rc;
+
+    #[test]
+    fn each_kind() {
+        let src = r#"import a.fs
+nonlocal top
+
+def f(used, unused, _ignored) {
+    fs.isfile(used)
+    temp = 1
+    return undefined_thing
+    print('never')
+}
+
+def outer(p) {
+    count = 0
+    def inner() {
+        nonlocal count
+        nonlocal missing
+        count = p + 1
+    }
+    inner
+}
+
+class C {
+    def m(self) = 1
+    def m(self) = 2
+}
+"#;
+        let source = Rc::new(Source::new("test".into(), None, src.into()));
+        let diagnostics = Globals::new().lint(source).unwrap();
+        let found: Vec<_> = diagnostics
+            .iter()
+            .map(|d| (d.kind(), d.mark().lineno(), d.message().to_string()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (
+                    DiagnosticKind::NonlocalMisuse,
+                    2,
+                    "nonlocal \"top\" used outside of a function".to_owned()
+                ),
+                (
+                    DiagnosticKind::UnusedParameter,
+                    4,
+                    "Parameter \"unused\" is never used".to_owned()
+                ),
+                (
+                    DiagnosticKind::UnusedVariable,
+                    6,
+                    "Variable \"temp\" is assigned but never used".to_owned()
+                ),
+                (
+                    DiagnosticKind::UndefinedName,
+                    7,
+                    "Name \"undefined_thing\" is not defined".to_owned()
+                ),
+                (
+                    DiagnosticKind::UnreachableCode,
+                    8,
+                    "Unreachable code after return".to_owned()
+                ),
+                (
+                    DiagnosticKind::NonlocalMisuse,
+                    15,
+                    "nonlocal \"missing\" does not refer to a variable in an enclosing scope"
+                        .to_owned()
+                ),
+                (
+                    DiagnosticKind::DuplicateMethod,
+                    23,
+                    "Method \"m\" is defined more than once in C".to_owned()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn shadowing() {
+        let src = r#"import a.fs
+count = 0
+
+def f(fs, print) {
+    count = 1
+    def inner(count) = count
+    inner(count + fs + print)
+}
+
+def g(_fs) {
+    str = 'ok'
+    def(x) = x + str + _fs
+}
+"#;
+        let source = Rc::new(Source::new("test".into(), None, src.into()));
+        let diagnostics = Globals::new().lint(source).unwrap();
+        let found: Vec<_> = diagnostics
+            .iter()
+            .filter(|d| d.kind() == DiagnosticKind::Shadowing)
+            .map(|d| (d.mark().lineno(), d.message().to_string()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (
+                    4,
+                    "Parameter \"fs\" shadows a variable in an enclosing scope".to_owned()
+                ),
+                (4, "Parameter \"print\" shadows a builtin".to_owned()),
+                (
+                    5,
+                    "Variable \"count\" shadows a variable in an enclosing scope".to_owned()
+                ),
+                (
+                    6,
+                    "Parameter \"count\" shadows a variable in an enclosing scope".to_owned()
+                ),
+                (11, "Variable \"str\" shadows a builtin".to_owned()),
+            ]
+        );
+    }
+}
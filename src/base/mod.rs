@@ -5,6 +5,8 @@ mod compiler;
 mod er;
 mod frontend;
 mod gl;
+mod linter;
+mod optimizer;
 mod value;
 
 pub use annotator::*;
@@ -14,9 +16,10 @@ pub use compiler::*;
 pub use er::*;
 pub use frontend::*;
 pub use gl::*;
+pub use linter::*;
+pub use optimizer::*;
 pub use value::*;
 
-#[cfg(feature = "line")]
 pub(crate) fn mtots_home() -> Option<std::path::PathBuf> {
     crate::home().and_then(|path| {
         let path = std::path::PathBuf::from(path).join(".mtots");
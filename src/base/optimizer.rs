@@ -0,0 +1,270 @@
+//! Peephole optimizer for the opcode streams emitted by the compiler
+//!
+//! Optimization levels:
+//!   0: no optimization
+//!   1: constant folding, jump threading, removal of unreachable code,
+//!      and simplification of redundant stack and variable operations
+use crate::Binop;
+use crate::Mark;
+use crate::Opcode;
+use crate::Unop;
+use crate::Value;
+use std::collections::HashSet;
+
+/// The optimization level used when none is specified
+pub const DEFAULT_OPT_LEVEL: u8 = 1;
+
+/// Optimizes the given opcodes in place.
+/// 'marks' is kept in sync with 'ops', and all jump destinations
+/// are updated as opcodes are removed
+pub(crate) fn optimize(ops: &mut Vec<Opcode>, marks: &mut Vec<Mark>, opt_level: u8) {
+    if opt_level == 0 {
+        return;
+    }
+    loop {
+        let mut changed = thread_jumps(ops);
+
+        let removed = unreachable(ops);
+        if removed.contains(&true) {
+            compact(ops, marks, &removed);
+            changed = true;
+        }
+
+        let removed = peephole(ops);
+        if removed.contains(&true) {
+            compact(ops, marks, &removed);
+            changed = true;
+        }
+
+        if !changed {
+            break;
+        }
+    }
+}
+
+/// Retargets jumps that land on an unconditional jump to that jump's
+/// destination, and replaces jumps to a 'Return' with the 'Return' itself
+fn thread_jumps(ops: &mut [Opcode]) -> bool {
+    let mut changed = false;
+    for i in 0..ops.len() {
+        let mut dest = match ops[i].jump_dest() {
+            Some(dest) => dest,
+            None => continue,
+        };
+        let original = dest;
+        // bounded, in case of a loop made entirely of jumps
+        for _ in 0..ops.len() {
+            match ops.get(dest) {
+                Some(Opcode::Jump(next)) if *next != dest => dest = *next,
+                _ => break,
+            }
+        }
+        if let (Opcode::Jump(_), Some(Opcode::Return)) = (&ops[i], ops.get(dest)) {
+            ops[i] = Opcode::Return;
+            changed = true;
+        } else if dest != original {
+            ops[i].patch_jump(dest);
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// Finds the opcodes that can never be reached from the start of the code
+fn unreachable(ops: &[Opcode]) -> Vec<bool> {
+    let mut seen = vec![false; ops.len()];
+    let mut stack = vec![0];
+    while let Some(i) = stack.pop() {
+        if i >= ops.len() || seen[i] {
+            continue;
+        }
+        seen[i] = true;
+        match &ops[i] {
+            Opcode::Jump(dest) => stack.push(*dest),
+            Opcode::Return | Opcode::Reraise => {}
+            op => {
+                stack.push(i + 1);
+                if let Some(dest) = op.jump_dest() {
+                    stack.push(dest);
+                }
+            }
+        }
+    }
+    seen.into_iter().map(|seen| !seen).collect()
+}
+
+/// Rewrites short sequences of opcodes into cheaper equivalents.
+/// Replacements are written over the first opcode in a sequence, and
+/// the returned flags mark the rest of it for removal
+fn peephole(ops: &mut [Opcode]) -> Vec<bool> {
+    let targets: HashSet<usize> = ops.iter().filter_map(Opcode::jump_dest).collect();
+    let oplen = ops.len();
+    let mut removed = vec![false; oplen];
+
+    // Whether the n opcodes starting at i can be treated as a unit,
+    // i.e. nothing jumps into the middle of them
+    let window =
+        |i: usize, n: usize| i + n <= oplen && (i + 1..i + n).all(|j| !targets.contains(&j));
+
+    let mut i = 0;
+    while i < ops.len() {
+        let (replacement, len) = if window(i, 3) {
+            match (constant(&ops[i]), constant(&ops[i + 1]), &ops[i + 2]) {
+                (Some(lhs), Some(rhs), Opcode::Binop(op)) => match fold_binop(*op, &lhs, &rhs) {
+                    Some(value) => (constant_opcode(value), 3),
+                    None => (None, 0),
+                },
+                _ => (None, 0),
+            }
+        } else {
+            (None, 0)
+        };
+        let (replacement, len) = if len > 0 || !window(i, 2) {
+            (replacement, len)
+        } else {
+            match (&ops[i], &ops[i + 1]) {
+                // pushing a value only to discard it
+                (Opcode::Dup, Opcode::Pop) => (None, 2),
+                (op, Opcode::Pop) if constant(op).is_some() => (None, 2),
+
+                (Opcode::SetVar(a), Opcode::GetVar(b)) if same_variable(a, b) => {
+                    (Some(Opcode::TeeVar(a.clone())), 2)
+                }
+                (Opcode::TeeVar(var), Opcode::Pop) => (Some(Opcode::SetVar(var.clone())), 2),
+
+                (op, Opcode::Unop(unop)) => match constant(op).and_then(|x| fold_unop(*unop, &x)) {
+                    Some(value) => (constant_opcode(value), 2),
+                    None => (None, 0),
+                },
+
+                // branches on a constant condition
+                (op, Opcode::JumpIfFalse(dest)) | (op, Opcode::JumpIfTrue(dest))
+                    if constant(op).is_some() =>
+                {
+                    let jumps_if = matches!(ops[i + 1], Opcode::JumpIfTrue(_));
+                    if constant(op).unwrap().truthy() == jumps_if {
+                        (Some(Opcode::Jump(*dest)), 2)
+                    } else {
+                        (None, 2)
+                    }
+                }
+                _ => (None, 0),
+            }
+        };
+        let len = match (&ops[i], len) {
+            // a jump to the very next opcode
+            (Opcode::Jump(dest), 0) if *dest == i + 1 => 1,
+            _ => len,
+        };
+        if len == 0 {
+            i += 1;
+            continue;
+        }
+        match replacement {
+            Some(op) => ops[i] = op,
+            None => removed[i] = true,
+        }
+        for flag in &mut removed[i + 1..i + len] {
+            *flag = true;
+        }
+        i += len;
+    }
+    removed
+}
+
+/// Removes the flagged opcodes and updates all jump destinations.
+/// A jump to a removed opcode lands on the next opcode that was kept
+fn compact(ops: &mut Vec<Opcode>, marks: &mut Vec<Mark>, removed: &[bool]) {
+    let mut new_index = Vec::with_capacity(ops.len() + 1);
+    let mut count = 0;
+    for flag in removed {
+        new_index.push(count);
+        if !flag {
+            count += 1;
+        }
+    }
+    new_index.push(count);
+
+    let old_ops = std::mem::take(ops);
+    let old_marks = std::mem::take(marks);
+    for ((mut op, mark), flag) in old_ops.into_iter().zip(old_marks).zip(removed) {
+        if *flag {
+            continue;
+        }
+        if let Some(dest) = op.jump_dest() {
+            op.patch_jump(new_index[dest]);
+        }
+        ops.push(op);
+        marks.push(mark);
+    }
+}
+
+fn same_variable(a: &crate::Variable, b: &crate::Variable) -> bool {
+    a.type_() == b.type_() && a.slot() == b.slot()
+}
+
+/// The value pushed by the given opcode, if it just pushes a constant
+fn constant(op: &Opcode) -> Option<Value> {
+    match op {
+        Opcode::Nil => Some(Value::Nil),
+        Opcode::Bool(b) => Some(Value::Bool(*b)),
+        Opcode::Number(x) => Some(Value::Number(*x)),
+        Opcode::Int(x) => Some(Value::Int(*x)),
+        Opcode::String(s) => Some(Value::String(s.clone())),
+        _ => None,
+    }
+}
+
+fn constant_opcode(value: Value) -> Option<Opcode> {
+    match value {
+        Value::Nil => Some(Opcode::Nil),
+        Value::Bool(b) => Some(Opcode::Bool(b)),
+        Value::Number(x) => Some(Opcode::Number(x)),
+        Value::Int(x) => Some(Opcode::Int(x)),
+        Value::String(s) => Some(Opcode::String(s)),
+        _ => None,
+    }
+}
+
+/// Evaluates a binary operation on constants ahead of time,
+/// but only when it would behave identically at runtime
+/// (i.e. would not fail or call a method)
+fn fold_binop(op: Binop, lhs: &Value, rhs: &Value) -> Option<Value> {
+    match Value::numeric_binop(op, lhs, rhs) {
+        Some(result) => result.ok(),
+        None => fold_comparison(op, lhs, rhs),
+    }
+}
+
+fn fold_comparison(op: Binop, lhs: &Value, rhs: &Value) -> Option<Value> {
+    let comparable = matches!(
+        (lhs, rhs),
+        (Value::Number(_), Value::Number(_))
+            | (Value::Int(_), Value::Int(_))
+            | (Value::Int(_), Value::Number(_))
+            | (Value::Number(_), Value::Int(_))
+            | (Value::String(_), Value::String(_))
+    );
+    let result = match op {
+        Binop::Eq => lhs == rhs,
+        Binop::Ne => lhs != rhs,
+        Binop::Lt if comparable => lhs.lt(rhs).ok()?,
+        Binop::Le if comparable => !rhs.lt(lhs).ok()?,
+        Binop::Gt if comparable => rhs.lt(lhs).ok()?,
+        Binop::Ge if comparable => !lhs.lt(rhs).ok()?,
+        _ => return None,
+    };
+    Some(Value::from(result))
+}
+
+fn fold_unop(op: Unop, arg: &Value) -> Option<Value> {
+    match (op, arg) {
+        (Unop::Pos, Value::Number(_)) => Some(arg.clone()),
+        (Unop::Neg, Value::Number(x)) => Some(Value::Number(-x)),
+        (Unop::Pos, Value::Int(_)) => Some(arg.clone()),
+        (Unop::Neg, Value::Int(x)) => x.checked_neg().map(Value::Int),
+        (Unop::Invert, Value::Int(x)) => Some(Value::Int(!x)),
+        (Unop::Not, _) => Some(Value::from(!arg.truthy())),
+        _ => None,
+    }
+}
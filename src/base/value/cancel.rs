@@ -0,0 +1,177 @@
+use crate::Error;
+use crate::Globals;
+use crate::Promise;
+use crate::Result;
+use crate::Value;
+use std::cell::Cell;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+type Cleanup = Box<dyn FnOnce(&mut Globals, &Error)>;
+type Resolve = Box<dyn FnOnce(&mut Globals, Result<Value>)>;
+
+/// A handle for requesting that async operations be abandoned,
+/// like an AbortSignal in Javascript.
+///
+/// Async native functions that accept a token register cleanups with it,
+/// which all run (in the order they were registered) when the token is cancelled.
+/// Clones of a token share the same state.
+#[derive(Clone, Default)]
+pub struct CancelToken(Rc<RefCell<CancelState>>);
+
+#[derive(Default)]
+struct CancelState {
+    reason: Option<Error>,
+    next_id: usize,
+    cleanups: Vec<(usize, Cleanup)>,
+}
+
+/// Identifies a cleanup registered with CancelToken::on_cancel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CancelId(usize);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gets the token out of an optional 'token' argument of a native function
+    pub fn from_arg(value: Value) -> Result<Option<Self>> {
+        match value {
+            Value::Nil => Ok(None),
+            value => Ok(Some(value.into_handle::<Self>()?.borrow().clone())),
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.borrow().reason.is_some()
+    }
+
+    /// The error that operations cancelled with this token fail with,
+    /// if the token has been cancelled
+    pub fn reason(&self) -> Option<Error> {
+        self.0.borrow().reason.clone()
+    }
+
+    /// Fails with the reason for the cancellation if this token has been cancelled
+    pub fn check(&self) -> Result<()> {
+        match self.reason() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    /// Cancels this token, and runs all registered cleanups.
+    /// Without an explicit reason, operations fail with a plain CancelledError.
+    /// Cancelling a token more than once has no effect
+    pub fn cancel(&self, globals: &mut Globals, reason: Option<Error>) {
+        let (reason, cleanups) = {
+            let mut state = self.0.borrow_mut();
+            if state.reason.is_some() {
+                return;
+            }
+            let reason = reason.unwrap_or_else(|| kinderr!(Cancelled, "Cancelled"));
+            state.reason = Some(reason.clone());
+            (reason, std::mem::take(&mut state.cleanups))
+        };
+        for (_, cleanup) in cleanups {
+            cleanup(globals, &reason);
+        }
+    }
+
+    /// Registers a cleanup to run when this token is cancelled.
+    /// If the token is already cancelled, the cleanup runs immediately,
+    /// and None is returned
+    pub fn on_cancel<F>(&self, globals: &mut Globals, f: F) -> Option<CancelId>
+    where
+        F: FnOnce(&mut Globals, &Error) + 'static,
+    {
+        let reason = {
+            let mut state = self.0.borrow_mut();
+            match &state.reason {
+                Some(reason) => reason.clone(),
+                None => {
+                    let id = state.next_id;
+                    state.next_id += 1;
+                    state.cleanups.push((id, Box::new(f)));
+                    return Some(CancelId(id));
+                }
+            }
+        };
+        f(globals, &reason);
+        None
+    }
+
+    /// Unregisters a cleanup, e.g. because the operation it was for finished.
+    /// Returns false if there was no such cleanup
+    pub fn remove(&self, id: CancelId) -> bool {
+        let mut state = self.0.borrow_mut();
+        let len = state.cleanups.len();
+        state.cleanups.retain(|(i, _)| *i != id.0);
+        state.cleanups.len() != len
+    }
+}
+
+impl Promise {
+    /// Like Promise::new, but if the given token is cancelled before the
+    /// promise settles, the cleanup returned by 'start' is run, and the
+    /// promise fails with the token's CancelledError instead.
+    /// With no token, the cleanup is never run
+    pub fn new_cancellable<F, C>(
+        globals: &mut Globals,
+        token: Option<&CancelToken>,
+        start: F,
+    ) -> Rc<RefCell<Self>>
+    where
+        F: FnOnce(&mut Globals, Resolve) -> C,
+        C: FnOnce(&mut Globals) + 'static,
+    {
+        let token = match token {
+            Some(token) => token.clone(),
+            None => {
+                return Self::new(globals, |globals, resolve| {
+                    start(globals, resolve);
+                })
+            }
+        };
+        Self::new(globals, |globals, resolve| {
+            if let Err(error) = token.check() {
+                resolve(globals, Err(error));
+                return;
+            }
+
+            // whichever of the operation and the cancellation comes first
+            // gets to settle the promise
+            let cell = Rc::new(RefCell::new(Some(resolve)));
+            let registration: Rc<Cell<Option<CancelId>>> = Rc::new(Cell::new(None));
+            let cleanup = {
+                let cell = cell.clone();
+                let token = token.clone();
+                let registration = registration.clone();
+                start(
+                    globals,
+                    Box::new(move |globals, result| {
+                        let resolve = cell.borrow_mut().take();
+                        if let Some(resolve) = resolve {
+                            if let Some(id) = registration.take() {
+                                token.remove(id);
+                            }
+                            resolve(globals, result);
+                        }
+                    }),
+                )
+            };
+            if cell.borrow().is_none() {
+                return;
+            }
+            let id = token.on_cancel(globals, move |globals, reason| {
+                let resolve = cell.borrow_mut().take();
+                if let Some(resolve) = resolve {
+                    cleanup(globals);
+                    resolve(globals, Err(reason.clone()));
+                }
+            });
+            registration.set(id);
+        })
+    }
+}
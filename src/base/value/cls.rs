@@ -5,6 +5,7 @@ const SETTER_PREFIX: &'static str = "__set_";
 
 pub struct Class {
     name: RcStr,
+    bases: Vec<Rc<Class>>,
     map: HashMap<RcStr, Value>,
     static_map: HashMap<RcStr, Value>,
     getter_map: HashMap<RcStr, Value>,
@@ -20,6 +21,19 @@ impl Class {
     ) -> Rc<Self> {
         Self::new_with_behavior(name, map, static_map, None)
     }
+    /// Creates a new class that inherits methods from the given base classes
+    /// (static methods are not inherited)
+    pub fn new_with_bases(
+        name: RcStr,
+        bases: Vec<Rc<Class>>,
+        map: HashMap<RcStr, Value>,
+        static_map: HashMap<RcStr, Value>,
+    ) -> Rc<Self> {
+        let map = Self::join_class_maps(map, bases.clone());
+        let mut cls = Self::new_with_behavior(name, map, static_map, None);
+        Rc::get_mut(&mut cls).unwrap().bases = bases;
+        cls
+    }
     pub fn new_with_behavior(
         name: RcStr,
         map: HashMap<RcStr, Value>,
@@ -38,6 +52,7 @@ impl Class {
         }
         Rc::new(Self {
             name,
+            bases: vec![],
             map,
             static_map,
             getter_map,
@@ -48,6 +63,14 @@ impl Class {
     pub fn name(&self) -> &RcStr {
         &self.name
     }
+    pub fn bases(&self) -> &Vec<Rc<Class>> {
+        &self.bases
+    }
+    /// Checks whether this class is the given class, or
+    /// (directly or indirectly) inherits from it
+    pub fn is_subclass_of(&self, other: &Class) -> bool {
+        self == other || self.bases.iter().any(|base| base.is_subclass_of(other))
+    }
     pub fn map(&self) -> &HashMap<RcStr, Value> {
         &self.map
     }
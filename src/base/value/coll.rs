@@ -36,19 +36,35 @@ impl List {
             Err(list) => list.borrow().clone(),
         }
     }
-    pub fn sort(vec: &mut Vec<Value>) -> Result<()> {
+    /// Sorts the values in place, taking '__lt' methods into account
+    /// (see Value::cmp_)
+    pub fn sort(globals: &mut Globals, vec: &mut [Value]) -> Result<()> {
         let mut error: Option<Error> = None;
-        vec.sort_by(|a, b| match a.partial_cmp(&b) {
-            Some(ord) => ord,
-            None => {
-                if error.is_none() {
-                    error = Some(rterr!("{:?} and {:?} are not comparable", a, b));
+        vec.sort_by(|a, b| {
+            if error.is_some() {
+                return cmp::Ordering::Equal;
+            }
+            match a.cmp_(globals, b) {
+                Ok(ord) => ord,
+                Err(e) => {
+                    error = Some(e);
+                    cmp::Ordering::Equal
                 }
-                cmp::Ordering::Equal
             }
         });
         error.map(Err).unwrap_or(Ok(()))
     }
+    /// Finds the first value equal to x, taking '__eq' methods into account
+    pub fn position(&self, globals: &mut Globals, x: &Value) -> Result<Option<usize>> {
+        // the list is copied, in case an '__eq' method modifies it
+        let values = self.borrow().clone();
+        for (i, value) in values.iter().enumerate() {
+            if x.eq_(globals, value)? {
+                return Ok(Some(i));
+            }
+        }
+        Ok(None)
+    }
 }
 
 impl FromIterator<Value> for List {
@@ -87,6 +103,16 @@ impl Set {
         vec.sort();
         vec
     }
+    /// Checks whether x is in the set, taking '__eq' methods into account
+    pub fn has(&self, globals: &mut Globals, x: &Value) -> Result<bool> {
+        match Key::try_from(x) {
+            Ok(key) => Ok(self.borrow().contains(&key)),
+            Err(error) => {
+                let keys = self.borrow().iter().cloned().collect();
+                scan_keys(globals, x, keys, error)
+            }
+        }
+    }
     pub fn generator(set: Rc<Self>) -> NativeGenerator {
         let mut i = 0;
         NativeGenerator::new("set-iterator", move |_globals, _arg| {
@@ -157,6 +183,16 @@ impl Map {
         vec.sort_by(|a, b| a.partial_cmp(&b).unwrap_or(cmp::Ordering::Equal));
         vec
     }
+    /// Checks whether x is a key in the map, taking '__eq' methods into account
+    pub fn has_key(&self, globals: &mut Globals, x: &Value) -> Result<bool> {
+        match Key::try_from(x) {
+            Ok(key) => Ok(self.borrow().contains_key(&key)),
+            Err(error) => {
+                let keys = self.borrow().keys().cloned().collect();
+                scan_keys(globals, x, keys, error)
+            }
+        }
+    }
     pub fn generator(map: Rc<Self>) -> NativeGenerator {
         let mut i = 0;
         NativeGenerator::new("map-iterator", move |_globals, _arg| {
@@ -246,3 +282,19 @@ impl FromIterator<(Key, Value)> for Map {
         }
     }
 }
+
+/// Values that cannot be keys can never be found by hashing, but instances
+/// of script classes (and handles) may still equal a key through '__eq'
+fn scan_keys(globals: &mut Globals, x: &Value, keys: Vec<Key>, error: Error) -> Result<bool> {
+    match x {
+        Value::Table(_) | Value::Handle(_) => {
+            for key in keys {
+                if x.eq_(globals, &key.into())? {
+                    return Ok(true);
+                }
+            }
+            Ok(false)
+        }
+        _ => Err(error),
+    }
+}
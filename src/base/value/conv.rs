@@ -16,61 +16,67 @@ impl From<bool> for Value {
 
 impl From<i64> for Value {
     fn from(x: i64) -> Self {
-        Self::Number(x as f64)
+        Self::Int(x)
     }
 }
 
 impl From<i32> for Value {
     fn from(x: i32) -> Self {
-        Self::Number(x as f64)
+        Self::Int(x as i64)
     }
 }
 
 impl From<i16> for Value {
     fn from(x: i16) -> Self {
-        Self::Number(x as f64)
+        Self::Int(x as i64)
     }
 }
 
 impl From<i8> for Value {
     fn from(x: i8) -> Self {
-        Self::Number(x as f64)
+        Self::Int(x as i64)
     }
 }
 
 impl From<u64> for Value {
     fn from(x: u64) -> Self {
-        Self::Number(x as f64)
+        match i64::try_from(x) {
+            Ok(x) => Self::Int(x),
+            Err(_) => Self::Number(x as f64),
+        }
     }
 }
 
 impl From<u32> for Value {
     fn from(x: u32) -> Self {
-        Self::Number(x as f64)
+        Self::Int(x as i64)
     }
 }
 
 impl From<u16> for Value {
     fn from(x: u16) -> Self {
-        Self::Number(x as f64)
+        Self::Int(x as i64)
     }
 }
 
 impl From<u8> for Value {
     fn from(x: u8) -> Self {
-        Self::Number(x as f64)
+        Self::Int(x as i64)
     }
 }
 
 impl From<usize> for Value {
     fn from(x: usize) -> Self {
-        Self::Number(x as f64)
+        match i64::try_from(x) {
+            Ok(x) => Self::Int(x),
+            Err(_) => Self::Number(x as f64),
+        }
     }
 }
 
 impl From<isize> for Value {
     fn from(x: isize) -> Self {
-        Self::Number(x as f64)
+        Self::Int(x as i64)
     }
 }
 
@@ -240,7 +246,8 @@ impl TryFrom<Value> for Key {
         match value {
             Value::Nil => Ok(Key::Nil),
             Value::Bool(x) => Ok(Key::Bool(x)),
-            Value::Number(x) => Ok(Key::NumberBits(x.to_bits() as i64)),
+            Value::Number(x) => Ok(Key::from(x)),
+            Value::Int(x) => Ok(Key::Int(x)),
             Value::String(x) => Ok(Key::String(x)),
             Value::List(x) => Ok(Key::List(
                 x.borrow()
@@ -263,6 +270,7 @@ impl From<Key> for Value {
             Key::Nil => Value::Nil,
             Key::Bool(x) => Value::Bool(x),
             Key::NumberBits(bits) => Value::Number(f64::from_bits(bits as u64)),
+            Key::Int(x) => Value::Int(x),
             Key::String(s) => Value::String(s),
             Key::List(list) => list
                 .into_iter()
@@ -301,6 +309,7 @@ impl From<ConstVal> for Value {
             ConstVal::Nil => Value::Nil,
             ConstVal::Bool(x) => Value::Bool(x),
             ConstVal::Number(x) => Value::Number(x),
+            ConstVal::Int(x) => Value::Int(x),
             ConstVal::String(x) => Value::String(x),
             ConstVal::List(x) => Value::from(x.into_iter().map(Value::from).collect::<Vec<_>>()),
         }
@@ -346,6 +355,11 @@ macro_rules! try_from_for_int {
             type Error = Error;
 
             fn try_from(v: &Value) -> Result<Self> {
+                if let Value::Int(i) = v {
+                    return <$t>::try_from(*i).map_err(|_| {
+                        rterr!(concat!("Expected ", stringify!($t), " but got {:?}"), i)
+                    });
+                }
                 let x = v.number()?;
                 if x < <$t>::MIN as f64
                     || x > <$t>::MAX as f64
@@ -7,6 +7,7 @@ pub enum ConstVal {
     Nil,
     Bool(bool),
     Number(f64),
+    Int(i64),
     String(RcStr),
     List(Vec<ConstVal>),
 }
@@ -27,6 +28,7 @@ impl fmt::Debug for ConstVal {
             Self::Nil => write!(f, "nil"),
             Self::Bool(b) => write!(f, "{}", if *b { "true" } else { "false" }),
             Self::Number(n) => write!(f, "{}", n),
+            Self::Int(n) => write!(f, "{}", n),
             Self::String(s) => write!(f, "{:?}", s),
             Self::List(xs) => {
                 write!(f, "[")?;
@@ -58,61 +60,67 @@ impl From<bool> for ConstVal {
 
 impl From<i64> for ConstVal {
     fn from(x: i64) -> Self {
-        Self::Number(x as f64)
+        Self::Int(x)
     }
 }
 
 impl From<i32> for ConstVal {
     fn from(x: i32) -> Self {
-        Self::Number(x as f64)
+        Self::Int(x as i64)
     }
 }
 
 impl From<i16> for ConstVal {
     fn from(x: i16) -> Self {
-        Self::Number(x as f64)
+        Self::Int(x as i64)
     }
 }
 
 impl From<i8> for ConstVal {
     fn from(x: i8) -> Self {
-        Self::Number(x as f64)
+        Self::Int(x as i64)
     }
 }
 
 impl From<u64> for ConstVal {
     fn from(x: u64) -> Self {
-        Self::Number(x as f64)
+        match i64::try_from(x) {
+            Ok(x) => Self::Int(x),
+            Err(_) => Self::Number(x as f64),
+        }
     }
 }
 
 impl From<u32> for ConstVal {
     fn from(x: u32) -> Self {
-        Self::Number(x as f64)
+        Self::Int(x as i64)
     }
 }
 
 impl From<u16> for ConstVal {
     fn from(x: u16) -> Self {
-        Self::Number(x as f64)
+        Self::Int(x as i64)
     }
 }
 
 impl From<u8> for ConstVal {
     fn from(x: u8) -> Self {
-        Self::Number(x as f64)
+        Self::Int(x as i64)
     }
 }
 
 impl From<usize> for ConstVal {
     fn from(x: usize) -> Self {
-        Self::Number(x as f64)
+        match i64::try_from(x) {
+            Ok(x) => Self::Int(x),
+            Err(_) => Self::Number(x as f64),
+        }
     }
 }
 
 impl From<isize> for ConstVal {
     fn from(x: isize) -> Self {
-        Self::Number(x as f64)
+        Self::Int(x as i64)
     }
 }
 
@@ -7,6 +7,7 @@ impl fmt::Debug for Value {
             Value::Nil => write!(f, "nil"),
             Value::Bool(x) => write!(f, "{}", if *x { "true" } else { "false" }),
             Value::Number(x) => write!(f, "{}", x),
+            Value::Int(x) => write!(f, "{}", x),
             Value::String(x) => {
                 write!(f, "\"")?;
                 for c in x.chars() {
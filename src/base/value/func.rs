@@ -49,6 +49,26 @@ impl ArgSpec {
             + if self.key.is_some() { 1 } else { 0 }
     }
 
+    pub fn req(&self) -> &Vec<RcStr> {
+        &self.req
+    }
+
+    pub fn def(&self) -> &Vec<(RcStr, ConstVal)> {
+        &self.def
+    }
+
+    pub fn var(&self) -> &Option<RcStr> {
+        &self.var
+    }
+
+    pub fn key(&self) -> &Option<RcStr> {
+        &self.key
+    }
+
+    pub fn mark(&self) -> &Option<Mark> {
+        &self.mark
+    }
+
     pub fn params(&self) -> Vec<RcStr> {
         let mut ret = self.req.clone();
         for (name, _) in &self.def {
@@ -132,11 +152,22 @@ impl ArgSpec {
         let argc = args.len();
         if argc < lower || (argc > upper && self.var.is_none()) {
             return Err(if self.var.is_some() {
-                rterr!("Expected at least {} args but got {}", lower, argc)
+                kinderr!(
+                    Argument,
+                    "Expected at least {} args but got {}",
+                    lower,
+                    argc
+                )
             } else if self.def.len() > 0 {
-                rterr!("Expected at {} to {} args but got {}", lower, upper, argc)
+                kinderr!(
+                    Argument,
+                    "Expected at {} to {} args but got {}",
+                    lower,
+                    upper,
+                    argc
+                )
             } else {
-                rterr!("Expected {} args but got {}", lower, argc)
+                kinderr!(Argument, "Expected {} args but got {}", lower, argc)
             });
         }
         if lower < upper && argc < upper {
@@ -476,12 +507,21 @@ impl Function {
             }
             FunctionKind::Async => Ok(Promise::new(globals, |globals, resolve| {
                 let mut frame = self.code.new_frame_with_args(self.bindings.clone(), args);
+                // the logical call chain of this frame, which it has to carry
+                // with it across any awaits
+                let chain = globals.trace().clone();
                 match self.code.start_async(globals, &mut frame) {
                     AsyncResult::Return(value) => resolve(globals, Ok(value)),
                     AsyncResult::Await(promise) => {
-                        continue_async(self.code.clone(), frame, promise, globals, resolve);
+                        continue_async(self.code.clone(), frame, chain, promise, globals, resolve);
+                    }
+                    AsyncResult::Err(error) => {
+                        // the caller gets a failed promise rather than an error,
+                        // so the marks of the failure have to move into the error
+                        let error = error.prepended(globals.trace().clone());
+                        globals.trace_unwind(chain.len());
+                        resolve(globals, Err(error))
                     }
-                    AsyncResult::Err(error) => resolve(globals, Err(error)),
                 }
             })
             .into()),
@@ -495,9 +535,17 @@ pub enum AsyncResult {
     Err(Error),
 }
 
+/// Waits on the given promise, and then resumes the suspended frame,
+/// either with the value of the promise, or by throwing its error at the await.
+///
+/// The chain is the trace at the time the async function was called. It is put
+/// back on the trace whenever the frame resumes, so the trace of an error that
+/// escapes an async function is built in causal order: the chain, an async
+/// boundary, and then the marks of the failure after the frame resumed
 fn continue_async(
     code: Rc<Code>,
     mut frame: Frame,
+    chain: Vec<Mark>,
     promise: Rc<RefCell<Promise>>,
     globals: &mut Globals,
     resolve: Box<dyn FnOnce(&mut Globals, Result<Value>)>,
@@ -505,32 +553,61 @@ fn continue_async(
     let mark = code.marks()[frame.pc() - 1].clone();
     promise
         .borrow_mut()
-        .register(globals, move |globals, result| match result {
-            Ok(arg) => {
+        .register(globals, move |globals, result| {
+            // whatever resolved the promise is not part of the logical call
+            // chain of this frame, so the trace only starts from here
+            let trace_len = globals.trace().len();
+            for mark in &chain {
                 globals.trace_push(mark.clone());
-                match code.resume_async(globals, &mut frame, arg) {
-                    AsyncResult::Return(value) => {
-                        globals.trace_pop();
-                        resolve(globals, Ok(value))
-                    }
-                    AsyncResult::Await(promise) => {
-                        globals.trace_pop();
-                        continue_async(code, frame, promise, globals, resolve);
+            }
+            let result = match result {
+                Ok(arg) => code.resume_async(globals, &mut frame, arg),
+                Err(error) => {
+                    let error = error_at_await(&code, &chain, mark, error);
+                    match code.throw_async(globals, &mut frame, error) {
+                        Ok(result) => result,
+                        Err(error) => {
+                            globals.trace_unwind(trace_len);
+                            resolve(globals, Err(error));
+                            return;
+                        }
                     }
-                    AsyncResult::Err(error) => resolve(globals, Err(error)),
                 }
-            }
-            Err(error) => {
-                // Unfortunately, this will mix up the order of the
-                // stacktraces (i.e. all the non-awaits will appear before the
-                // awaits regardless of whether they actually occurred after
-                // or before an await)
-                //
-                // At least this way though, we will at least have all the
-                // stack traces, and within each group (i.e. all the
-                // non-await traces, and all the await traces) should be
-                // in roughly the correct order
-                resolve(globals, Err(error.prepended(vec![mark])))
+            };
+            match result {
+                AsyncResult::Return(value) => {
+                    globals.trace_unwind(trace_len);
+                    resolve(globals, Ok(value))
+                }
+                AsyncResult::Await(promise) => {
+                    globals.trace_unwind(trace_len);
+                    continue_async(code, frame, chain, promise, globals, resolve);
+                }
+                AsyncResult::Err(error) => {
+                    let error = error
+                        .prepended(globals.trace()[trace_len + chain.len()..].to_vec())
+                        .prepended_async(chain);
+                    globals.trace_unwind(trace_len);
+                    resolve(globals, Err(error))
+                }
             }
         });
 }
+
+/// The error to throw at an await, when the promise being waited on failed.
+/// If the failure came from something this function called itself (e.g. another
+/// async function), its trace already starts with how it got there.
+/// Otherwise, it is put after the chain and the await point
+fn error_at_await(code: &Code, chain: &[Mark], mark: Mark, error: Error) -> Error {
+    let trace = error.trace();
+    if trace.len() > chain.len()
+        && trace[..chain.len()] == *chain
+        && code.marks().contains(&trace[chain.len()])
+    {
+        error
+    } else {
+        let mut prefix = chain.to_vec();
+        prefix.push(mark);
+        error.prepended_async(prefix)
+    }
+}
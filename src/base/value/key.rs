@@ -4,16 +4,79 @@ use std::cmp;
 use std::hash::Hash;
 use std::hash::Hasher;
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// Numeric keys are equal when their values are, so that e.g. '1' and '1.0'
+/// are the same key, but each key keeps the type it was created with
+#[derive(Debug, Clone)]
 pub enum Key {
     Nil,
     Bool(bool),
+    Int(i64),
     NumberBits(i64), // f64 stored as bits
     String(RcStr),
     List(Vec<Key>),
     Set(HSet),
 }
 
+/// What keys are compared and hashed by: floats with an exact integer value
+/// are treated as the equal Int
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum Canonical<'a> {
+    Nil,
+    Bool(bool),
+    Int(i64),
+    NumberBits(i64),
+    String(&'a RcStr),
+    List(&'a [Key]),
+    Set(&'a HSet),
+}
+
+impl Key {
+    fn canonical(&self) -> Canonical<'_> {
+        match self {
+            Self::Nil => Canonical::Nil,
+            Self::Bool(x) => Canonical::Bool(*x),
+            Self::Int(x) => Canonical::Int(*x),
+            Self::NumberBits(bits) => {
+                let x = f64::from_bits(*bits as u64);
+                if x.fract() == 0.0 && x >= -(2f64.powi(63)) && x < 2f64.powi(63) {
+                    Canonical::Int(x as i64)
+                } else {
+                    Canonical::NumberBits(*bits)
+                }
+            }
+            Self::String(s) => Canonical::String(s),
+            Self::List(list) => Canonical::List(list),
+            Self::Set(set) => Canonical::Set(set),
+        }
+    }
+}
+
+impl cmp::PartialEq for Key {
+    fn eq(&self, other: &Self) -> bool {
+        self.canonical() == other.canonical()
+    }
+}
+
+impl cmp::Eq for Key {}
+
+impl Hash for Key {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.canonical().hash(state);
+    }
+}
+
+impl cmp::PartialOrd for Key {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl cmp::Ord for Key {
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        self.canonical().cmp(&other.canonical())
+    }
+}
+
 /// Basically a IndexSet that has been made Ord and Hash
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct HSet(pub IndexSet<Key>);
@@ -56,6 +119,18 @@ impl From<bool> for Key {
     }
 }
 
+impl From<f64> for Key {
+    fn from(x: f64) -> Self {
+        Self::NumberBits(x.to_bits() as i64)
+    }
+}
+
+impl From<i64> for Key {
+    fn from(x: i64) -> Self {
+        Self::Int(x)
+    }
+}
+
 impl From<RcStr> for Key {
     fn from(s: RcStr) -> Self {
         Self::String(s)
@@ -1,3 +1,4 @@
+mod cancel;
 mod cls;
 mod coll;
 mod conv;
@@ -9,6 +10,7 @@ mod hnd;
 mod key;
 mod m;
 mod num;
+mod ops;
 mod promise;
 mod strs;
 mod table;
@@ -39,6 +41,7 @@ use std::marker::PhantomData;
 use std::rc::Rc;
 use std::rc::Weak;
 
+pub use cancel::*;
 pub use cls::*;
 pub use coll::*;
 pub use conv::*;
@@ -52,12 +55,13 @@ pub use promise::*;
 pub use table::*;
 pub use xrefm::*;
 
-#[derive(Clone, PartialEq)]
+#[derive(Clone)]
 pub enum Value {
     Invalid,
     Nil,
     Bool(bool),
     Number(f64),
+    Int(i64),
     String(RcStr),
     List(Rc<List>),
     Set(Rc<Set>),
@@ -80,6 +84,7 @@ impl Value {
             Self::Nil => false,
             Self::Bool(b) => *b,
             Self::Number(x) => *x != 0.0,
+            Self::Int(x) => *x != 0,
             Self::String(s) => s.len() > 0,
             Self::List(list) => list.borrow().len() > 0,
             Self::Set(set) => set.borrow().len() > 0,
@@ -101,6 +106,7 @@ impl Value {
             Self::Nil => "Nil".into(),
             Self::Bool(_) => "Bool".into(),
             Self::Number(_) => "Number".into(),
+            Self::Int(_) => "Int".into(),
             Self::String(_) => "String".into(),
             Self::List(_) => "List".into(),
             Self::Set(_) => "Set".into(),
@@ -117,7 +123,7 @@ impl Value {
         }
     }
     fn terr(&self, etype: &str) -> Error {
-        rterr!("Expected {} but got {}", etype, self.debug_typename())
+        kinderr!(Type, "Expected {} but got {}", etype, self.debug_typename())
     }
     pub fn is(&self, other: &Self) -> bool {
         match (self, other) {
@@ -125,6 +131,8 @@ impl Value {
             (Self::Nil, Self::Nil) => true,
             (Self::Bool(a), Self::Bool(b)) => a == b,
             (Self::Number(a), Self::Number(b)) => a == b,
+            (Self::Int(a), Self::Int(b)) => a == b,
+            (Self::Int(_), Self::Number(_)) | (Self::Number(_), Self::Int(_)) => self == other,
             (Self::String(a), Self::String(b)) => a.as_ptr() == b.as_ptr(),
             (Self::List(a), Self::List(b)) => Rc::as_ptr(a) == Rc::as_ptr(b),
             (Self::Set(a), Self::Set(b)) => Rc::as_ptr(a) == Rc::as_ptr(b),
@@ -149,7 +157,8 @@ impl Value {
     pub fn lt(&self, other: &Self) -> Result<bool> {
         match self.partial_cmp(other) {
             Some(ord) => Ok(matches!(ord, cmp::Ordering::Less)),
-            None => Err(rterr!(
+            None => Err(kinderr!(
+                Type,
                 "{} and {} are not comparable",
                 self.debug_typename(),
                 other.debug_typename()
@@ -167,12 +176,22 @@ impl Value {
         }
     }
     pub fn number(&self) -> Result<f64> {
-        if let Self::Number(x) = self {
+        match self {
+            Self::Number(x) => Ok(*x),
+            Self::Int(x) => Ok(*x as f64),
+            _ => Err(self.terr("number")),
+        }
+    }
+    pub fn int(&self) -> Result<i64> {
+        if let Self::Int(x) = self {
             Ok(*x)
         } else {
-            Err(self.terr("number"))
+            Err(self.terr("int"))
         }
     }
+    pub fn is_number(&self) -> bool {
+        matches!(self, Self::Number(_) | Self::Int(_))
+    }
     pub fn string(&self) -> Result<&RcStr> {
         if let Self::String(x) = self {
             Ok(x)
@@ -329,16 +348,18 @@ impl Value {
         match self {
             Self::Bool(b) => Ok(if b { 1 } else { 0 }),
             Self::Number(x) => Ok(x as i64),
+            Self::Int(x) => Ok(x),
             Self::String(r) => Ok(r.parse::<i64>()?),
-            x => Err(rterr!("Could not convert {:?} into int", x)),
+            x => Err(kinderr!(Type, "Could not convert {:?} into int", x)),
         }
     }
     pub fn convert_to_float(self) -> Result<f64> {
         match self {
             Self::Bool(b) => Ok(if b { 1.0 } else { 0.0 }),
             Self::Number(x) => Ok(x),
+            Self::Int(x) => Ok(x as f64),
             Self::String(r) => Ok(r.parse::<f64>()?),
-            x => Err(rterr!("Could not convert {:?} into float", x)),
+            x => Err(kinderr!(Type, "Could not convert {:?} into float", x)),
         }
     }
     pub fn convert_to_rcstr(self) -> RcStr {
@@ -391,7 +412,12 @@ impl Value {
     pub fn getattr(&self, globals: &mut Globals, attr: &RcStr) -> Result<Value> {
         match self.getattr_opt(globals, attr)? {
             Some(value) => Ok(value),
-            None => Err(rterr!("Attribute {:?} not found in {:?}", attr, self)),
+            None => Err(kinderr!(
+                Attribute,
+                "Attribute {:?} not found in {:?}",
+                attr,
+                self
+            )),
         }
     }
     pub fn getattrs(&self) -> Vec<RcStr> {
@@ -430,7 +456,12 @@ impl Value {
                     if let Some(setter) = table.cls().get_setter(attr) {
                         setter.apply(globals, vec![self.clone(), value], None)?;
                     } else {
-                        return Err(rterr!("Attribute {:?} not found in {:?}", attr, self));
+                        return Err(kinderr!(
+                            Attribute,
+                            "Attribute {:?} not found in {:?}",
+                            attr,
+                            self
+                        ));
                     }
                 }
             }
@@ -438,7 +469,14 @@ impl Value {
                 let setattr = handle.cls().behavior().setattr().as_ref().unwrap();
                 setattr(globals, self.clone(), attr.str(), value)?;
             }
-            _ => return Err(rterr!("Attribute {:?} not found in {:?}", attr, self)),
+            _ => {
+                return Err(kinderr!(
+                    Attribute,
+                    "Attribute {:?} not found in {:?}",
+                    attr,
+                    self
+                ))
+            }
         }
         #[allow(unreachable_code)]
         Ok(())
@@ -454,12 +492,13 @@ impl Value {
             Self::NativeFunction(func) => func.apply(globals, args, kwargs),
             Self::Class(cls) => match cls.get_call() {
                 Some(func) => func.apply(globals, args, kwargs),
-                None => Err(rterr!(
+                None => Err(kinderr!(
+                    Type,
                     "{:?} is not callable (the class has no static __call method)",
                     cls
                 )),
             },
-            _ => Err(rterr!("{:?} is not a function", self)),
+            _ => Err(kinderr!(Type, "{:?} is not a function", self)),
         }
     }
     pub fn apply_method<M>(
@@ -476,11 +515,21 @@ impl Value {
         match self {
             Self::Class(cls) => match cls.static_map().get(method_name) {
                 Some(method) => method.apply(globals, args, kwargs),
-                None => Err(rterr!("{:?} not found in {:?}", method_name, cls)),
+                None => Err(kinderr!(
+                    Attribute,
+                    "{:?} not found in {:?}",
+                    method_name,
+                    cls
+                )),
             },
             Self::Module(module) => match module.get(method_name) {
                 Some(method) => method.apply(globals, args, kwargs),
-                None => Err(rterr!("{:?} not found in {:?}", method_name, module)),
+                None => Err(kinderr!(
+                    Attribute,
+                    "{:?} not found in {:?}",
+                    method_name,
+                    module
+                )),
             },
             Self::Handle(handle) if handle.cls().behavior().method_call().is_some() => {
                 let method_call = handle.cls().behavior().method_call().as_ref().unwrap();
@@ -493,7 +542,8 @@ impl Value {
                         args.insert(0, self.clone());
                         method.apply(globals, args, kwargs)
                     }
-                    None => Err(rterr!(
+                    None => Err(kinderr!(
+                        Attribute,
                         "Method {:?} not found for instance of {:?}",
                         method_name,
                         cls
@@ -506,7 +556,7 @@ impl Value {
         match self {
             Self::Generator(gen) => gen.borrow_mut().resume(globals, arg),
             Self::NativeGenerator(gen) => gen.borrow_mut().resume(globals, arg),
-            _ => ResumeResult::Err(rterr!("{:?} is not a generator", self)),
+            _ => ResumeResult::Err(kinderr!(Type, "{:?} is not a generator", self)),
         }
     }
     pub fn to_index(&self, len: usize) -> Result<usize> {
@@ -562,7 +612,7 @@ impl Value {
                 let key = Key::try_from(index)?;
                 match map.get(&key) {
                     Some(value) => Ok(value.clone()),
-                    None => Err(rterr!("Key {:?} not found in map", key)),
+                    None => Err(kinderr!(NotFound, "Key {:?} not found in map", key)),
                 }
             }
             _ => self.apply_method(globals, "__getitem", vec![index.clone()], None),
@@ -588,12 +638,47 @@ impl Value {
     }
 }
 
+/// Ints and floats compare by their exact mathematical values
+impl cmp::PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Invalid, Self::Invalid) => true,
+            (Self::Nil, Self::Nil) => true,
+            (Self::Bool(a), Self::Bool(b)) => a == b,
+            (Self::Number(a), Self::Number(b)) => a == b,
+            (Self::Int(a), Self::Int(b)) => a == b,
+            (Self::Int(a), Self::Number(b)) | (Self::Number(b), Self::Int(a)) => {
+                num::cmp_int_float(*a, *b) == Some(cmp::Ordering::Equal)
+            }
+            (Self::String(a), Self::String(b)) => a == b,
+            (Self::List(a), Self::List(b)) => a == b,
+            (Self::Set(a), Self::Set(b)) => a == b,
+            (Self::Map(a), Self::Map(b)) => a == b,
+            (Self::Table(a), Self::Table(b)) => a == b,
+            (Self::Function(a), Self::Function(b)) => a == b,
+            (Self::NativeFunction(a), Self::NativeFunction(b)) => a == b,
+            (Self::Generator(a), Self::Generator(b)) => a == b,
+            (Self::NativeGenerator(a), Self::NativeGenerator(b)) => a == b,
+            (Self::Promise(a), Self::Promise(b)) => a == b,
+            (Self::Class(a), Self::Class(b)) => a == b,
+            (Self::Module(a), Self::Module(b)) => a == b,
+            (Self::Handle(a), Self::Handle(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
 impl cmp::PartialOrd for Value {
     fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
         match (self, other) {
             (Self::Nil, Self::Nil) => Some(cmp::Ordering::Equal),
             (Self::Bool(a), Self::Bool(b)) => a.partial_cmp(b),
             (Self::Number(a), Self::Number(b)) => a.partial_cmp(b),
+            (Self::Int(a), Self::Int(b)) => a.partial_cmp(b),
+            (Self::Int(a), Self::Number(b)) => num::cmp_int_float(*a, *b),
+            (Self::Number(a), Self::Int(b)) => {
+                num::cmp_int_float(*b, *a).map(cmp::Ordering::reverse)
+            }
             (Self::String(a), Self::String(b)) => a.partial_cmp(b),
             (Self::List(a), Self::List(b)) => a.partial_cmp(b),
             (Self::Set(a), Self::Set(b)) => a.partial_cmp(b),
@@ -0,0 +1,276 @@
+//! Operator overloading for script class instances and handles
+//!
+//! Arithmetic operators call '__add', '__sub', '__mul', '__div', '__truncdiv',
+//! '__rem', '__erem' and '__pow' on the left operand, or if it does not have
+//! the method, the reflected variant ('__radd', '__rsub', ...) on the right.
+//!
+//! '==' and '!=' call '__eq' on either operand (left first).
+//! '<' calls '__lt' on the left operand, or '__rlt' on the right, and
+//! '<=' calls '__le' or '__rle' the same way, falling back to '!(b < a)'.
+//! '>' and '>=' are '<' and '<=' with the operands swapped.
+//!
+//! '-x', '+x' and '!x' call '__neg', '__pos' and '__not' respectively.
+//!
+//! The bitwise operators '&', '|', '^', '<<', '>>' and '~' call
+//! '__and', '__or', '__xor', '__shl', '__shr' and '__invert' (with reflected
+//! variants for the binary ones).
+//!
+//! Arithmetic on two ints is exact, and fails on overflow
+//! ('Int' has 'wrapping_*' methods for when wrapping is wanted).
+//! If either operand is a float, both are converted to floats,
+//! except for the bitwise operators, which only accept ints.
+use super::*;
+use crate::Binop;
+use crate::Unop;
+
+impl Value {
+    /// Looks up an operator method on the class of a script class instance
+    /// or handle. Other values have fixed behavior for operators
+    /// (their methods are still called for arithmetic, but are never reflected)
+    fn operator_method(&self, globals: &Globals, name: &str) -> Option<Value> {
+        match self {
+            Self::Table(_) | Self::Handle(_) => self.get_class(globals).map().get(name).cloned(),
+            _ => None,
+        }
+    }
+
+    fn apply_operator_method(
+        &self,
+        globals: &mut Globals,
+        method: Value,
+        mut args: Vec<Value>,
+    ) -> Result<Value> {
+        args.insert(0, self.clone());
+        method.apply(globals, args, None)
+    }
+
+    /// Applies an arithmetic or bitwise operator to two ints
+    pub fn int_binop(op: Binop, a: i64, b: i64) -> Result<Value> {
+        let overflow = || rterr!("Integer overflow ({} {:?} {})", a, op, b);
+        let nonzero = || {
+            if b == 0 {
+                Err(rterr!("Integer division by zero"))
+            } else {
+                Ok(())
+            }
+        };
+        let x = match op {
+            Binop::Add => a.checked_add(b).ok_or_else(overflow)?,
+            Binop::Sub => a.checked_sub(b).ok_or_else(overflow)?,
+            Binop::Mul => a.checked_mul(b).ok_or_else(overflow)?,
+            Binop::Div => return Ok(Value::Number(a as f64 / b as f64)),
+            Binop::TruncDiv => {
+                nonzero()?;
+                a.checked_div(b).ok_or_else(overflow)?
+            }
+            Binop::Rem => {
+                nonzero()?;
+                a.wrapping_rem(b)
+            }
+            Binop::ERem => {
+                nonzero()?;
+                a.wrapping_rem_euclid(b)
+            }
+            Binop::Pow if b < 0 => return Ok(Value::Number((a as f64).powf(b as f64))),
+            Binop::Pow => u32::try_from(b)
+                .ok()
+                .and_then(|b| a.checked_pow(b))
+                .ok_or_else(overflow)?,
+            Binop::BitAnd => a & b,
+            Binop::BitOr => a | b,
+            Binop::BitXor => a ^ b,
+            Binop::Shl | Binop::Shr if b < 0 => return Err(rterr!("Negative shift count ({})", b)),
+            Binop::Shl => u32::try_from(b)
+                .ok()
+                .and_then(|b| a.checked_shl(b))
+                .unwrap_or(0),
+            Binop::Shr => u32::try_from(b)
+                .ok()
+                .and_then(|b| a.checked_shr(b))
+                .unwrap_or(if a < 0 { -1 } else { 0 }),
+            _ => panic!("int_binop({:?})", op),
+        };
+        Ok(Value::Int(x))
+    }
+
+    /// Applies an arithmetic or bitwise operator if both operands are numbers.
+    /// Returns None if the operator is not one of these, or if either operand
+    /// is not a number
+    pub fn numeric_binop(op: Binop, lhs: &Value, rhs: &Value) -> Option<Result<Value>> {
+        if !lhs.is_number() || !rhs.is_number() {
+            return None;
+        }
+        if let (Self::Int(a), Self::Int(b)) = (lhs, rhs) {
+            if is_arithmetic(op) || is_bitwise(op) {
+                return Some(Self::int_binop(op, *a, *b));
+            }
+        }
+        if is_bitwise(op) {
+            let float = if let Self::Number(_) = lhs { lhs } else { rhs };
+            return Some(Err(float.terr("int")));
+        }
+        let (a, b) = (lhs.number().ok()?, rhs.number().ok()?);
+        let x = match op {
+            Binop::Add => a + b,
+            Binop::Sub => a - b,
+            Binop::Mul => a * b,
+            Binop::Div => a / b,
+            Binop::TruncDiv => (a / b).trunc(),
+            Binop::Rem => a % b,
+            Binop::ERem => a.rem_euclid(b),
+            Binop::Pow => a.powf(b),
+            _ => return None,
+        };
+        Some(Ok(Self::Number(x)))
+    }
+
+    /// Applies an arithmetic or bitwise operator where the fast path
+    /// for numbers does not apply
+    pub fn binop_method(self, globals: &mut Globals, op: Binop, rhs: Value) -> Result<Value> {
+        if let Some(result) = Self::numeric_binop(op, &self, &rhs) {
+            return result;
+        }
+        let (name, rname) = match op {
+            Binop::Add => ("__add", "__radd"),
+            Binop::Sub => ("__sub", "__rsub"),
+            Binop::Mul => ("__mul", "__rmul"),
+            Binop::Div => ("__div", "__rdiv"),
+            Binop::TruncDiv => ("__truncdiv", "__rtruncdiv"),
+            Binop::Rem => ("__rem", "__rrem"),
+            Binop::ERem => ("__erem", "__rerem"),
+            Binop::Pow => ("__pow", "__rpow"),
+            Binop::BitAnd => ("__and", "__rand"),
+            Binop::BitOr => ("__or", "__ror"),
+            Binop::BitXor => ("__xor", "__rxor"),
+            Binop::Shl => ("__shl", "__rshl"),
+            Binop::Shr => ("__shr", "__rshr"),
+            _ => panic!("binop_method({:?})", op),
+        };
+        if let Some(method) = self.operator_method(globals, name) {
+            return self.apply_operator_method(globals, method, vec![rhs]);
+        }
+        if let Some(method) = rhs.operator_method(globals, rname) {
+            return rhs.apply_operator_method(globals, method, vec![self]);
+        }
+        match self {
+            Self::Int(_) if is_bitwise(op) => Err(rhs.terr("int")),
+            Self::Number(_) | Self::Int(_) => Err(rhs.terr("number")),
+            _ => self.apply_method(globals, name, vec![rhs], None),
+        }
+    }
+
+    /// Applies a unary operator where the fast path for numbers does not apply
+    pub fn unop_method(self, globals: &mut Globals, op: Unop) -> Result<Value> {
+        match (op, &self) {
+            (Unop::Pos, Self::Number(_)) | (Unop::Pos, Self::Int(_)) => return Ok(self),
+            (Unop::Neg, Self::Number(x)) => return Ok(Self::Number(-x)),
+            (Unop::Neg, Self::Int(x)) => {
+                return x
+                    .checked_neg()
+                    .map(Self::Int)
+                    .ok_or_else(|| rterr!("Integer overflow (-{})", x))
+            }
+            (Unop::Invert, Self::Int(x)) => return Ok(Self::Int(!x)),
+            _ => {}
+        }
+        let name = match op {
+            Unop::Pos => "__pos",
+            Unop::Neg => "__neg",
+            Unop::Not => "__not",
+            Unop::Invert => "__invert",
+        };
+        match self.operator_method(globals, name) {
+            Some(method) => self.apply_operator_method(globals, method, vec![]),
+            None if matches!(op, Unop::Not) => Ok(Value::from(!self.truthy())),
+            None => Err(rterr!(
+                "Unop {:?} not supported for {:?}",
+                op,
+                self.get_class(globals)
+            )),
+        }
+    }
+
+    /// '==', taking '__eq' methods into account
+    pub fn eq_(&self, globals: &mut Globals, other: &Self) -> Result<bool> {
+        if let Some(method) = self.operator_method(globals, "__eq") {
+            Ok(self
+                .apply_operator_method(globals, method, vec![other.clone()])?
+                .truthy())
+        } else if let Some(method) = other.operator_method(globals, "__eq") {
+            Ok(other
+                .apply_operator_method(globals, method, vec![self.clone()])?
+                .truthy())
+        } else {
+            Ok(self == other)
+        }
+    }
+
+    /// '<', taking '__lt' and '__rlt' methods into account
+    pub fn lt_(&self, globals: &mut Globals, other: &Self) -> Result<bool> {
+        if let Some(method) = self.operator_method(globals, "__lt") {
+            Ok(self
+                .apply_operator_method(globals, method, vec![other.clone()])?
+                .truthy())
+        } else if let Some(method) = other.operator_method(globals, "__rlt") {
+            Ok(other
+                .apply_operator_method(globals, method, vec![self.clone()])?
+                .truthy())
+        } else {
+            self.lt(other)
+        }
+    }
+
+    /// '<=', taking '__le' and '__rle' methods into account
+    pub fn le_(&self, globals: &mut Globals, other: &Self) -> Result<bool> {
+        if let Some(method) = self.operator_method(globals, "__le") {
+            Ok(self
+                .apply_operator_method(globals, method, vec![other.clone()])?
+                .truthy())
+        } else if let Some(method) = other.operator_method(globals, "__rle") {
+            Ok(other
+                .apply_operator_method(globals, method, vec![self.clone()])?
+                .truthy())
+        } else {
+            Ok(!other.lt_(globals, self)?)
+        }
+    }
+
+    /// Compares two values for sorting, taking operator methods into account
+    pub fn cmp_(&self, globals: &mut Globals, other: &Self) -> Result<cmp::Ordering> {
+        let overloaded = |x: &Value| matches!(x, Value::Table(_) | Value::Handle(_));
+        if !overloaded(self) && !overloaded(other) {
+            return match self.partial_cmp(other) {
+                Some(ord) => Ok(ord),
+                None => Err(rterr!("{:?} and {:?} are not comparable", self, other)),
+            };
+        }
+        if self.lt_(globals, other)? {
+            Ok(cmp::Ordering::Less)
+        } else if other.lt_(globals, self)? {
+            Ok(cmp::Ordering::Greater)
+        } else {
+            Ok(cmp::Ordering::Equal)
+        }
+    }
+}
+
+fn is_arithmetic(op: Binop) -> bool {
+    matches!(
+        op,
+        Binop::Add
+            | Binop::Sub
+            | Binop::Mul
+            | Binop::Div
+            | Binop::TruncDiv
+            | Binop::Rem
+            | Binop::ERem
+            | Binop::Pow
+    )
+}
+
+fn is_bitwise(op: Binop) -> bool {
+    matches!(
+        op,
+        Binop::BitAnd | Binop::BitOr | Binop::BitXor | Binop::Shl | Binop::Shr
+    )
+}
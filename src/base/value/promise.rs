@@ -14,6 +14,10 @@ use std::rc::Rc;
 /// We also make use of the fact that mtots will never be
 /// multi-threaded (like JS), so we don't worry about Send.
 ///
+/// Like in JS, callbacks always run as tasks on the event loop, even when
+/// the promise has already been resolved, so that they never run before the code
+/// that registered them has finished, and run in the same order whether or not
+/// the promise was already resolved
 pub enum Promise {
     Pending(Vec<Box<dyn FnOnce(&mut Globals, Result<Value>)>>),
     Resolved(Result<Value>),
@@ -36,35 +40,65 @@ impl Promise {
         f(
             globals,
             Box::new(move |globals, result| {
-                let mut ref_ = rc.borrow_mut();
-                let ref_: &mut Promise = &mut ref_;
-                {
-                    let vec = match ref_ {
-                        Self::Pending(vec) => vec,
-                        Self::Resolved(_) => panic!("Promise resolved more than once"),
-                    };
-                    for callback in vec.drain(..) {
-                        callback(globals, result.clone());
+                // The promise is marked resolved before the callbacks run, so
+                // that they may use the promise again
+                let callbacks = match std::mem::replace(
+                    &mut *rc.borrow_mut(),
+                    Self::Resolved(result.clone()),
+                ) {
+                    Self::Pending(callbacks) => callbacks,
+                    Self::Resolved(_) => panic!("Promise resolved more than once"),
+                };
+                if callbacks.is_empty() {
+                    if let Err(error) = &result {
+                        globals.track_rejection(&rc, error.clone());
                     }
                 }
-                *ref_ = Self::Resolved(result);
+                for callback in callbacks {
+                    schedule(globals, callback, result.clone());
+                }
             }),
         );
         ret
     }
 
     /// Register a callback to be called for when this promise is resolved.
-    /// If the promise is resolved, the callback is called immediately.
+    /// If the promise is resolved, the callback is queued right away.
     pub fn register<F>(&mut self, globals: &mut Globals, f: F)
     where
         F: FnOnce(&mut Globals, Result<Value>) + 'static,
     {
         match self {
             Self::Pending(vec) => vec.push(Box::new(f)),
-            Self::Resolved(result) => f(globals, result.clone()),
+            Self::Resolved(result) => {
+                let result = result.clone();
+                if result.is_err() {
+                    globals.handle_rejection(self);
+                }
+                schedule(globals, Box::new(f), result)
+            }
         }
     }
 
+    /// Like 'register', but for when the promise is only available
+    /// through its Rc
+    pub fn observe<F>(this: &Rc<RefCell<Self>>, globals: &mut Globals, f: F)
+    where
+        F: FnOnce(&mut Globals, Result<Value>) + 'static,
+    {
+        let result = match &mut *this.borrow_mut() {
+            Self::Pending(vec) => {
+                vec.push(Box::new(f));
+                return;
+            }
+            Self::Resolved(result) => result.clone(),
+        };
+        if result.is_err() {
+            globals.handle_rejection(this.as_ptr());
+        }
+        schedule(globals, Box::new(f), result)
+    }
+
     /// Ensures that if this promise errors out, that it will
     /// panic and dump an error message for the user to see
     pub fn ordie(&mut self, globals: &mut Globals) {
@@ -101,6 +135,12 @@ impl Promise {
     }
 }
 
+type Callback = Box<dyn FnOnce(&mut Globals, Result<Value>)>;
+
+fn schedule(globals: &mut Globals, callback: Callback, result: Result<Value>) {
+    globals.enqueue_task(move |globals| callback(globals, result));
+}
+
 impl cmp::PartialEq for Promise {
     fn eq(&self, other: &Self) -> bool {
         self as *const _ == other as *const _
@@ -39,3 +39,426 @@ impl Value {
         Ok(ret)
     }
 }
+
+impl Value {
+    /// Formats a template in the style of 'String.format'.
+    ///
+    /// Replacement fields are written '{[field][!conv][:spec]}', where 'field'
+    /// is empty (the next positional argument), an index into 'args' or a
+    /// keyword argument name, 'conv' is 'r' (repr) or 's' (str), and 'spec'
+    /// is a format spec as accepted by 'format_spec'.
+    /// '{{' and '}}' stand for literal braces.
+    pub fn format_template(
+        template: &str,
+        args: &[Value],
+        kwargs: Option<&HashMap<RcStr, Value>>,
+    ) -> Result<String> {
+        let mut ret = String::new();
+        let mut next_index = 0;
+        let mut rest = template;
+        while let Some(i) = rest.find(['{', '}']) {
+            ret.push_str(&rest[..i]);
+            let brace = &rest[i..i + 1];
+            rest = &rest[i + 1..];
+            if rest.starts_with(brace) {
+                ret.push_str(brace);
+                rest = &rest[1..];
+                continue;
+            }
+            if brace == "}" {
+                return Err(rterr!("Single '}}' encountered in format string"));
+            }
+            let end = match rest.find('}') {
+                Some(end) => end,
+                None => return Err(rterr!("Unmatched '{{' in format string")),
+            };
+            let field = &rest[..end];
+            rest = &rest[end + 1..];
+
+            let (field, spec) = match field.find(':') {
+                Some(j) => (&field[..j], &field[j + 1..]),
+                None => (field, ""),
+            };
+            let (name, conv) = match field.find('!') {
+                Some(j) => (&field[..j], Some(&field[j + 1..])),
+                None => (field, None),
+            };
+            let value = if name.is_empty() {
+                next_index += 1;
+                format_arg(args, next_index - 1)?
+            } else if name.chars().all(|c| c.is_ascii_digit()) {
+                format_arg(args, name.parse().unwrap())?
+            } else {
+                match kwargs.and_then(|kwargs| kwargs.get(name)) {
+                    Some(value) => value.clone(),
+                    None => {
+                        return Err(kinderr!(
+                            NotFound,
+                            "No keyword argument named {:?} for format string",
+                            name
+                        ))
+                    }
+                }
+            };
+            let value = match conv {
+                None => value,
+                Some("s") => Value::from(format!("{}", value)),
+                Some("r") => Value::from(format!("{:?}", value)),
+                Some(conv) => {
+                    return Err(rterr!(
+                        "Invalid conversion {:?} in format string (must be 'r' or 's')",
+                        conv
+                    ))
+                }
+            };
+            ret.push_str(&value.format_spec(spec)?);
+        }
+        ret.push_str(rest);
+        Ok(ret)
+    }
+
+    /// Formats this value according to a format spec of the form
+    /// `[[fill]align][sign][#][0][width][,|_][.precision][type]`, where 'align' is one of '<', '>', '^' or '=' (pad after the sign),
+    /// 'sign' is one of '+', '-' or ' ', '#' adds a '0b', '0o' or '0x' prefix,
+    /// and 'type' is one of 's', 'd', 'b', 'o', 'x', 'X', 'e', 'E', 'f', 'F',
+    /// 'g', 'G' or '%'.
+    pub fn format_spec(&self, spec: &str) -> Result<String> {
+        let spec = FormatSpec::parse(spec)?;
+        let body = match (self, spec.kind) {
+            (Value::Bool(b), Some(kind)) if kind != 's' => {
+                return Value::from(*b as i64).format_spec_parsed(&spec);
+            }
+            (Value::Int(_), _) | (Value::Number(_), _) => {
+                return self.format_spec_parsed(&spec);
+            }
+            (_, None) | (_, Some('s')) => {
+                if spec.sign.is_some() || spec.alt || spec.grouping.is_some() {
+                    return Err(kinderr!(
+                        Type,
+                        "Sign, '#' and grouping are not allowed when formatting {:?}",
+                        self
+                    ));
+                }
+                let mut body = format!("{}", self);
+                if let Some(precision) = spec.precision {
+                    if let Some((i, _)) = body.char_indices().nth(precision) {
+                        body.truncate(i);
+                    }
+                }
+                body
+            }
+            (_, Some(kind)) => {
+                return Err(kinderr!(
+                    Type,
+                    "Format code {:?} is not supported for {:?}",
+                    kind,
+                    self
+                ))
+            }
+        };
+        Ok(spec.pad("", "", &body, '<'))
+    }
+
+    fn format_spec_parsed(&self, spec: &FormatSpec) -> Result<String> {
+        let kind = spec.kind.unwrap_or(match self {
+            Value::Int(_) => 'd',
+            _ => 'n',
+        });
+        let (negative, prefix, body) = match (self, kind) {
+            (Value::Int(x), 'd')
+            | (Value::Int(x), 'b')
+            | (Value::Int(x), 'o')
+            | (Value::Int(x), 'x')
+            | (Value::Int(x), 'X') => {
+                if spec.precision.is_some() {
+                    return Err(kinderr!(
+                        Type,
+                        "Precision is not allowed when formatting integers"
+                    ));
+                }
+                let x = *x;
+                let (radix_prefix, digits) = match kind {
+                    'b' => ("0b", format!("{:b}", x.unsigned_abs())),
+                    'o' => ("0o", format!("{:o}", x.unsigned_abs())),
+                    'x' => ("0x", format!("{:x}", x.unsigned_abs())),
+                    'X' => ("0X", format!("{:X}", x.unsigned_abs())),
+                    _ => ("", format!("{}", x.unsigned_abs())),
+                };
+                let digits = match spec.grouping {
+                    Some(',') if kind != 'd' => {
+                        return Err(kinderr!(
+                            Type,
+                            "',' grouping is only allowed with decimal integers"
+                        ))
+                    }
+                    Some(sep) => group_digits(&digits, sep, if kind == 'd' { 3 } else { 4 }),
+                    None => digits,
+                };
+                let prefix = if spec.alt { radix_prefix } else { "" };
+                (x < 0, prefix, digits)
+            }
+            (Value::Number(_), 'd')
+            | (Value::Number(_), 'b')
+            | (Value::Number(_), 'o')
+            | (Value::Number(_), 'x')
+            | (Value::Number(_), 'X') => {
+                return Err(kinderr!(
+                    Type,
+                    "Format code {:?} is not supported for floats",
+                    kind
+                ));
+            }
+            (_, 's') => {
+                if spec.sign.is_some() || spec.alt || spec.grouping.is_some() {
+                    return Err(kinderr!(
+                        Type,
+                        "Sign, '#' and grouping are not allowed with format code 's'"
+                    ));
+                }
+                let body = format!("{}", self);
+                return Ok(spec.pad("", "", &body, '>'));
+            }
+            _ => {
+                let x = self.number()?;
+                let body = format_float(x.abs(), kind, spec.precision, spec.alt);
+                let body = match spec.grouping {
+                    Some(sep) if x.is_finite() => {
+                        let end = body
+                            .find(|c: char| !c.is_ascii_digit())
+                            .unwrap_or(body.len());
+                        format!("{}{}", group_digits(&body[..end], sep, 3), &body[end..])
+                    }
+                    _ => body,
+                };
+                (x.is_sign_negative() && !x.is_nan(), "", body)
+            }
+        };
+        let sign = match (negative, spec.sign) {
+            (true, _) => "-",
+            (false, Some('+')) => "+",
+            (false, Some(' ')) => " ",
+            _ => "",
+        };
+        Ok(spec.pad(sign, prefix, &body, '>'))
+    }
+}
+
+fn format_arg(args: &[Value], index: usize) -> Result<Value> {
+    match args.get(index) {
+        Some(arg) => Ok(arg.clone()),
+        None => Err(kinderr!(
+            Argument,
+            "Format string expected an argument at index {}, but only {} were given",
+            index,
+            args.len()
+        )),
+    }
+}
+
+struct FormatSpec {
+    fill: char,
+    align: Option<char>,
+    sign: Option<char>,
+    alt: bool,
+    width: usize,
+    grouping: Option<char>,
+    precision: Option<usize>,
+    kind: Option<char>,
+}
+
+impl FormatSpec {
+    fn parse(spec: &str) -> Result<Self> {
+        let chars: Vec<char> = spec.chars().collect();
+        let mut i = 0;
+        let mut ret = Self {
+            fill: ' ',
+            align: None,
+            sign: None,
+            alt: false,
+            width: 0,
+            grouping: None,
+            precision: None,
+            kind: None,
+        };
+        let is_align =
+            |c: Option<&char>| matches!(c, Some('<') | Some('>') | Some('^') | Some('='));
+        if is_align(chars.get(1)) {
+            ret.fill = chars[0];
+            ret.align = Some(chars[1]);
+            i = 2;
+        } else if is_align(chars.first()) {
+            ret.align = Some(chars[0]);
+            i = 1;
+        }
+        if let Some('+') | Some('-') | Some(' ') = chars.get(i) {
+            ret.sign = Some(chars[i]);
+            i += 1;
+        }
+        if chars.get(i) == Some(&'#') {
+            ret.alt = true;
+            i += 1;
+        }
+        if chars.get(i) == Some(&'0') {
+            if ret.align.is_none() {
+                ret.fill = '0';
+                ret.align = Some('=');
+            }
+            i += 1;
+        }
+        let start = i;
+        while chars.get(i).map(|c| c.is_ascii_digit()).unwrap_or(false) {
+            i += 1;
+        }
+        if i > start {
+            ret.width = parse_count(&chars[start..i], spec)?;
+        }
+        if let Some(',') | Some('_') = chars.get(i) {
+            ret.grouping = Some(chars[i]);
+            i += 1;
+        }
+        if chars.get(i) == Some(&'.') {
+            i += 1;
+            let start = i;
+            while chars.get(i).map(|c| c.is_ascii_digit()).unwrap_or(false) {
+                i += 1;
+            }
+            if i == start {
+                return Err(rterr!("Format spec {:?} is missing a precision", spec));
+            }
+            ret.precision = Some(parse_count(&chars[start..i], spec)?);
+        }
+        if let Some(c) = chars.get(i) {
+            if !"sdboxXeEfFgG%".contains(*c) {
+                return Err(rterr!("Unknown format code {:?} in {:?}", c, spec));
+            }
+            ret.kind = Some(*c);
+            i += 1;
+        }
+        if i < chars.len() {
+            return Err(rterr!("Invalid format spec {:?}", spec));
+        }
+        Ok(ret)
+    }
+
+    /// Pads the formatted sign, prefix and body to the spec's width
+    fn pad(&self, sign: &str, prefix: &str, body: &str, default_align: char) -> String {
+        let len = sign.chars().count() + prefix.chars().count() + body.chars().count();
+        let padding = self.width.saturating_sub(len);
+        let fill = |n: usize| std::iter::repeat_n(self.fill, n).collect::<String>();
+        match self.align.unwrap_or(default_align) {
+            '<' => format!("{}{}{}{}", sign, prefix, body, fill(padding)),
+            '^' => format!(
+                "{}{}{}{}{}",
+                fill(padding / 2),
+                sign,
+                prefix,
+                body,
+                fill(padding - padding / 2)
+            ),
+            '=' => format!("{}{}{}{}", sign, prefix, fill(padding), body),
+            _ => format!("{}{}{}{}", fill(padding), sign, prefix, body),
+        }
+    }
+}
+
+fn parse_count(digits: &[char], spec: &str) -> Result<usize> {
+    match digits.iter().collect::<String>().parse() {
+        Ok(n) => Ok(n),
+        Err(_) => Err(rterr!(
+            "Format spec {:?} has a count that is too large",
+            spec
+        )),
+    }
+}
+
+/// Inserts 'sep' between every group of 'every' digits, counting from the right
+fn group_digits(digits: &str, sep: char, every: usize) -> String {
+    let mut ret = String::new();
+    let len = digits.chars().count();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (len - i).is_multiple_of(every) {
+            ret.push(sep);
+        }
+        ret.push(c);
+    }
+    ret
+}
+
+/// Formats a non-negative float with one of the float format codes,
+/// or 'n' for the default formatting
+fn format_float(x: f64, kind: char, precision: Option<usize>, alt: bool) -> String {
+    if !x.is_finite() {
+        let s = if x.is_nan() { "nan" } else { "inf" };
+        return if kind.is_ascii_uppercase() {
+            s.to_uppercase()
+        } else {
+            s.to_owned()
+        };
+    }
+    match kind {
+        'f' | 'F' => {
+            let mut s = format!("{:.*}", precision.unwrap_or(6), x);
+            if alt && !s.contains('.') {
+                s.push('.');
+            }
+            s
+        }
+        '%' => format!("{}%", format_float(x * 100.0, 'f', precision, alt)),
+        'e' | 'E' => {
+            let s = format_exp(x, precision.unwrap_or(6), alt);
+            if kind == 'E' {
+                s.to_uppercase()
+            } else {
+                s
+            }
+        }
+        'g' | 'G' => {
+            let s = format_general(x, precision.unwrap_or(6), alt);
+            if kind == 'G' {
+                s.to_uppercase()
+            } else {
+                s
+            }
+        }
+        _ => match precision {
+            Some(precision) => format_general(x, precision, alt),
+            None => format!("{}", x),
+        },
+    }
+}
+
+/// Scientific notation with a signed exponent of at least two digits, e.g. '1.50e+02'
+fn format_exp(x: f64, precision: usize, alt: bool) -> String {
+    let s = format!("{:.*e}", precision, x);
+    let (mantissa, exp) = s.split_at(s.find('e').unwrap());
+    let exp: i32 = exp[1..].parse().unwrap();
+    let dot = if alt && precision == 0 { "." } else { "" };
+    let exp_sign = if exp < 0 { '-' } else { '+' };
+    format!("{}{}e{}{:02}", mantissa, dot, exp_sign, exp.abs())
+}
+
+/// Fixed or scientific notation depending on the exponent, with 'precision'
+/// significant digits and (unless 'alt') trailing zeros removed
+fn format_general(x: f64, precision: usize, alt: bool) -> String {
+    let precision = precision.max(1);
+    let exp = if x == 0.0 {
+        0
+    } else {
+        let s = format!("{:.*e}", precision - 1, x);
+        s[s.find('e').unwrap() + 1..].parse::<i64>().unwrap()
+    };
+    let strip = |s: String| {
+        if alt || !s.contains('.') {
+            s
+        } else {
+            s.trim_end_matches('0').trim_end_matches('.').to_owned()
+        }
+    };
+    if -4 <= exp && exp < precision as i64 {
+        strip(format!("{:.*}", (precision as i64 - 1 - exp) as usize, x))
+    } else {
+        let s = format_exp(x, precision - 1, alt);
+        let i = s.find('e').unwrap();
+        format!("{}{}", strip(s[..i].to_owned()), &s[i..])
+    }
+}
@@ -34,7 +34,12 @@ impl Table {
         if self.set_opt(key, value).is_ok() {
             Ok(())
         } else {
-            Err(rterr!("Attribute {:?} not found in {:?}", key, self))
+            Err(kinderr!(
+                Attribute,
+                "Attribute {:?} not found in {:?}",
+                key,
+                self
+            ))
         }
     }
     pub fn set_opt(&self, key: &RcStr, value: Value) -> std::result::Result<(), Value> {
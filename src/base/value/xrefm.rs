@@ -57,8 +57,12 @@ pub trait ConvertValue: Sized + 'static {
         // method, and we really just want Value::convert to just check whether
         // it is a Handle
         Err(match globals.get_handle_class::<Self>() {
-            Some(cls) => rterr!("Expected {} value", cls.name()),
-            None => rterr!("Expected {:?} native handle", std::any::type_name::<Self>()),
+            Some(cls) => kinderr!(Type, "Expected {} value", cls.name()),
+            None => kinderr!(
+                Type,
+                "Expected {:?} native handle",
+                std::any::type_name::<Self>()
+            ),
         })
     }
 }
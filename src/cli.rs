@@ -1,23 +1,55 @@
+use crate::ErrorKind;
 use crate::FunctionKind;
 use crate::Globals;
 use crate::RcStr;
 use crate::Result;
+use crate::Source;
+use crate::UnhandledRejectionPolicy;
 use crate::Value;
+use std::io::Read;
 use std::path::Path;
+use std::path::PathBuf;
+use std::rc::Rc;
 
 pub fn climain(mut globals: Globals) {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("fmt") => return fmt(globals, &args[1..]),
+        Some("lint") => return lint(globals, &args[1..]),
+        _ => {}
+    }
+
     let mut source_roots = Vec::new();
     let mut script_args = Vec::new();
     let mut mode = Mode::Normal;
     let mut command = Command::Unspecified;
 
-    for argstr in std::env::args().skip(1) {
+    for argstr in args {
         let arg: &str = &argstr;
         match mode {
             Mode::Normal => match arg {
                 "-m" => mode = Mode::SetRunModule,
                 "-d" => mode = Mode::SetDocModule,
                 "-r" => command = Command::Repl,
+                "--dap" => command = Command::Dap,
+                "--lsp" => command = Command::Lsp,
+                "--no-cache" => globals.set_bytecode_cache(false),
+                "-O0" | "-O1" => globals.set_opt_level(arg[2..].parse().unwrap()),
+                _ if arg.starts_with("--unhandled-rejections=") => {
+                    let mode = arg.split('=').nth(1).unwrap();
+                    globals.set_unhandled_rejection_policy(match mode {
+                        "warn" => UnhandledRejectionPolicy::Warn,
+                        "die" => UnhandledRejectionPolicy::Die,
+                        "ignore" => UnhandledRejectionPolicy::Ignore,
+                        _ => {
+                            eprintln!(
+                                "Unknown --unhandled-rejections mode {:?} (expected warn, die or ignore)",
+                                mode
+                            );
+                            std::process::exit(1);
+                        }
+                    });
+                }
                 "--" => mode = Mode::ScriptArgs,
                 _ => {
                     let path = Path::new(arg);
@@ -51,13 +83,25 @@ pub fn climain(mut globals: Globals) {
     }
 
     globals.set_argv(script_args);
+    let project_dir = match &command {
+        Command::RunPath(path) if Path::new(path).is_dir() => PathBuf::from(path),
+        Command::RunPath(path) => match Path::new(path).parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_owned(),
+            _ => PathBuf::from("."),
+        },
+        _ => PathBuf::from("."),
+    };
     for source_root in source_roots {
         globals.add_source_root(source_root);
     }
+    let r = globals.init_source_roots(&project_dir);
+    ordie(&mut globals, r);
 
     match command {
         Command::Unspecified => panic!("Command::Unspecified should be unreachable"),
         Command::Repl => repl(globals),
+        Command::Dap => crate::dapmain(globals),
+        Command::Lsp => crate::lspmain(globals),
         Command::DocModule(module) => doc_module(globals, &module.into()),
         Command::RunModule(module) => run_module(globals, &module.into()),
         Command::RunPath(pathstr) => run_path(globals, pathstr),
@@ -74,6 +118,8 @@ enum Mode {
 enum Command {
     Unspecified,
     Repl,
+    Dap,
+    Lsp,
     DocModule(String),
     RunModule(String),
     RunPath(String),
@@ -107,6 +153,7 @@ fn repl(mut globals: Globals) {
                 globals.trace_unwind(trace_base);
             }
         }
+        globals.run_pending();
         globals.save_line_history().unwrap();
     }
 }
@@ -161,7 +208,7 @@ fn doc_module(mut globals: Globals, module: &RcStr) {
 
 fn short_printable_value(value: &Value) -> bool {
     match value {
-        Value::Nil | Value::Bool(_) | Value::Number(_) => true,
+        Value::Nil | Value::Bool(_) | Value::Number(_) | Value::Int(_) => true,
         Value::String(s) => {
             s.len() < 40 && s.chars().all(|c| !c.is_control() && c != '\n' && c != '\t')
         }
@@ -193,7 +240,7 @@ fn format_doc(doc: &str, indent: usize) -> String {
 fn run_module(mut globals: Globals, module: &RcStr) {
     globals.set_main(module.clone());
     let r = globals.load(module).map(|_| ());
-    globals.handle_trampoline_and_last_result(r);
+    run_event_loop_and_finish(globals, r);
 }
 
 fn run_path(mut globals: Globals, pathstr: String) {
@@ -205,14 +252,168 @@ fn run_path(mut globals: Globals, pathstr: String) {
         let data = std::fs::read_to_string(path).unwrap();
         globals.set_main("__main".into());
         let r = globals.exec_str("__main", Some(&pathstr), &data);
-        globals.handle_trampoline_and_last_result(r);
+        run_event_loop_and_finish(globals, r);
     }
 }
 
+/// Once the main module has finished, runs the event loop until there is
+/// nothing left to do (unless the main module failed or requested a trampoline)
+fn run_event_loop_and_finish<T>(mut globals: Globals, r: Result<T>) {
+    if r.is_ok() {
+        globals.run_event_loop();
+    }
+    if globals.debugger_quit() || globals.report_unhandled_rejections() {
+        std::process::exit(1);
+    }
+    globals.handle_trampoline_and_last_result(r);
+}
+
+/// mtots fmt [--check] [paths...]
+/// Formats the given files (or all '.u' files under the given directories)
+/// in place. With no paths, formats stdin to stdout.
+/// With --check, nothing is written, and the names of the files that would
+/// change are printed instead, exiting nonzero if there are any
+fn fmt(globals: Globals, args: &[String]) {
+    let mut check = false;
+    let mut paths = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--check" => check = true,
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+
+    if paths.is_empty() {
+        let mut data = String::new();
+        std::io::stdin().read_to_string(&mut data).unwrap();
+        let source = Rc::new(Source::new("<stdin>".into(), None, data.clone().into()));
+        match globals.format(source) {
+            Ok(formatted) if check => std::process::exit(if formatted == data { 0 } else { 1 }),
+            Ok(formatted) => print!("{}", formatted),
+            Err(error) => {
+                eprintln!("{}", error.format());
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let mut files = Vec::new();
+    for path in paths {
+        if let Err(error) = collect_source_files(&path, &mut files) {
+            eprintln!("{:?}: {}", path, error.format());
+            std::process::exit(1);
+        }
+    }
+
+    let mut failed = false;
+    for path in files {
+        let data = match std::fs::read_to_string(&path) {
+            Ok(data) => data,
+            Err(error) => {
+                eprintln!("{:?}: {}", path, error);
+                failed = true;
+                continue;
+            }
+        };
+        let name = path.to_string_lossy().into_owned().into();
+        let source = Rc::new(Source::new(
+            name,
+            Some(path.clone().into()),
+            data.clone().into(),
+        ));
+        match globals.format(source) {
+            Ok(formatted) if formatted == data => {}
+            Ok(_) if check => {
+                println!("{}", path.display());
+                failed = true;
+            }
+            Ok(formatted) => {
+                if let Err(error) = std::fs::write(&path, formatted) {
+                    eprintln!("{:?}: {}", path, error);
+                    failed = true;
+                }
+            }
+            Err(error) => {
+                eprintln!("{}", error.format());
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+/// mtots lint [paths...]
+/// Prints the diagnostics for the given files (or all '.u' files under the
+/// given directories), exiting nonzero if there are any
+fn lint(globals: Globals, args: &[String]) {
+    let mut files = Vec::new();
+    for arg in args {
+        let path = PathBuf::from(arg);
+        if let Err(error) = collect_source_files(&path, &mut files) {
+            eprintln!("{:?}: {}", path, error.format());
+            std::process::exit(1);
+        }
+    }
+
+    let mut failed = false;
+    for path in files {
+        let data = match std::fs::read_to_string(&path) {
+            Ok(data) => data,
+            Err(error) => {
+                eprintln!("{:?}: {}", path, error);
+                failed = true;
+                continue;
+            }
+        };
+        let name = path.to_string_lossy().into_owned().into();
+        let source = Rc::new(Source::new(name, Some(path.clone().into()), data.into()));
+        match globals.lint(source) {
+            Ok(diagnostics) => {
+                for diagnostic in &diagnostics {
+                    println!("{}", diagnostic);
+                }
+                failed |= !diagnostics.is_empty();
+            }
+            Err(error) => {
+                eprintln!("{}", error.format());
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+fn collect_source_files(path: &Path, out: &mut Vec<PathBuf>) -> Result<()> {
+    if path.is_dir() {
+        let mut entries = Vec::new();
+        for entry in path.read_dir()? {
+            entries.push(entry?.path());
+        }
+        entries.sort();
+        for entry in entries {
+            if entry.is_dir() || entry.extension().map(|ext| ext == "u").unwrap_or(false) {
+                collect_source_files(&entry, out)?;
+            }
+        }
+    } else {
+        out.push(path.to_owned());
+    }
+    Ok(())
+}
+
 pub fn ordie<T>(globals: &mut Globals, r: Result<T>) -> T {
     match r {
         Ok(t) => t,
         Err(error) => {
+            // the user asked to stop at the debugger prompt, so there is nothing to report
+            if let ErrorKind::DebuggerQuit = error.kind() {
+                std::process::exit(1);
+            }
             let error = error.prepended(globals.trace().clone());
             globals.eprint(&format!("{}", error.format()));
             std::process::exit(1);
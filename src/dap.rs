@@ -0,0 +1,714 @@
+//! Debug Adapter Protocol server, so that editors like VS Code
+//! can debug mtots scripts (mtots --dap)
+//! Requests and responses are exchanged over stdin/stdout, and
+//! output from the script is forwarded as 'output' events.
+use crate::DebugAction;
+use crate::DebugFrame;
+use crate::DebugHook;
+use crate::Globals;
+use crate::Json;
+use crate::Mark;
+use crate::PauseReason;
+use crate::RcStr;
+use crate::Result;
+use crate::Value;
+use std::cell::Cell;
+use std::cell::RefCell;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::path::Path;
+use std::rc::Rc;
+
+/// The single thread id reported to the client
+const THREAD_ID: i64 = 1;
+
+/// variablesReference for the locals of the innermost frame.
+/// References to structured values are allocated after this one
+const LOCALS_REF: usize = 1;
+
+pub fn dapmain(globals: Globals) {
+    let conn = Connection {
+        input: RefCell::new(Box::new(BufReader::new(std::io::stdin()))),
+        output: RefCell::new(Box::new(std::io::stdout())),
+        seq: Cell::new(1),
+        exit_on_disconnect: true,
+    };
+    if let Err(error) = run_session(globals, Rc::new(conn)) {
+        eprintln!("{}", error.format());
+        std::process::exit(1);
+    }
+}
+
+/// Reads and writes Debug Adapter Protocol messages
+struct Connection {
+    input: RefCell<Box<dyn BufRead>>,
+    output: RefCell<Box<dyn Write>>,
+    seq: Cell<i64>,
+
+    /// Whether a disconnect request while paused should exit the process
+    /// (false when driven from tests)
+    exit_on_disconnect: bool,
+}
+
+impl Connection {
+    /// Reads the next message, or None if the client closed the stream
+    fn read(&self) -> Result<Option<Json>> {
+        Json::read_message(&mut **self.input.borrow_mut())
+    }
+
+    fn send(&self, type_: &str, mut pairs: Vec<(&str, Json)>) -> Result<()> {
+        let seq = self.seq.get();
+        self.seq.set(seq + 1);
+        pairs.insert(0, ("seq", seq.into()));
+        pairs.insert(1, ("type", type_.into()));
+        Json::object(pairs).write_message(&mut **self.output.borrow_mut())
+    }
+
+    fn respond(&self, request: &Json, body: Json) -> Result<()> {
+        self.send(
+            "response",
+            vec![
+                (
+                    "request_seq",
+                    request.get("seq").cloned().unwrap_or(Json::Null),
+                ),
+                ("success", true.into()),
+                (
+                    "command",
+                    request.get("command").cloned().unwrap_or(Json::Null),
+                ),
+                ("body", body),
+            ],
+        )
+    }
+
+    fn respond_error(&self, request: &Json, message: &str) -> Result<()> {
+        self.send(
+            "response",
+            vec![
+                (
+                    "request_seq",
+                    request.get("seq").cloned().unwrap_or(Json::Null),
+                ),
+                ("success", false.into()),
+                (
+                    "command",
+                    request.get("command").cloned().unwrap_or(Json::Null),
+                ),
+                ("message", message.into()),
+            ],
+        )
+    }
+
+    fn event(&self, event: &str, body: Json) -> Result<()> {
+        self.send("event", vec![("event", event.into()), ("body", body)])
+    }
+}
+
+fn command(request: &Json) -> &str {
+    request.get("command").and_then(Json::as_str).unwrap_or("")
+}
+
+fn arguments(request: &Json) -> &Json {
+    request.get("arguments").unwrap_or(&Json::Null)
+}
+
+/// Handles the requests that are valid both while the script is paused
+/// and while no script is running.
+/// Returns false if the request was not recognized
+fn handle_common(globals: &mut Globals, conn: &Connection, request: &Json) -> Result<bool> {
+    match command(request) {
+        "threads" => conn.respond(
+            request,
+            Json::object(vec![(
+                "threads",
+                vec![Json::object(vec![
+                    ("id", THREAD_ID.into()),
+                    ("name", "main".into()),
+                ])]
+                .into(),
+            )]),
+        )?,
+        "setBreakpoints" => {
+            let args = arguments(request);
+            let path = match args
+                .get("source")
+                .and_then(|source| source.get("path"))
+                .and_then(Json::as_str)
+            {
+                Some(path) => path,
+                None => {
+                    conn.respond_error(
+                        request,
+                        "Breakpoints can only be set in a source with a path",
+                    )?;
+                    return Ok(true);
+                }
+            };
+            let lines: Vec<usize> = args
+                .get("breakpoints")
+                .and_then(Json::as_array)
+                .map(|bps| {
+                    bps.iter()
+                        .filter_map(|bp| bp.get("line").and_then(Json::as_i64))
+                        .map(|line| line as usize)
+                        .collect()
+                })
+                .unwrap_or_default();
+            // lines without code (e.g. blank lines or comments) are never
+            // reached, so they are reported back as unverified
+            let code_lines = globals
+                .breakpoint_lines(Path::new(path))
+                .unwrap_or_default();
+            let breakpoints = lines
+                .iter()
+                .map(|line| {
+                    Json::object(vec![
+                        ("verified", code_lines.contains(line).into()),
+                        ("line", (*line).into()),
+                    ])
+                })
+                .collect::<Vec<_>>();
+            let lines = lines
+                .into_iter()
+                .filter(|line| code_lines.contains(line))
+                .collect();
+            globals.set_breakpoints(Path::new(path), lines);
+            conn.respond(
+                request,
+                Json::object(vec![("breakpoints", breakpoints.into())]),
+            )?;
+        }
+        "setExceptionBreakpoints" => conn.respond(
+            request,
+            Json::object(vec![("breakpoints", Json::Array(vec![]))]),
+        )?,
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+fn run_session(mut globals: Globals, conn: Rc<Connection>) -> Result<()> {
+    let mut launch = None;
+    let mut configured = false;
+    while let Some(request) = conn.read()? {
+        if handle_common(&mut globals, &conn, &request)? {
+            continue;
+        }
+        match command(&request) {
+            "initialize" => {
+                conn.respond(
+                    &request,
+                    Json::object(vec![
+                        ("supportsConfigurationDoneRequest", true.into()),
+                        ("supportsEvaluateForHovers", true.into()),
+                    ]),
+                )?;
+                conn.event("initialized", Json::object::<&str>(vec![]))?;
+            }
+            "launch" => {
+                launch = Some(arguments(&request).clone());
+                conn.respond(&request, Json::Null)?;
+            }
+            "configurationDone" => {
+                configured = true;
+                conn.respond(&request, Json::Null)?;
+            }
+            "disconnect" => {
+                conn.respond(&request, Json::Null)?;
+                break;
+            }
+            _ => conn.respond_error(&request, "Unsupported request")?,
+        }
+        if configured {
+            if let Some(args) = launch.take() {
+                globals = launch_program(globals, &conn, &args)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Runs the program described by the arguments of a 'launch' request
+fn launch_program(mut globals: Globals, conn: &Rc<Connection>, args: &Json) -> Result<Globals> {
+    let program = args.get("program").and_then(Json::as_str).unwrap_or("");
+    let argv = args
+        .get("args")
+        .and_then(Json::as_array)
+        .map(|args| {
+            args.iter()
+                .filter_map(Json::as_str)
+                .map(RcStr::from)
+                .collect()
+        })
+        .unwrap_or_default();
+    globals.set_argv(argv);
+    globals.set_debug_hook(DapHook {
+        conn: conn.clone(),
+        refs: vec![],
+    });
+    for (category, stderr) in &[("stdout", false), ("stderr", true)] {
+        let conn = conn.clone();
+        let print = move |text: &str| {
+            conn.event(
+                "output",
+                Json::object(vec![
+                    ("category", (*category).into()),
+                    ("output", text.into()),
+                ]),
+            )
+            .unwrap_or(());
+        };
+        if *stderr {
+            globals.set_eprint(print);
+        } else {
+            globals.set_print(print);
+        }
+    }
+    if args
+        .get("stopOnEntry")
+        .and_then(Json::as_bool)
+        .unwrap_or(false)
+    {
+        globals.request_debug_pause();
+    }
+
+    let result = std::fs::read_to_string(program)
+        .map_err(crate::Error::from)
+        .and_then(|data| {
+            globals.set_main("__main".into());
+            globals.exec_str("__main", Some(program), &data)
+        });
+    let exit_code = match result {
+        // like the CLI, timers and promise callbacks get to run once
+        // the main module has finished
+        Ok(_) => {
+            globals.run_event_loop();
+            if globals.debugger_quit() || globals.report_unhandled_rejections() {
+                1i64
+            } else {
+                0
+            }
+        }
+        Err(error) => {
+            let error = error.prepended(globals.trace().clone());
+            globals.trace_unwind(0);
+            globals.eprint(&error.format());
+            1
+        }
+    };
+    conn.event("exited", Json::object(vec![("exitCode", exit_code.into())]))?;
+    conn.event("terminated", Json::object::<&str>(vec![]))?;
+    Ok(globals)
+}
+
+/// Services requests from the client while the script is paused
+struct DapHook {
+    conn: Rc<Connection>,
+
+    /// Values whose children can be requested with 'variables'.
+    /// The variablesReference for refs[i] is i + LOCALS_REF + 1
+    refs: Vec<Value>,
+}
+
+impl DapHook {
+    fn variable(&mut self, name: String, value: Value) -> Json {
+        let reference = match &value {
+            Value::List(_) | Value::Map(_) | Value::Table(_) => {
+                self.refs.push(value.clone());
+                self.refs.len() + LOCALS_REF
+            }
+            _ => 0,
+        };
+        Json::object(vec![
+            ("name", name.into()),
+            ("value", format!("{:?}", value).into()),
+            ("variablesReference", reference.into()),
+        ])
+    }
+
+    fn children(&self, reference: usize) -> Vec<(String, Value)> {
+        match self.refs.get(reference.wrapping_sub(LOCALS_REF + 1)) {
+            Some(Value::List(list)) => list
+                .borrow()
+                .iter()
+                .enumerate()
+                .map(|(i, item)| (format!("[{}]", i), item.clone()))
+                .collect(),
+            Some(Value::Map(map)) => map
+                .borrow()
+                .iter()
+                .map(|(key, val)| (format!("{:?}", Value::from(key)), val.clone()))
+                .collect(),
+            Some(Value::Table(table)) => {
+                let mut fields: Vec<_> = table
+                    .map()
+                    .iter()
+                    .map(|(key, val)| (key.to_string(), val.borrow().clone()))
+                    .collect();
+                fields.sort_by(|a, b| a.0.cmp(&b.0));
+                fields
+            }
+            _ => vec![],
+        }
+    }
+}
+
+fn stack_frame(id: usize, name: &str, mark: &Mark) -> Json {
+    let source = mark.source();
+    let line_start = source.data()[..mark.pos()]
+        .rfind('\n')
+        .map(|i| i + 1)
+        .unwrap_or(0);
+    let column = source.data()[line_start..mark.pos()].chars().count() + 1;
+    let mut source_pairs = vec![("name", source.name().str().into())];
+    if let Some(path) = source.path() {
+        source_pairs.push(("path", path.to_string_lossy().as_ref().into()));
+    }
+    Json::object(vec![
+        ("id", id.into()),
+        ("name", name.into()),
+        ("source", Json::object(source_pairs)),
+        ("line", mark.lineno().into()),
+        ("column", column.into()),
+    ])
+}
+
+impl DebugHook for DapHook {
+    fn pause(
+        &mut self,
+        globals: &mut Globals,
+        frame: &mut DebugFrame,
+        reason: PauseReason,
+    ) -> Result<DebugAction> {
+        self.refs.clear();
+        let conn = self.conn.clone();
+        conn.event(
+            "stopped",
+            Json::object(vec![
+                (
+                    "reason",
+                    match reason {
+                        PauseReason::Breakpoint => "breakpoint",
+                        PauseReason::Step => "step",
+                        PauseReason::Requested => "entry",
+                    }
+                    .into(),
+                ),
+                ("threadId", THREAD_ID.into()),
+            ]),
+        )?;
+        loop {
+            let request = match conn.read()? {
+                Some(request) => request,
+                None => return Ok(DebugAction::Continue),
+            };
+            if handle_common(globals, &conn, &request)? {
+                continue;
+            }
+            let args = arguments(&request);
+            let action = match command(&request) {
+                "continue" => Some(DebugAction::Continue),
+                "next" => Some(DebugAction::StepOver),
+                "stepIn" => Some(DebugAction::StepIn),
+                "stepOut" => Some(DebugAction::StepOut),
+                _ => None,
+            };
+            if let Some(action) = action {
+                conn.respond(
+                    &request,
+                    Json::object(vec![("allThreadsContinued", true.into())]),
+                )?;
+                return Ok(action);
+            }
+            match command(&request) {
+                "stackTrace" => {
+                    // The innermost frame (id 1) is the only one with variables;
+                    // for the rest, only the locations are known
+                    let trace = frame.trace(globals);
+                    let frames: Vec<_> = trace
+                        .iter()
+                        .rev()
+                        .enumerate()
+                        .map(|(i, mark)| {
+                            let name = if i == 0 {
+                                frame.name().str().to_owned()
+                            } else {
+                                mark.source().name().str().to_owned()
+                            };
+                            stack_frame(i + 1, &name, mark)
+                        })
+                        .collect();
+                    conn.respond(
+                        &request,
+                        Json::object(vec![
+                            ("totalFrames", frames.len().into()),
+                            ("stackFrames", frames.into()),
+                        ]),
+                    )?;
+                }
+                "scopes" => {
+                    let scopes = if args.get("frameId").and_then(Json::as_i64) == Some(1) {
+                        vec![Json::object(vec![
+                            ("name", "Locals".into()),
+                            ("variablesReference", LOCALS_REF.into()),
+                            ("expensive", false.into()),
+                        ])]
+                    } else {
+                        vec![]
+                    };
+                    conn.respond(&request, Json::object(vec![("scopes", scopes.into())]))?;
+                }
+                "variables" => {
+                    let reference = args
+                        .get("variablesReference")
+                        .and_then(Json::as_i64)
+                        .unwrap_or(0) as usize;
+                    let children = if reference == LOCALS_REF {
+                        frame
+                            .variables()
+                            .into_iter()
+                            .map(|(name, value)| (name.to_string(), value))
+                            .collect()
+                    } else {
+                        self.children(reference)
+                    };
+                    let variables: Vec<_> = children
+                        .into_iter()
+                        .map(|(name, value)| self.variable(name, value))
+                        .collect();
+                    conn.respond(
+                        &request,
+                        Json::object(vec![("variables", variables.into())]),
+                    )?;
+                }
+                "evaluate" => {
+                    let expression = args.get("expression").and_then(Json::as_str).unwrap_or("");
+                    match frame.eval(globals, expression) {
+                        Ok(value) => {
+                            let variable = self.variable("".into(), value);
+                            conn.respond(
+                                &request,
+                                Json::object(vec![
+                                    ("result", variable.get("value").unwrap().clone()),
+                                    (
+                                        "variablesReference",
+                                        variable.get("variablesReference").unwrap().clone(),
+                                    ),
+                                ]),
+                            )?;
+                        }
+                        Err(error) => conn.respond_error(
+                            &request,
+                            &format!("{}: {}", error.type_(), error.message()),
+                        )?,
+                    }
+                }
+                "disconnect" => {
+                    conn.respond(&request, Json::Null)?;
+                    if conn.exit_on_disconnect {
+                        std::process::exit(0);
+                    }
+                    return Ok(DebugAction::Continue);
+                }
+                _ => conn.respond_error(&request, "Unsupported request")?,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Drives a whole session with a scripted client.
+    /// Since the script runs on the same thread, all requests can be
+    /// written up front; the session reads them as it goes
+    #[test]
+    fn scripted_session() {
+        let dir = std::env::temp_dir().join(format!("mtots-dap-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let program = dir.join("main.u");
+        std::fs::write(
+            &program,
+            concat!(
+                "def f(a) {\n  b = [a, a + 1]\n  return b\n}\nx = f(1)\nprint(x)\n",
+                "\n",
+                "# then some async code\n",
+                "import a.time\n",
+                "async def g() = 'awaited'\n",
+                "async def h() { print(g().await) }\n",
+                "h()\n",
+                "time.set_timeout(def() = print('timer'), 0)\n",
+            ),
+        )
+        .unwrap();
+        let program = program.to_str().unwrap();
+
+        let requests = vec![
+            r#"{"command":"initialize","arguments":{}}"#.to_owned(),
+            format!(
+                r#"{{"command":"launch","arguments":{{"program":{:?}}}}}"#,
+                program
+            ),
+            format!(
+                r#"{{"command":"setBreakpoints","arguments":{{"source":{{"path":{:?}}},"breakpoints":[{{"line":3}},{{"line":7}},{{"line":8}}]}}}}"#,
+                program
+            ),
+            r#"{"command":"setBreakpoints","arguments":{"source":{},"breakpoints":[{"line":1}]}}"#
+                .to_owned(),
+            r#"{"command":"configurationDone"}"#.to_owned(),
+            r#"{"command":"stackTrace","arguments":{"threadId":1}}"#.to_owned(),
+            r#"{"command":"variables","arguments":{"variablesReference":1}}"#.to_owned(),
+            r#"{"command":"variables","arguments":{"variablesReference":2}}"#.to_owned(),
+            r#"{"command":"evaluate","arguments":{"expression":"a + 10","frameId":1}}"#.to_owned(),
+            r#"{"command":"next","arguments":{"threadId":1}}"#.to_owned(),
+            r#"{"command":"continue","arguments":{"threadId":1}}"#.to_owned(),
+            r#"{"command":"disconnect"}"#.to_owned(),
+        ];
+        let mut input = String::new();
+        for (i, request) in requests.iter().enumerate() {
+            let request = format!(r#"{{"seq":{},"type":"request",{}"#, i + 1, &request[1..]);
+            input.push_str(&format!(
+                "Content-Length: {}\r\n\r\n{}",
+                request.len(),
+                request
+            ));
+        }
+
+        let output = Rc::new(RefCell::new(Vec::new()));
+        struct SharedWriter(Rc<RefCell<Vec<u8>>>);
+        impl Write for SharedWriter {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.borrow_mut().write(buf)
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        let conn = Connection {
+            input: RefCell::new(Box::new(std::io::Cursor::new(input.into_bytes()))),
+            output: RefCell::new(Box::new(SharedWriter(output.clone()))),
+            seq: Cell::new(1),
+            exit_on_disconnect: false,
+        };
+        run_session(Globals::new(), Rc::new(conn)).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let output = String::from_utf8(output.borrow().clone()).unwrap();
+        let messages: Vec<_> = output
+            .split("Content-Length: ")
+            .filter(|part| !part.is_empty())
+            .map(|part| Json::parse(&part[part.find("\r\n\r\n").unwrap() + 4..]).unwrap())
+            .collect();
+        let find = |pred: &dyn Fn(&Json) -> bool| {
+            messages
+                .iter()
+                .filter(|m| pred(m))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        let response = |command: &str| {
+            let found = find(&|m| m.get("command").and_then(Json::as_str) == Some(command));
+            assert_eq!(found.len(), 1, "{}", output);
+            assert_eq!(
+                found[0].get("success"),
+                Some(&Json::Bool(true)),
+                "{}",
+                output
+            );
+            found[0].get("body").unwrap().clone()
+        };
+        let events = |event: &str| find(&|m| m.get("event").and_then(Json::as_str) == Some(event));
+
+        // a source without a path is refused, rather than having its
+        // breakpoints apply to an empty path
+        let set_breakpoints =
+            find(&|m| m.get("command").and_then(Json::as_str) == Some("setBreakpoints"));
+        let successes: Vec<_> = set_breakpoints
+            .iter()
+            .map(|m| m.get("success").cloned())
+            .collect();
+        assert_eq!(
+            successes,
+            vec![Some(Json::Bool(true)), Some(Json::Bool(false))]
+        );
+
+        // only lines with code can have breakpoints
+        let verified: Vec<_> = set_breakpoints[0]
+            .get("body")
+            .unwrap()
+            .get("breakpoints")
+            .unwrap()
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|bp| bp.get("verified").cloned())
+            .collect();
+        assert_eq!(
+            verified,
+            vec![
+                Some(Json::Bool(true)),
+                Some(Json::Bool(false)),
+                Some(Json::Bool(false)),
+            ]
+        );
+
+        let stopped = events("stopped");
+        assert_eq!(stopped.len(), 2, "{}", output);
+        assert_eq!(
+            stopped[0]
+                .get("body")
+                .unwrap()
+                .get("reason")
+                .unwrap()
+                .as_str(),
+            Some("breakpoint")
+        );
+        assert_eq!(
+            stopped[1]
+                .get("body")
+                .unwrap()
+                .get("reason")
+                .unwrap()
+                .as_str(),
+            Some("step")
+        );
+
+        let frames = response("stackTrace");
+        let frames = frames.get("stackFrames").unwrap().as_array().unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].get("name").unwrap().as_str(), Some("__main#f"));
+        assert_eq!(frames[0].get("line").unwrap().as_i64(), Some(3));
+        assert_eq!(frames[1].get("line").unwrap().as_i64(), Some(5));
+
+        let body = response("evaluate");
+        assert_eq!(body.get("result").unwrap().as_str(), Some("11"));
+
+        let output_text: String = events("output")
+            .iter()
+            .filter_map(|e| e.get("body").unwrap().get("output").unwrap().as_str())
+            .collect();
+        assert_eq!(output_text, "[1, 2]\nawaited\ntimer\n");
+        assert_eq!(
+            events("exited")[0].get("body").unwrap().get("exitCode"),
+            Some(&Json::Number(0.0))
+        );
+
+        // the first 'variables' request lists locals, and the second
+        // expands the list 'b' found in the first
+        let variables: Vec<_> =
+            find(&|m| m.get("command").and_then(Json::as_str) == Some("variables"))
+                .into_iter()
+                .map(|m| m.get("body").unwrap().get("variables").unwrap().to_string())
+                .collect();
+        assert_eq!(
+            variables,
+            vec![
+                r#"[{"name":"a","value":"1","variablesReference":0},{"name":"b","value":"[1, 2]","variablesReference":2}]"#,
+                r#"[{"name":"[0]","value":"1","variablesReference":0},{"name":"[1]","value":"2","variablesReference":0}]"#,
+            ]
+        );
+    }
+}
@@ -31,8 +31,22 @@ macro_rules! rterr {
     };
 }
 
+/// Like rterr!, but for errors with a specific ErrorKind
+/// e.g. kinderr!(Type, "Expected {} but got {}", a, b)
+#[macro_export]
+macro_rules! kinderr {
+    ( $kind:ident, $($args:expr),+ $(,)?) => {
+        $crate::Error::new_with_kind(
+            $crate::ErrorKind::$kind,
+            format!( $($args),+ ).into(),
+            vec![])
+    };
+}
+
 mod base;
 mod cli;
+mod dap;
+mod lsp;
 mod nlibs;
 mod util;
 
@@ -44,8 +58,10 @@ pub extern crate indexmap;
 pub use base::*;
 pub use cli::climain;
 pub use cli::ordie;
+pub use dap::dapmain;
 pub use indexmap::IndexMap;
 pub use indexmap::IndexSet;
+pub use lsp::lspmain;
 pub use nlibs::*;
 pub use util::*;
 
@@ -69,4 +85,305 @@ mod tests {
             )
             .unwrap();
     }
+
+    #[test]
+    fn format_string_marks() {
+        let globals = Globals::new();
+        let source = "x = 1\ny = f'a\n{x} {x +}'\n";
+        let error = globals
+            .parse(std::rc::Rc::new(Source::new(
+                "[test]".into(),
+                None,
+                source.into(),
+            )))
+            .err()
+            .unwrap();
+        let mark = &error.trace()[0];
+        assert_eq!(mark.pos(), source.rfind('}').unwrap());
+        assert_eq!(mark.lineno(), 3);
+        assert_eq!(mark.column(), 9);
+    }
+
+    #[test]
+    fn run_pending() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+        use std::time::Duration;
+
+        let mut globals = Globals::new();
+        let log = Rc::new(RefCell::new(Vec::new()));
+        for (name, millis) in [("b", 20), ("a", 0), ("c", 20)] {
+            let log = log.clone();
+            globals
+                .set_timer(Duration::from_millis(millis), move |_| {
+                    log.borrow_mut().push(name);
+                })
+                .unwrap();
+        }
+        let log2 = log.clone();
+        globals.enqueue_task(move |_| log2.borrow_mut().push("task"));
+
+        assert!(globals.run_pending());
+        assert_eq!(*log.borrow(), vec!["task", "a"]);
+        globals.run_event_loop();
+        assert_eq!(*log.borrow(), vec!["task", "a", "b", "c"]);
+        assert!(!globals.run_pending());
+
+        // a deadline that cannot be represented is an error rather than a panic
+        assert!(globals.set_timer(Duration::MAX, |_| ()).is_err());
+    }
+
+    #[test]
+    fn cancel_timer() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+        use std::time::Duration;
+
+        let mut globals = Globals::new();
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let log2 = log.clone();
+        let id = globals
+            .set_timer(Duration::from_millis(1), move |_| {
+                log2.borrow_mut().push("cancelled");
+            })
+            .unwrap();
+        // both timers are due by the time the loop runs, but the first still
+        // gets to cancel the second
+        globals
+            .set_timer(Duration::from_millis(0), move |globals| {
+                assert!(globals.cancel_timer(id));
+            })
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+        globals.run_event_loop();
+        assert!(log.borrow().is_empty());
+        assert!(!globals.cancel_timer(id));
+    }
+
+    /// Checks that nothing was left behind on the trace, by making sure
+    /// an unrelated error has only its own mark
+    fn assert_next_error_trace_is_clean(globals: &mut Globals) {
+        assert!(globals.trace().is_empty());
+        let error = globals
+            .exec_str("[second]", None, "\nthrow('second')\n")
+            .err()
+            .unwrap();
+        let error = error.prepended(globals.trace().clone());
+        let lines: Vec<_> = error.trace().iter().map(|m| m.lineno()).collect();
+        assert_eq!(lines, vec![2]);
+    }
+
+    #[test]
+    fn callback_failure_leaves_trace_clean() {
+        let mut globals = Globals::new();
+        globals
+            .exec_str(
+                "[first]",
+                None,
+                "import a.time\ntime.set_timeout(def() = throw('first'), 0)\n",
+            )
+            .unwrap();
+        globals.run_event_loop();
+        assert_next_error_trace_is_clean(&mut globals);
+    }
+
+    #[test]
+    fn promise_callback_failure_leaves_trace_clean() {
+        let mut globals = Globals::new();
+        globals
+            .exec_str(
+                "[first]",
+                None,
+                r###"
+                Promise(def(resolve, reject) = nil.nope)
+                Promise.resolved(1).then(def(x) = x.nope)
+                Promise.rejected('no').catch(def(exc) = exc.nope)
+                Promise.resolved(1).finally(def() = nil.nope)
+                "###,
+            )
+            .unwrap();
+        globals.run_event_loop();
+        assert_next_error_trace_is_clean(&mut globals);
+    }
+
+    #[test]
+    fn async_trace_order() {
+        let mut globals = Globals::new();
+        let promise = globals
+            .exec_repl(concat!(
+                "import a.time\n",
+                "async def g() {\n",
+                "    time.sleep_async(0).await\n",
+                "    nil\n",
+                "    throw('boom')\n",
+                "}\n",
+                "async def f() {\n",
+                "    nil\n",
+                "    g().await\n",
+                "}\n",
+                "def main() {\n",
+                "    nil\n",
+                "    return f()\n",
+                "}\n",
+                "nil\n",
+                "main()\n",
+            ))
+            .unwrap()
+            .into_promise()
+            .unwrap();
+        globals.run_event_loop();
+        assert!(globals.trace().is_empty());
+        let error = match &*promise.borrow() {
+            Promise::Resolved(Err(error)) => error.clone(),
+            _ => panic!("Expected a failed promise"),
+        };
+
+        // the callers of f and g are kept across the await in g
+        let lines: Vec<_> = error.trace().iter().map(Mark::lineno).collect();
+        assert_eq!(lines, vec![16, 13, 9, 5]);
+        assert_eq!(error.async_breaks(), &vec![3]);
+        assert!(error.format().contains("g().await\n     *\n[async]\n"));
+    }
+
+    #[test]
+    fn async_trace_through_awaits() {
+        let mut globals = Globals::new();
+        let promises = globals
+            .exec_repl(concat!(
+                "import a.time\n",
+                "async def g() {\n",
+                "    time.sleep_async(0).await\n",
+                "    throw('boom')\n",
+                "}\n",
+                "async def f() = g().await\n",
+                "async def main() = f().await\n",
+                "async def h() {\n",
+                "    time.sleep_async(0).await\n",
+                "    Promise.rejected('native').await\n",
+                "}\n",
+                "[main(), h()]\n",
+            ))
+            .unwrap()
+            .unpack(&mut globals)
+            .unwrap();
+        globals.run_event_loop();
+        let traces: Vec<_> = promises
+            .into_iter()
+            .map(|promise| match &*promise.into_promise().unwrap().borrow() {
+                Promise::Resolved(Err(error)) => (
+                    error.trace().iter().map(Mark::lineno).collect::<Vec<_>>(),
+                    error.async_breaks().clone(),
+                ),
+                _ => panic!("Expected a failed promise"),
+            })
+            .collect();
+
+        // awaiting an async function does not repeat the chain it already has
+        assert_eq!(traces[0], (vec![12, 7, 6, 4], vec![3]));
+
+        // but other failures get the chain and the await point
+        assert_eq!(traces[1], (vec![12, 10], vec![2]));
+    }
+
+    #[test]
+    fn generator_resumed_at_another_trace_depth() {
+        let mut globals = Globals::new();
+        let gen = globals
+            .exec_repl(concat!(
+                "def* gen() {\n",
+                "    try {\n",
+                "        yield 1\n",
+                "        throw('x')\n",
+                "    } catch e {\n",
+                "        yield e.message\n",
+                "    }\n",
+                "}\n",
+                "gen()\n",
+            ))
+            .unwrap();
+
+        // enter the try block with some unrelated marks on the trace,
+        // and then resume the generator without them
+        globals
+            .exec_str("[deep]", None, "def f() = throw('deep')\nf()\n")
+            .err()
+            .unwrap();
+        assert_eq!(globals.trace().len(), 2);
+        assert!(matches!(
+            gen.resume(&mut globals, Value::Nil),
+            ResumeResult::Yield(_)
+        ));
+        globals.trace_unwind(0);
+        match gen.resume(&mut globals, Value::Nil) {
+            ResumeResult::Yield(value) => assert_eq!(value, Value::from("x")),
+            _ => panic!("expected the error to be caught inside the generator"),
+        }
+        assert!(globals.trace().is_empty());
+    }
+
+    #[test]
+    fn unhandled_rejections() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let mut globals = Globals::new();
+        let reports = Rc::new(RefCell::new(Vec::new()));
+        let reports2 = reports.clone();
+        globals.set_unhandled_rejection_hook(move |error| {
+            reports2.borrow_mut().push(error.clone());
+        });
+        globals
+            .exec_repl(concat!(
+                "import a.time\n",
+                "async def f() {\n",
+                "    time.sleep_async(0).await\n",
+                "    throw('lost')\n",
+                "}\n",
+                "f()\n",
+                "Promise.rejected('caught').catch(def(e) = nil)\n",
+                "p = Promise.rejected('caught later')\n",
+                "p.catch(def(e) = nil)\n",
+            ))
+            .unwrap();
+        globals.run_event_loop();
+        let reports = reports.borrow();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].message().str(), "lost");
+        let lines: Vec<_> = reports[0].trace().iter().map(Mark::lineno).collect();
+        assert_eq!(lines, vec![6, 4]);
+        assert_eq!(reports[0].async_breaks(), &vec![1]);
+        drop(reports);
+        assert!(!globals.report_unhandled_rejections());
+    }
+
+    #[test]
+    fn unhandled_rejection_policies() {
+        use std::cell::Cell;
+        use std::rc::Rc;
+        use std::time::Duration;
+
+        let run = |policy| {
+            let mut globals = Globals::new();
+            globals.set_unhandled_rejection_policy(policy);
+            let reports = Rc::new(Cell::new(0));
+            let reports2 = reports.clone();
+            globals.set_unhandled_rejection_hook(move |_| reports2.set(reports2.get() + 1));
+            let later = Rc::new(Cell::new(false));
+            let later2 = later.clone();
+            globals
+                .set_timer(Duration::from_millis(10), move |_| later2.set(true))
+                .unwrap();
+            globals.exec_repl("Promise.rejected('lost')\n").unwrap();
+            globals.run_event_loop();
+            (
+                reports.get(),
+                later.get(),
+                globals.report_unhandled_rejections(),
+            )
+        };
+
+        // the event loop stops at the first report, and the host is told to fail
+        assert_eq!(run(UnhandledRejectionPolicy::Die), (1, false, true));
+        assert_eq!(run(UnhandledRejectionPolicy::Ignore), (0, true, false));
+    }
 }
@@ -0,0 +1,1136 @@
+//! Language Server Protocol server (mtots --lsp)
+//! Provides diagnostics, go-to-definition, hover and completion of
+//! module members using the parser and annotator.
+//! Only full document sync is supported; every change reparses the document.
+use crate::annotate;
+use crate::AssignTarget;
+use crate::AssignTargetDesc;
+use crate::Expr;
+use crate::ExprDesc;
+use crate::Globals;
+use crate::Json;
+use crate::Mark;
+use crate::ModuleDisplay;
+use crate::RcStr;
+use crate::Result;
+use crate::Source;
+use crate::Value;
+use crate::VarSpec;
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+// CompletionItemKind values from the LSP spec
+const KIND_FUNCTION: i64 = 3;
+const KIND_VARIABLE: i64 = 6;
+const KIND_CLASS: i64 = 7;
+
+pub fn lspmain(globals: Globals) {
+    let mut input = BufReader::new(std::io::stdin());
+    let mut output = std::io::stdout();
+    let mut server = Server {
+        globals,
+        docs: HashMap::new(),
+    };
+    if let Err(error) = server.run(&mut input, &mut output) {
+        eprintln!("{}", error.format());
+        std::process::exit(1);
+    }
+}
+
+struct Server {
+    globals: Globals,
+    docs: HashMap<String, Document>,
+}
+
+struct Document {
+    text: String,
+
+    /// The most recent version of the text that parsed successfully.
+    /// Used to resolve imports while the current text is being edited
+    last_good: Option<String>,
+}
+
+impl Server {
+    fn run(&mut self, input: &mut dyn BufRead, output: &mut dyn Write) -> Result<()> {
+        while let Some(message) = Json::read_message(input)? {
+            let method = message.get("method").and_then(Json::as_str).unwrap_or("");
+            let params = message.get("params").unwrap_or(&Json::Null);
+            let id = message.get("id");
+            let result = match method {
+                "initialize" => {
+                    let root = params
+                        .get("rootUri")
+                        .and_then(Json::as_str)
+                        .map(uri_to_path)
+                        .or_else(|| {
+                            params
+                                .get("rootPath")
+                                .and_then(Json::as_str)
+                                .map(PathBuf::from)
+                        });
+                    if let Some(root) = root {
+                        self.globals
+                            .add_source_root(root.to_string_lossy().as_ref());
+                    }
+                    Json::object(vec![(
+                        "capabilities",
+                        Json::object(vec![
+                            ("textDocumentSync", 1i64.into()),
+                            ("definitionProvider", true.into()),
+                            ("hoverProvider", true.into()),
+                            (
+                                "completionProvider",
+                                Json::object(vec![("triggerCharacters", vec![".".into()].into())]),
+                            ),
+                        ]),
+                    )])
+                }
+                "shutdown" => Json::Null,
+                "exit" => return Ok(()),
+                "textDocument/didOpen" => {
+                    let doc = params.get("textDocument").unwrap_or(&Json::Null);
+                    let uri = doc.get("uri").and_then(Json::as_str).unwrap_or("");
+                    let text = doc.get("text").and_then(Json::as_str).unwrap_or("");
+                    self.update(output, uri, text.to_owned())?;
+                    continue;
+                }
+                "textDocument/didChange" => {
+                    let uri = text_document_uri(params);
+                    let text = params
+                        .get("contentChanges")
+                        .and_then(Json::as_array)
+                        .and_then(|changes| changes.last())
+                        .and_then(|change| change.get("text"))
+                        .and_then(Json::as_str)
+                        .unwrap_or("");
+                    self.update(output, &uri, text.to_owned())?;
+                    continue;
+                }
+                "textDocument/didClose" => {
+                    let uri = text_document_uri(params);
+                    self.docs.remove(&uri);
+                    publish_diagnostics(output, &uri, vec![])?;
+                    continue;
+                }
+                "textDocument/definition" => self.definition(params),
+                "textDocument/hover" => self.hover(params),
+                "textDocument/completion" => self.completion(params),
+                _ => {
+                    if let Some(id) = id {
+                        Json::object(vec![
+                            ("jsonrpc", "2.0".into()),
+                            ("id", id.clone()),
+                            (
+                                "error",
+                                Json::object(vec![
+                                    ("code", (-32601i64).into()),
+                                    ("message", format!("Unsupported method {:?}", method).into()),
+                                ]),
+                            ),
+                        ])
+                        .write_message(output)?;
+                    }
+                    continue;
+                }
+            };
+            if let Some(id) = id {
+                Json::object(vec![
+                    ("jsonrpc", "2.0".into()),
+                    ("id", id.clone()),
+                    ("result", result),
+                ])
+                .write_message(output)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Stores the new text of a document and publishes its diagnostics
+    /// (the parse error if there is one, and lint warnings otherwise)
+    fn update(&mut self, output: &mut dyn Write, uri: &str, text: String) -> Result<()> {
+        let source = new_source(uri, &text);
+        let (parsed, diagnostics) = match self.globals.lint(source) {
+            Ok(found) => {
+                let warnings = found
+                    .iter()
+                    .map(|diagnostic| {
+                        let span = diagnostic.span();
+                        Json::object(vec![
+                            ("range", range(&text, span.start, span.end)),
+                            ("severity", 2i64.into()),
+                            ("source", "mtots".into()),
+                            ("code", diagnostic.kind().name().into()),
+                            ("message", diagnostic.message().str().into()),
+                        ])
+                    })
+                    .collect();
+                (true, warnings)
+            }
+            Err(error) => {
+                let pos = error.trace().last().map(Mark::pos).unwrap_or(0);
+                let diagnostic = Json::object(vec![
+                    ("range", range(&text, pos, pos)),
+                    ("severity", 1i64.into()),
+                    ("source", "mtots".into()),
+                    ("message", error.message().str().into()),
+                ]);
+                (false, vec![diagnostic])
+            }
+        };
+        let last_good = if parsed {
+            Some(text.clone())
+        } else {
+            self.docs.get_mut(uri).and_then(|doc| doc.last_good.take())
+        };
+        self.docs
+            .insert(uri.to_owned(), Document { text, last_good });
+        publish_diagnostics(output, uri, diagnostics)
+    }
+
+    /// Finds the document, the analysis of its current text and the
+    /// offset of the position in the given request params
+    fn lookup(&self, params: &Json) -> Option<(String, Rc<Source>, ModuleDisplay, usize)> {
+        let uri = text_document_uri(params);
+        let doc = self.docs.get(&uri)?;
+        let position = params.get("position")?;
+        let line = position.get("line").and_then(Json::as_i64)? as usize;
+        let character = position.get("character").and_then(Json::as_i64)? as usize;
+        let offset = offset_at(&doc.text, line, character);
+        let source = new_source(&uri, &doc.text);
+        let display = analyze(&self.globals, source.clone()).ok()?;
+        Some((uri, source, display, offset))
+    }
+
+    fn definition(&mut self, params: &Json) -> Json {
+        let (uri, source, display, offset) = match self.lookup(params) {
+            Some(found) => found,
+            None => return Json::Null,
+        };
+        let index = Index::new(&source, &display);
+        match index.target_at(offset) {
+            Some(Target::Name(name)) => match index.resolve(name) {
+                Some(mark) => match index.imports.get(&mark.pos()) {
+                    Some(import) => {
+                        module_location(&mut self.globals, &import.module, &import.field)
+                    }
+                    None => location(&uri, source.data(), mark.pos(), name.name.len()),
+                },
+                None => Json::Null,
+            },
+            Some(Target::Attr(owner, attr)) => match index.import_of(owner) {
+                Some(Import {
+                    module,
+                    field: None,
+                    ..
+                }) => module_location(&mut self.globals, module, &Some(attr.clone())),
+                _ => Json::Null,
+            },
+            None => Json::Null,
+        }
+    }
+
+    fn hover(&mut self, params: &Json) -> Json {
+        let (_, source, display, offset) = match self.lookup(params) {
+            Some(found) => found,
+            None => return Json::Null,
+        };
+        let index = Index::new(&source, &display);
+        let (title, doc) = match index.target_at(offset) {
+            Some(Target::Name(name)) => match index.resolve(name) {
+                Some(mark) => match index.imports.get(&mark.pos()) {
+                    Some(Import {
+                        module,
+                        field: None,
+                        ..
+                    }) => {
+                        let doc = module_info(&mut self.globals, module).and_then(|info| info.doc);
+                        (format!("module {}", module), doc)
+                    }
+                    Some(Import {
+                        module,
+                        field: Some(field),
+                        ..
+                    }) => (
+                        format!("{}.{}", module, field),
+                        member_doc(&mut self.globals, module, field),
+                    ),
+                    None => (name.name.to_string(), index.docs.get(&mark.pos()).cloned()),
+                },
+                None => match self.globals.builtins().get(&name.name) {
+                    Some(value) => (format!("{} (builtin)", name.name), value_doc(value)),
+                    None => return Json::Null,
+                },
+            },
+            Some(Target::Attr(owner, attr)) => match index.import_of(owner) {
+                Some(Import {
+                    module,
+                    field: None,
+                    ..
+                }) => (
+                    format!("{}.{}", module, attr),
+                    member_doc(&mut self.globals, module, attr),
+                ),
+                _ => return Json::Null,
+            },
+            None => return Json::Null,
+        };
+        let mut value = format!("```\n{}\n```", title);
+        if let Some(doc) = doc {
+            if !doc.is_empty() {
+                value.push_str("\n\n");
+                value.push_str(&doc);
+            }
+        }
+        Json::object(vec![(
+            "contents",
+            Json::object(vec![("kind", "markdown".into()), ("value", value.into())]),
+        )])
+    }
+
+    fn completion(&mut self, params: &Json) -> Json {
+        let uri = text_document_uri(params);
+        let doc = match self.docs.get(&uri) {
+            Some(doc) => doc,
+            None => return Json::Null,
+        };
+        let position = params.get("position").unwrap_or(&Json::Null);
+        let line = position.get("line").and_then(Json::as_i64).unwrap_or(0) as usize;
+        let character = position
+            .get("character")
+            .and_then(Json::as_i64)
+            .unwrap_or(0) as usize;
+        let offset = offset_at(&doc.text, line, character);
+        let line_start = doc.text[..offset].rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let prefix = &doc.text[line_start..offset];
+
+        // Completing a name after the dot in 'owner.', or after 'from x import'
+        let module = if let Some(rest) = prefix.trim_start().strip_prefix("from ") {
+            rest.find(" import ").map(|i| RcStr::from(rest[..i].trim()))
+        } else {
+            let before_partial = prefix.trim_end_matches(is_name_char);
+            match before_partial.strip_suffix('.') {
+                Some(owner) => {
+                    let owner_start = owner.trim_end_matches(is_name_char).len();
+                    let owner = &owner[owner_start..];
+                    doc.last_good.as_ref().and_then(|text| {
+                        let source = new_source(&uri, text);
+                        let display = analyze(&self.globals, source.clone()).ok()?;
+                        let index = Index::new(&source, &display);
+                        index
+                            .imports
+                            .values()
+                            .find(|import| import.field.is_none() && import.alias.str() == owner)
+                            .map(|import| import.module.clone())
+                    })
+                }
+                None => None,
+            }
+        };
+        let members = module
+            .and_then(|module| module_info(&mut self.globals, &module))
+            .map(|info| info.members)
+            .unwrap_or_default();
+        let items: Vec<_> = members
+            .into_iter()
+            .map(|member| {
+                let mut pairs = vec![
+                    ("label", member.name.str().into()),
+                    ("kind", member.kind.into()),
+                ];
+                if let Some(doc) = member.doc {
+                    pairs.push(("documentation", doc.str().into()));
+                }
+                Json::object(pairs)
+            })
+            .collect();
+        items.into()
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c == '_' || c.is_alphanumeric()
+}
+
+fn text_document_uri(params: &Json) -> String {
+    params
+        .get("textDocument")
+        .and_then(|doc| doc.get("uri"))
+        .and_then(Json::as_str)
+        .unwrap_or("")
+        .to_owned()
+}
+
+fn publish_diagnostics(output: &mut dyn Write, uri: &str, diagnostics: Vec<Json>) -> Result<()> {
+    Json::object(vec![
+        ("jsonrpc", "2.0".into()),
+        ("method", "textDocument/publishDiagnostics".into()),
+        (
+            "params",
+            Json::object(vec![
+                ("uri", uri.into()),
+                ("diagnostics", diagnostics.into()),
+            ]),
+        ),
+    ])
+    .write_message(output)
+}
+
+fn uri_to_path(uri: &str) -> PathBuf {
+    let path = uri.strip_prefix("file://").unwrap_or(uri);
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        match (bytes[i], path.get(i + 1..i + 3)) {
+            (b'%', Some(hex)) if u8::from_str_radix(hex, 16).is_ok() => {
+                decoded.push(u8::from_str_radix(hex, 16).unwrap());
+                i += 3;
+            }
+            (b, _) => {
+                decoded.push(b);
+                i += 1;
+            }
+        }
+    }
+    PathBuf::from(String::from_utf8_lossy(&decoded).as_ref())
+}
+
+fn path_to_uri(path: &Path) -> String {
+    let mut uri = "file://".to_owned();
+    for b in path.to_string_lossy().bytes() {
+        if b.is_ascii_alphanumeric() || b"/-_.~".contains(&b) {
+            uri.push(b as char);
+        } else {
+            uri.push_str(&format!("%{:02X}", b));
+        }
+    }
+    uri
+}
+
+fn new_source(uri: &str, text: &str) -> Rc<Source> {
+    let path = uri_to_path(uri);
+    let name = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    Rc::new(Source::new(name.into(), Some(path.into()), text.into()))
+}
+
+fn analyze(globals: &Globals, source: Rc<Source>) -> Result<ModuleDisplay> {
+    let mut display = globals.parse(source)?;
+    annotate(&mut display)?;
+    Ok(display)
+}
+
+/// Converts a zero-based line and UTF-16 character offset into a byte offset
+fn offset_at(text: &str, line: usize, character: usize) -> usize {
+    let line_start = if line == 0 {
+        0
+    } else {
+        match text.match_indices('\n').nth(line - 1) {
+            Some((i, _)) => i + 1,
+            None => return text.len(),
+        }
+    };
+    let mut units = 0;
+    for (i, c) in text[line_start..].char_indices() {
+        if units >= character || c == '\n' {
+            return line_start + i;
+        }
+        units += c.len_utf16();
+    }
+    text.len()
+}
+
+fn position(text: &str, offset: usize) -> Json {
+    let line_start = text[..offset].rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let line = text[..offset].matches('\n').count();
+    let character: usize = text[line_start..offset].chars().map(char::len_utf16).sum();
+    Json::object(vec![("line", line.into()), ("character", character.into())])
+}
+
+fn range(text: &str, start: usize, end: usize) -> Json {
+    Json::object(vec![
+        ("start", position(text, start)),
+        ("end", position(text, end)),
+    ])
+}
+
+fn location(uri: &str, text: &str, pos: usize, len: usize) -> Json {
+    Json::object(vec![
+        ("uri", uri.into()),
+        ("range", range(text, pos, (pos + len).min(text.len()))),
+    ])
+}
+
+fn value_doc(value: &Value) -> Option<RcStr> {
+    match value {
+        Value::Function(f) => f.doc().clone(),
+        Value::NativeFunction(f) => f.doc().clone(),
+        _ => None,
+    }
+}
+
+/// What is known about a module without running it
+struct ModuleInfo {
+    source: Option<Rc<Source>>,
+    doc: Option<RcStr>,
+    members: Vec<Member>,
+}
+
+struct Member {
+    name: RcStr,
+    doc: Option<RcStr>,
+    kind: i64,
+
+    /// Where the member is defined in the module's source, if it has one
+    pos: Option<usize>,
+}
+
+/// Gathers the members of a module.
+/// Script modules are found with find_source_path and analyzed (not run);
+/// native modules are loaded
+fn module_info(globals: &mut Globals, name: &RcStr) -> Option<ModuleInfo> {
+    if globals.is_native_module(name) {
+        let module = globals.load(name).ok()?.clone();
+        let mut members: Vec<_> = module
+            .map()
+            .iter()
+            .map(|(key, cell)| Member {
+                name: key.clone(),
+                doc: module
+                    .docmap()
+                    .get(key)
+                    .cloned()
+                    .or_else(|| value_doc(&cell.borrow())),
+                kind: match &*cell.borrow() {
+                    Value::Function(_) | Value::NativeFunction(_) => KIND_FUNCTION,
+                    Value::Class(_) => KIND_CLASS,
+                    _ => KIND_VARIABLE,
+                },
+                pos: None,
+            })
+            .collect();
+        members.sort_by(|a, b| a.name.cmp(&b.name));
+        return Some(ModuleInfo {
+            source: None,
+            doc: module.doc().clone(),
+            members,
+        });
+    }
+    let path = globals.find_source_path(name)?;
+    let data = std::fs::read_to_string(&path).ok()?;
+    let source = Rc::new(Source::new(name.clone(), Some(path.into()), data.into()));
+    let display = analyze(globals, source.clone()).ok()?;
+    let index = Index::new(&source, &display);
+    let members = display
+        .varspec()
+        .as_ref()
+        .unwrap()
+        .owned()
+        .iter()
+        .filter(|(name, _)| !name.contains('/'))
+        .map(|(name, mark)| Member {
+            name: name.clone(),
+            doc: index.docs.get(&mark.pos()).cloned(),
+            kind: index
+                .kinds
+                .get(&mark.pos())
+                .cloned()
+                .unwrap_or(KIND_VARIABLE),
+            pos: Some(mark.pos()),
+        })
+        .collect();
+    Some(ModuleInfo {
+        source: Some(source.clone()),
+        doc: display.doc().clone(),
+        members,
+    })
+}
+
+fn member_doc(globals: &mut Globals, module: &RcStr, member: &RcStr) -> Option<RcStr> {
+    module_info(globals, module)?
+        .members
+        .into_iter()
+        .find(|m| &m.name == member)
+        .and_then(|m| m.doc)
+}
+
+/// The location of a module (or of one of its members) for go-to-definition
+fn module_location(globals: &mut Globals, module: &RcStr, member: &Option<RcStr>) -> Json {
+    let info = match module_info(globals, module) {
+        Some(info) => info,
+        None => return Json::Null,
+    };
+    let source = match &info.source {
+        Some(source) => source,
+        None => return Json::Null,
+    };
+    let uri = path_to_uri(source.path().as_ref().unwrap());
+    let (pos, len) = match member {
+        Some(member) => match info.members.iter().find(|m| &m.name == member) {
+            Some(Member { pos: Some(pos), .. }) => (*pos, member.len()),
+            _ => return Json::Null,
+        },
+        None => (0, 0),
+    };
+    location(&uri, source.data(), pos, len)
+}
+
+/// A name in the source, along with the scopes it appears in (innermost last)
+struct NameRef<'a> {
+    name: RcStr,
+    pos: usize,
+    scopes: Vec<&'a VarSpec>,
+}
+
+/// What the cursor is on
+enum Target<'a, 'b> {
+    Name(&'b NameRef<'a>),
+    /// An attribute of a name (e.g. a module member)
+    Attr(&'b NameRef<'a>, &'b RcStr),
+}
+
+/// Index of the names, docs and imports in an annotated module
+struct Index<'a> {
+    source: &'a Source,
+    names: Vec<NameRef<'a>>,
+
+    /// (owner, attribute name, position of the attribute name)
+    attrs: Vec<(NameRef<'a>, RcStr, usize)>,
+
+    /// Docs from AssignDoc, keyed by the position of the assignment target
+    docs: HashMap<usize, RcStr>,
+
+    /// Completion kinds of documented assignments, keyed like docs
+    kinds: HashMap<usize, i64>,
+
+    /// Names bound by import statements, keyed by the position of the binding
+    imports: HashMap<usize, Import>,
+}
+
+struct Import {
+    alias: RcStr,
+    module: RcStr,
+
+    /// The imported member, for 'from' imports
+    field: Option<RcStr>,
+}
+
+impl<'a> Index<'a> {
+    fn new(source: &'a Source, display: &'a ModuleDisplay) -> Self {
+        let mut index = Self {
+            source,
+            names: vec![],
+            attrs: vec![],
+            docs: HashMap::new(),
+            kinds: HashMap::new(),
+            imports: HashMap::new(),
+        };
+        let mut scopes = vec![display.varspec().as_ref().unwrap()];
+        index.expr(display.body(), &mut scopes);
+        index
+    }
+
+    fn name_ref(&self, name: &RcStr, mark: &Mark, scopes: &[&'a VarSpec]) -> Option<NameRef<'a>> {
+        // Some names are synthesized by the parser (e.g. the variable a
+        // named function is assigned to), and don't appear at their mark
+        if self.source.data()[mark.pos()..].starts_with(name.str()) {
+            Some(NameRef {
+                name: name.clone(),
+                pos: mark.pos(),
+                scopes: scopes.to_vec(),
+            })
+        } else {
+            None
+        }
+    }
+
+    fn add_name(&mut self, name: &RcStr, mark: &Mark, scopes: &[&'a VarSpec]) {
+        if let Some(name_ref) = self.name_ref(name, mark, scopes) {
+            self.names.push(name_ref);
+        }
+    }
+
+    fn add_attr(&mut self, owner: &'a Expr, attr: &RcStr, scopes: &[&'a VarSpec]) {
+        if let ExprDesc::Name(name) = owner.desc() {
+            if let Some(owner_ref) = self.name_ref(name, owner.mark(), scopes) {
+                let after_owner = owner_ref.pos + name.len();
+                if let Some(pos) = self.attr_pos(after_owner, attr) {
+                    self.attrs.push((owner_ref, attr.clone(), pos));
+                }
+            }
+        }
+    }
+
+    /// Finds the attribute name that follows the dot after the owner ending
+    /// at the given position. The name must match as a whole, so that
+    /// e.g. 'foo' is not found in 'foobar'
+    fn attr_pos(&self, after_owner: usize, attr: &RcStr) -> Option<usize> {
+        let data = self.source.data().str();
+        let skip = |pos: usize| pos + data[pos..].len() - data[pos..].trim_start().len();
+        let dot = skip(after_owner);
+        if !data[dot..].starts_with('.') {
+            return None;
+        }
+        let start = skip(dot + 1);
+        let rest = &data[start..];
+        let len = rest
+            .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        if &rest[..len] == attr.str() {
+            Some(start)
+        } else {
+            None
+        }
+    }
+
+    fn target(&mut self, target: &'a AssignTarget, scopes: &mut Vec<&'a VarSpec>) {
+        match target.desc() {
+            AssignTargetDesc::Name(name) => self.add_name(name, target.mark(), scopes),
+            AssignTargetDesc::List(targets) => {
+                for target in targets {
+                    self.target(target, scopes);
+                }
+            }
+            AssignTargetDesc::Attr(owner, _) => self.expr(owner, scopes),
+            AssignTargetDesc::Subscript(owner, index) => {
+                self.expr(owner, scopes);
+                self.expr(index, scopes);
+            }
+        }
+    }
+
+    fn exprs<I: IntoIterator<Item = &'a Expr>>(&mut self, exprs: I, scopes: &mut Vec<&'a VarSpec>) {
+        for expr in exprs {
+            self.expr(expr, scopes);
+        }
+    }
+
+    fn args(&mut self, args: &'a crate::Args, scopes: &mut Vec<&'a VarSpec>) {
+        self.exprs(&args.args, scopes);
+        self.exprs(args.varargs.iter().map(|e| &**e), scopes);
+        self.exprs(args.kwargs.iter().map(|(_, e)| e), scopes);
+        self.exprs(args.kwmap.iter().map(|e| &**e), scopes);
+    }
+
+    fn expr(&mut self, expr: &'a Expr, scopes: &mut Vec<&'a VarSpec>) {
+        match expr.desc() {
+            ExprDesc::Nil
+            | ExprDesc::Bool(_)
+            | ExprDesc::Number(_)
+            | ExprDesc::Int(_)
+            | ExprDesc::String(_)
+            | ExprDesc::Nonlocal(_)
+            | ExprDesc::Del(_)
+            | ExprDesc::Import(_)
+            | ExprDesc::BreakPoint
+            | ExprDesc::Break(_)
+            | ExprDesc::Continue(_)
+            | ExprDesc::GetCallingModule => {}
+            ExprDesc::Name(name) => self.add_name(name, expr.mark(), scopes),
+            ExprDesc::List(exprs) | ExprDesc::Block(exprs) => self.exprs(exprs, scopes),
+            ExprDesc::Map(pairs) => {
+                for (key, val) in pairs {
+                    self.expr(key, scopes);
+                    self.expr(val, scopes);
+                }
+            }
+            ExprDesc::Parentheses(inner)
+            | ExprDesc::Unop(_, inner)
+            | ExprDesc::Yield(inner)
+            | ExprDesc::Await(inner)
+            | ExprDesc::NonlocalAssign(_, inner) => self.expr(inner, scopes),
+            ExprDesc::Switch(value, pairs, default) => {
+                self.expr(value, scopes);
+                for (cases, body) in pairs {
+                    self.exprs(cases, scopes);
+                    self.expr(body, scopes);
+                }
+                self.exprs(default.iter().map(|e| &**e), scopes);
+            }
+            ExprDesc::If(pairs, other) => {
+                for (cond, body) in pairs {
+                    self.expr(cond, scopes);
+                    self.expr(body, scopes);
+                }
+                self.exprs(other.iter().map(|e| &**e), scopes);
+            }
+            ExprDesc::For(target, container, body, _) => {
+                self.target(target, scopes);
+                self.expr(container, scopes);
+                self.expr(body, scopes);
+            }
+            ExprDesc::While(cond, body, _) => {
+                self.expr(cond, scopes);
+                self.expr(body, scopes);
+            }
+            ExprDesc::Try(body, clauses, finally) => {
+                self.expr(body, scopes);
+                for clause in clauses {
+                    self.exprs(clause.cls.iter(), scopes);
+                    self.expr(&clause.body, scopes);
+                }
+                self.exprs(finally.iter().map(|e| &**e), scopes);
+            }
+            ExprDesc::Binop(_, lhs, rhs) | ExprDesc::LogicalBinop(_, lhs, rhs) => {
+                self.expr(lhs, scopes);
+                self.expr(rhs, scopes);
+            }
+            ExprDesc::Subscript(owner, index) => {
+                self.expr(owner, scopes);
+                self.expr(index, scopes);
+            }
+            ExprDesc::Slice(owner, start, end) => {
+                self.expr(owner, scopes);
+                self.exprs(start.iter().map(|e| &**e), scopes);
+                self.exprs(end.iter().map(|e| &**e), scopes);
+            }
+            ExprDesc::Attr(owner, attr) => {
+                self.add_attr(owner, attr, scopes);
+                self.expr(owner, scopes);
+            }
+            ExprDesc::CallFunction(f, args) => {
+                self.expr(f, scopes);
+                self.args(args, scopes);
+            }
+            ExprDesc::CallMethod(owner, method, args) => {
+                self.add_attr(owner, method, scopes);
+                self.expr(owner, scopes);
+                self.args(args, scopes);
+            }
+            ExprDesc::Assign(target, value) => {
+                if let AssignTargetDesc::Name(alias) = target.desc() {
+                    let import = match value.desc() {
+                        ExprDesc::Import(module) => Some((module.clone(), None)),
+                        ExprDesc::Attr(owner, field) => match owner.desc() {
+                            ExprDesc::Import(module) => Some((module.clone(), Some(field.clone()))),
+                            _ => None,
+                        },
+                        _ => None,
+                    };
+                    if let Some((module, field)) = import {
+                        let alias = alias.clone();
+                        let import = Import {
+                            alias,
+                            module,
+                            field,
+                        };
+                        self.imports.insert(target.mark().pos(), import);
+                    }
+                }
+                self.target(target, scopes);
+                self.expr(value, scopes);
+            }
+            ExprDesc::AugAssign(target, _, value) => {
+                self.target(target, scopes);
+                self.expr(value, scopes);
+            }
+            ExprDesc::New(_, pairs) => self.exprs(pairs.iter().map(|(_, e)| e), scopes),
+            ExprDesc::Return(value) => self.exprs(value.iter().map(|e| &**e), scopes),
+            ExprDesc::AssignDoc(inner, _, doc) => {
+                if let ExprDesc::Assign(target, value) = inner.desc() {
+                    let pos = target.mark().pos();
+                    self.docs.insert(pos, doc.clone());
+                    self.kinds.insert(
+                        pos,
+                        match value.desc() {
+                            ExprDesc::Function { .. } => KIND_FUNCTION,
+                            ExprDesc::Class { .. } => KIND_CLASS,
+                            _ => KIND_VARIABLE,
+                        },
+                    );
+                }
+                self.expr(inner, scopes);
+            }
+            ExprDesc::Function { body, varspec, .. } => {
+                scopes.push(varspec.as_ref().unwrap());
+                self.expr(body, scopes);
+                scopes.pop();
+            }
+            ExprDesc::Class {
+                bases,
+                methods,
+                static_methods,
+                ..
+            } => {
+                self.exprs(bases, scopes);
+                self.exprs(methods.iter().map(|(_, e)| e), scopes);
+                self.exprs(static_methods.iter().map(|(_, e)| e), scopes);
+            }
+        }
+    }
+
+    /// Finds the name or attribute at the given offset
+    fn target_at<'b>(&'b self, offset: usize) -> Option<Target<'a, 'b>> {
+        let contains = |pos: usize, len: usize| pos <= offset && offset <= pos + len;
+        if let Some((owner, attr, _)) = self
+            .attrs
+            .iter()
+            .find(|(_, attr, pos)| contains(*pos, attr.len()))
+        {
+            return Some(Target::Attr(owner, attr));
+        }
+        self.names
+            .iter()
+            .find(|name| contains(name.pos, name.name.len()))
+            .map(Target::Name)
+    }
+
+    /// Finds where the given name is bound, searching from the innermost scope out
+    fn resolve(&self, name: &NameRef<'a>) -> Option<&'a Mark> {
+        for scope in name.scopes.iter().rev() {
+            if let Some((_, mark)) = scope
+                .local()
+                .iter()
+                .chain(scope.owned())
+                .find(|(n, _)| n == &name.name)
+            {
+                return Some(mark);
+            }
+        }
+        None
+    }
+
+    /// If the given name is bound by an import, returns the imported module
+    fn import_of(&self, name: &NameRef<'a>) -> Option<&Import> {
+        self.imports.get(&self.resolve(name)?.pos())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Drives a session with a scripted client, and returns the messages sent back
+    fn session(root: &Path, messages: Vec<Json>) -> Vec<Json> {
+        let mut input = Vec::new();
+        for message in messages {
+            message.write_message(&mut input).unwrap();
+        }
+        let mut output = Vec::new();
+        let mut server = Server {
+            globals: Globals::new(),
+            docs: HashMap::new(),
+        };
+        server.globals.add_source_root(root.to_str().unwrap());
+        server
+            .run(&mut std::io::Cursor::new(input), &mut output)
+            .unwrap();
+        let mut reader = std::io::Cursor::new(output);
+        let mut ret = Vec::new();
+        while let Some(message) = Json::read_message(&mut reader).unwrap() {
+            ret.push(message);
+        }
+        ret
+    }
+
+    fn request(id: i64, method: &str, params: Json) -> Json {
+        Json::object(vec![
+            ("jsonrpc", "2.0".into()),
+            ("id", id.into()),
+            ("method", method.into()),
+            ("params", params),
+        ])
+    }
+
+    fn notification(method: &str, params: Json) -> Json {
+        Json::object(vec![
+            ("jsonrpc", "2.0".into()),
+            ("method", method.into()),
+            ("params", params),
+        ])
+    }
+
+    fn at(uri: &str, line: i64, character: i64) -> Json {
+        Json::object(vec![
+            ("textDocument", Json::object(vec![("uri", uri.into())])),
+            (
+                "position",
+                Json::object(vec![("line", line.into()), ("character", character.into())]),
+            ),
+        ])
+    }
+
+    fn result(messages: &[Json], id: i64) -> Json {
+        messages
+            .iter()
+            .find(|m| m.get("id").and_then(Json::as_i64) == Some(id))
+            .and_then(|m| m.get("result"))
+            .cloned()
+            .unwrap()
+    }
+
+    #[test]
+    fn attributes_match_whole_names() {
+        let globals = Globals::new();
+        let text = "x = 1\nprint(x\n  .foo, x . foobar, x.foo)\n";
+        let source = Rc::new(Source::new("main".into(), None, text.into()));
+        let display = analyze(&globals, source.clone()).unwrap();
+        let index = Index::new(&source, &display);
+        let attrs: Vec<_> = index
+            .attrs
+            .iter()
+            .map(|(owner, attr, pos)| (owner.name.str(), attr.str(), *pos))
+            .collect();
+        assert_eq!(
+            attrs,
+            vec![
+                ("x", "foo", text.find(".foo,").unwrap() + 1),
+                ("x", "foobar", text.find("foobar").unwrap()),
+                ("x", "foo", text.find(".foo)").unwrap() + 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn scripted_session() {
+        let root = std::env::temp_dir().join(format!("mtots-lsp-test-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(
+            root.join("helper.u"),
+            "r\"Helper module docs\"\n\ndef twice(x) {\n  r\"Doubles x\"\n  return x * 2\n}\n",
+        )
+        .unwrap();
+        let main_path = root.join("main.u");
+        let uri = path_to_uri(&main_path);
+        let text = "import helper\nvalue = 5\n# The starting value\ndef f(a) = a + value\nprint(helper.twice(f(1)))\n";
+        let bad_text = "x = [1, 2\n";
+        let messages = session(
+            &root,
+            vec![
+                request(1, "initialize", Json::object::<&str>(vec![])),
+                notification(
+                    "textDocument/didOpen",
+                    Json::object(vec![(
+                        "textDocument",
+                        Json::object(vec![("uri", uri.as_str().into()), ("text", text.into())]),
+                    )]),
+                ),
+                // 'value' inside f resolves to the module level assignment
+                request(2, "textDocument/definition", at(&uri, 3, 15)),
+                request(3, "textDocument/hover", at(&uri, 3, 15)),
+                // 'twice' in 'helper.twice' is found in helper.u
+                request(4, "textDocument/definition", at(&uri, 4, 14)),
+                request(5, "textDocument/hover", at(&uri, 4, 14)),
+                request(6, "textDocument/hover", at(&uri, 4, 7)),
+                notification(
+                    "textDocument/didChange",
+                    Json::object(vec![
+                        (
+                            "textDocument",
+                            Json::object(vec![("uri", uri.as_str().into())]),
+                        ),
+                        (
+                            "contentChanges",
+                            vec![Json::object(vec![(
+                                "text",
+                                format!("{}helper.\n", text).into(),
+                            )])]
+                            .into(),
+                        ),
+                    ]),
+                ),
+                request(7, "textDocument/completion", at(&uri, 5, 7)),
+                notification(
+                    "textDocument/didChange",
+                    Json::object(vec![
+                        (
+                            "textDocument",
+                            Json::object(vec![("uri", uri.as_str().into())]),
+                        ),
+                        (
+                            "contentChanges",
+                            vec![Json::object(vec![("text", bad_text.into())])].into(),
+                        ),
+                    ]),
+                ),
+                request(8, "shutdown", Json::Null),
+                notification("exit", Json::Null),
+            ],
+        );
+
+        let definition = result(&messages, 2);
+        assert_eq!(definition.get("uri").unwrap().as_str(), Some(uri.as_str()));
+        assert_eq!(
+            definition
+                .get("range")
+                .unwrap()
+                .get("start")
+                .unwrap()
+                .to_string(),
+            r#"{"line":1,"character":0}"#
+        );
+
+        let hover = result(&messages, 3);
+        let hover = hover.get("contents").unwrap().get("value").unwrap();
+        assert!(
+            hover.as_str().unwrap().contains("The starting value"),
+            "{}",
+            hover
+        );
+
+        let definition = result(&messages, 4);
+        assert!(definition
+            .get("uri")
+            .unwrap()
+            .as_str()
+            .unwrap()
+            .ends_with("/helper.u"));
+        assert_eq!(
+            definition
+                .get("range")
+                .unwrap()
+                .get("start")
+                .unwrap()
+                .to_string(),
+            r#"{"line":2,"character":0}"#
+        );
+
+        let hover = result(&messages, 5).get("contents").unwrap().to_string();
+        assert!(
+            hover.contains("helper.twice") && hover.contains("Doubles x"),
+            "{}",
+            hover
+        );
+        let hover = result(&messages, 6).get("contents").unwrap().to_string();
+        assert!(
+            hover.contains("module helper") && hover.contains("Helper module docs"),
+            "{}",
+            hover
+        );
+
+        let completion = result(&messages, 7);
+        let labels: Vec<_> = completion
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item.get("label").unwrap().as_str().unwrap().to_owned())
+            .collect();
+        assert_eq!(labels, vec!["twice"]);
+
+        let diagnostics: Vec<_> = messages
+            .iter()
+            .filter(|m| {
+                m.get("method").and_then(Json::as_str) == Some("textDocument/publishDiagnostics")
+            })
+            .map(|m| {
+                m.get("params")
+                    .unwrap()
+                    .get("diagnostics")
+                    .unwrap()
+                    .as_array()
+                    .unwrap()
+                    .len()
+            })
+            .collect();
+        assert_eq!(diagnostics, vec![0, 1, 1]);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
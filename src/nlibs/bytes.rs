@@ -150,7 +150,7 @@ fn add_bytes(globals: &mut Globals, out: &mut Vec<u8>, value: &Value) -> Result<
         _ if value.is_handle::<Vec<u8>>() => {
             out.extend(value.clone().into_handle::<Vec<u8>>()?.borrow().iter());
         }
-        Value::Number(_) => out.push(u8::try_from(value)?),
+        Value::Number(_) | Value::Int(_) => out.push(u8::try_from(value)?),
         Value::String(string) => out.extend(string.str().as_bytes()),
         _ => {
             value.easy_iter_unpack(|iter| {
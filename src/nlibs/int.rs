@@ -1,14 +1,18 @@
+use crate::ArgSpec;
+use crate::BigInt;
 use crate::ConvertValue;
 use crate::Globals;
+use crate::NativeClassBuilder;
 use crate::NativeModule;
 use crate::Result;
 use crate::Value;
+use std::convert::TryFrom;
 
 const NAME: &'static str = "a.int";
 
 pub(super) fn new() -> NativeModule {
     NativeModule::new(NAME, |m| {
-        m.doc("Utilities for dealing with integers");
+        m.doc("Utilities for dealing with integers, including arbitrary-precision BigInts");
         m.class::<IntType, _>("IntType", |cls| {
             cls.ifunc("and_", ["a", "b"], "", |owner, _globals, args, _| {
                 let mut args = args.into_iter();
@@ -29,6 +33,220 @@ pub(super) fn new() -> NativeModule {
                 Ok(Value::from(owner.borrow().xor(a, b)))
             });
         });
+        m.class::<BigInt, _>("BigInt", |cls| {
+            cls.doc(concat!(
+                "Arbitrary-precision integers\n",
+                "Ints and integral floats are converted to BigInts where ",
+                "one is expected, so e.g. 'BigInt(2) ** 100 + 1' works.\n",
+            ));
+            cls.eq(|a, b| a == b);
+            cls.str(|x| x.to_string().into());
+            cls.repr(|x| format!("{:?}", x).into());
+            cls.sfunc(
+                "__call",
+                ["x"],
+                "Converts an int, integral float or decimal string into a BigInt",
+                |globals, args, _| {
+                    let x = args.into_iter().next().unwrap();
+                    let x = match &x {
+                        Value::String(s) => parse(s.str(), 10)?,
+                        _ => x.convert::<BigInt>(globals)?,
+                    };
+                    globals.new_handle(x).map(Value::from)
+                },
+            );
+            cls.sfunc(
+                "parse",
+                ArgSpec::builder().req("s").def("radix", 10),
+                "Parses a string of digits in the given radix (2 to 36)",
+                |globals, args, _| {
+                    let x = parse(args[0].string()?.str(), radix(&args[1])?)?;
+                    globals.new_handle(x).map(Value::from)
+                },
+            );
+            cls.sfunc(
+                "from_bytes",
+                ArgSpec::builder()
+                    .req("bytes")
+                    .def("order", "big")
+                    .def("signed", false),
+                concat!(
+                    "Converts bytes into a BigInt. 'order' is 'big' or 'little', and ",
+                    "if 'signed' is true, the bytes are read as two's complement",
+                ),
+                |globals, args, _| {
+                    let mut args = args.into_iter();
+                    let bytes = args.next().unwrap().convert::<Vec<u8>>(globals)?;
+                    let big_endian = big_endian(&args.next().unwrap())?;
+                    let signed = args.next().unwrap().truthy();
+                    let x = BigInt::from_bytes(&bytes, big_endian, signed);
+                    globals.new_handle(x).map(Value::from)
+                },
+            );
+            cls.ifunc(
+                "format",
+                ArgSpec::builder().def("radix", 10),
+                "Formats this value as a string of digits in the given radix (2 to 36)",
+                |owner, _globals, args, _| Ok(owner.borrow().to_str_radix(radix(&args[0])?).into()),
+            );
+            cls.ifunc(
+                "to_bytes",
+                ArgSpec::builder()
+                    .def("order", "big")
+                    .def("signed", false)
+                    .def("length", ()),
+                concat!(
+                    "Converts this value into bytes. 'order' is 'big' or 'little', and ",
+                    "if 'signed' is true, the value is written in two's complement.\n",
+                    "Uses as few bytes as possible, unless 'length' is given, in which ",
+                    "case the result is padded to exactly that many bytes",
+                ),
+                |owner, globals, args, _| {
+                    let mut args = args.into_iter();
+                    let big_endian = big_endian(&args.next().unwrap())?;
+                    let signed = args.next().unwrap().truthy();
+                    let length = args.next().unwrap();
+                    let x = owner.borrow();
+                    let mut bytes = match x.to_bytes(false, signed) {
+                        Some(bytes) => bytes,
+                        None => return Err(rterr!("Negative value {} requires signed=true", x)),
+                    };
+                    if !length.is_nil() {
+                        let length = length.usize()?;
+                        if bytes.len() > length {
+                            return Err(rterr!("{} does not fit in {} bytes", x, length));
+                        }
+                        let fill = if x.is_negative() { 0xff } else { 0 };
+                        bytes.resize(length, fill);
+                    }
+                    if big_endian {
+                        bytes.reverse();
+                    }
+                    // the Bytes class is registered by 'a.bytes', which depends on
+                    // this module, so it can't be declared as a dependency here
+                    globals.load(&"a.bytes".into())?;
+                    globals.new_handle(bytes).map(Value::from)
+                },
+            );
+            cls.ifunc(
+                "to_int",
+                [],
+                "Converts to an int, failing if the value does not fit in 64 bits",
+                |owner, _globals, _args, _| {
+                    let x = owner.borrow();
+                    match x.to_i64() {
+                        Some(i) => Ok(i.into()),
+                        None => Err(rterr!("{} does not fit in an int", x)),
+                    }
+                },
+            );
+            cls.ifunc(
+                "to_float",
+                [],
+                "Converts to the nearest float",
+                |owner, _globals, _args, _| Ok(owner.borrow().to_f64().into()),
+            );
+            cls.ifunc(
+                "bit_length",
+                [],
+                "The number of bits needed to represent the absolute value",
+                |owner, _globals, _args, _| Ok(owner.borrow().bit_length().into()),
+            );
+            cls.ifunc(
+                "sign",
+                [],
+                "-1, 0 or 1 for negative, zero or positive values",
+                |owner, _globals, _args, _| Ok(owner.borrow().signum().into()),
+            );
+            cls.ifunc("abs", [], "", |owner, globals, _args, _| {
+                let x = owner.borrow().abs();
+                globals.new_handle(x).map(Value::from)
+            });
+            cls.ifunc(
+                "pow_mod",
+                ["exp", "modulus"],
+                "(self ** exp) %% modulus, computed without the large intermediate value",
+                |owner, globals, args, _| {
+                    let mut args = args.into_iter();
+                    let exp = args.next().unwrap().convert::<BigInt>(globals)?;
+                    let modulus = args.next().unwrap().convert::<BigInt>(globals)?;
+                    let x = match owner.borrow().pow_mod(&exp, &modulus) {
+                        Some(x) => x,
+                        None if modulus.is_zero() => {
+                            return Err(rterr!("pow_mod with zero modulus"))
+                        }
+                        None => return Err(rterr!("pow_mod with negative exponent {}", exp)),
+                    };
+                    globals.new_handle(x).map(Value::from)
+                },
+            );
+
+            binop(cls, "__add", "__radd", |a, b| Ok(a + b));
+            binop(cls, "__sub", "__rsub", |a, b| Ok(a - b));
+            binop(cls, "__mul", "__rmul", |a, b| Ok(a * b));
+            binop(
+                cls,
+                "__truncdiv",
+                "__rtruncdiv",
+                |a, b| Ok(div_rem(a, b)?.0),
+            );
+            binop(cls, "__rem", "__rrem", |a, b| Ok(div_rem(a, b)?.1));
+            binop(cls, "__erem", "__rerem", |a, b| {
+                a.rem_euclid(b)
+                    .ok_or_else(|| rterr!("BigInt division by zero"))
+            });
+            binop(cls, "__pow", "__rpow", |a, b| {
+                Ok(a.pow(small(b, "exponent")?))
+            });
+            binop(cls, "__and", "__rand", |a, b| Ok(a.and(b)));
+            binop(cls, "__or", "__ror", |a, b| Ok(a.or(b)));
+            binop(cls, "__xor", "__rxor", |a, b| Ok(a.xor(b)));
+            binop(cls, "__shl", "__rshl", |a, b| {
+                Ok(a.shl(small(b, "shift count")? as usize))
+            });
+            binop(cls, "__shr", "__rshr", |a, b| {
+                Ok(a.shr(small(b, "shift count")? as usize))
+            });
+            cls.ifunc("__div", ["other"], "", |owner, globals, args, _| {
+                let other = args
+                    .into_iter()
+                    .next()
+                    .unwrap()
+                    .convert::<BigInt>(globals)?;
+                Ok((owner.borrow().to_f64() / other.to_f64()).into())
+            });
+            cls.ifunc("__rdiv", ["other"], "", |owner, globals, args, _| {
+                let other = args
+                    .into_iter()
+                    .next()
+                    .unwrap()
+                    .convert::<BigInt>(globals)?;
+                Ok((other.to_f64() / owner.borrow().to_f64()).into())
+            });
+            cls.ifunc("__neg", [], "", |owner, globals, _args, _| {
+                let x = -&*owner.borrow();
+                globals.new_handle(x).map(Value::from)
+            });
+            cls.ifunc("__pos", [], "", |owner, _globals, _args, _| {
+                Ok(Value::from(owner))
+            });
+            cls.ifunc("__invert", [], "", |owner, globals, _args, _| {
+                let x = owner.borrow().not();
+                globals.new_handle(x).map(Value::from)
+            });
+            cls.ifunc("__not", [], "", |owner, _globals, _args, _| {
+                Ok(owner.borrow().is_zero().into())
+            });
+            cls.ifunc("__eq", ["other"], "", |owner, globals, args, _| {
+                let other = args.into_iter().next().unwrap();
+                Ok(match other.convert::<BigInt>(globals) {
+                    Ok(other) => (*owner.borrow() == other).into(),
+                    Err(_) => false.into(),
+                })
+            });
+            cmpop(cls, "__lt", "__rlt", |a, b| a < b);
+            cmpop(cls, "__le", "__rle", |a, b| a <= b);
+        });
         m.field("u8", "", |globals, _| {
             Ok(globals.new_handle(IntType::u8)?.into())
         });
@@ -50,6 +268,104 @@ pub(super) fn new() -> NativeModule {
     })
 }
 
+impl ConvertValue for BigInt {
+    fn convert(_globals: &mut Globals, value: &Value) -> Result<Self> {
+        match value {
+            Value::Int(x) => Ok(BigInt::from(*x)),
+            Value::Number(x) => match BigInt::from_f64(*x) {
+                Some(x) => Ok(x),
+                None => Err(rterr!("Expected an integral value, but got {}", x)),
+            },
+            _ => Err(rterr!("Expected BigInt, but got {:?}", value)),
+        }
+    }
+}
+
+/// Declares an operator method along with its reflected variant
+fn binop<F>(cls: &mut NativeClassBuilder<BigInt>, name: &str, rname: &str, f: F)
+where
+    F: Fn(&BigInt, &BigInt) -> Result<BigInt> + Copy + 'static,
+{
+    cls.ifunc(name, ["other"], "", move |owner, globals, args, _| {
+        let other = args
+            .into_iter()
+            .next()
+            .unwrap()
+            .convert::<BigInt>(globals)?;
+        let x = f(&owner.borrow(), &other)?;
+        globals.new_handle(x).map(Value::from)
+    });
+    cls.ifunc(rname, ["other"], "", move |owner, globals, args, _| {
+        let other = args
+            .into_iter()
+            .next()
+            .unwrap()
+            .convert::<BigInt>(globals)?;
+        let x = f(&other, &owner.borrow())?;
+        globals.new_handle(x).map(Value::from)
+    });
+}
+
+fn cmpop<F>(cls: &mut NativeClassBuilder<BigInt>, name: &str, rname: &str, f: F)
+where
+    F: Fn(&BigInt, &BigInt) -> bool + Copy + 'static,
+{
+    cls.ifunc(name, ["other"], "", move |owner, globals, args, _| {
+        let other = args
+            .into_iter()
+            .next()
+            .unwrap()
+            .convert::<BigInt>(globals)?;
+        Ok(f(&owner.borrow(), &other).into())
+    });
+    cls.ifunc(rname, ["other"], "", move |owner, globals, args, _| {
+        let other = args
+            .into_iter()
+            .next()
+            .unwrap()
+            .convert::<BigInt>(globals)?;
+        Ok(f(&other, &owner.borrow()).into())
+    });
+}
+
+fn div_rem(a: &BigInt, b: &BigInt) -> Result<(BigInt, BigInt)> {
+    a.div_rem(b)
+        .ok_or_else(|| rterr!("BigInt division by zero"))
+}
+
+/// Converts an exponent or shift count
+fn small(x: &BigInt, what: &str) -> Result<u32> {
+    match x.to_i64().map(u32::try_from) {
+        Some(Ok(x)) => Ok(x),
+        _ if x.is_negative() => Err(rterr!("Negative {} ({})", what, x)),
+        _ => Err(rterr!("The {} {} is too large", what, x)),
+    }
+}
+
+fn parse(s: &str, radix: u32) -> Result<BigInt> {
+    match BigInt::parse_radix(s, radix) {
+        Some(x) => Ok(x),
+        None => Err(rterr!("Invalid base {} integer: {:?}", radix, s)),
+    }
+}
+
+fn radix(value: &Value) -> Result<u32> {
+    let radix = value.u32()?;
+    if (2..=36).contains(&radix) {
+        Ok(radix)
+    } else {
+        Err(rterr!("Invalid radix {} (expected 2 to 36)", radix))
+    }
+}
+
+fn big_endian(order: &Value) -> Result<bool> {
+    match order.string()?.str() {
+        "big" => Ok(true),
+        "little" => Ok(false),
+        order => Err(rterr!("Expected 'big' or 'little', but got {:?}", order)),
+    }
+}
+
 #[allow(non_camel_case_types)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum IntType {
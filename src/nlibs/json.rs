@@ -0,0 +1,172 @@
+use crate::write_json_string;
+use crate::ArgSpec;
+use crate::IndexMap;
+use crate::Json;
+use crate::Key;
+use crate::NativeModule;
+use crate::Result;
+use crate::Value;
+use std::fmt::Write;
+
+const NAME: &str = "a.json";
+
+pub(super) fn new() -> NativeModule {
+    NativeModule::new(NAME, |m| {
+        m.doc("JSON encoding and decoding");
+        m.func(
+            "parse",
+            ["text"],
+            concat!(
+                "Parses JSON text.\n",
+                "Objects become Maps (keeping their key order), arrays become Lists, ",
+                "numbers become floats and null becomes nil",
+            ),
+            |_globals, args, _| {
+                let json = Json::parse(args[0].string()?.str())?;
+                Ok(from_json(json))
+            },
+        );
+        m.func(
+            "dump",
+            ArgSpec::builder()
+                .req("value")
+                .def("indent", ())
+                .def("sort_keys", false),
+            concat!(
+                "Converts a value into JSON text.\n",
+                "Only nil, bools, numbers, strings, Lists and Maps with string keys ",
+                "can be converted, and Lists and Maps may not contain themselves.\n",
+                "If 'indent' is given, the output is pretty printed with that many ",
+                "spaces per level. If 'sort_keys' is true, object keys are sorted",
+            ),
+            |_globals, args, _| {
+                let mut args = args.into_iter();
+                let value = args.next().unwrap();
+                let indent = args.next().unwrap();
+                let indent = if indent.is_nil() {
+                    None
+                } else {
+                    Some(indent.usize()?)
+                };
+                let sort_keys = args.next().unwrap().truthy();
+                let mut dumper = Dumper {
+                    out: String::new(),
+                    indent,
+                    sort_keys,
+                    stack: vec![],
+                };
+                dumper.dump(&value)?;
+                Ok(dumper.out.into())
+            },
+        );
+    })
+}
+
+fn from_json(json: Json) -> Value {
+    match json {
+        Json::Null => Value::Nil,
+        Json::Bool(b) => b.into(),
+        Json::Number(x) => x.into(),
+        Json::String(s) => s.into(),
+        Json::Array(vec) => vec.into_iter().map(from_json).collect::<Vec<_>>().into(),
+        Json::Object(map) => map
+            .into_iter()
+            .map(|(k, v)| (Key::String(k.into()), from_json(v)))
+            .collect::<IndexMap<_, _>>()
+            .into(),
+    }
+}
+
+struct Dumper {
+    out: String,
+    indent: Option<usize>,
+    sort_keys: bool,
+
+    // addresses of the Lists and Maps currently being dumped,
+    // for detecting cycles
+    stack: Vec<*const ()>,
+}
+
+impl Dumper {
+    fn dump(&mut self, value: &Value) -> Result<()> {
+        match value {
+            Value::Nil => self.out.push_str("null"),
+            Value::Bool(b) => write!(self.out, "{}", b)?,
+            Value::Int(x) => write!(self.out, "{}", x)?,
+            Value::Number(x) if !x.is_finite() => {
+                return Err(rterr!("{} cannot be converted to JSON", x));
+            }
+            Value::Number(x) => write!(self.out, "{}", x)?,
+            Value::String(s) => write_json_string(&mut self.out, s)?,
+            Value::List(list) => {
+                self.enter(list.as_ref() as *const _ as *const ())?;
+                let list = list.borrow();
+                self.out.push('[');
+                for (i, item) in list.iter().enumerate() {
+                    if i > 0 {
+                        self.out.push(',');
+                    }
+                    self.newline();
+                    self.dump(item)?;
+                }
+                self.exit(!list.is_empty());
+                self.out.push(']');
+            }
+            Value::Map(map) => {
+                self.enter(map.as_ref() as *const _ as *const ())?;
+                let map = map.borrow();
+                let mut pairs = Vec::new();
+                for (key, val) in map.iter() {
+                    match key {
+                        Key::String(key) => pairs.push((key, val)),
+                        key => {
+                            return Err(rterr!("JSON object keys must be strings, got {:?}", key))
+                        }
+                    }
+                }
+                if self.sort_keys {
+                    pairs.sort_by(|a, b| a.0.cmp(b.0));
+                }
+                self.out.push('{');
+                for (i, (key, val)) in pairs.iter().enumerate() {
+                    if i > 0 {
+                        self.out.push(',');
+                    }
+                    self.newline();
+                    write_json_string(&mut self.out, key)?;
+                    self.out.push(':');
+                    if self.indent.is_some() {
+                        self.out.push(' ');
+                    }
+                    self.dump(val)?;
+                }
+                self.exit(!pairs.is_empty());
+                self.out.push('}');
+            }
+            _ => return Err(kinderr!(Type, "{:?} cannot be converted to JSON", value)),
+        }
+        Ok(())
+    }
+    fn enter(&mut self, ptr: *const ()) -> Result<()> {
+        if self.stack.contains(&ptr) {
+            return Err(rterr!("Circular reference found while converting to JSON"));
+        }
+        self.stack.push(ptr);
+        Ok(())
+    }
+    fn exit(&mut self, nonempty: bool) {
+        self.stack.pop();
+        if nonempty {
+            self.newline();
+        }
+    }
+    /// When pretty printing, starts a new line indented for the current depth
+    fn newline(&mut self) {
+        if let Some(indent) = self.indent {
+            self.out.push('\n');
+            for _ in 0..indent * self.stack.len() {
+                self.out.push(' ');
+            }
+        }
+    }
+}
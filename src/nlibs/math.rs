@@ -5,6 +5,7 @@ use crate::RcStr;
 use crate::Result;
 use crate::Value;
 use std::collections::HashMap;
+use std::convert::TryFrom;
 
 pub(super) fn new() -> NativeModule {
     NativeModule::new("a.math", |builder| {
@@ -12,6 +13,8 @@ pub(super) fn new() -> NativeModule {
             .val("pi", "", std::f64::consts::PI)
             .val("tau", "", std::f64::consts::PI * 2.0)
             .val("e", "", std::f64::consts::E)
+            .val("inf", "", f64::INFINITY)
+            .val("nan", "", f64::NAN)
             .func("sin", ["x"], "Computes sin in radians", wrap1(f64::sin))
             .func("cos", ["x"], "Computes cos in radians", wrap1(f64::cos))
             .func("tan", ["x"], "Computes tan in radians", wrap1(f64::tan))
@@ -47,7 +50,231 @@ pub(super) fn new() -> NativeModule {
                 "Computes the natural logarithm",
                 wrap1(f64::ln),
             )
-            .func("log2", ["x"], "Computes log base 2", wrap1(f64::log2));
+            .func("log2", ["x"], "Computes log base 2", wrap1(f64::log2))
+            .func("exp", ["x"], "Computes e ** x", wrap1(f64::exp))
+            .func(
+                "hypot",
+                ["x", "y"],
+                "Computes sqrt(x ** 2 + y ** 2) without undue overflow",
+                wrap2(f64::hypot),
+            )
+            .func(
+                "copysign",
+                ["x", "y"],
+                "Returns x with the sign of y",
+                wrap2(f64::copysign),
+            )
+            .func(
+                "isnan",
+                ["x"],
+                "Checks whether x is nan",
+                test1(f64::is_nan),
+            )
+            .func(
+                "isinf",
+                ["x"],
+                "Checks whether x is positive or negative infinity",
+                test1(f64::is_infinite),
+            )
+            .func(
+                "isfinite",
+                ["x"],
+                "Checks whether x is neither infinite nor nan",
+                test1(f64::is_finite),
+            )
+            .func(
+                "floor",
+                ["x"],
+                "Rounds towards negative infinity, returning an int when the result fits",
+                round1(f64::floor),
+            )
+            .func(
+                "ceil",
+                ["x"],
+                "Rounds towards positive infinity, returning an int when the result fits",
+                round1(f64::ceil),
+            )
+            .func(
+                "trunc",
+                ["x"],
+                "Rounds towards zero, returning an int when the result fits",
+                round1(f64::trunc),
+            )
+            .func(
+                "round",
+                ArgSpec::builder().req("x").def("ndigits", ()),
+                concat!(
+                    "Rounds to the nearest integer, with halfway cases rounded away from zero.\n",
+                    "Without ndigits, returns an int when the result fits.\n",
+                    "With ndigits, rounds to that many decimal places and returns a float",
+                ),
+                |globals, args, _| {
+                    let mut args = args.into_iter();
+                    let x = args.next().unwrap();
+                    let ndigits = args.next().unwrap();
+                    if ndigits.is_nil() {
+                        round1(f64::round)(globals, vec![x], None)
+                    } else {
+                        let x = x.number()?;
+                        let scale = 10f64.powi(ndigits.i32()?);
+                        Ok(Value::from((x * scale).round() / scale))
+                    }
+                },
+            )
+            .func(
+                "clamp",
+                ["x", "min", "max"],
+                "Restricts x to the range [min, max]",
+                |_globals, args, _| {
+                    let mut args = args.into_iter();
+                    let x = args.next().unwrap();
+                    let min = args.next().unwrap();
+                    let max = args.next().unwrap();
+                    if let (Value::Int(x), Value::Int(min), Value::Int(max)) = (&x, &min, &max) {
+                        if min > max {
+                            return Err(rterr!("clamp: min ({}) > max ({})", min, max));
+                        }
+                        return Ok(Value::from(*x.max(min).min(max)));
+                    }
+                    let (x, min, max) = (x.number()?, min.number()?, max.number()?);
+                    // f64::clamp panics if min > max or either is nan
+                    if min.partial_cmp(&max).map(|o| o.is_gt()).unwrap_or(true) {
+                        return Err(rterr!("clamp: min ({}) > max ({})", min, max));
+                    }
+                    Ok(Value::from(x.clamp(min, max)))
+                },
+            )
+            .func(
+                "gcd",
+                ArgSpec::builder().var("xs"),
+                "Greatest common divisor of the given integers (0 if none are given)",
+                |_globals, args, _| {
+                    let mut ret = 0;
+                    for x in args {
+                        ret = gcd(ret, x.i64()?.unsigned_abs());
+                    }
+                    to_int(ret)
+                },
+            )
+            .func(
+                "lcm",
+                ArgSpec::builder().var("xs"),
+                "Least common multiple of the given integers (1 if none are given)",
+                |_globals, args, _| {
+                    let mut ret: u64 = 1;
+                    for x in args {
+                        let x = x.i64()?;
+                        if x == 0 {
+                            return Ok(Value::from(0));
+                        }
+                        let x = x.unsigned_abs();
+                        ret = match (ret / gcd(ret, x)).checked_mul(x) {
+                            Some(ret) => ret,
+                            None => return Err(rterr!("lcm overflowed")),
+                        };
+                    }
+                    to_int(ret)
+                },
+            )
+            .func(
+                "sum",
+                ["xs"],
+                concat!(
+                    "Sums the numbers in an iterable.\n",
+                    "Ints are summed exactly; as soon as a float is involved, ",
+                    "Kahan (Neumaier) compensated summation is used to limit rounding error",
+                ),
+                |globals, args, _| {
+                    let xs = args.into_iter().next().unwrap().unpack(globals)?;
+                    let mut int_total: i64 = 0;
+                    let mut i = 0;
+                    while i < xs.len() {
+                        if let Value::Int(x) = &xs[i] {
+                            int_total = match int_total.checked_add(*x) {
+                                Some(total) => total,
+                                None => break,
+                            };
+                            i += 1;
+                        } else {
+                            break;
+                        }
+                    }
+                    if i == xs.len() {
+                        return Ok(Value::from(int_total));
+                    }
+                    let mut rest = vec![int_total as f64];
+                    for x in &xs[i..] {
+                        rest.push(x.number()?);
+                    }
+                    Ok(Value::from(fsum(&rest)))
+                },
+            )
+            .func(
+                "mean",
+                ["xs"],
+                "Arithmetic mean of a non-empty iterable of numbers",
+                |globals, args, _| {
+                    let xs = numbers(globals, args, "mean")?;
+                    Ok(Value::from(mean(&xs)))
+                },
+            )
+            .func(
+                "median",
+                ["xs"],
+                concat!(
+                    "Median of a non-empty iterable of numbers.\n",
+                    "With an even number of values, the mean of the middle two is returned",
+                ),
+                |globals, args, _| {
+                    let mut xs = numbers(globals, args, "median")?;
+                    xs.sort_by(f64::total_cmp);
+                    let mid = xs.len() / 2;
+                    Ok(Value::from(if xs.len() % 2 == 0 {
+                        (xs[mid - 1] + xs[mid]) / 2.0
+                    } else {
+                        xs[mid]
+                    }))
+                },
+            )
+            .func(
+                "variance",
+                ArgSpec::builder().req("xs").def("sample", false),
+                concat!(
+                    "Variance of an iterable of numbers.\n",
+                    "Computes the population variance, or if 'sample' is true, ",
+                    "the sample variance (dividing by n - 1)",
+                ),
+                |globals, args, _| Ok(Value::from(variance(globals, args)?)),
+            )
+            .func(
+                "stddev",
+                ArgSpec::builder().req("xs").def("sample", false),
+                concat!(
+                    "Standard deviation of an iterable of numbers.\n",
+                    "See 'variance' for the meaning of 'sample'",
+                ),
+                |globals, args, _| Ok(Value::from(variance(globals, args)?.sqrt())),
+            )
+            .func(
+                "percentile",
+                ["xs", "p"],
+                concat!(
+                    "The p-th percentile (0 <= p <= 100) of a non-empty iterable of numbers, ",
+                    "interpolating linearly between the closest values",
+                ),
+                |globals, mut args, _| {
+                    let p = args.pop().unwrap().number()?;
+                    if !(0.0..=100.0).contains(&p) {
+                        return Err(rterr!("percentile: p must be in [0, 100], but got {}", p));
+                    }
+                    let mut xs = numbers(globals, args, "percentile")?;
+                    xs.sort_by(f64::total_cmp);
+                    let pos = p / 100.0 * (xs.len() - 1) as f64;
+                    let lo = pos.floor() as usize;
+                    let hi = pos.ceil() as usize;
+                    Ok(Value::from(xs[lo] + (xs[hi] - xs[lo]) * (pos - lo as f64)))
+                },
+            );
     })
 }
 
@@ -70,3 +297,97 @@ fn wrap2<F: Fn(f64, f64) -> f64 + 'static>(
         Ok(Value::from(f(a, b)))
     }
 }
+
+fn test1<F: Fn(f64) -> bool + 'static>(
+    f: F,
+) -> impl Fn(&mut Globals, Vec<Value>, Option<HashMap<RcStr, Value>>) -> Result<Value> + 'static {
+    move |_, args, _| {
+        let x = args.into_iter().next().unwrap().number()?;
+        Ok(Value::from(f(x)))
+    }
+}
+
+/// Wraps a rounding function; ints are returned as is, and integral results
+/// that fit are returned as ints
+fn round1<F: Fn(f64) -> f64 + 'static>(
+    f: F,
+) -> impl Fn(&mut Globals, Vec<Value>, Option<HashMap<RcStr, Value>>) -> Result<Value> + 'static {
+    move |_, args, _| {
+        let x = args.into_iter().next().unwrap();
+        if let Value::Int(_) = x {
+            return Ok(x);
+        }
+        let x = f(x.number()?);
+        if x >= i64::MIN as f64 && x < i64::MAX as f64 {
+            Ok(Value::from(x as i64))
+        } else {
+            Ok(Value::from(x))
+        }
+    }
+}
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        let r = a % b;
+        a = b;
+        b = r;
+    }
+    a
+}
+
+fn to_int(x: u64) -> Result<Value> {
+    match i64::try_from(x) {
+        Ok(x) => Ok(Value::from(x)),
+        Err(_) => Err(rterr!("{} does not fit in an int", x)),
+    }
+}
+
+/// Neumaier's variant of Kahan summation.
+/// Once the total is no longer finite, the compensation is meaningless
+/// (inf - inf is NaN), so the plain sum is returned instead
+fn fsum(xs: &[f64]) -> f64 {
+    let mut total = 0.0;
+    let mut compensation = 0.0;
+    for &x in xs {
+        let t = total + x;
+        if !t.is_finite() {
+            return xs.iter().sum();
+        }
+        if f64::abs(total) >= f64::abs(x) {
+            compensation += (total - t) + x;
+        } else {
+            compensation += (x - t) + total;
+        }
+        total = t;
+    }
+    total + compensation
+}
+
+fn mean(xs: &[f64]) -> f64 {
+    fsum(xs) / xs.len() as f64
+}
+
+/// Unpacks the first argument into a non-empty list of numbers
+fn numbers(globals: &mut Globals, args: Vec<Value>, fname: &str) -> Result<Vec<f64>> {
+    let xs = args.into_iter().next().unwrap().unpack(globals)?;
+    if xs.is_empty() {
+        return Err(rterr!("{}: expected at least one value", fname));
+    }
+    xs.iter().map(Value::number).collect()
+}
+
+fn variance(globals: &mut Globals, args: Vec<Value>) -> Result<f64> {
+    let mut args = args.into_iter();
+    let xs = args.next().unwrap();
+    let sample = args.next().unwrap().truthy();
+    let xs = numbers(globals, vec![xs], "variance")?;
+    let n = xs.len() as f64;
+    if sample && xs.len() < 2 {
+        return Err(rterr!(
+            "variance: sample variance needs at least two values"
+        ));
+    }
+    let mean = mean(&xs);
+    let squares: Vec<_> = xs.iter().map(|x| (x - mean) * (x - mean)).collect();
+    Ok(fsum(&squares) / if sample { n - 1.0 } else { n })
+}
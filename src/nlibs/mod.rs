@@ -6,14 +6,18 @@ mod encoding;
 mod env;
 mod fs;
 mod int;
+mod json;
 mod math;
 mod os;
 mod procc;
+mod random;
+mod re;
 mod sys;
 mod time;
 
 pub use encoding::Encoding;
 pub use int::IntType;
+pub use random::Rng;
 
 impl Globals {
     pub fn add_builtin_native_libraries(&mut self) {
@@ -21,9 +25,12 @@ impl Globals {
         self.add_native_module(env::new()).unwrap();
         self.add_native_module(fs::new()).unwrap();
         self.add_native_module(int::new()).unwrap();
+        self.add_native_module(json::new()).unwrap();
         self.add_native_module(math::new()).unwrap();
         self.add_native_module(os::new()).unwrap();
         self.add_native_module(procc::new()).unwrap();
+        self.add_native_module(random::new()).unwrap();
+        self.add_native_module(re::new()).unwrap();
         self.add_native_module(sys::new()).unwrap();
         self.add_native_module(time::new()).unwrap();
     }
@@ -0,0 +1,260 @@
+use crate::ArgSpec;
+use crate::Globals;
+use crate::Handle;
+use crate::NativeModule;
+use crate::Result;
+use crate::Value;
+use std::time::SystemTime;
+
+const NAME: &str = "a.random";
+
+/// A small, fast, seedable pseudo-random number generator (xoshiro256**).
+/// Not suitable for cryptographic use.
+pub struct Rng {
+    s: [u64; 4],
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // expand the seed with splitmix64, which never yields the all-zero state
+        let mut x = seed;
+        let mut next = || {
+            x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = x;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^ (z >> 31)
+        };
+        Self {
+            s: [next(), next(), next(), next()],
+        }
+    }
+    pub fn from_time() -> Self {
+        let nanos = match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+            Ok(dur) => dur.as_nanos() as u64,
+            Err(error) => error.duration().as_nanos() as u64,
+        };
+        Self::new(nanos)
+    }
+    pub fn next_u64(&mut self) -> u64 {
+        let s = &mut self.s;
+        let ret = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+        ret
+    }
+    /// A float uniformly distributed in [0, 1)
+    pub fn random(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 * (1.0 / (1u64 << 53) as f64)
+    }
+    /// An integer uniformly distributed in [0, n), or any u64 if n is 0
+    pub fn below(&mut self, n: u64) -> u64 {
+        if n == 0 {
+            return self.next_u64();
+        }
+        // reject the top partial copy of [0, n) to avoid modulo bias
+        let zone = u64::MAX - u64::MAX % n;
+        loop {
+            let x = self.next_u64();
+            if x < zone {
+                return x % n;
+            }
+        }
+    }
+    /// A normally distributed float (Box-Muller)
+    pub fn normal(&mut self, mu: f64, sigma: f64) -> f64 {
+        let u1 = 1.0 - self.random();
+        let u2 = self.random();
+        mu + sigma * (-2.0 * u1.ln()).sqrt() * (std::f64::consts::TAU * u2).cos()
+    }
+    /// An exponentially distributed float with the given rate
+    pub fn exponential(&mut self, lambd: f64) -> f64 {
+        -(1.0 - self.random()).ln() / lambd
+    }
+}
+
+/// The generator used by the module level functions
+struct DefaultRng(Handle<Rng>);
+
+type Op = fn(&Handle<Rng>, &mut Globals, Vec<Value>) -> Result<Value>;
+
+pub(super) fn new() -> NativeModule {
+    NativeModule::new(NAME, |m| {
+        m.doc(concat!(
+            "Pseudo-random numbers\n",
+            "The module level functions share a default generator that is seeded ",
+            "from the current time, unless 'seed' is called explicitly. ",
+            "For independent streams, create separate 'Rng' instances.\n",
+        ));
+        m.class::<Rng, _>("Rng", |cls| {
+            cls.doc("A seedable pseudo-random number generator (xoshiro256**)");
+            cls.sfunc(
+                "__call",
+                ArgSpec::builder().def("seed", ()),
+                "Creates a new generator, seeded from the current time if no seed is given",
+                |globals, args, _| {
+                    let rng = Rng::new(seed_from(&args[0])?);
+                    globals.new_handle(rng).map(Value::from)
+                },
+            );
+            for (name, argspec, doc, op) in ops() {
+                cls.ifunc(name, argspec, doc, move |owner, globals, args, _| {
+                    op(&owner, globals, args)
+                });
+            }
+        });
+        m.func(
+            "default",
+            (),
+            "Returns the generator used by the module level functions",
+            |globals, _, _| Ok(default_rng(globals)?.into()),
+        );
+        for (name, argspec, doc, op) in ops() {
+            m.func(name, argspec, doc, move |globals, args, _| {
+                let rng = default_rng(globals)?;
+                op(&rng, globals, args)
+            });
+        }
+    })
+}
+
+fn default_rng(globals: &mut Globals) -> Result<Handle<Rng>> {
+    if !globals.stash().has::<DefaultRng>() {
+        let rng = globals.new_handle(Rng::from_time())?;
+        globals.stash_mut().set(DefaultRng(rng))?;
+    }
+    Ok(globals.stash().get::<DefaultRng>()?.0.clone())
+}
+
+fn seed_from(seed: &Value) -> Result<u64> {
+    match seed {
+        Value::Nil => Ok(Rng::from_time().next_u64()),
+        Value::Int(x) => Ok(*x as u64),
+        Value::Number(x) => Ok(x.to_bits()),
+        _ => Err(rterr!("Expected an int or float seed, but got {:?}", seed)),
+    }
+}
+
+/// Methods shared by 'Rng' instances and the module level default generator
+fn ops() -> Vec<(&'static str, ArgSpec, &'static str, Op)> {
+    vec![
+        (
+            "seed",
+            ArgSpec::builder().def("seed", ()).build(),
+            concat!(
+                "Resets the generator state from an int or float seed ",
+                "(e.g. a.time.now()), or from the current time if no seed is given",
+            ),
+            |rng, _globals, args| {
+                *rng.borrow_mut() = Rng::new(seed_from(&args[0])?);
+                Ok(Value::Nil)
+            },
+        ),
+        (
+            "random",
+            ArgSpec::builder().build(),
+            "Returns a float uniformly distributed in [0, 1)",
+            |rng, _globals, _args| Ok(rng.borrow_mut().random().into()),
+        ),
+        (
+            "randint",
+            ArgSpec::builder().req("a").req("b").build(),
+            "Returns an int uniformly distributed in [a, b] (both ends inclusive)",
+            |rng, _globals, args| {
+                let a = args[0].i64()?;
+                let b = args[1].i64()?;
+                if a > b {
+                    return Err(rterr!("randint: a ({}) > b ({})", a, b));
+                }
+                let n = (b as i128 - a as i128 + 1) as u64;
+                let x = rng.borrow_mut().below(n);
+                Ok(Value::from(a.wrapping_add(x as i64)))
+            },
+        ),
+        (
+            "choice",
+            ArgSpec::builder().req("xs").build(),
+            "Returns a uniformly chosen element of a non-empty list or iterable",
+            |rng, globals, args| {
+                let xs = args.into_iter().next().unwrap();
+                let choose = |xs: &[Value]| {
+                    if xs.is_empty() {
+                        return Err(rterr!("choice: empty sequence"));
+                    }
+                    let i = rng.borrow_mut().below(xs.len() as u64) as usize;
+                    Ok(xs[i].clone())
+                };
+                match xs {
+                    Value::List(list) => choose(&list.borrow()),
+                    xs => choose(&xs.unpack(globals)?),
+                }
+            },
+        ),
+        (
+            "shuffle",
+            ArgSpec::builder().req("list").build(),
+            "Shuffles a list in place",
+            |rng, _globals, args| {
+                let list = args[0].list()?;
+                let mut list = list.borrow_mut();
+                let mut rng = rng.borrow_mut();
+                for i in (1..list.len()).rev() {
+                    let j = rng.below(i as u64 + 1) as usize;
+                    list.swap(i, j);
+                }
+                Ok(Value::Nil)
+            },
+        ),
+        (
+            "sample",
+            ArgSpec::builder().req("xs").req("k").build(),
+            "Returns a list of k distinct elements chosen from an iterable",
+            |rng, globals, args| {
+                let mut args = args.into_iter();
+                let mut xs = args.next().unwrap().unpack(globals)?;
+                let k = args.next().unwrap().usize()?;
+                if k > xs.len() {
+                    return Err(rterr!(
+                        "sample: k ({}) is larger than the population ({})",
+                        k,
+                        xs.len()
+                    ));
+                }
+                let mut rng = rng.borrow_mut();
+                for i in 0..k {
+                    let j = i + rng.below((xs.len() - i) as u64) as usize;
+                    xs.swap(i, j);
+                }
+                xs.truncate(k);
+                Ok(xs.into())
+            },
+        ),
+        (
+            "normal",
+            ArgSpec::builder().def("mu", 0).def("sigma", 1).build(),
+            "Returns a normally distributed float with mean mu and standard deviation sigma",
+            |rng, _globals, args| {
+                let mu = args[0].number()?;
+                let sigma = args[1].number()?;
+                Ok(rng.borrow_mut().normal(mu, sigma).into())
+            },
+        ),
+        (
+            "exponential",
+            ArgSpec::builder().def("lambd", 1).build(),
+            "Returns an exponentially distributed float with rate lambd (mean 1 / lambd)",
+            |rng, _globals, args| {
+                let lambd = args[0].number()?;
+                if lambd <= 0.0 {
+                    return Err(rterr!("exponential: lambd must be positive, got {}", lambd));
+                }
+                Ok(rng.borrow_mut().exponential(lambd).into())
+            },
+        ),
+    ]
+}
@@ -0,0 +1,397 @@
+use crate::ArgSpec;
+use crate::Captures;
+use crate::Globals;
+use crate::Handle;
+use crate::IndexMap;
+use crate::Key;
+use crate::NativeModule;
+use crate::RcStr;
+use crate::Regex;
+use crate::RegexFlags;
+use crate::Result;
+use crate::Value;
+
+const NAME: &str = "a.re";
+
+/// A successful match of a Regex against some text
+struct Match {
+    regex: Handle<Regex>,
+    text: RcStr,
+    caps: Captures,
+}
+
+impl Match {
+    fn group(&self, group: usize) -> Value {
+        match self.caps.get(group) {
+            Some((start, end)) => self.text[start..end].into(),
+            None => Value::Nil,
+        }
+    }
+    /// Finds a group by number or name
+    fn index(&self, group: &Value) -> Result<usize> {
+        let index = match group {
+            Value::String(name) => match self.regex.borrow().names().get(name.str()) {
+                Some(index) => *index,
+                None => return Err(kinderr!(NotFound, "No group named {:?}", name)),
+            },
+            group => group.usize()?,
+        };
+        if index >= self.caps.len() {
+            return Err(kinderr!(NotFound, "No group {}", index));
+        }
+        Ok(index)
+    }
+}
+
+pub(super) fn new() -> NativeModule {
+    NativeModule::new(NAME, |m| {
+        m.doc(concat!(
+            "Regular expressions\n",
+            "Matches are leftmost-first, as in Perl and Python, and all positions ",
+            "are byte offsets.\n",
+            "Flags may be given as a string of 'i' (ignore case), 'm' ('^' and '$' ",
+            "match at line boundaries) and 's' ('.' matches newlines), or inline, ",
+            "e.g. '(?i)'.\n",
+        ));
+        m.func(
+            "compile",
+            ArgSpec::builder().req("pattern").def("flags", ""),
+            "Compiles a pattern into a Regex",
+            |globals, args, _| compile(globals, args),
+        );
+        m.func(
+            "escape",
+            ["text"],
+            "Escapes all special characters, so that the result matches the text literally",
+            |_globals, args, _| {
+                let text = args[0].string()?;
+                let mut ret = String::new();
+                for c in text.chars() {
+                    if "\\.+*?()|[]{}^$#&-~".contains(c) {
+                        ret.push('\\');
+                    }
+                    ret.push(c);
+                }
+                Ok(ret.into())
+            },
+        );
+        m.class::<Regex, _>("Regex", |cls| {
+            cls.doc("A compiled regular expression");
+            cls.repr(|regex| format!("Regex({:?})", regex.pattern()).into());
+            cls.sfunc(
+                "__call",
+                ArgSpec::builder().req("pattern").def("flags", ""),
+                "Compiles a pattern into a Regex",
+                |globals, args, _| compile(globals, args),
+            );
+            cls.ifunc("pattern", [], "", |owner, _globals, _args, _| {
+                Ok(owner.borrow().pattern().into())
+            });
+            cls.ifunc(
+                "names",
+                [],
+                "Returns a Map of group names to group numbers",
+                |owner, _globals, _args, _| {
+                    Ok(owner
+                        .borrow()
+                        .names()
+                        .iter()
+                        .map(|(name, index)| (Key::from(name), Value::from(*index)))
+                        .collect::<IndexMap<_, _>>()
+                        .into())
+                },
+            );
+            cls.ifunc(
+                "match",
+                ArgSpec::builder().req("text").def("pos", 0),
+                "Matches only at the start of the text (or at 'pos'), returning a Match or nil",
+                |owner, globals, args, _| {
+                    let (text, pos) = text_and_pos(args)?;
+                    let caps = owner.borrow().match_at(&text, pos);
+                    new_match(globals, &owner, text, caps)
+                },
+            );
+            cls.ifunc(
+                "fullmatch",
+                ArgSpec::builder().req("text").def("pos", 0),
+                "Like 'match', but the match must also extend to the end of the text",
+                |owner, globals, args, _| {
+                    let (text, pos) = text_and_pos(args)?;
+                    let caps = owner.borrow().fullmatch_at(&text, pos);
+                    new_match(globals, &owner, text, caps)
+                },
+            );
+            cls.ifunc(
+                "search",
+                ArgSpec::builder().req("text").def("pos", 0),
+                "Finds the first match anywhere in the text (from 'pos'), returning a Match or nil",
+                |owner, globals, args, _| {
+                    let (text, pos) = text_and_pos(args)?;
+                    let caps = owner.borrow().search(&text, pos);
+                    new_match(globals, &owner, text, caps)
+                },
+            );
+            cls.ifunc(
+                "find_all",
+                ["text"],
+                "Returns a List of all non-overlapping Matches",
+                |owner, globals, args, _| {
+                    let text = args.into_iter().next().unwrap().into_string()?;
+                    let all: Vec<_> = owner.borrow().find_iter(&text).collect();
+                    let mut ret = Vec::new();
+                    for caps in all {
+                        ret.push(new_match(globals, &owner, text.clone(), Some(caps))?);
+                    }
+                    Ok(ret.into())
+                },
+            );
+            cls.ifunc(
+                "sub",
+                ArgSpec::builder().req("repl").req("text").def("count", 0),
+                concat!(
+                    "Replaces matches in the text.\n",
+                    "'repl' may be a function, which is called with each Match and ",
+                    "returns the replacement, or a string, in which '\\1' or '\\g<1>' ",
+                    "insert a numbered group, '\\g<name>' a named group and '\\\\' ",
+                    "a backslash.\n",
+                    "If count is positive, at most that many matches are replaced",
+                ),
+                |owner, globals, args, _| {
+                    let mut args = args.into_iter();
+                    let repl = args.next().unwrap();
+                    let text = args.next().unwrap().into_string()?;
+                    let count = args.next().unwrap().usize()?;
+                    let mut all: Vec<_> = owner.borrow().find_iter(&text).collect();
+                    if count > 0 {
+                        all.truncate(count);
+                    }
+                    let mut ret = String::new();
+                    let mut last = 0;
+                    for caps in all {
+                        ret.push_str(&text[last..caps.start()]);
+                        last = caps.end();
+                        if let Value::String(template) = &repl {
+                            expand(&mut ret, template, &owner.borrow(), &text, &caps)?;
+                        } else {
+                            let m = new_match(globals, &owner, text.clone(), Some(caps))?;
+                            let r = repl.apply(globals, vec![m], None)?.into_string()?;
+                            ret.push_str(&r);
+                        }
+                    }
+                    ret.push_str(&text[last..]);
+                    Ok(ret.into())
+                },
+            );
+            cls.ifunc(
+                "split",
+                ArgSpec::builder().req("text").def("maxsplit", 0),
+                concat!(
+                    "Splits the text at each match, returning a List of strings.\n",
+                    "If maxsplit is positive, at most that many splits are done",
+                ),
+                |owner, _globals, args, _| {
+                    let mut args = args.into_iter();
+                    let text = args.next().unwrap().into_string()?;
+                    let maxsplit = args.next().unwrap().usize()?;
+                    let mut all: Vec<_> = owner.borrow().find_iter(&text).collect();
+                    if maxsplit > 0 {
+                        all.truncate(maxsplit);
+                    }
+                    let mut ret = Vec::new();
+                    let mut last = 0;
+                    for caps in all {
+                        ret.push(Value::from(&text[last..caps.start()]));
+                        last = caps.end();
+                    }
+                    ret.push(Value::from(&text[last..]));
+                    Ok(ret.into())
+                },
+            );
+        });
+        m.class::<Match, _>("Match", |cls| {
+            cls.doc("The result of a successful match");
+            cls.repr(|m| {
+                format!(
+                    "<Match [{}, {}] {:?}>",
+                    m.caps.start(),
+                    m.caps.end(),
+                    &m.text[m.caps.start()..m.caps.end()]
+                )
+                .into()
+            });
+            cls.ifunc(
+                "group",
+                ArgSpec::builder().def("group", 0),
+                concat!(
+                    "Returns the text matched by the given group (by number or name), ",
+                    "or nil if the group did not participate in the match.\n",
+                    "Group 0 is the whole match",
+                ),
+                |owner, _globals, args, _| {
+                    let m = owner.borrow();
+                    Ok(m.group(m.index(&args[0])?))
+                },
+            );
+            cls.ifunc("__getitem", ["group"], "", |owner, _globals, args, _| {
+                let m = owner.borrow();
+                Ok(m.group(m.index(&args[0])?))
+            });
+            cls.ifunc(
+                "groups",
+                [],
+                "Returns a List of all groups other than group 0",
+                |owner, _globals, _args, _| {
+                    let m = owner.borrow();
+                    Ok((1..m.caps.len())
+                        .map(|i| m.group(i))
+                        .collect::<Vec<_>>()
+                        .into())
+                },
+            );
+            cls.ifunc(
+                "named",
+                [],
+                "Returns a Map of the named groups",
+                |owner, _globals, _args, _| {
+                    let m = owner.borrow();
+                    let regex = m.regex.borrow();
+                    Ok(regex
+                        .names()
+                        .iter()
+                        .map(|(name, index)| (Key::from(name), m.group(*index)))
+                        .collect::<IndexMap<_, _>>()
+                        .into())
+                },
+            );
+            cls.ifunc(
+                "start",
+                ArgSpec::builder().def("group", 0),
+                "Start position of the group, or nil if it did not participate",
+                |owner, _globals, args, _| {
+                    let m = owner.borrow();
+                    Ok(m.caps
+                        .get(m.index(&args[0])?)
+                        .map(|(s, _)| Value::from(s))
+                        .unwrap_or(Value::Nil))
+                },
+            );
+            cls.ifunc(
+                "end",
+                ArgSpec::builder().def("group", 0),
+                "End position of the group, or nil if it did not participate",
+                |owner, _globals, args, _| {
+                    let m = owner.borrow();
+                    Ok(m.caps
+                        .get(m.index(&args[0])?)
+                        .map(|(_, e)| Value::from(e))
+                        .unwrap_or(Value::Nil))
+                },
+            );
+            cls.ifunc(
+                "span",
+                ArgSpec::builder().def("group", 0),
+                "[start, end] of the group, or nil if it did not participate",
+                |owner, _globals, args, _| {
+                    let m = owner.borrow();
+                    Ok(match m.caps.get(m.index(&args[0])?) {
+                        Some((s, e)) => vec![Value::from(s), Value::from(e)].into(),
+                        None => Value::Nil,
+                    })
+                },
+            );
+        });
+    })
+}
+
+fn compile(globals: &mut Globals, args: Vec<Value>) -> Result<Value> {
+    let mut args = args.into_iter();
+    let pattern = args.next().unwrap().into_string()?;
+    let flags = RegexFlags::parse(args.next().unwrap().string()?)?;
+    let regex = Regex::with_flags(&pattern, flags)?;
+    globals.new_handle(regex).map(Value::from)
+}
+
+fn text_and_pos(args: Vec<Value>) -> Result<(RcStr, usize)> {
+    let mut args = args.into_iter();
+    let text = args.next().unwrap().into_string()?;
+    let pos = args.next().unwrap().to_start_index(text.len())?;
+    Ok((text, pos))
+}
+
+fn new_match(
+    globals: &mut Globals,
+    regex: &Handle<Regex>,
+    text: RcStr,
+    caps: Option<Captures>,
+) -> Result<Value> {
+    match caps {
+        Some(caps) => globals
+            .new_handle(Match {
+                regex: regex.clone(),
+                text,
+                caps,
+            })
+            .map(Value::from),
+        None => Ok(Value::Nil),
+    }
+}
+
+/// Expands a replacement template for 'sub'
+fn expand(
+    out: &mut String,
+    template: &str,
+    regex: &Regex,
+    text: &str,
+    caps: &Captures,
+) -> Result<()> {
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        let group = match chars.next() {
+            Some('\\') => {
+                out.push('\\');
+                continue;
+            }
+            Some(d) if d.is_ascii_digit() => {
+                let mut group = d.to_digit(10).unwrap() as usize;
+                if let Some(d) = chars.peek().and_then(|d| d.to_digit(10)) {
+                    if group * 10 + (d as usize) < caps.len() {
+                        group = group * 10 + d as usize;
+                        chars.next();
+                    }
+                }
+                group
+            }
+            Some('g') if chars.peek() == Some(&'<') => {
+                chars.next();
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('>') => break,
+                        Some(c) => name.push(c),
+                        None => return Err(rterr!("Unterminated group name in {:?}", template)),
+                    }
+                }
+                match name.parse::<usize>() {
+                    Ok(group) => group,
+                    Err(_) => match regex.names().get(&name) {
+                        Some(group) => *group,
+                        None => return Err(kinderr!(NotFound, "No group named {:?}", name)),
+                    },
+                }
+            }
+            Some(c) => return Err(rterr!("Bad escape \\{} in replacement {:?}", c, template)),
+            None => return Err(rterr!("Trailing backslash in replacement {:?}", template)),
+        };
+        if group >= caps.len() {
+            return Err(kinderr!(NotFound, "No group {}", group));
+        }
+        if let Some((start, end)) = caps.get(group) {
+            out.push_str(&text[start..end]);
+        }
+    }
+    Ok(())
+}
@@ -1,6 +1,9 @@
+use crate::ArgSpec;
 use crate::NativeModule;
 use crate::Source;
 use crate::Value;
+use std::convert::TryFrom;
+use std::path::Path;
 
 const NAME: &'static str = "a.sys";
 
@@ -27,5 +30,78 @@ pub(super) fn new() -> NativeModule {
                 .map(Value::from)
                 .unwrap_or(Value::Nil))
         });
+        m.func(
+            "path",
+            (),
+            concat!(
+                "Returns a list of the directories searched for modules, in order\n",
+                "Changing the list has no effect; use add_path and remove_path instead",
+            ),
+            |globals, _args, _| {
+                let roots: Vec<Value> = globals.source_roots().iter().map(Value::from).collect();
+                Ok(roots.into())
+            },
+        );
+        m.func(
+            "add_path",
+            ArgSpec::builder().req("dir").def("first", false),
+            concat!(
+                "Adds a directory to search for modules in\n",
+                "It is searched after all the others, unless 'first' is true\n",
+                "Modules that are already loaded are not affected",
+            ),
+            |globals, args, _| {
+                let mut args = args.into_iter();
+                let dir = args.next().unwrap().into_string()?;
+                let first = args.next().unwrap().truthy();
+                if first {
+                    globals.prepend_source_root(dir);
+                } else {
+                    globals.add_source_root(dir);
+                }
+                Ok(Value::Nil)
+            },
+        );
+        m.func(
+            "remove_path",
+            ["dir"],
+            concat!(
+                "Stops searching the given directory for modules\n",
+                "Returns true if it was being searched",
+            ),
+            |globals, args, _| {
+                let dir = args.into_iter().next().unwrap().into_string()?;
+                Ok(globals.remove_source_root(&dir).into())
+            },
+        );
+        m.func(
+            "add_manifest",
+            ["path"],
+            concat!(
+                "Adds the directories declared by the given project manifest ",
+                "(mtots.toml) and its dependencies to the end of the search path",
+            ),
+            |globals, args, _| {
+                let path = args.into_iter().next().unwrap().into_string()?;
+                globals.add_source_roots_from_manifest(Path::new(path.str()))?;
+                Ok(Value::Nil)
+            },
+        );
+        m.func(
+            "find_module",
+            ["name"],
+            concat!(
+                "Returns the path of the script that would be loaded for ",
+                "the given module name, or nil if there is none\n",
+                "(Native modules and custom sources have no path)",
+            ),
+            |globals, args, _| {
+                let name = args.into_iter().next().unwrap().into_string()?;
+                match globals.find_source_path(&name) {
+                    Some(path) => Ok(Value::try_from(path.into_os_string())?),
+                    None => Ok(Value::Nil),
+                }
+            },
+        );
     })
 }
@@ -1,7 +1,10 @@
+use crate::ArgSpec;
+use crate::CancelToken;
+use crate::Globals;
 use crate::NativeModule;
+use crate::Promise;
 use crate::Value;
 use std::thread::sleep;
-use std::time::Duration;
 use std::time::SystemTime;
 
 const NAME: &'static str = "a.time";
@@ -29,10 +32,54 @@ pub(super) fn new() -> NativeModule {
             ["duration"],
             concat!("Sleeps for the given number of seconds\n",),
             |_, args, _| {
-                let nsecs = args.into_iter().next().unwrap().number()?;
-                sleep(Duration::from_secs_f64(nsecs));
+                sleep(args[0].duration()?);
                 Ok(Value::Nil)
             },
         );
+
+        m.func(
+            "sleep_async",
+            ArgSpec::builder().req("duration").def("token", ()),
+            concat!(
+                "Returns a promise that resolves to nil after the given number of seconds\n",
+                "Unlike 'sleep', this does not block other tasks on the event loop\n",
+                "If the given CancelToken is cancelled first, the promise fails with ",
+                "a CancelledError instead\n",
+            ),
+            |globals, args, _| {
+                let mut args = args.into_iter();
+                let delay = args.next().unwrap().duration()?;
+                let token = CancelToken::from_arg(args.next().unwrap())?;
+                Ok(globals.sleep_async(delay, token.as_ref())?.into())
+            },
+        );
+
+        m.func(
+            "set_timeout",
+            ArgSpec::builder().req("f").req("duration").def("token", ()),
+            concat!(
+                "Calls f with no arguments on the event loop after the given number of ",
+                "seconds, and returns a promise for its result\n",
+                "If the given CancelToken is cancelled first, f is never called, ",
+                "and the promise fails with a CancelledError instead\n",
+            ),
+            |globals, args, _| {
+                let mut args = args.into_iter();
+                let f = args.next().unwrap();
+                let deadline = Globals::deadline_after(args.next().unwrap().duration()?)?;
+                let token = CancelToken::from_arg(args.next().unwrap())?;
+                let promise =
+                    Promise::new_cancellable(globals, token.as_ref(), |globals, resolve| {
+                        let id = globals.set_timer_at(deadline, move |globals| {
+                            let result = globals.catch(|globals| f.apply(globals, vec![], None));
+                            resolve(globals, result);
+                        });
+                        move |globals: &mut Globals| {
+                            globals.cancel_timer(id);
+                        }
+                    });
+                Ok(promise.into())
+            },
+        );
     })
 }
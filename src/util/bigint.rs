@@ -0,0 +1,660 @@
+//! Arbitrary-precision signed integers
+//!
+//! The magnitude is stored as little endian base 2^32 limbs, with no
+//! trailing zero limbs (so zero is the empty vector, and is never negative).
+//! Division truncates towards zero, like the builtin ints, and
+//! the bitwise operators behave as if on infinite two's complement values.
+use std::cmp;
+use std::convert::TryFrom;
+use std::fmt;
+
+#[derive(Clone, PartialEq, Eq, Hash, Default)]
+pub struct BigInt {
+    neg: bool,
+    mag: Vec<u32>,
+}
+
+const DIGITS: &[u8; 36] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+
+impl BigInt {
+    pub fn zero() -> Self {
+        Self::default()
+    }
+    pub fn one() -> Self {
+        Self::from_parts(false, vec![1])
+    }
+    fn from_parts(neg: bool, mut mag: Vec<u32>) -> Self {
+        while mag.last() == Some(&0) {
+            mag.pop();
+        }
+        let neg = neg && !mag.is_empty();
+        Self { neg, mag }
+    }
+    pub fn is_zero(&self) -> bool {
+        self.mag.is_empty()
+    }
+    pub fn is_negative(&self) -> bool {
+        self.neg
+    }
+    pub fn signum(&self) -> i64 {
+        if self.neg {
+            -1
+        } else if self.is_zero() {
+            0
+        } else {
+            1
+        }
+    }
+    pub fn abs(&self) -> Self {
+        Self::from_parts(false, self.mag.clone())
+    }
+
+    /// Converts an integral float exactly, or returns None
+    /// if it is not finite or has a fractional part
+    pub fn from_f64(x: f64) -> Option<Self> {
+        if !x.is_finite() || x.fract() != 0.0 {
+            return None;
+        }
+        let bits = x.to_bits();
+        let exp = ((bits >> 52) & 0x7ff) as i64;
+        if exp == 0 {
+            // zero or subnormal (and since x is integral, zero)
+            return Some(Self::zero());
+        }
+        let mantissa = (bits & ((1 << 52) - 1)) | (1 << 52);
+        let shift = exp - 1075;
+        let mag = Self::from(mantissa);
+        let mag = if shift >= 0 {
+            mag.shl(shift as usize)
+        } else {
+            mag.shr((-shift) as usize)
+        };
+        Some(if x < 0.0 { -&mag } else { mag })
+    }
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.mag.len() > 2 {
+            return None;
+        }
+        let mag = self.mag_u64();
+        if self.neg {
+            if mag <= 1 << 63 {
+                Some((mag as i64).wrapping_neg())
+            } else {
+                None
+            }
+        } else {
+            i64::try_from(mag).ok()
+        }
+    }
+    fn mag_u64(&self) -> u64 {
+        let lo = self.mag.first().cloned().unwrap_or(0) as u64;
+        let hi = self.mag.get(1).cloned().unwrap_or(0) as u64;
+        (hi << 32) | lo
+    }
+    pub fn to_f64(&self) -> f64 {
+        let mut x = 0.0;
+        for limb in self.mag.iter().rev() {
+            x = x * 4294967296.0 + *limb as f64;
+        }
+        if self.neg {
+            -x
+        } else {
+            x
+        }
+    }
+
+    /// Parses an optionally signed integer in the given radix (2 to 36).
+    /// Underscores between digits are ignored
+    pub fn parse_radix(s: &str, radix: u32) -> Option<Self> {
+        assert!((2..=36).contains(&radix), "Invalid radix {}", radix);
+        let (neg, digits) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        if digits.is_empty() || digits.starts_with('_') || digits.ends_with('_') {
+            return None;
+        }
+        let mut mag = Vec::new();
+        for c in digits.chars() {
+            if c == '_' {
+                continue;
+            }
+            let digit = c.to_digit(radix)?;
+            mul_add_small(&mut mag, radix, digit);
+        }
+        Some(Self::from_parts(neg, mag))
+    }
+    pub fn to_str_radix(&self, radix: u32) -> String {
+        assert!((2..=36).contains(&radix), "Invalid radix {}", radix);
+        if self.is_zero() {
+            return "0".to_owned();
+        }
+        // divide by the largest power of the radix that fits in a limb,
+        // and convert each remainder into a fixed number of digits
+        let mut chunk = radix;
+        let mut chunk_digits = 1;
+        while let Some(next) = chunk.checked_mul(radix) {
+            chunk = next;
+            chunk_digits += 1;
+        }
+        let mut digits = Vec::new();
+        let mut mag = self.mag.clone();
+        while !mag.is_empty() {
+            let mut rem = div_small(&mut mag, chunk);
+            for _ in 0..chunk_digits {
+                digits.push(DIGITS[(rem % radix) as usize]);
+                rem /= radix;
+                if mag.is_empty() && rem == 0 {
+                    break;
+                }
+            }
+        }
+        if self.neg {
+            digits.push(b'-');
+        }
+        digits.reverse();
+        String::from_utf8(digits).unwrap()
+    }
+
+    /// Returns the truncated quotient and the remainder
+    /// (which has the same sign as 'self'), or None if 'other' is zero
+    pub fn div_rem(&self, other: &Self) -> Option<(Self, Self)> {
+        if other.is_zero() {
+            return None;
+        }
+        let (q, r) = divrem_mag(&self.mag, &other.mag);
+        Some((
+            Self::from_parts(self.neg != other.neg, q),
+            Self::from_parts(self.neg, r),
+        ))
+    }
+    /// The remainder of euclidean division, which is never negative
+    pub fn rem_euclid(&self, other: &Self) -> Option<Self> {
+        let (_, r) = self.div_rem(other)?;
+        Some(if r.neg { &r + &other.abs() } else { r })
+    }
+    pub fn pow(&self, mut exp: u32) -> Self {
+        let mut base = self.clone();
+        let mut ret = Self::one();
+        while exp > 0 {
+            if exp & 1 == 1 {
+                ret = &ret * &base;
+            }
+            exp >>= 1;
+            if exp > 0 {
+                base = &base * &base;
+            }
+        }
+        ret
+    }
+    /// (self ** exp) %% modulus, or None if the modulus is zero
+    /// or the exponent is negative
+    pub fn pow_mod(&self, exp: &Self, modulus: &Self) -> Option<Self> {
+        if modulus.is_zero() || exp.neg {
+            return None;
+        }
+        let mut base = self.rem_euclid(modulus)?;
+        let mut ret = Self::one().rem_euclid(modulus)?;
+        let nbits = exp.bit_length();
+        for i in 0..nbits {
+            if exp.mag[i / 32] & (1 << (i % 32)) != 0 {
+                ret = (&ret * &base).rem_euclid(modulus)?;
+            }
+            if i + 1 < nbits {
+                base = (&base * &base).rem_euclid(modulus)?;
+            }
+        }
+        Some(ret)
+    }
+
+    /// The number of bits needed to represent the magnitude
+    pub fn bit_length(&self) -> usize {
+        match self.mag.last() {
+            Some(top) => self.mag.len() * 32 - top.leading_zeros() as usize,
+            None => 0,
+        }
+    }
+    pub fn shl(&self, n: usize) -> Self {
+        Self::from_parts(self.neg, shl_mag(&self.mag, n))
+    }
+    /// Arithmetic shift right (i.e. rounds towards negative infinity)
+    pub fn shr(&self, n: usize) -> Self {
+        if self.neg {
+            // -((|x| - 1) >> n) - 1
+            let m = &self.abs() - &Self::one();
+            -&(&Self::from_parts(false, shr_mag(&m.mag, n)) + &Self::one())
+        } else {
+            Self::from_parts(false, shr_mag(&self.mag, n))
+        }
+    }
+    /// The limbs of this value in two's complement, sign extended to 'len'
+    fn twos(&self, len: usize) -> Vec<u32> {
+        let mut limbs = self.mag.clone();
+        limbs.resize(len, 0);
+        if self.neg {
+            let mut carry = true;
+            for limb in &mut limbs {
+                *limb = !*limb;
+                if carry {
+                    let (x, c) = limb.overflowing_add(1);
+                    *limb = x;
+                    carry = c;
+                }
+            }
+        }
+        limbs
+    }
+    fn from_twos(mut limbs: Vec<u32>) -> Self {
+        let neg = limbs.last().map(|top| top >> 31 == 1).unwrap_or(false);
+        if neg {
+            let mut borrow = true;
+            for limb in &mut limbs {
+                if borrow {
+                    let (x, b) = limb.overflowing_sub(1);
+                    *limb = x;
+                    borrow = b;
+                }
+                *limb = !*limb;
+            }
+        }
+        Self::from_parts(neg, limbs)
+    }
+    fn bitwise(&self, other: &Self, f: impl Fn(u32, u32) -> u32) -> Self {
+        let len = cmp::max(self.mag.len(), other.mag.len()) + 1;
+        let (a, b) = (self.twos(len), other.twos(len));
+        Self::from_twos(a.into_iter().zip(b).map(|(a, b)| f(a, b)).collect())
+    }
+    pub fn and(&self, other: &Self) -> Self {
+        self.bitwise(other, |a, b| a & b)
+    }
+    pub fn or(&self, other: &Self) -> Self {
+        self.bitwise(other, |a, b| a | b)
+    }
+    pub fn xor(&self, other: &Self) -> Self {
+        self.bitwise(other, |a, b| a ^ b)
+    }
+    pub fn not(&self) -> Self {
+        -&(self + &Self::one())
+    }
+
+    /// Converts to bytes, in two's complement if 'signed' is set.
+    /// Uses the fewest bytes possible (zero bytes for zero).
+    /// Returns None if the value is negative and 'signed' is not set
+    pub fn to_bytes(&self, big_endian: bool, signed: bool) -> Option<Vec<u8>> {
+        if self.neg && !signed {
+            return None;
+        }
+        let limbs = if signed {
+            self.twos(self.mag.len() + 1)
+        } else {
+            self.mag.clone()
+        };
+        let mut bytes: Vec<u8> = limbs.iter().flat_map(|limb| limb.to_le_bytes()).collect();
+        let fill = if self.neg { 0xff } else { 0 };
+        while bytes.last() == Some(&fill) {
+            // in two's complement, the byte below must still have the right sign bit
+            let below = bytes.len().checked_sub(2).map(|i| bytes[i] >> 7 == 1);
+            if signed && below.unwrap_or(false) != self.neg {
+                break;
+            }
+            bytes.pop();
+        }
+        if big_endian {
+            bytes.reverse();
+        }
+        Some(bytes)
+    }
+    pub fn from_bytes(bytes: &[u8], big_endian: bool, signed: bool) -> Self {
+        let mut bytes = bytes.to_vec();
+        if big_endian {
+            bytes.reverse();
+        }
+        let neg = signed && bytes.last().map(|top| top >> 7 == 1).unwrap_or(false);
+        bytes.resize(bytes.len().div_ceil(4) * 4, if neg { 0xff } else { 0 });
+        let limbs: Vec<u32> = bytes
+            .chunks(4)
+            .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+            .collect();
+        if signed {
+            Self::from_twos(limbs)
+        } else {
+            Self::from_parts(false, limbs)
+        }
+    }
+}
+
+impl From<i64> for BigInt {
+    fn from(x: i64) -> Self {
+        let mut ret = Self::from(x.unsigned_abs());
+        ret.neg = x < 0;
+        ret
+    }
+}
+
+impl From<i32> for BigInt {
+    fn from(x: i32) -> Self {
+        Self::from(x as i64)
+    }
+}
+
+impl From<u64> for BigInt {
+    fn from(x: u64) -> Self {
+        Self::from_parts(false, vec![x as u32, (x >> 32) as u32])
+    }
+}
+
+impl cmp::Ord for BigInt {
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        match (self.neg, other.neg) {
+            (false, true) => cmp::Ordering::Greater,
+            (true, false) => cmp::Ordering::Less,
+            (false, false) => cmp_mag(&self.mag, &other.mag),
+            (true, true) => cmp_mag(&other.mag, &self.mag),
+        }
+    }
+}
+
+impl cmp::PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl std::ops::Neg for &BigInt {
+    type Output = BigInt;
+    fn neg(self) -> BigInt {
+        BigInt::from_parts(!self.neg, self.mag.clone())
+    }
+}
+
+impl std::ops::Add for &BigInt {
+    type Output = BigInt;
+    fn add(self, other: &BigInt) -> BigInt {
+        if self.neg == other.neg {
+            BigInt::from_parts(self.neg, add_mag(&self.mag, &other.mag))
+        } else {
+            match cmp_mag(&self.mag, &other.mag) {
+                cmp::Ordering::Less => {
+                    BigInt::from_parts(other.neg, sub_mag(&other.mag, &self.mag))
+                }
+                _ => BigInt::from_parts(self.neg, sub_mag(&self.mag, &other.mag)),
+            }
+        }
+    }
+}
+
+impl std::ops::Sub for &BigInt {
+    type Output = BigInt;
+    fn sub(self, other: &BigInt) -> BigInt {
+        self + &-other
+    }
+}
+
+impl std::ops::Mul for &BigInt {
+    type Output = BigInt;
+    fn mul(self, other: &BigInt) -> BigInt {
+        BigInt::from_parts(self.neg != other.neg, mul_mag(&self.mag, &other.mag))
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_str_radix(10))
+    }
+}
+
+impl fmt::Debug for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "BigInt({})", self)
+    }
+}
+
+fn cmp_mag(a: &[u32], b: &[u32]) -> cmp::Ordering {
+    a.len()
+        .cmp(&b.len())
+        .then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let (a, b) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    let mut ret = Vec::with_capacity(a.len() + 1);
+    let mut carry = 0u64;
+    for (i, &x) in a.iter().enumerate() {
+        let sum = x as u64 + b.get(i).cloned().unwrap_or(0) as u64 + carry;
+        ret.push(sum as u32);
+        carry = sum >> 32;
+    }
+    ret.push(carry as u32);
+    ret
+}
+
+/// a - b, where |a| >= |b|
+fn sub_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut ret = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+    for (i, &x) in a.iter().enumerate() {
+        let diff = x as i64 - b.get(i).cloned().unwrap_or(0) as i64 - borrow;
+        ret.push(diff as u32);
+        borrow = if diff < 0 { 1 } else { 0 };
+    }
+    ret
+}
+
+fn mul_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    if a.is_empty() || b.is_empty() {
+        return vec![];
+    }
+    let mut ret = vec![0u32; a.len() + b.len()];
+    for (i, x) in a.iter().enumerate() {
+        let mut carry = 0u64;
+        for (j, y) in b.iter().enumerate() {
+            let t = *x as u64 * *y as u64 + ret[i + j] as u64 + carry;
+            ret[i + j] = t as u32;
+            carry = t >> 32;
+        }
+        ret[i + b.len()] = carry as u32;
+    }
+    ret
+}
+
+fn mul_add_small(mag: &mut Vec<u32>, m: u32, a: u32) {
+    let mut carry = a as u64;
+    for limb in mag.iter_mut() {
+        let t = *limb as u64 * m as u64 + carry;
+        *limb = t as u32;
+        carry = t >> 32;
+    }
+    if carry > 0 {
+        mag.push(carry as u32);
+    }
+}
+
+/// Divides in place, returning the remainder
+fn div_small(mag: &mut Vec<u32>, d: u32) -> u32 {
+    let mut rem = 0u64;
+    for limb in mag.iter_mut().rev() {
+        let t = (rem << 32) | *limb as u64;
+        *limb = (t / d as u64) as u32;
+        rem = t % d as u64;
+    }
+    while mag.last() == Some(&0) {
+        mag.pop();
+    }
+    rem as u32
+}
+
+fn shl_mag(mag: &[u32], n: usize) -> Vec<u32> {
+    if mag.is_empty() {
+        return vec![];
+    }
+    let (limbs, bits) = (n / 32, n % 32);
+    let mut ret = vec![0u32; limbs];
+    if bits == 0 {
+        ret.extend_from_slice(mag);
+    } else {
+        let mut carry = 0;
+        for limb in mag {
+            ret.push((limb << bits) | carry);
+            carry = limb >> (32 - bits);
+        }
+        ret.push(carry);
+    }
+    ret
+}
+
+fn shr_mag(mag: &[u32], n: usize) -> Vec<u32> {
+    let (limbs, bits) = (n / 32, n % 32);
+    if limbs >= mag.len() {
+        return vec![];
+    }
+    let mag = &mag[limbs..];
+    if bits == 0 {
+        return mag.to_vec();
+    }
+    (0..mag.len())
+        .map(|i| {
+            let hi = mag.get(i + 1).cloned().unwrap_or(0);
+            (mag[i] >> bits) | (hi << (32 - bits))
+        })
+        .collect()
+}
+
+/// Long division of magnitudes (Knuth's algorithm D), returning the
+/// quotient and remainder. 'v' must be nonzero
+fn divrem_mag(u: &[u32], v: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    if cmp_mag(u, v) == cmp::Ordering::Less {
+        return (vec![], u.to_vec());
+    }
+    if v.len() == 1 {
+        let mut q = u.to_vec();
+        let r = div_small(&mut q, v[0]);
+        return (q, vec![r]);
+    }
+    const B: u64 = 1 << 32;
+    let n = v.len();
+    let m = u.len() - n;
+
+    // normalize, so that the top limb of the divisor has its high bit set
+    let s = v[n - 1].leading_zeros() as usize;
+    let vn = shl_mag(v, s);
+    let mut un = shl_mag(u, s);
+    un.resize(u.len() + 1, 0);
+
+    let mut q = vec![0u32; m + 1];
+    for j in (0..=m).rev() {
+        let num = ((un[j + n] as u64) << 32) | un[j + n - 1] as u64;
+        let mut qhat = num / vn[n - 1] as u64;
+        let mut rhat = num % vn[n - 1] as u64;
+        while qhat >= B || qhat * vn[n - 2] as u64 > ((rhat << 32) | un[j + n - 2] as u64) {
+            qhat -= 1;
+            rhat += vn[n - 1] as u64;
+            if rhat >= B {
+                break;
+            }
+        }
+
+        // multiply and subtract
+        let mut k = 0i64;
+        for i in 0..n {
+            let p = qhat * vn[i] as u64;
+            let t = un[i + j] as i64 - k - (p & 0xffff_ffff) as i64;
+            un[i + j] = t as u32;
+            k = (p >> 32) as i64 - (t >> 32);
+        }
+        let t = un[j + n] as i64 - k;
+        un[j + n] = t as u32;
+
+        q[j] = qhat as u32;
+        if t < 0 {
+            // subtracted too much, so add one divisor back
+            q[j] = q[j].wrapping_sub(1);
+            let mut carry = 0u64;
+            for i in 0..n {
+                let t = un[i + j] as u64 + vn[i] as u64 + carry;
+                un[i + j] = t as u32;
+                carry = t >> 32;
+            }
+            un[j + n] = un[j + n].wrapping_add(carry as u32);
+        }
+    }
+    un.truncate(n);
+    (q, shr_mag(&un, s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn big(s: &str) -> BigInt {
+        BigInt::parse_radix(s, 10).unwrap()
+    }
+
+    #[test]
+    fn bigint_arithmetic() {
+        let a = big("123456789012345678901234567890");
+        let b = big("-987654321098765432109876543210");
+        assert_eq!(
+            (&a * &b).to_string(),
+            "-121932631137021795226185032733622923332237463801111263526900"
+        );
+        assert_eq!((&a + &b).to_string(), "-864197532086419753208641975320");
+        let (q, r) = b.div_rem(&a).unwrap();
+        assert_eq!(
+            (q.to_string(), r.to_string()),
+            ("-8".to_owned(), "-9000000000900000000090".to_owned())
+        );
+        assert_eq!(&(&q * &a) + &r, b);
+
+        // exercises the 'add back' step of long division
+        let u = BigInt::from_parts(false, vec![0, 0, 0x8000_0000, 0x7fff_ffff]);
+        let v = BigInt::from_parts(false, vec![1, 0, 0x8000_0000]);
+        let (q, r) = u.div_rem(&v).unwrap();
+        assert_eq!(&(&q * &v) + &r, u);
+        assert!(r < v);
+
+        assert_eq!(
+            BigInt::from(2).pow(100).to_str_radix(16),
+            format!("1{}", "0".repeat(25))
+        );
+        assert_eq!(
+            BigInt::from(4).pow_mod(&BigInt::from(13), &BigInt::from(497)),
+            Some(BigInt::from(445))
+        );
+    }
+
+    #[test]
+    fn bigint_bits_and_bytes() {
+        assert_eq!(
+            BigInt::from(-6).and(&BigInt::from(0xff)),
+            BigInt::from(-6 & 0xff)
+        );
+        assert_eq!(BigInt::from(-6).or(&BigInt::from(3)), BigInt::from(-6 | 3));
+        assert_eq!(
+            BigInt::from(-6).xor(&BigInt::from(-3)),
+            BigInt::from(-6 ^ -3)
+        );
+        assert_eq!(BigInt::from(-5).shr(1), BigInt::from(-3));
+        assert_eq!(BigInt::from(-5).not(), BigInt::from(4));
+        assert_eq!(
+            BigInt::from(-129).to_bytes(true, true),
+            Some(vec![0xff, 0x7f])
+        );
+        assert_eq!(BigInt::from(128).to_bytes(false, true), Some(vec![0x80, 0]));
+        assert_eq!(BigInt::from(-1).to_bytes(true, false), None);
+        assert_eq!(BigInt::from(-1).to_bytes(true, true), Some(vec![0xff]));
+        assert_eq!(BigInt::zero().to_bytes(true, true), Some(vec![]));
+        assert_eq!(
+            BigInt::from_bytes(&[0xff, 0x7f], true, true),
+            BigInt::from(-129)
+        );
+        assert_eq!(
+            BigInt::from_bytes(&[0xff, 0x7f], true, false),
+            BigInt::from(0xff7f)
+        );
+        assert_eq!(
+            BigInt::from_f64(-(2f64.powi(70))),
+            Some(BigInt::from(-1).shl(70))
+        );
+        assert_eq!(BigInt::from(i64::MIN).to_i64(), Some(i64::MIN));
+    }
+}
@@ -0,0 +1,485 @@
+use crate::Error;
+use crate::ErrorKind;
+use crate::IndexMap;
+use crate::Mark;
+use crate::Result;
+use crate::Source;
+use std::fmt;
+use std::io::BufRead;
+use std::io::Write;
+use std::rc::Rc;
+
+/// A minimal JSON value, used for protocols that talk to editors
+/// (e.g. the debug adapter)
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(IndexMap<String, Json>),
+}
+
+impl Json {
+    /// Parses JSON text.
+    /// Errors are reported with the line and column where they occurred
+    pub fn parse(text: &str) -> Result<Json> {
+        let mut parser = JsonParser {
+            text,
+            chars: text.char_indices().peekable(),
+            last: 0,
+        };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        match parser.chars.next() {
+            None => Ok(value),
+            Some((i, c)) => Err(parser.error(i, format!("Unexpected {:?} after JSON value", c))),
+        }
+    }
+
+    /// Convenience method for creating an object from key-value pairs
+    pub fn object<K: Into<String>>(pairs: Vec<(K, Json)>) -> Json {
+        Json::Object(pairs.into_iter().map(|(k, v)| (k.into(), v)).collect())
+    }
+
+    /// Gets the value of the given key, if this is an object that has it
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(map) => map.get(key),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Json::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Json::Number(x) => Some(*x),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Json::Number(x) if x.fract() == 0.0 => Some(*x as i64),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&Vec<Json>> {
+        match self {
+            Json::Array(vec) => Some(vec),
+            _ => None,
+        }
+    }
+
+    pub fn as_object(&self) -> Option<&IndexMap<String, Json>> {
+        match self {
+            Json::Object(map) => Some(map),
+            _ => None,
+        }
+    }
+
+    /// Reads a message framed with a Content-Length header, as used by
+    /// the Debug Adapter and Language Server protocols.
+    /// Returns None if the input is closed before a message starts
+    pub fn read_message(input: &mut dyn BufRead) -> Result<Option<Json>> {
+        let mut content_length = None;
+        loop {
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+            let line = line.trim();
+            if line.is_empty() {
+                if content_length.is_some() {
+                    break;
+                }
+            } else if let Some(len) = line.strip_prefix("Content-Length:") {
+                content_length = Some(len.trim().parse::<usize>()?);
+            }
+        }
+        let mut buf = vec![0; content_length.unwrap()];
+        input.read_exact(&mut buf)?;
+        Ok(Some(Json::parse(std::str::from_utf8(&buf)?)?))
+    }
+
+    /// Writes this value as a message framed with a Content-Length header
+    pub fn write_message(&self, output: &mut dyn Write) -> Result<()> {
+        let text = self.to_string();
+        write!(output, "Content-Length: {}\r\n\r\n{}", text.len(), text)?;
+        output.flush()?;
+        Ok(())
+    }
+}
+
+struct JsonParser<'a> {
+    text: &'a str,
+    chars: std::iter::Peekable<std::str::CharIndices<'a>>,
+
+    // position of the last char returned by 'next'
+    last: usize,
+}
+
+impl<'a> JsonParser<'a> {
+    fn error(&self, pos: usize, message: String) -> Error {
+        let source = Rc::new(Source::new("<json>".into(), None, self.text.into()));
+        let lineno = self.text[..pos].matches('\n').count() + 1;
+        let mark = Mark::new(source, pos, lineno);
+        let message = format!(
+            "{} (line {}, column {})",
+            message,
+            mark.lineno(),
+            mark.column()
+        );
+        Error::new_with_kind(ErrorKind::Parse, message.into(), vec![mark])
+    }
+    /// Error at the last char returned by 'next'
+    fn error_last(&self, message: String) -> Error {
+        self.error(self.last, message)
+    }
+    fn eof(&self) -> Error {
+        self.error(self.text.len(), "Unexpected end of JSON input".into())
+    }
+    fn skip_whitespace(&mut self) {
+        while let Some((_, ' ')) | Some((_, '\t')) | Some((_, '\n')) | Some((_, '\r')) =
+            self.chars.peek()
+        {
+            self.chars.next();
+        }
+    }
+    fn next(&mut self) -> Result<char> {
+        match self.chars.next() {
+            Some((i, c)) => {
+                self.last = i;
+                Ok(c)
+            }
+            None => Err(self.eof()),
+        }
+    }
+    fn expect(&mut self, expected: char) -> Result<()> {
+        match self.next()? {
+            c if c == expected => Ok(()),
+            c => Err(self.error_last(format!("Expected {:?} but got {:?}", expected, c))),
+        }
+    }
+    fn keyword(&mut self, word: &str, value: Json) -> Result<Json> {
+        for c in word.chars() {
+            self.expect(c)?;
+        }
+        Ok(value)
+    }
+    fn value(&mut self) -> Result<Json> {
+        self.skip_whitespace();
+        match self.chars.peek().map(|(_, c)| *c) {
+            Some('n') => self.keyword("null", Json::Null),
+            Some('t') => self.keyword("true", Json::Bool(true)),
+            Some('f') => self.keyword("false", Json::Bool(false)),
+            Some('"') => Ok(Json::String(self.string()?)),
+            Some('[') => {
+                self.next()?;
+                let mut vec = Vec::new();
+                self.skip_whitespace();
+                if let Some((_, ']')) = self.chars.peek() {
+                    self.next()?;
+                    return Ok(Json::Array(vec));
+                }
+                loop {
+                    vec.push(self.value()?);
+                    self.skip_whitespace();
+                    match self.next()? {
+                        ',' => {}
+                        ']' => return Ok(Json::Array(vec)),
+                        c => {
+                            return Err(
+                                self.error_last(format!("Expected ',' or ']' but got {:?}", c))
+                            )
+                        }
+                    }
+                }
+            }
+            Some('{') => {
+                self.next()?;
+                let mut map = IndexMap::new();
+                self.skip_whitespace();
+                if let Some((_, '}')) = self.chars.peek() {
+                    self.next()?;
+                    return Ok(Json::Object(map));
+                }
+                loop {
+                    self.skip_whitespace();
+                    let key = self.string()?;
+                    self.skip_whitespace();
+                    self.expect(':')?;
+                    let value = self.value()?;
+                    map.insert(key, value);
+                    self.skip_whitespace();
+                    match self.next()? {
+                        ',' => {}
+                        '}' => return Ok(Json::Object(map)),
+                        c => {
+                            return Err(
+                                self.error_last(format!("Expected ',' or '}}' but got {:?}", c))
+                            )
+                        }
+                    }
+                }
+            }
+            Some(c) if c == '-' || c.is_ascii_digit() => {
+                let start = self.chars.peek().unwrap().0;
+                let mut text = String::new();
+                while let Some((_, c)) = self.chars.peek() {
+                    if c.is_ascii_digit() || "+-.eE".contains(*c) {
+                        text.push(*c);
+                        self.chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                match text.parse() {
+                    Ok(x) if is_json_number(&text) => Ok(Json::Number(x)),
+                    _ => Err(self.error(start, format!("Invalid number {:?}", text))),
+                }
+            }
+            Some(c) => {
+                let pos = self.chars.peek().unwrap().0;
+                Err(self.error(pos, format!("Unexpected {:?} in JSON input", c)))
+            }
+            None => Err(self.eof()),
+        }
+    }
+    fn string(&mut self) -> Result<String> {
+        self.expect('"')?;
+        let mut ret = String::new();
+        loop {
+            match self.next()? {
+                '"' => return Ok(ret),
+                '\\' => match self.next()? {
+                    '"' => ret.push('"'),
+                    '\\' => ret.push('\\'),
+                    '/' => ret.push('/'),
+                    'b' => ret.push('\u{8}'),
+                    'f' => ret.push('\u{c}'),
+                    'n' => ret.push('\n'),
+                    'r' => ret.push('\r'),
+                    't' => ret.push('\t'),
+                    'u' => {
+                        let high = self.hex4()?;
+                        let code = if (0xD800..0xDC00).contains(&high) {
+                            self.expect('\\')?;
+                            self.expect('u')?;
+                            let low = self.hex4()?;
+                            0x10000 + ((high - 0xD800) << 10) + (low.wrapping_sub(0xDC00) & 0x3FF)
+                        } else {
+                            high
+                        };
+                        match std::char::from_u32(code) {
+                            Some(c) => ret.push(c),
+                            None => {
+                                return Err(self.error_last(format!("Invalid escape \\u{:x}", code)))
+                            }
+                        }
+                    }
+                    c => return Err(self.error_last(format!("Invalid escape {:?}", c))),
+                },
+                c => ret.push(c),
+            }
+        }
+    }
+    fn hex4(&mut self) -> Result<u32> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let c = self.next()?;
+            match c.to_digit(16) {
+                Some(d) => code = code * 16 + d,
+                None => return Err(self.error_last(format!("Invalid hex digit {:?}", c))),
+            }
+        }
+        Ok(code)
+    }
+}
+
+/// Checks text against the JSON number grammar, which is stricter than Rust's:
+/// no leading zeros, and at least one digit after '.' and after the exponent
+fn is_json_number(text: &str) -> bool {
+    fn digits(s: &str) -> &str {
+        s.trim_start_matches(|c: char| c.is_ascii_digit())
+    }
+    let s = text.strip_prefix('-').unwrap_or(text);
+    let s = match s.strip_prefix('0') {
+        Some(rest) => rest,
+        None if s.starts_with(|c: char| c.is_ascii_digit()) => digits(s),
+        None => return false,
+    };
+    let s = match s.strip_prefix('.') {
+        Some(frac) if digits(frac).len() < frac.len() => digits(frac),
+        Some(_) => return false,
+        None => s,
+    };
+    let s = match s.strip_prefix(|c| c == 'e' || c == 'E') {
+        Some(exp) => {
+            let exp = exp.strip_prefix(|c| c == '+' || c == '-').unwrap_or(exp);
+            if digits(exp).len() == exp.len() {
+                return false;
+            }
+            digits(exp)
+        }
+        None => s,
+    };
+    s.is_empty()
+}
+
+/// Writes s as a quoted and escaped JSON string
+pub(crate) fn write_json_string<W: fmt::Write>(f: &mut W, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+/// Formats the value as compact JSON text
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Number(x) if !x.is_finite() => write!(f, "null"),
+            Json::Number(x) => write!(f, "{}", x),
+            Json::String(s) => write_json_string(f, s),
+            Json::Array(vec) => {
+                write!(f, "[")?;
+                for (i, item) in vec.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(map) => {
+                write!(f, "{{")?;
+                for (i, (key, val)) in map.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_json_string(f, key)?;
+                    write!(f, ":{}", val)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+impl From<bool> for Json {
+    fn from(b: bool) -> Self {
+        Json::Bool(b)
+    }
+}
+
+impl From<f64> for Json {
+    fn from(x: f64) -> Self {
+        Json::Number(x)
+    }
+}
+
+impl From<i64> for Json {
+    fn from(x: i64) -> Self {
+        Json::Number(x as f64)
+    }
+}
+
+impl From<usize> for Json {
+    fn from(x: usize) -> Self {
+        Json::Number(x as f64)
+    }
+}
+
+impl From<&str> for Json {
+    fn from(s: &str) -> Self {
+        Json::String(s.to_owned())
+    }
+}
+
+impl From<String> for Json {
+    fn from(s: String) -> Self {
+        Json::String(s)
+    }
+}
+
+impl From<Vec<Json>> for Json {
+    fn from(vec: Vec<Json>) -> Self {
+        Json::Array(vec)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let text = r#"{"a":[1,2.5,-3e2],"b":{"c":null,"d":true},"e":"x\"y\né😀"}"#;
+        let json = Json::parse(text).unwrap();
+        assert_eq!(
+            json.get("a").unwrap().as_array().unwrap()[2],
+            Json::Number(-300.0)
+        );
+        assert_eq!(json.get("e").unwrap().as_str(), Some("x\"y\né😀"));
+        assert_eq!(Json::parse(&json.to_string()).unwrap(), json);
+        assert!(Json::parse("[1,").is_err());
+        assert!(Json::parse("1 2").is_err());
+        let err = Json::parse("{\n  \"a\": [1, 2,\n    x]}").unwrap_err();
+        assert!(
+            err.message().ends_with("(line 3, column 5)"),
+            "{}",
+            err.message()
+        );
+    }
+
+    #[test]
+    fn numbers() {
+        for (text, x) in &[
+            ("0", 0.0),
+            ("-0.5", -0.5),
+            ("10", 10.0),
+            ("1E+2", 100.0),
+            ("2e-1", 0.2),
+        ] {
+            assert_eq!(Json::parse(text).unwrap(), Json::Number(*x), "{}", text);
+        }
+        for text in &[
+            "01", "1.", "1.e5", "-01.5", "-", ".5", "1e", "1e+", "+1", "1.5.2",
+        ] {
+            assert!(Json::parse(text).is_err(), "{}", text);
+        }
+        let err = Json::parse("[1,\n 01]").unwrap_err();
+        assert!(
+            err.message().ends_with("(line 2, column 2)"),
+            "{}",
+            err.message()
+        );
+    }
+}
@@ -1,5 +1,11 @@
+mod bigint;
 mod home;
+mod json;
 mod rcstr;
+mod regex;
 
+pub use bigint::*;
 pub use home::*;
+pub use json::*;
 pub use rcstr::*;
+pub use regex::*;
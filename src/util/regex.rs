@@ -0,0 +1,1423 @@
+//! A small backtracking regular expression engine
+//!
+//! Supported syntax:
+//!   literals, '.', classes ('[a-z]', '[^0-9]'), '\d \w \s' and their negations,
+//!   '^ $ \A \z \b \B', groups ('(..)', '(?:..)', '(?P<name>..)', '(?<name>..)'),
+//!   alternation, greedy and lazy quantifiers ('* + ? {n} {n,} {n,m}'),
+//!   backreferences ('\1'), lookahead ('(?=..)', '(?!..)'),
+//!   lookbehind ('(?<=..)', '(?<!..)') and flags ('i', 'm', 's'),
+//!   either passed in separately or inline ('(?i)', '(?i:..)', '(?-i)').
+//!
+//! Matches are leftmost-first (like Perl, Python and JavaScript), and all
+//! positions are byte offsets into the searched text.
+//!
+//! Like Python, an iteration of a '*' or '+' loop that matches empty ends the
+//! loop (so '(a*)*' on "b" captures ""), and an empty match in 'find_iter' may
+//! be followed by a non-empty match starting at the same position.
+//!
+//! Patterns are compiled into a program that is run by a backtracking VM.
+//! Unless the pattern uses backreferences, the VM remembers every (pc, pos)
+//! state it has already tried, which bounds a search to O(pattern * text) steps,
+//! apart from the steps a loop iteration takes before it consumes anything.
+use crate::Error;
+use crate::ErrorKind;
+use crate::IndexMap;
+use crate::Result;
+use std::fmt;
+
+/// Counted repetitions are expanded when compiled, so they are capped
+const MAX_REPEAT: u32 = 1000;
+
+/// Above this many (pc, pos) states, the VM runs without memoization
+const MAX_MEMO_BITS: usize = 1 << 25;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RegexFlags {
+    /// 'i': letters match regardless of case
+    pub ignore_case: bool,
+
+    /// 'm': '^' and '$' also match at the start and end of lines
+    pub multiline: bool,
+
+    /// 's': '.' also matches newlines
+    pub dotall: bool,
+}
+
+impl RegexFlags {
+    pub fn parse(flags: &str) -> Result<Self> {
+        let mut ret = Self::default();
+        for c in flags.chars() {
+            if !ret.set(c, true) {
+                return Err(regex_error(format!("Unrecognized regex flag {:?}", c)));
+            }
+        }
+        Ok(ret)
+    }
+    fn set(&mut self, flag: char, on: bool) -> bool {
+        match flag {
+            'i' => self.ignore_case = on,
+            'm' => self.multiline = on,
+            's' => self.dotall = on,
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// A compiled regular expression
+pub struct Regex {
+    pattern: String,
+    prog: Vec<Inst>,
+    ngroups: usize,
+    nslots: usize,
+    names: IndexMap<String, usize>,
+    has_backrefs: bool,
+}
+
+/// The positions of a match and its capture groups
+#[derive(Debug, Clone, PartialEq)]
+pub struct Captures {
+    slots: Vec<Option<usize>>,
+}
+
+impl Captures {
+    /// The (start, end) of the given group, where group 0 is the whole match
+    pub fn get(&self, group: usize) -> Option<(usize, usize)> {
+        match (self.slots.get(2 * group)?, self.slots.get(2 * group + 1)?) {
+            (Some(start), Some(end)) => Some((*start, *end)),
+            _ => None,
+        }
+    }
+    pub fn start(&self) -> usize {
+        self.slots[0].unwrap()
+    }
+    pub fn end(&self) -> usize {
+        self.slots[1].unwrap()
+    }
+    /// Number of groups, including group 0
+    pub fn len(&self) -> usize {
+        self.slots.len() / 2
+    }
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Self> {
+        Self::with_flags(pattern, RegexFlags::default())
+    }
+    pub fn with_flags(pattern: &str, flags: RegexFlags) -> Result<Self> {
+        let mut parser = Parser {
+            chars: pattern.chars().collect(),
+            i: 0,
+            flags,
+            ngroups: 1,
+            names: IndexMap::new(),
+            has_backrefs: false,
+        };
+        let node = parser.alt()?;
+        if parser.i < parser.chars.len() {
+            // the only way to stop early is an unmatched ')'
+            return Err(parser.error("Unmatched ')'"));
+        }
+
+        let mut compiler = Compiler {
+            prog: vec![],
+            nslots: 2 * parser.ngroups,
+        };
+
+        // an unanchored search is a match of '(?s:.)*?' followed by the pattern;
+        // anchored searches start after the prefix
+        compiler.emit(Inst::Split(3, 1));
+        compiler.emit(Inst::Any(true));
+        compiler.emit(Inst::Jmp(0));
+        compiler.emit(Inst::Save(0));
+        compiler.compile(&node)?;
+        compiler.emit(Inst::Save(1));
+        compiler.emit(Inst::Match);
+
+        Ok(Self {
+            pattern: pattern.to_owned(),
+            prog: compiler.prog,
+            ngroups: parser.ngroups,
+            nslots: compiler.nslots,
+            names: parser.names,
+            has_backrefs: parser.has_backrefs,
+        })
+    }
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+    /// Number of capture groups, including group 0 (the whole match)
+    pub fn ngroups(&self) -> usize {
+        self.ngroups
+    }
+    /// Names of named groups, mapped to their group numbers
+    pub fn names(&self) -> &IndexMap<String, usize> {
+        &self.names
+    }
+
+    /// Finds the leftmost match starting at or after 'start'
+    pub fn search(&self, text: &str, start: usize) -> Option<Captures> {
+        self.exec(text, start, 0, Accept::Any)
+    }
+    /// Matches only at 'start'
+    pub fn match_at(&self, text: &str, start: usize) -> Option<Captures> {
+        self.exec(text, start, ANCHORED_START, Accept::Any)
+    }
+    /// Matches only if the match starts at 'start' and extends to the end of the text
+    pub fn fullmatch_at(&self, text: &str, start: usize) -> Option<Captures> {
+        self.exec(text, start, ANCHORED_START, Accept::At(text.len()))
+    }
+    /// All non-overlapping matches, from left to right.
+    /// An empty match is allowed directly after the previous match, and after an
+    /// empty match, the next match is the leftmost one that is not that same empty match
+    pub fn find_iter<'a>(&'a self, text: &'a str) -> impl Iterator<Item = Captures> + 'a {
+        let mut pos = 0;
+        let mut accept = Accept::Any;
+        std::iter::from_fn(move || {
+            let caps = self.exec(text, pos, 0, accept)?;
+            accept = if caps.start() == caps.end() {
+                Accept::Advanced
+            } else {
+                Accept::Any
+            };
+            pos = caps.end();
+            Some(caps)
+        })
+    }
+
+    fn exec(&self, text: &str, start: usize, pc: usize, accept: Accept) -> Option<Captures> {
+        if start > text.len() || !text.is_char_boundary(start) {
+            return None;
+        }
+        let mut slots = vec![None; self.nslots];
+        let memo = !self.has_backrefs;
+        if self.run(text, pc, start, &mut slots, accept, memo) {
+            slots.truncate(2 * self.ngroups);
+            Some(Captures { slots })
+        } else {
+            None
+        }
+    }
+
+    /// Runs the program from the given pc and position.
+    /// On success, returns true, with 'slots' updated to reflect the match
+    fn run(
+        &self,
+        text: &str,
+        pc: usize,
+        pos: usize,
+        slots: &mut [Option<usize>],
+        accept: Accept,
+        memo: bool,
+    ) -> bool {
+        let width = text.len() - pos + 1;
+        let base = pos;
+        let mut visited = if memo && self.prog.len() * width <= MAX_MEMO_BITS {
+            Some(vec![0u64; (self.prog.len() * width).div_ceil(64)])
+        } else {
+            None
+        };
+        let mut stack = vec![Job::Try(pc, pos)];
+        while let Some(job) = stack.pop() {
+            let (mut pc, mut pos) = match job {
+                Job::Try(pc, pos) => (pc, pos),
+                Job::Restore(slot, value) => {
+                    slots[slot] = value;
+                    continue;
+                }
+            };
+            loop {
+                // states inside a loop iteration that has not consumed anything yet
+                // depend on more than (pc, pos), since they end the loop on an empty
+                // iteration, so they are not memoized
+                let unadvanced = slots[2 * self.ngroups..].contains(&Some(pos));
+                if let (Some(visited), false) = (&mut visited, unadvanced) {
+                    let i = pc * width + (pos - base);
+                    if visited[i / 64] & (1 << (i % 64)) != 0 {
+                        break;
+                    }
+                    visited[i / 64] |= 1 << (i % 64);
+                }
+                match &self.prog[pc] {
+                    Inst::Char(expected) => match text[pos..].chars().next() {
+                        Some(c) if c == *expected => {
+                            pc += 1;
+                            pos += c.len_utf8();
+                        }
+                        _ => break,
+                    },
+                    Inst::CharFold(expected) => match text[pos..].chars().next() {
+                        Some(c) if fold(c) == *expected => {
+                            pc += 1;
+                            pos += c.len_utf8();
+                        }
+                        _ => break,
+                    },
+                    Inst::Any(dotall) => match text[pos..].chars().next() {
+                        Some(c) if *dotall || c != '\n' => {
+                            pc += 1;
+                            pos += c.len_utf8();
+                        }
+                        _ => break,
+                    },
+                    Inst::Class(class) => match text[pos..].chars().next() {
+                        Some(c) if class.matches(c) => {
+                            pc += 1;
+                            pos += c.len_utf8();
+                        }
+                        _ => break,
+                    },
+                    Inst::Assert(assertion) => {
+                        if assertion.check(text, pos) {
+                            pc += 1;
+                        } else {
+                            break;
+                        }
+                    }
+                    Inst::Backref {
+                        group,
+                        fold: ignore_case,
+                    } => {
+                        let captured = match (slots[2 * group], slots[2 * group + 1]) {
+                            (Some(start), Some(end)) => &text[start..end],
+                            // like JavaScript, a group that did not participate matches empty
+                            _ => "",
+                        };
+                        match match_backref(captured, &text[pos..], *ignore_case) {
+                            Some(len) => {
+                                pc += 1;
+                                pos += len;
+                            }
+                            None => break,
+                        }
+                    }
+                    Inst::Look {
+                        start,
+                        next,
+                        behind,
+                        negate,
+                    } => {
+                        let mut sub = slots.to_vec();
+                        let found = if *behind {
+                            // try every start position, nearest first
+                            let mut found = false;
+                            let mut q = pos;
+                            loop {
+                                if self.run(text, *start, q, &mut sub, Accept::At(pos), false) {
+                                    found = true;
+                                    break;
+                                }
+                                match text[..q].chars().next_back() {
+                                    Some(c) => q -= c.len_utf8(),
+                                    None => break,
+                                }
+                            }
+                            found
+                        } else {
+                            self.run(text, *start, pos, &mut sub, Accept::Any, false)
+                        };
+                        if found == *negate {
+                            break;
+                        }
+                        if !*negate {
+                            // keep the groups captured inside a positive lookaround
+                            for (i, value) in sub.into_iter().enumerate() {
+                                if slots[i] != value {
+                                    stack.push(Job::Restore(i, slots[i]));
+                                    slots[i] = value;
+                                }
+                            }
+                        }
+                        pc = *next;
+                    }
+                    Inst::Split(a, b) => {
+                        stack.push(Job::Try(*b, pos));
+                        pc = *a;
+                    }
+                    Inst::Jmp(target) => pc = *target,
+                    Inst::Save(slot) => {
+                        stack.push(Job::Restore(*slot, slots[*slot]));
+                        slots[*slot] = Some(pos);
+                        pc += 1;
+                    }
+                    Inst::ProgressSave(slot) => {
+                        stack.push(Job::Restore(*slot, slots[*slot]));
+                        slots[*slot] = Some(pos);
+                        pc += 1;
+                    }
+                    Inst::ProgressReset(slot) => {
+                        stack.push(Job::Restore(*slot, slots[*slot]));
+                        slots[*slot] = None;
+                        pc += 1;
+                    }
+                    Inst::ProgressCheck { slot, exit } => {
+                        // an iteration that matched empty ends the loop
+                        pc = if slots[*slot] == Some(pos) {
+                            *exit
+                        } else {
+                            pc + 1
+                        };
+                    }
+                    Inst::Match => {
+                        let ok = match accept {
+                            Accept::Any => true,
+                            Accept::At(end) => pos == end,
+                            Accept::Advanced => pos != base,
+                        };
+                        if ok {
+                            return true;
+                        }
+                        break;
+                    }
+                }
+            }
+        }
+        false
+    }
+}
+
+impl fmt::Debug for Regex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Regex({:?})", self.pattern)
+    }
+}
+
+const ANCHORED_START: usize = 3;
+
+enum Job {
+    Try(usize, usize),
+    Restore(usize, Option<usize>),
+}
+
+/// Where a run is allowed to end
+#[derive(Debug, Clone, Copy)]
+enum Accept {
+    Any,
+    /// only at this position (for fullmatch and lookbehind)
+    At(usize),
+    /// anywhere but where the run started (for 'find_iter', after an empty match)
+    Advanced,
+}
+
+fn regex_error(message: String) -> Error {
+    Error::new_with_kind(ErrorKind::Parse, message.into(), vec![])
+}
+
+/// Simple case folding: maps a char to its lowercase form, if that is a single char
+fn fold(c: char) -> char {
+    let mut lower = c.to_lowercase();
+    match (lower.next(), lower.next()) {
+        (Some(l), None) => l,
+        _ => c,
+    }
+}
+
+fn unfold(c: char) -> char {
+    let mut upper = c.to_uppercase();
+    match (upper.next(), upper.next()) {
+        (Some(u), None) => u,
+        _ => c,
+    }
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Returns the byte length of the matched prefix of 'text', if it starts with 'captured'
+fn match_backref(captured: &str, text: &str, ignore_case: bool) -> Option<usize> {
+    if !ignore_case {
+        return if text.starts_with(captured) {
+            Some(captured.len())
+        } else {
+            None
+        };
+    }
+    let mut len = 0;
+    let mut chars = text.chars();
+    for expected in captured.chars() {
+        match chars.next() {
+            Some(c) if fold(c) == fold(expected) => len += c.len_utf8(),
+            _ => return None,
+        }
+    }
+    Some(len)
+}
+
+enum Inst {
+    Char(char),
+    /// Matches a char whose folded form is the given (already folded) char
+    CharFold(char),
+    /// Any char; newlines only if the flag is set
+    Any(bool),
+    Class(Box<Class>),
+    Assert(Assertion),
+    Backref {
+        group: usize,
+        fold: bool,
+    },
+    /// Runs the sub-program at 'start' as a lookaround, then continues at 'next'
+    Look {
+        start: usize,
+        next: usize,
+        behind: bool,
+        negate: bool,
+    },
+    /// Try the first target, falling back to the second
+    Split(usize, usize),
+    Jmp(usize),
+    Save(usize),
+    /// Records where a loop iteration starts
+    ProgressSave(usize),
+    /// Leaves the loop for 'exit' if the iteration that just ended matched empty
+    ProgressCheck {
+        slot: usize,
+        exit: usize,
+    },
+    /// Forgets where the last iteration of a loop started, once the loop is done
+    ProgressReset(usize),
+    Match,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Assertion {
+    StartText,
+    EndText,
+    StartLine,
+    EndLine,
+    WordBoundary,
+    NotWordBoundary,
+}
+
+impl Assertion {
+    fn check(self, text: &str, pos: usize) -> bool {
+        let before = text[..pos].chars().next_back();
+        let after = text[pos..].chars().next();
+        match self {
+            Self::StartText => pos == 0,
+            Self::EndText => pos == text.len(),
+            Self::StartLine => before.map(|c| c == '\n').unwrap_or(true),
+            Self::EndLine => after.map(|c| c == '\n').unwrap_or(true),
+            Self::WordBoundary => {
+                before.map(is_word).unwrap_or(false) != after.map(is_word).unwrap_or(false)
+            }
+            Self::NotWordBoundary => {
+                before.map(is_word).unwrap_or(false) == after.map(is_word).unwrap_or(false)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ClassItem {
+    Range(char, char),
+    Digit(bool),
+    Word(bool),
+    Space(bool),
+}
+
+impl ClassItem {
+    fn matches(self, c: char) -> bool {
+        match self {
+            Self::Range(lo, hi) => lo <= c && c <= hi,
+            Self::Digit(negate) => c.is_ascii_digit() != negate,
+            Self::Word(negate) => is_word(c) != negate,
+            Self::Space(negate) => c.is_whitespace() != negate,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Class {
+    negate: bool,
+    fold: bool,
+    items: Vec<ClassItem>,
+}
+
+impl Class {
+    fn matches(&self, c: char) -> bool {
+        let found = self.items.iter().any(|item| {
+            item.matches(c) || (self.fold && (item.matches(fold(c)) || item.matches(unfold(c))))
+        });
+        found != self.negate
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Empty,
+    Char(char, bool),
+    Any(bool),
+    Class(Class),
+    Assert(Assertion),
+    Group(Box<Node>, Option<usize>),
+    Concat(Vec<Node>),
+    Alt(Vec<Node>),
+    Repeat {
+        node: Box<Node>,
+        min: u32,
+        max: Option<u32>,
+        greedy: bool,
+    },
+    Backref(usize, bool),
+    Look {
+        node: Box<Node>,
+        behind: bool,
+        negate: bool,
+    },
+}
+
+struct Parser {
+    chars: Vec<char>,
+    i: usize,
+    flags: RegexFlags,
+    ngroups: usize,
+    names: IndexMap<String, usize>,
+    has_backrefs: bool,
+}
+
+impl Parser {
+    fn error(&self, message: &str) -> Error {
+        let pattern: String = self.chars.iter().collect();
+        regex_error(format!(
+            "{} at position {} in regex {:?}",
+            message, self.i, pattern
+        ))
+    }
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.i).cloned()
+    }
+    fn consume(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.i += 1;
+            true
+        } else {
+            false
+        }
+    }
+    fn consume_str(&mut self, s: &str) -> bool {
+        if self.chars[self.i..]
+            .iter()
+            .cloned()
+            .take(s.len())
+            .eq(s.chars())
+        {
+            self.i += s.len();
+            true
+        } else {
+            false
+        }
+    }
+    fn next(&mut self) -> Result<char> {
+        match self.peek() {
+            Some(c) => {
+                self.i += 1;
+                Ok(c)
+            }
+            None => Err(self.error("Unexpected end of pattern")),
+        }
+    }
+    /// Parses alternatives, up to the end of the pattern or an unmatched ')'.
+    /// Inline flags only last until the end of the enclosing group
+    fn alt(&mut self) -> Result<Node> {
+        let saved_flags = self.flags;
+        let mut alts = vec![self.concat()?];
+        while self.consume('|') {
+            alts.push(self.concat()?);
+        }
+        self.flags = saved_flags;
+        Ok(if alts.len() == 1 {
+            alts.pop().unwrap()
+        } else {
+            Node::Alt(alts)
+        })
+    }
+
+    fn concat(&mut self) -> Result<Node> {
+        let mut nodes = vec![];
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = match self.atom()? {
+                Some(atom) => atom,
+                None => continue,
+            };
+            let atom = self.quantifier(atom)?;
+            nodes.push(atom);
+        }
+        Ok(match nodes.len() {
+            0 => Node::Empty,
+            1 => nodes.pop().unwrap(),
+            _ => Node::Concat(nodes),
+        })
+    }
+
+    fn quantifier(&mut self, atom: Node) -> Result<Node> {
+        let start = self.i;
+        let (min, max) = match self.repetition()? {
+            Some(range) => range,
+            None => return Ok(atom),
+        };
+        if let Node::Assert(_) | Node::Look { .. } | Node::Empty = atom {
+            self.i = start;
+            return Err(self.error("Nothing to repeat"));
+        }
+        let greedy = !self.consume('?');
+        let start = self.i;
+        if self.repetition()?.is_some() {
+            self.i = start;
+            return Err(self.error("Multiple repeat"));
+        }
+        Ok(Node::Repeat {
+            node: atom.into(),
+            min,
+            max,
+            greedy,
+        })
+    }
+
+    /// Parses a quantifier, if there is one
+    fn repetition(&mut self) -> Result<Option<(u32, Option<u32>)>> {
+        let range = match self.peek() {
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('?') => (0, Some(1)),
+            Some('{') => return self.counted(),
+            _ => return Ok(None),
+        };
+        self.i += 1;
+        Ok(Some(range))
+    }
+
+    /// Parses '{n}', '{n,}' or '{n,m}'.
+    /// If the brace does not start a valid counted repetition, it is treated
+    /// as a literal, and None is returned with the position unchanged
+    fn counted(&mut self) -> Result<Option<(u32, Option<u32>)>> {
+        let start = self.i;
+        self.i += 1;
+        let min = self.number();
+        let max = if self.consume(',') {
+            self.number()
+        } else {
+            min
+        };
+        if min.is_none() || !self.consume('}') {
+            self.i = start;
+            return Ok(None);
+        }
+        let min = min.unwrap();
+        if min > MAX_REPEAT || max.unwrap_or(0) > MAX_REPEAT {
+            return Err(self.error(&format!("Repetition count above {}", MAX_REPEAT)));
+        }
+        if let Some(max) = max {
+            if max < min {
+                return Err(self.error("Repetition range out of order"));
+            }
+        }
+        Ok(Some((min, max)))
+    }
+
+    fn number(&mut self) -> Option<u32> {
+        let start = self.i;
+        let mut n: u32 = 0;
+        while let Some(d) = self.peek().and_then(|c| c.to_digit(10)) {
+            n = n.saturating_mul(10).saturating_add(d);
+            self.i += 1;
+        }
+        if self.i == start {
+            None
+        } else {
+            Some(n)
+        }
+    }
+
+    /// Parses a single atom.
+    /// Returns None for constructs that produce no node (inline flags)
+    fn atom(&mut self) -> Result<Option<Node>> {
+        let c = self.next()?;
+        Ok(Some(match c {
+            '.' => Node::Any(self.flags.dotall),
+            '^' => Node::Assert(if self.flags.multiline {
+                Assertion::StartLine
+            } else {
+                Assertion::StartText
+            }),
+            '$' => Node::Assert(if self.flags.multiline {
+                Assertion::EndLine
+            } else {
+                Assertion::EndText
+            }),
+            '[' => Node::Class(self.class()?),
+            '(' => return self.group(),
+            '\\' => self.escape()?,
+            '*' | '+' | '?' => {
+                self.i -= 1;
+                return Err(self.error("Nothing to repeat"));
+            }
+            c => self.literal(c),
+        }))
+    }
+
+    fn literal(&self, c: char) -> Node {
+        if self.flags.ignore_case {
+            Node::Char(fold(c), true)
+        } else {
+            Node::Char(c, false)
+        }
+    }
+
+    fn group(&mut self) -> Result<Option<Node>> {
+        let open = self.i - 1;
+        let mut capture = true;
+        let mut name = None;
+        let mut look = None;
+        let mut scoped_flags = None;
+        if self.consume('?') {
+            capture = false;
+            if self.consume(':') {
+            } else if self.consume('=') {
+                look = Some((false, false));
+            } else if self.consume('!') {
+                look = Some((false, true));
+            } else if self.consume_str("<=") {
+                look = Some((true, false));
+            } else if self.consume_str("<!") {
+                look = Some((true, true));
+            } else if self.consume_str("P<") || self.consume('<') {
+                capture = true;
+                name = Some(self.group_name()?);
+            } else {
+                // inline flags: '(?im)', '(?-i)' or '(?i:...)'
+                let mut flags = self.flags;
+                let mut on = true;
+                loop {
+                    match self.next()? {
+                        ')' => {
+                            self.flags = flags;
+                            return Ok(None);
+                        }
+                        ':' => break,
+                        '-' if on => on = false,
+                        c => {
+                            if !flags.set(c, on) {
+                                self.i -= 1;
+                                return Err(self.error("Unrecognized group syntax"));
+                            }
+                        }
+                    }
+                }
+                scoped_flags = Some(flags);
+            }
+        }
+        let index = if capture {
+            let index = self.ngroups;
+            self.ngroups += 1;
+            if let Some(name) = name {
+                if self.names.insert(name, index).is_some() {
+                    self.i = open;
+                    return Err(self.error("Duplicate group name"));
+                }
+            }
+            Some(index)
+        } else {
+            None
+        };
+        let saved_flags = self.flags;
+        if let Some(flags) = scoped_flags {
+            self.flags = flags;
+        }
+        let node = self.alt()?;
+        self.flags = saved_flags;
+        if !self.consume(')') {
+            self.i = open;
+            return Err(self.error("Unclosed group"));
+        }
+        Ok(Some(match look {
+            Some((behind, negate)) => Node::Look {
+                node: node.into(),
+                behind,
+                negate,
+            },
+            None => Node::Group(node.into(), index),
+        }))
+    }
+
+    fn group_name(&mut self) -> Result<String> {
+        let mut name = String::new();
+        loop {
+            match self.next()? {
+                '>' => break,
+                c if c == '_'
+                    || c.is_ascii_alphabetic()
+                    || (!name.is_empty() && c.is_ascii_digit()) =>
+                {
+                    name.push(c)
+                }
+                _ => {
+                    self.i -= 1;
+                    return Err(self.error("Invalid group name"));
+                }
+            }
+        }
+        if name.is_empty() {
+            return Err(self.error("Empty group name"));
+        }
+        Ok(name)
+    }
+
+    fn escape(&mut self) -> Result<Node> {
+        let c = self.next()?;
+        Ok(match c {
+            'd' | 'D' | 'w' | 'W' | 's' | 'S' => Node::Class(Class {
+                negate: false,
+                fold: false,
+                items: vec![class_escape(c).unwrap()],
+            }),
+            'b' => Node::Assert(Assertion::WordBoundary),
+            'B' => Node::Assert(Assertion::NotWordBoundary),
+            'A' => Node::Assert(Assertion::StartText),
+            'z' | 'Z' => Node::Assert(Assertion::EndText),
+            '1'..='9' => {
+                let mut group = c.to_digit(10).unwrap() as usize;
+                if let Some(d) = self.peek().and_then(|c| c.to_digit(10)) {
+                    // two digit references only if there are enough groups so far
+                    if group * 10 + (d as usize) < self.ngroups {
+                        group = group * 10 + d as usize;
+                        self.i += 1;
+                    }
+                }
+                self.has_backrefs = true;
+                if group >= self.ngroups {
+                    self.i -= 1;
+                    return Err(self.error("Backreference to an undefined group"));
+                }
+                Node::Backref(group, self.flags.ignore_case)
+            }
+            c => {
+                let c = self.char_escape(c)?;
+                self.literal(c)
+            }
+        })
+    }
+
+    /// Escapes that stand for a single char, valid both inside and outside classes
+    fn char_escape(&mut self, c: char) -> Result<char> {
+        Ok(match c {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            'f' => '\u{c}',
+            'v' => '\u{b}',
+            '0' => '\0',
+            'x' => self.hex(2)?,
+            'u' => {
+                if self.consume('{') {
+                    let start = self.i;
+                    while self.peek().map(|c| c != '}').unwrap_or(false) {
+                        self.i += 1;
+                    }
+                    let digits: String = self.chars[start..self.i].iter().collect();
+                    self.next()?;
+                    match u32::from_str_radix(&digits, 16)
+                        .ok()
+                        .and_then(std::char::from_u32)
+                    {
+                        Some(c) => c,
+                        None => return Err(self.error("Invalid unicode escape")),
+                    }
+                } else {
+                    self.hex(4)?
+                }
+            }
+            c if c.is_ascii_alphanumeric() => {
+                self.i -= 1;
+                return Err(self.error("Unrecognized escape"));
+            }
+            c => c,
+        })
+    }
+
+    fn hex(&mut self, n: usize) -> Result<char> {
+        let mut code = 0;
+        for _ in 0..n {
+            match self.next()?.to_digit(16) {
+                Some(d) => code = code * 16 + d,
+                None => {
+                    self.i -= 1;
+                    return Err(self.error("Invalid hex escape"));
+                }
+            }
+        }
+        match std::char::from_u32(code) {
+            Some(c) => Ok(c),
+            None => Err(self.error("Invalid hex escape")),
+        }
+    }
+
+    fn class(&mut self) -> Result<Class> {
+        let open = self.i - 1;
+        let negate = self.consume('^');
+        let mut items = vec![];
+        let mut first = true;
+        loop {
+            let c = match self.peek() {
+                Some(c) => c,
+                None => {
+                    self.i = open;
+                    return Err(self.error("Unclosed character class"));
+                }
+            };
+            self.i += 1;
+            if c == ']' && !first {
+                break;
+            }
+            first = false;
+            let lo = if c == '\\' {
+                let e = self.next()?;
+                if let Some(item) = class_escape(e) {
+                    if self.peek() == Some('-') && self.chars.get(self.i + 1) != Some(&']') {
+                        return Err(self.error("Invalid character class range"));
+                    }
+                    items.push(item);
+                    continue;
+                }
+                if e == 'b' {
+                    '\u{8}'
+                } else {
+                    self.char_escape(e)?
+                }
+            } else {
+                c
+            };
+            // a '-' at the end of the class is a literal
+            if self.peek() == Some('-')
+                && self
+                    .chars
+                    .get(self.i + 1)
+                    .map(|c| *c != ']')
+                    .unwrap_or(false)
+            {
+                self.i += 1;
+                let hi = match self.next()? {
+                    '\\' => {
+                        let e = self.next()?;
+                        if class_escape(e).is_some() {
+                            self.i -= 2;
+                            return Err(self.error("Invalid character class range"));
+                        }
+                        self.char_escape(e)?
+                    }
+                    c => c,
+                };
+                if hi < lo {
+                    return Err(self.error("Character class range out of order"));
+                }
+                items.push(ClassItem::Range(lo, hi));
+            } else {
+                items.push(ClassItem::Range(lo, lo));
+            }
+        }
+        Ok(Class {
+            negate,
+            fold: self.flags.ignore_case,
+            items,
+        })
+    }
+}
+
+fn class_escape(c: char) -> Option<ClassItem> {
+    match c {
+        'd' => Some(ClassItem::Digit(false)),
+        'D' => Some(ClassItem::Digit(true)),
+        'w' => Some(ClassItem::Word(false)),
+        'W' => Some(ClassItem::Word(true)),
+        's' => Some(ClassItem::Space(false)),
+        'S' => Some(ClassItem::Space(true)),
+        _ => None,
+    }
+}
+
+struct Compiler {
+    prog: Vec<Inst>,
+
+    // capture slots come first, followed by registers for progress checks
+    nslots: usize,
+}
+
+impl Compiler {
+    fn emit(&mut self, inst: Inst) -> usize {
+        self.prog.push(inst);
+        self.prog.len() - 1
+    }
+    fn patch_split(&mut self, at: usize, a: usize, b: usize) {
+        self.prog[at] = Inst::Split(a, b);
+    }
+    fn compile(&mut self, node: &Node) -> Result<()> {
+        match node {
+            Node::Empty => {}
+            Node::Char(c, false) => {
+                self.emit(Inst::Char(*c));
+            }
+            Node::Char(c, true) => {
+                self.emit(Inst::CharFold(*c));
+            }
+            Node::Any(dotall) => {
+                self.emit(Inst::Any(*dotall));
+            }
+            Node::Class(class) => {
+                self.emit(Inst::Class(class.clone().into()));
+            }
+            Node::Assert(assertion) => {
+                self.emit(Inst::Assert(*assertion));
+            }
+            Node::Group(node, None) => self.compile(node)?,
+            Node::Group(node, Some(index)) => {
+                self.emit(Inst::Save(2 * index));
+                self.compile(node)?;
+                self.emit(Inst::Save(2 * index + 1));
+            }
+            Node::Concat(nodes) => {
+                for node in nodes {
+                    self.compile(node)?;
+                }
+            }
+            Node::Alt(alts) => {
+                let mut jumps = vec![];
+                for (i, alt) in alts.iter().enumerate() {
+                    if i + 1 < alts.len() {
+                        let split = self.emit(Inst::Match);
+                        self.compile(alt)?;
+                        jumps.push(self.emit(Inst::Match));
+                        let next = self.prog.len();
+                        self.patch_split(split, split + 1, next);
+                    } else {
+                        self.compile(alt)?;
+                    }
+                }
+                let end = self.prog.len();
+                for jump in jumps {
+                    self.prog[jump] = Inst::Jmp(end);
+                }
+            }
+            Node::Repeat {
+                node,
+                min,
+                max,
+                greedy,
+            } => {
+                for _ in 0..*min {
+                    self.compile(node)?;
+                }
+                match max {
+                    None => {
+                        let register = self.nslots;
+                        self.nslots += 1;
+                        let split = self.emit(Inst::Match);
+                        self.emit(Inst::ProgressSave(register));
+                        self.compile(node)?;
+                        let check = self.emit(Inst::Match);
+                        self.emit(Inst::Jmp(split));
+                        let end = self.emit(Inst::ProgressReset(register));
+                        self.prog[check] = Inst::ProgressCheck {
+                            slot: register,
+                            exit: end,
+                        };
+                        self.split(split, split + 1, end, *greedy);
+                    }
+                    Some(max) => {
+                        let mut splits = vec![];
+                        for _ in *min..*max {
+                            splits.push(self.emit(Inst::Match));
+                            self.compile(node)?;
+                        }
+                        let end = self.prog.len();
+                        for split in splits {
+                            self.split(split, split + 1, end, *greedy);
+                        }
+                    }
+                }
+            }
+            Node::Backref(group, fold) => {
+                self.emit(Inst::Backref {
+                    group: *group,
+                    fold: *fold,
+                });
+            }
+            Node::Look {
+                node,
+                behind,
+                negate,
+            } => {
+                // the body follows the Look instruction, and ends in its own Match
+                let look = self.emit(Inst::Match);
+                self.compile(node)?;
+                self.emit(Inst::Match);
+                self.prog[look] = Inst::Look {
+                    start: look + 1,
+                    next: self.prog.len(),
+                    behind: *behind,
+                    negate: *negate,
+                };
+            }
+        }
+        if self.prog.len() > 1_000_000 {
+            return Err(regex_error("Regex is too large".into()));
+        }
+        Ok(())
+    }
+    fn split(&mut self, at: usize, body: usize, exit: usize, greedy: bool) {
+        if greedy {
+            self.patch_split(at, body, exit);
+        } else {
+            self.patch_split(at, exit, body);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// (pattern, flags, text, expected groups of the first match, or None for no match)
+    type Case = (
+        &'static str,
+        &'static str,
+        &'static str,
+        Option<&'static [Option<&'static str>]>,
+    );
+
+    #[rustfmt::skip]
+    const CONFORMANCE: &[Case] = &[
+        // literals and dot
+        ("abc", "", "xabcx", Some(&[Some("abc")])),
+        ("abc", "", "xabx", None),
+        ("a.c", "", "abc", Some(&[Some("abc")])),
+        ("a.c", "", "a\nc", None),
+        ("a.c", "s", "a\nc", Some(&[Some("a\nc")])),
+        ("(?s)a.c", "", "a\nc", Some(&[Some("a\nc")])),
+        ("é.", "", "xéü", Some(&[Some("éü")])),
+        ("", "", "abc", Some(&[Some("")])),
+        // classes
+        ("[abc]+", "", "xxbcay", Some(&[Some("bca")])),
+        ("[^abc]+", "", "abxyzc", Some(&[Some("xyz")])),
+        ("[a-z0-9_]+", "", "--ab_9--", Some(&[Some("ab_9")])),
+        ("[]a]+", "", "x]a]", Some(&[Some("]a]")])),
+        ("[a-]+", "", "x-a-", Some(&[Some("-a-")])),
+        ("[\\d.]+", "", "v1.25;", Some(&[Some("1.25")])),
+        ("[^\\s]+", "", "  hi  ", Some(&[Some("hi")])),
+        ("\\d+", "", "abc123def", Some(&[Some("123")])),
+        ("\\D+", "", "123abc456", Some(&[Some("abc")])),
+        ("\\w+", "", "  foo_bar1!", Some(&[Some("foo_bar1")])),
+        ("\\W+", "", "ab, cd", Some(&[Some(", ")])),
+        ("\\s+", "", "a \t\nb", Some(&[Some(" \t\n")])),
+        ("\\S+", "", "  ab  ", Some(&[Some("ab")])),
+        ("\\x41\\u0042\\u{43}", "", "ABC", Some(&[Some("ABC")])),
+        ("a\\.b\\*", "", "a.b*", Some(&[Some("a.b*")])),
+        ("a\\.b", "", "axb", None),
+        // quantifiers
+        ("ab*", "", "abbbc", Some(&[Some("abbb")])),
+        ("ab*?", "", "abbbc", Some(&[Some("a")])),
+        ("ab+", "", "ac", None),
+        ("ab+?", "", "abbb", Some(&[Some("ab")])),
+        ("ab?c", "", "ac", Some(&[Some("ac")])),
+        ("ab??", "", "ab", Some(&[Some("a")])),
+        ("a{3}", "", "aaaa", Some(&[Some("aaa")])),
+        ("a{2,}", "", "aaaa", Some(&[Some("aaaa")])),
+        ("a{2,3}", "", "aaaa", Some(&[Some("aaa")])),
+        ("a{2,3}?", "", "aaaa", Some(&[Some("aa")])),
+        ("a{,2}", "", "a{,2}", Some(&[Some("a{,2}")])),
+        ("x{", "", "x{", Some(&[Some("x{")])),
+        ("<.*>", "", "<a><b>", Some(&[Some("<a><b>")])),
+        ("<.*?>", "", "<a><b>", Some(&[Some("<a>")])),
+        // alternation and groups
+        ("cat|dog", "", "hotdog", Some(&[Some("dog")])),
+        ("a|ab", "", "ab", Some(&[Some("a")])),
+        ("(a|ab)(c|bcd)", "", "abcd", Some(&[Some("abcd"), Some("a"), Some("bcd")])),
+        ("(a)|(b)", "", "b", Some(&[Some("b"), None, Some("b")])),
+        ("(?:ab)+", "", "ababx", Some(&[Some("abab")])),
+        ("(a)+", "", "aaa", Some(&[Some("aaa"), Some("a")])),
+        ("(\\d+)-(\\d+)", "", "call 555-1234", Some(&[Some("555-1234"), Some("555"), Some("1234")])),
+        ("(?P<y>\\d{4})-(?<m>\\d\\d)", "", "2020-06", Some(&[Some("2020-06"), Some("2020"), Some("06")])),
+        ("(a*)*", "", "b", Some(&[Some(""), Some("")])),
+        ("(a*)+", "", "b", Some(&[Some(""), Some("")])),
+        ("(a|b)*c", "", "abac", Some(&[Some("abac"), Some("a")])),
+        ("(a?)*?b", "", "aab", Some(&[Some("aab"), Some("a")])),
+        // an iteration that matches empty ends the loop
+        ("(.??)*", "", "11abb", Some(&[Some(""), Some("")])),
+        ("((a|b)?)*", "", "ab", Some(&[Some("ab"), Some(""), Some("b")])),
+        ("(a?)*", "", "aa", Some(&[Some("aa"), Some("")])),
+        ("(a??)+?", "", "aa", Some(&[Some(""), Some("")])),
+        ("(?:a|())*", "", "ab", Some(&[Some("a"), Some("")])),
+        // anchors
+        ("^abc", "", "abc", Some(&[Some("abc")])),
+        ("^abc", "", "xabc", None),
+        ("abc$", "", "xabc", Some(&[Some("abc")])),
+        ("abc$", "", "abc\n", None),
+        ("^b$", "", "a\nb\nc", None),
+        ("^b$", "m", "a\nb\nc", Some(&[Some("b")])),
+        ("(?m)^\\w+$", "", "a!\nbc\n", Some(&[Some("bc")])),
+        ("\\Aa", "m", "b\na", None),
+        ("a\\z", "m", "a\nb", None),
+        ("\\bfoo\\b", "", "a foo b", Some(&[Some("foo")])),
+        ("\\bfoo\\b", "", "afoo", None),
+        ("\\Boo\\B", "", "food", Some(&[Some("oo")])),
+        ("\\b.", "", " x", Some(&[Some("x")])),
+        ("\\B.", "", " x", Some(&[Some(" ")])),
+        ("x\\b", "", " x", Some(&[Some("x")])),
+        ("\\b", "", "  ", None),
+        ("\\B\\s", "", "  ", Some(&[Some(" ")])),
+        // case insensitivity
+        ("hello", "i", "HeLLo", Some(&[Some("HeLLo")])),
+        ("[a-z]+", "i", "ABC", Some(&[Some("ABC")])),
+        ("[A-Z]+", "i", "abc", Some(&[Some("abc")])),
+        ("[^a]", "i", "Ab", Some(&[Some("b")])),
+        ("(?i)straße", "", "STRAßE", Some(&[Some("STRAßE")])),
+        ("a(?i:b)c", "", "aBc", Some(&[Some("aBc")])),
+        ("a(?i:b)c", "", "aBC", None),
+        ("(?i)a(?-i)b", "", "Ab", Some(&[Some("Ab")])),
+        ("(?i)a(?-i)b", "", "AB", None),
+        ("((?i)a)b", "", "Ab", Some(&[Some("Ab"), Some("A")])),
+        ("((?i)a)b", "", "AB", None),
+        // backreferences
+        ("(\\w)\\1", "", "abccd", Some(&[Some("cc"), Some("c")])),
+        ("(a)|b\\1", "", "b", Some(&[Some("b"), None])),
+        ("(\\w+) \\1", "i", "Hey HEY", Some(&[Some("Hey HEY"), Some("Hey")])),
+        ("<(\\w+)>.*</\\1>", "", "<a><b>x</b></a>", Some(&[Some("<a><b>x</b></a>"), Some("a")])),
+        // lookaround
+        ("foo(?=bar)", "", "foobaz foobar", Some(&[Some("foo")])),
+        ("foo(?!bar)", "", "foobar foobaz", Some(&[Some("foo")])),
+        ("(?<=\\$)\\d+", "", "cost: $42", Some(&[Some("42")])),
+        ("(?<!\\$)\\b\\d+", "", "$42 17", Some(&[Some("17")])),
+        ("(?=(\\w+))\\w", "", "abc", Some(&[Some("a"), Some("abc")])),
+        ("(?<=a|bc)d", "", "bcd", Some(&[Some("d")])),
+    ];
+
+    fn groups<'a>(regex: &Regex, text: &'a str) -> Option<Vec<Option<&'a str>>> {
+        let caps = regex.search(text, 0)?;
+        Some(
+            (0..caps.len())
+                .map(|i| caps.get(i).map(|(s, e)| &text[s..e]))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn conformance() {
+        for (pattern, flags, text, expected) in CONFORMANCE {
+            let flags = RegexFlags::parse(flags).unwrap();
+            let regex = match Regex::with_flags(pattern, flags) {
+                Ok(regex) => regex,
+                Err(error) => panic!("{:?} failed to compile: {:?}", pattern, error),
+            };
+            let expected = expected.map(|groups| groups.to_vec());
+            assert_eq!(
+                groups(&regex, text),
+                expected,
+                "{:?} on {:?}",
+                pattern,
+                text
+            );
+            if !regex.has_backrefs {
+                // the result must not depend on memoization
+                let mut slots = vec![None; regex.nslots];
+                let found = (0..=text.len())
+                    .filter(|i| text.is_char_boundary(*i))
+                    .any(|i| regex.run(text, 0, i, &mut slots, Accept::Any, false));
+                assert_eq!(found, expected.is_some(), "{:?} on {:?}", pattern, text);
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        for pattern in &[
+            "(",
+            ")",
+            "a)",
+            "[a",
+            "*a",
+            "a**",
+            "a{3,2}",
+            "\\1",
+            "(a)\\2",
+            "(?P<x>a)(?P<x>b)",
+            "(?<>a)",
+            "\\q",
+            "[z-a]",
+            "[\\d-z]",
+            "(?z)",
+            "a{1001}",
+            "\\x4",
+            "^*",
+        ] {
+            assert!(
+                Regex::new(pattern).is_err(),
+                "{:?} should not compile",
+                pattern
+            );
+        }
+        assert!(RegexFlags::parse("ix").is_err());
+    }
+
+    #[test]
+    fn match_and_iteration() {
+        let re = Regex::new("\\d+").unwrap();
+        assert!(re.match_at("a12", 0).is_none());
+        assert_eq!(re.match_at("a12", 1).unwrap().get(0), Some((1, 3)));
+        assert!(re.fullmatch_at("12a", 0).is_none());
+        assert!(Regex::new("a|ab").unwrap().fullmatch_at("ab", 0).is_some());
+        let spans: Vec<_> = re
+            .find_iter("1 22 333")
+            .map(|c| c.get(0).unwrap())
+            .collect();
+        assert_eq!(spans, vec![(0, 1), (2, 4), (5, 8)]);
+        let spans: Vec<_> = Regex::new("x*")
+            .unwrap()
+            .find_iter("abxd")
+            .map(|c| c.get(0).unwrap())
+            .collect();
+        assert_eq!(spans, vec![(0, 0), (1, 1), (2, 3), (3, 3), (4, 4)]);
+        let spans: Vec<_> = Regex::new("\\b")
+            .unwrap()
+            .find_iter(" ab ")
+            .map(|c| c.get(0).unwrap())
+            .collect();
+        assert_eq!(spans, vec![(1, 1), (3, 3)]);
+        // a non-empty match may start where an empty match ended
+        let spans: Vec<_> = Regex::new("|a")
+            .unwrap()
+            .find_iter("a")
+            .map(|c| c.get(0).unwrap())
+            .collect();
+        assert_eq!(spans, vec![(0, 0), (0, 1), (1, 1)]);
+        let spans: Vec<_> = Regex::new("a|")
+            .unwrap()
+            .find_iter("aab")
+            .map(|c| c.get(0).unwrap())
+            .collect();
+        assert_eq!(spans, vec![(0, 1), (1, 2), (2, 2), (3, 3)]);
+        assert_eq!(re.names().len(), 0);
+        let re = Regex::new("(?P<a>x)(y)(?<b>z)").unwrap();
+        assert_eq!(re.ngroups(), 4);
+        assert_eq!(re.names().get("b"), Some(&3));
+    }
+
+    #[test]
+    fn pathological() {
+        // would take exponential time without memoization
+        let text = "a".repeat(40);
+        assert!(Regex::new("(a*)*b").unwrap().search(&text, 0).is_none());
+        assert!(Regex::new("(a|aa)+$").unwrap().search(&text, 0).is_some());
+        assert!(Regex::new("(x+x+)+y")
+            .unwrap()
+            .search(&"x".repeat(30), 0)
+            .is_none());
+        let text = "a".repeat(2000);
+        assert!(Regex::new("((a?)*)*b").unwrap().search(&text, 0).is_none());
+        assert!(Regex::new("(?:a?a?)*c").unwrap().search(&text, 0).is_none());
+    }
+}